@@ -58,13 +58,13 @@ lastMove = TIMER
 IF k$ <> "" THEN
     IF LEN(k$) >= 1 THEN
         c = ASC(k$)
-        ' Check for arrow keys (escape sequences)
-        IF c = 27 AND LEN(k$) >= 3 THEN
-            arrow$ = RIGHT$(k$, 1)
-            IF arrow$ = "A" AND direction <> 2 THEN direction = 1
-            IF arrow$ = "B" AND direction <> 1 THEN direction = 2
-            IF arrow$ = "D" AND direction <> 4 THEN direction = 3
-            IF arrow$ = "C" AND direction <> 3 THEN direction = 4
+        ' Check for arrow keys (CHR$(0) + scan code)
+        IF LEN(k$) = 2 AND LEFT$(k$, 1) = CHR$(0) THEN
+            arrow = ASC(RIGHT$(k$, 1))
+            IF arrow = 72 AND direction <> 2 THEN direction = 1
+            IF arrow = 80 AND direction <> 1 THEN direction = 2
+            IF arrow = 75 AND direction <> 4 THEN direction = 3
+            IF arrow = 77 AND direction <> 3 THEN direction = 4
         END IF
         ' WASD keys
         IF c = 119 OR c = 87 THEN
@@ -1,14 +1,27 @@
 //! Main application module
 
+use std::collections::HashMap;
 use std::io;
+use std::time::{Duration, Instant};
 use crate::terminal::{self, Terminal, Color};
 use crate::screen::Screen;
 use crate::input::{self, InputEvent};
 use crate::state::{AppState, Focus, RunState};
 use crate::ui::{Rect, compute_layout, ModalDialog, ModalResult, ModalAction, WidgetAction, Widgets};
-use crate::ui::dialogs::{Dialogs, DialogContext, DialogResult, DialogController};
+use crate::ui::dialogs::{find_definition, ConfirmChoice, Dialogs, DialogContext, DialogResult, DialogController, SubsDialog};
 use crate::ui::layout::main_screen_layout;
 use crate::basic::{self, Lexer, Parser, Interpreter};
+use crate::config;
+
+/// How long typing has to pause before the buffer is syntax checked
+const SYNTAX_CHECK_DELAY: Duration = Duration::from_millis(500);
+
+/// Action held back while asking whether to save unsaved changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnsavedAction {
+    OpenFile,
+    Quit,
+}
 
 /// Main application
 pub struct App {
@@ -20,30 +33,57 @@ pub struct App {
     interpreter: Interpreter,
     clipboard: Option<arboard::Clipboard>,
     /// Parsed program stored for resuming execution after NeedsInput
-    current_program: Option<Vec<basic::parser::Stmt>>,
+    current_program: Option<basic::parser::Block>,
     /// Modal dialog (captures all events when open)
     modal: Option<Box<dyn ModalDialog>>,
     /// All dialog instances
     dialogs: Dialogs,
+    /// Show the welcome dialog on startup (skipped when a file is given)
+    show_welcome: bool,
+    /// Action waiting on the "Save changes?" prompt (or the Save As it led to)
+    pending_unsaved: Option<UnsavedAction>,
+    /// When to next check syntax, once input has stopped for a moment
+    syntax_check_at: Option<Instant>,
+    /// Window title last sent to the terminal
+    window_title: String,
 }
 
 impl App {
     pub fn new() -> io::Result<Self> {
         let terminal = Terminal::new()?;
+        let mut state = AppState::new();
+        config::load(&mut state);
+        Ok(Self::with_state(terminal, state))
+    }
+
+    /// Build the app on `terminal`, starting from `state`
+    fn with_state(terminal: Terminal, mut state: AppState) -> Self {
         let (width, height) = terminal.size();
         let screen = Screen::new(width, height);
 
-        Ok(Self {
+        let mut dialogs = Dialogs::new(width, height);
+        if !state.help_path.is_empty() && dialogs.help.load_help_path(&state.help_path).is_err() {
+            state.help_path.clear();
+        }
+
+        let mut widgets = Widgets::new();
+        widgets.output.set_max_output(state.output_scrollback);
+
+        Self {
             terminal,
             screen,
-            state: AppState::new(),
-            widgets: Widgets::new(),
+            state,
+            widgets,
             interpreter: Interpreter::new(),
             clipboard: arboard::Clipboard::new().ok(),
             current_program: None,
             modal: None,
-            dialogs: Dialogs::new(width, height),
-        })
+            dialogs,
+            show_welcome: true,
+            pending_unsaved: None,
+            syntax_check_at: None,
+            window_title: String::new(),
+        }
     }
 
     pub fn run(&mut self) -> io::Result<()> {
@@ -52,11 +92,13 @@ impl App {
         self.dialogs.set_screen_size(width, height);
 
         // Show welcome dialog on startup
-        let mut ctx = DialogContext {
-            editor: &mut self.widgets.editor,
-            state: &mut self.state,
-        };
-        self.dialogs.welcome.open(&mut ctx);
+        if self.show_welcome {
+            let mut ctx = DialogContext {
+                editor: &mut self.widgets.editor,
+                state: &mut self.state,
+            };
+            self.dialogs.welcome.open(&mut ctx);
+        }
 
         loop {
             // Handle resize
@@ -91,14 +133,28 @@ impl App {
                 self.screen.apply_mouse_cursor(self.state.mouse_row, self.state.mouse_col);
             }
 
+            // Keep the window title on the file name and modified flag
+            let title = self.state.window_title();
+            if title != self.window_title {
+                self.terminal.set_title(&title)?;
+                self.window_title = title;
+            }
+
             // Flush to terminal
             self.screen.flush(&mut self.terminal)?;
 
+            // BEEP rings the terminal bell; there's no audio backend for SOUND/PLAY tones
+            let events = self.interpreter.take_sound_events();
+            if events.iter().any(|e| matches!(e, basic::sound::SoundEvent::Beep)) {
+                self.terminal.write_raw("\x07")?;
+                self.terminal.flush()?;
+            }
+
             // Handle ALL available input events before next draw cycle
             // This ensures scroll events and other rapid inputs are processed smoothly
             let mut had_input = false;
             loop {
-                let (maybe_key, raw_bytes) = self.terminal.read_key_raw()?;
+                let maybe_key = self.terminal.read_key()?;
 
                 // If waiting for input, handle differently depending on whether it's INPUT or INKEY$
                 if self.state.run_state == RunState::WaitingForInput {
@@ -132,6 +188,16 @@ impl App {
                                     terminal::Key::Char(c) => {
                                         self.interpreter.add_input_char(*c);
                                     }
+                                    terminal::Key::Paste(text) => {
+                                        // Pasted text is typed up to its first line
+                                        // break, which answers the INPUT like Enter
+                                        let line = text.split(['\r', '\n']).next().unwrap_or_default();
+                                        line.chars().filter(|c| !c.is_control()).for_each(|c| self.interpreter.add_input_char(c));
+                                        if line.len() < text.len() {
+                                            self.interpreter.complete_input();
+                                            self.continue_after_input();
+                                        }
+                                    }
                                     _ => {}
                                 }
                             }
@@ -156,20 +222,7 @@ impl App {
                                 self.continue_after_input();
                             } else {
                                 // Convert key to string for INKEY$
-                                let key_str = if !raw_bytes.is_empty() {
-                                    // Use raw bytes for escape sequences (arrow keys, etc.)
-                                    String::from_utf8_lossy(&raw_bytes).to_string()
-                                } else {
-                                    match key {
-                                        terminal::Key::Char(c) => c.to_string(),
-                                        terminal::Key::Enter => "\r".to_string(),
-                                        terminal::Key::Escape => "\x1b".to_string(),
-                                        terminal::Key::Tab => "\t".to_string(),
-                                        _ => String::new(),
-                                    }
-                                };
-
-                                if !key_str.is_empty() {
+                                if let Some(key_str) = input::inkey_code(key) {
                                     self.interpreter.set_pending_key(Some(key_str));
                                 }
                                 // Continue execution (INKEY$ should return empty string if no key available)
@@ -221,6 +274,16 @@ impl App {
                             self.state.run_state = RunState::Finished;
                             self.current_program = None;
                         }
+                        Ok(ExecutionResult::Exited) => {
+                            for line in self.interpreter.take_output() {
+                                self.widgets.output.add_output(&line);
+                            }
+                            self.state.set_status("Program ended");
+                            self.state.current_line = None;
+                            self.state.show_output = false;
+                            self.state.run_state = RunState::Editing;
+                            self.current_program = None;
+                        }
                         Ok(ExecutionResult::NeedsInput) => {
                             for line in self.interpreter.take_output() {
                                 self.widgets.output.add_output(&line);
@@ -234,33 +297,31 @@ impl App {
                             self.state.current_line = Some(line);
                             self.state.run_state = RunState::Paused;
                             self.state.show_output = false;
-                            self.state.set_status(format!("Breakpoint hit at line {}", line + 1));
+                            self.show_breakpoint_status(line);
                             self.widgets.editor.go_to_line(line + 1);
                         }
                         Ok(ExecutionResult::Stepped(line)) => {
                             self.state.current_line = Some(line);
                             self.state.run_state = RunState::Stepping;
                         }
-                        Err(e) => {
-                            self.state.show_output = false;
-                            self.state.current_line = None;
-                            self.state.run_state = RunState::Editing;
-                            self.current_program = None;
-
-                            self.dialogs.message.set_message("Runtime Error".to_string(), e.clone());
-                            let mut ctx = DialogContext {
-                                editor: &mut self.widgets.editor,
-                                state: &mut self.state,
-                            };
-                            self.dialogs.message.open(&mut ctx);
-                        }
+                        Err(e) => self.show_runtime_error(e),
                     }
                 }
             }
 
+            // Check syntax once editing pauses
+            if self.state.syntax_checking && self.state.run_state == RunState::Editing {
+                if had_input {
+                    self.syntax_check_at = Some(Instant::now() + SYNTAX_CHECK_DELAY);
+                } else if self.syntax_check_at.is_some_and(|at| Instant::now() >= at) {
+                    self.syntax_check_at = None;
+                    self.check_syntax();
+                }
+            }
+
             if !had_input && !matches!(self.state.run_state, RunState::Running | RunState::WaitingForInput) {
                 // No input this cycle and not running a program - sleep briefly to avoid 100% CPU
-                std::thread::sleep(std::time::Duration::from_millis(10));
+                std::thread::sleep(Duration::from_millis(10));
             }
 
             if self.state.should_quit {
@@ -268,6 +329,8 @@ impl App {
             }
         }
 
+        // Keep settings for next time; failing to write them shouldn't fail the exit
+        let _ = config::save(&self.state);
         Ok(())
     }
 
@@ -276,8 +339,10 @@ impl App {
 
         // If output window is visible, draw it fullscreen
         if self.state.show_output {
-            // Use graphics screen for running programs (uses LOCATE, COLOR, etc.)
-            if matches!(self.state.run_state, RunState::Running | RunState::WaitingForInput | RunState::Finished) {
+            // Use graphics screen for running programs (uses LOCATE, COLOR, etc.),
+            // and the captured output once the user scrolls back through it
+            let scrolled_back = self.state.run_state == RunState::Finished && self.widgets.output.scroll > 0;
+            if matches!(self.state.run_state, RunState::Running | RunState::WaitingForInput | RunState::Finished) && !scrolled_back {
                 self.widgets.output.draw_graphics_screen(&mut self.screen, &mut self.interpreter.graphics_mut(), &self.state);
             } else {
                 self.widgets.output.draw_fullscreen(&mut self.screen, &self.state);
@@ -332,24 +397,35 @@ impl App {
             if self.state.run_state == RunState::Running {
                 return true;
             }
-            // Program finished - wait for a KEYBOARD key press to close output
-            // Ignore all mouse events including movement
+            // Program finished - scroll back through the output until Enter
+            // or Escape closes it
             if self.state.run_state == RunState::Finished {
-                let is_keyboard = !matches!(event,
-                    InputEvent::MouseClick { .. } |
-                    InputEvent::MouseDrag { .. } |
-                    InputEvent::MouseRelease { .. } |
-                    InputEvent::MouseMove { .. } |
-                    InputEvent::ScrollUp { .. } |
-                    InputEvent::ScrollDown { .. }
-                );
-                if is_keyboard {
+                let (_, height) = self.screen.size();
+                let was_scrolled = self.widgets.output.scroll > 0;
+                match event {
+                    InputEvent::ScrollUp { .. } => self.widgets.output.scroll_up(3, height as usize),
+                    InputEvent::ScrollDown { .. } => self.widgets.output.scroll_down(3),
+                    _ => {
+                        self.widgets.output.handle_scroll_key(&event, height as usize);
+                    }
+                }
+                if was_scrolled != (self.widgets.output.scroll > 0) {
+                    // Switching between the program's screen and the scrollback
+                    self.screen.clear_sixel();
+                    self.screen.invalidate();
+                    self.interpreter.graphics_mut().force_redraw();
+                }
+                if matches!(event, InputEvent::Enter | InputEvent::Escape | InputEvent::Ctrl('s')) {
+                    self.widgets.output.scroll = 0;
                     self.state.show_output = false;
                     self.state.run_state = RunState::Editing;
                     // Clear sixel mode and force full redraw of IDE
                     self.screen.clear_sixel();
                     self.screen.invalidate();
                     self.clear_terminal_graphics();
+                    if event == InputEvent::Ctrl('s') {
+                        self.save_output();
+                    }
                 }
                 return true;
             }
@@ -391,10 +467,37 @@ impl App {
                 // Check if welcome dialog wants to open help before closing
                 let should_open_help = self.dialogs.welcome.show_help_on_close
                     && self.dialogs.welcome.is_open();
+                let confirm_closed = self.dialogs.confirm.is_open();
+                let save_as_closed = self.dialogs.file_save.is_open();
 
                 self.dialogs.close_active();
                 self.state.focus = Focus::Editor;
 
+                if confirm_closed {
+                    self.handle_modal_action(ModalAction::Confirm(self.dialogs.confirm.choice));
+                } else if save_as_closed {
+                    // Continue an open or quit that was waiting on Save As
+                    if let Some(action) = self.pending_unsaved.take() {
+                        if !self.state.modified {
+                            self.perform_unsaved_action(action);
+                        }
+                    }
+                }
+
+                // Reload help topics if a new help path was committed
+                if let Some(path) = self.dialogs.help_path.committed_path.take() {
+                    self.apply_help_path(path);
+                }
+
+                // Ask where to write what the Print dialog chose
+                if let Some(text) = self.dialogs.print.pending_text.take() {
+                    let mut ctx = DialogContext {
+                        editor: &mut self.widgets.editor,
+                        state: &mut self.state,
+                    };
+                    self.dialogs.file_save.open_for_print(&mut ctx, text);
+                }
+
                 // Open help if welcome dialog requested it
                 if should_open_help {
                     self.dialogs.help.set_topic("Index".to_string());
@@ -444,7 +547,7 @@ impl App {
         // Global shortcuts (only when no dialog is open)
         match &event {
             InputEvent::Alt('x') | InputEvent::Ctrl('q') => {
-                self.state.should_quit = true;
+                self.confirm_unsaved(UnsavedAction::Quit);
                 return true;
             }
             InputEvent::F(10) => {
@@ -473,16 +576,28 @@ impl App {
                 self.run_program();
                 return true;
             }
+            InputEvent::F(8) => {
+                self.step_program(false);
+                return true;
+            }
             InputEvent::F(2) => {
                 self.show_subs_list();
                 return true;
             }
+            InputEvent::ShiftF(2) => {
+                self.go_to_definition();
+                return true;
+            }
             InputEvent::F(6) => {
                 self.state.toggle_focus();
                 return true;
             }
             InputEvent::F(3) => {
-                self.repeat_find();
+                self.repeat_find(false);
+                return true;
+            }
+            InputEvent::ShiftF(3) => {
+                self.repeat_find(true);
                 return true;
             }
             // Search shortcuts
@@ -508,11 +623,7 @@ impl App {
                 return true;
             }
             InputEvent::Ctrl('o') => {
-                let mut ctx = DialogContext {
-                    editor: &mut self.widgets.editor,
-                    state: &mut self.state,
-                };
-                self.dialogs.file_open.open(&mut ctx);
+                self.confirm_unsaved(UnsavedAction::OpenFile);
                 return true;
             }
             InputEvent::Ctrl('n') => {
@@ -572,6 +683,9 @@ impl App {
         let layout = self.state.main_layout.clone();
         let Some(layout) = layout else { return };
 
+        if self.state.focus == Focus::Immediate && *event == InputEvent::Tab {
+            self.widgets.immediate.set_variables(self.interpreter.variable_names());
+        }
         let action = self.widgets.handle_keyboard_event(event, &mut self.state, &layout);
         self.handle_widget_action(action);
     }
@@ -580,13 +694,7 @@ impl App {
         match (menu_idx, item_idx) {
             // File menu
             (0, 0) => self.new_file(),
-            (0, 1) => {
-                let mut ctx = DialogContext {
-                    editor: &mut self.widgets.editor,
-                    state: &mut self.state,
-                };
-                self.dialogs.file_open.open(&mut ctx);
-            }
+            (0, 1) => self.confirm_unsaved(UnsavedAction::OpenFile),
             (0, 2) => self.save_file(),
             (0, 3) => {
                 let mut ctx = DialogContext {
@@ -595,14 +703,15 @@ impl App {
                 };
                 self.dialogs.file_save.open(&mut ctx);
             }
-            (0, 5) => {
+            (0, 4) => self.save_output(),
+            (0, 6) => {
                 let mut ctx = DialogContext {
                     editor: &mut self.widgets.editor,
                     state: &mut self.state,
                 };
                 self.dialogs.print.open(&mut ctx);
             }
-            (0, 7) => self.state.should_quit = true,
+            (0, 8) => self.confirm_unsaved(UnsavedAction::Quit),
 
             // Edit menu
             (1, 0) => { // Undo
@@ -633,16 +742,18 @@ impl App {
                 };
                 self.dialogs.new_function.open(&mut ctx);
             }
+            (1, 10) => self.format_program(),
 
             // View menu
             (2, 0) => self.show_subs_list(),
-            (2, 1) => { // Next Statement
+            (2, 1) => self.go_to_definition(),
+            (2, 2) => { // Next Statement
                 if let Some(line) = self.state.current_line {
                     self.widgets.editor.go_to_line(line + 1);
                     self.state.set_status(format!("Next statement at line {}", line + 1));
                 }
             }
-            (2, 2) => { // Output screen (F4)
+            (2, 3) => { // Output screen (F4)
                 self.state.show_output = !self.state.show_output;
                 if self.state.show_output {
                     self.state.set_status("Output window shown");
@@ -650,8 +761,8 @@ impl App {
                     self.state.set_status("Output window hidden");
                 }
             }
-            (2, 4) => self.state.set_status("No included files"),
             (2, 5) => self.state.set_status("No included files"),
+            (2, 6) => self.state.set_status("No included files"),
 
             // Search menu
             (3, 0) => {
@@ -661,7 +772,7 @@ impl App {
                 };
                 self.dialogs.find.open(&mut ctx);
             }
-            (3, 1) => self.repeat_find(),
+            (3, 1) => self.repeat_find(false),
             (3, 2) => {
                 let mut ctx = DialogContext {
                     editor: &mut self.widgets.editor,
@@ -689,14 +800,21 @@ impl App {
             }
 
             // Debug menu
-            (5, 0) => self.step_program(),
-            (5, 1) => self.step_program(),
+            (5, 0) => self.step_program(false),
+            (5, 1) => self.step_program(true),
             (5, 3) => self.state.toggle_breakpoint(self.widgets.editor.cursor_line),
             (5, 4) => {
+                let mut ctx = DialogContext {
+                    editor: &mut self.widgets.editor,
+                    state: &mut self.state,
+                };
+                self.dialogs.breakpoint_condition.open(&mut ctx);
+            }
+            (5, 5) => {
                 self.state.breakpoints.clear();
                 self.state.set_status("All breakpoints cleared");
             }
-            (5, 6) => { // Set Next Statement
+            (5, 7) => { // Set Next Statement
                 if self.state.run_state == RunState::Paused {
                     self.state.current_line = Some(self.widgets.editor.cursor_line);
                     self.state.set_status(format!("Next statement set to line {}", self.widgets.editor.cursor_line + 1));
@@ -730,6 +848,14 @@ impl App {
                     self.state.syntax_errors.clear();
                 }
             }
+            (6, 3) => { // DOS Line Endings toggle
+                self.state.crlf_line_endings = !self.state.crlf_line_endings;
+                if self.state.crlf_line_endings {
+                    self.state.set_status("Saving with DOS (CRLF) line endings");
+                } else {
+                    self.state.set_status("Saving with Unix (LF) line endings");
+                }
+            }
 
             // Help menu
             (7, 0) => {
@@ -816,13 +942,64 @@ impl App {
                 };
                 self.dialogs.help.open(&mut ctx);
             }
-            ModalAction::Confirm(confirmed) => {
-                // Handle confirmation result
-                if confirmed {
-                    self.state.set_status("Confirmed");
+            ModalAction::Confirm(choice) => {
+                self.state.focus = Focus::Editor;
+                let Some(action) = self.pending_unsaved.take() else { return };
+                match choice {
+                    ConfirmChoice::Yes => {
+                        self.save_file();
+                        if !self.state.modified {
+                            self.perform_unsaved_action(action);
+                        } else if self.dialogs.file_save.is_open() {
+                            // Untitled program: finish after Save As
+                            self.pending_unsaved = Some(action);
+                        }
+                    }
+                    ConfirmChoice::No => self.perform_unsaved_action(action),
+                    ConfirmChoice::Cancel => {}
                 }
+            }
+            ModalAction::GoToLine(line) => {
+                self.widgets.editor.go_to_line(line);
                 self.state.focus = Focus::Editor;
+                self.state.set_status(format!("Line {}", line));
+            }
+        }
+    }
+
+    /// Run `action`, first asking whether to save if the program has unsaved changes
+    fn confirm_unsaved(&mut self, action: UnsavedAction) {
+        if !self.state.modified {
+            self.perform_unsaved_action(action);
+            return;
+        }
+        let name = self.state.file_path.as_ref()
+            .and_then(|p| p.file_name())
+            .and_then(|s| s.to_str())
+            .unwrap_or("Untitled")
+            .to_string();
+        self.dialogs.confirm.set_message(
+            "Unsaved Changes".to_string(),
+            format!("Save changes to {}?", name),
+        );
+        self.pending_unsaved = Some(action);
+        let mut ctx = DialogContext {
+            editor: &mut self.widgets.editor,
+            state: &mut self.state,
+        };
+        self.dialogs.confirm.open(&mut ctx);
+    }
+
+    fn perform_unsaved_action(&mut self, action: UnsavedAction) {
+        match action {
+            UnsavedAction::OpenFile => {
+                let mut ctx = DialogContext {
+                    editor: &mut self.widgets.editor,
+                    state: &mut self.state,
+                };
+                self.dialogs.file_open.open(&mut ctx);
             }
+            UnsavedAction::Quit => self.state.should_quit = true,
         }
     }
 
@@ -840,6 +1017,16 @@ impl App {
         }
     }
 
+    /// Ask where to save the last program's output
+    fn save_output(&mut self) {
+        let output = self.widgets.output.to_text();
+        let mut ctx = DialogContext {
+            editor: &mut self.widgets.editor,
+            state: &mut self.state,
+        };
+        self.dialogs.file_save.open_for_output(&mut ctx, output);
+    }
+
     fn save_file(&mut self) {
         if self.state.file_path.is_none() {
             let mut ctx = DialogContext {
@@ -850,7 +1037,7 @@ impl App {
         } else {
             // Save to file
             if let Some(path) = &self.state.file_path {
-                if let Err(e) = std::fs::write(path, self.widgets.editor.content()) {
+                if let Err(e) = std::fs::write(path, self.widgets.editor.content_for_save(self.state.crlf_line_endings)) {
                     self.state.set_status(format!("Error saving: {}", e));
                 } else {
                     self.state.modified = false;
@@ -864,6 +1051,7 @@ impl App {
         match std::fs::read_to_string(&path) {
             Ok(content) => {
                 self.widgets.editor.load(&content);
+                self.state.crlf_line_endings = crate::ui::editor::uses_crlf(&content);
                 self.state.file_path = Some(path);
                 self.state.modified = false;
                 self.state.set_status("File loaded");
@@ -875,8 +1063,26 @@ impl App {
         }
     }
 
+    /// Open the file given on the command line.
+    /// A path that doesn't exist yet starts an empty buffer that will be saved there.
+    pub fn open_startup_file(&mut self, path: std::path::PathBuf) {
+        self.show_welcome = false;
+        if path.exists() {
+            self.load_file_from_path(path);
+        } else {
+            self.widgets.editor.clear();
+            let name = path.file_name()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Untitled")
+                .to_string();
+            self.state.file_path = Some(path);
+            self.state.modified = false;
+            self.state.set_status(format!("New file: {}", name));
+        }
+    }
+
     fn save_file_to_path(&mut self, path: std::path::PathBuf) {
-        let content = self.widgets.editor.content();
+        let content = self.widgets.editor.content_for_save(self.state.crlf_line_endings);
         match std::fs::write(&path, &content) {
             Ok(()) => {
                 self.state.file_path = Some(path);
@@ -976,6 +1182,7 @@ impl App {
         match parser.parse() {
             Ok(program) => {
                 self.interpreter.reset();
+                self.interpreter.graphics_mut().release_resolution();
 
                 // Size graphics buffer to terminal size (using actual pixel dimensions)
                 let (cols, rows) = self.terminal.size();
@@ -986,6 +1193,7 @@ impl App {
                 );
                 // Enable graphics mode so PRINT/LOCATE/COLOR work with screen buffer
                 self.interpreter.graphics_mut().mode = 12;
+                self.interpreter.graphics_mut().screen_output = true;
                 self.interpreter.graphics_mut().cls();
 
                 // Update character cell size for sixel positioning
@@ -995,13 +1203,7 @@ impl App {
                     self.screen.set_char_size(char_w, char_h);
                 }
 
-                // Pass breakpoints to interpreter
-                let bp_lines: Vec<usize> = self.state.breakpoints
-                    .iter()
-                    .filter(|bp| bp.enabled)
-                    .map(|bp| bp.line)
-                    .collect();
-                self.interpreter.set_breakpoints(&bp_lines);
+                self.pass_breakpoints();
                 self.interpreter.set_step_mode(false);
 
                 // Store program for potential resume after NeedsInput
@@ -1029,6 +1231,16 @@ impl App {
                         self.state.run_state = RunState::Finished;
                         self.current_program = None;
                     }
+                    Ok(ExecutionResult::Exited) => {
+                        for line in self.interpreter.take_output() {
+                            self.widgets.output.add_output(&line);
+                        }
+                        self.state.set_status("Program ended");
+                        self.state.current_line = None;
+                        self.state.show_output = false;
+                        self.state.run_state = RunState::Editing;
+                        self.current_program = None;
+                    }
                     Ok(ExecutionResult::Breakpoint(line)) => {
                         // Show output so far
                         for output_line in self.interpreter.take_output() {
@@ -1037,7 +1249,7 @@ impl App {
                         self.state.current_line = Some(line);
                         self.state.run_state = RunState::Paused;
                         self.state.show_output = false; // Return to editor for breakpoint
-                        self.state.set_status(format!("Breakpoint hit at line {}", line + 1));
+                        self.show_breakpoint_status(line);
                         self.widgets.editor.go_to_line(line + 1);
                     }
                     Ok(ExecutionResult::Stepped(line)) => {
@@ -1056,20 +1268,7 @@ impl App {
                         // Still running, main loop will continue execution
                         // State is already Running
                     }
-                    Err(e) => {
-                        // Show runtime error in popup dialog
-                        self.state.show_output = false;
-                        self.state.current_line = None;
-                        self.state.run_state = RunState::Editing;
-                        self.current_program = None;
-
-                        self.dialogs.message.set_message("Runtime Error".to_string(), e.clone());
-                        let mut ctx = DialogContext {
-                            editor: &mut self.widgets.editor,
-                            state: &mut self.state,
-                        };
-                        self.dialogs.message.open(&mut ctx);
-                    }
+                    Err(e) => self.show_runtime_error(e),
                 }
             }
             Err(e) => {
@@ -1093,7 +1292,61 @@ impl App {
         self.run_program();
     }
 
-    fn step_program(&mut self) {
+    /// Pass the enabled breakpoints and their conditions to the interpreter
+    fn pass_breakpoints(&mut self) {
+        let mut lines = Vec::new();
+        let mut conditions = HashMap::new();
+        for bp in self.state.breakpoints.iter().filter(|bp| bp.enabled) {
+            lines.push(bp.line);
+            if let Some(condition) = &bp.condition {
+                // The dialog only accepts conditions that parse
+                if let Ok(expr) = Parser::new(Lexer::new(condition).tokenize()).parse_expression() {
+                    conditions.insert(bp.line, expr);
+                }
+            }
+        }
+        self.interpreter.set_breakpoints(&lines);
+        self.interpreter.set_breakpoint_conditions(conditions);
+    }
+
+    fn show_breakpoint_status(&mut self, line: usize) {
+        match self.interpreter.take_breakpoint_error() {
+            Some(e) => self.state.set_status(format!("Breakpoint condition error at line {}: {}", line + 1, e)),
+            None => self.state.set_status(format!("Breakpoint hit at line {}", line + 1)),
+        }
+    }
+
+    /// Stop the run and report a runtime error in a dialog, the output
+    /// window and the status bar, with the cursor on the line it happened at
+    fn show_runtime_error(&mut self, e: String) {
+        for line in self.interpreter.take_output() {
+            self.widgets.output.add_output(&line);
+        }
+        let message = match self.interpreter.error_line() {
+            Some(line) => {
+                self.widgets.editor.go_to_line(line + 1);
+                format!("{} in line {}", e, line + 1)
+            }
+            None => e,
+        };
+        self.widgets.output.add_output(&message);
+        self.state.set_status(message.clone());
+        self.state.show_output = false;
+        self.state.current_line = None;
+        self.state.run_state = RunState::Editing;
+        self.current_program = None;
+
+        self.dialogs.message.set_message("Runtime Error".to_string(), message);
+        let mut ctx = DialogContext {
+            editor: &mut self.widgets.editor,
+            state: &mut self.state,
+        };
+        self.dialogs.message.open(&mut ctx);
+    }
+
+    /// Run to the next statement, going into SUB and FUNCTION calls or,
+    /// with `over`, running them to completion (Procedure Step)
+    fn step_program(&mut self, over: bool) {
         let source = self.widgets.editor.content();
         let mut lexer = Lexer::new(&source);
         let tokens = lexer.tokenize();
@@ -1106,14 +1359,9 @@ impl App {
                     self.interpreter.reset();
                 }
 
-                // Pass breakpoints to interpreter
-                let bp_lines: Vec<usize> = self.state.breakpoints
-                    .iter()
-                    .filter(|bp| bp.enabled)
-                    .map(|bp| bp.line)
-                    .collect();
-                self.interpreter.set_breakpoints(&bp_lines);
+                self.pass_breakpoints();
                 self.interpreter.set_step_mode(true);
+                self.interpreter.set_step_over(over);
 
                 // Store program for potential resume
                 self.current_program = Some(program.clone());
@@ -1144,6 +1392,16 @@ impl App {
                         self.state.run_state = RunState::Editing;
                         self.current_program = None;
                     }
+                    Ok(ExecutionResult::Exited) => {
+                        for line in self.interpreter.take_output() {
+                            self.widgets.output.add_output(&line);
+                        }
+                        self.state.set_status("Program ended");
+                        self.state.current_line = None;
+                        self.state.show_output = false;
+                        self.state.run_state = RunState::Editing;
+                        self.current_program = None;
+                    }
                     Ok(ExecutionResult::Breakpoint(line)) | Ok(ExecutionResult::Stepped(line)) => {
                         for output_line in self.interpreter.take_output() {
                             self.widgets.output.add_output(&output_line);
@@ -1162,19 +1420,7 @@ impl App {
                     Ok(ExecutionResult::Running) => {
                         self.state.run_state = RunState::Running;
                     }
-                    Err(e) => {
-                        self.state.show_output = false;
-                        self.state.current_line = None;
-                        self.state.run_state = RunState::Editing;
-                        self.current_program = None;
-
-                        self.dialogs.message.set_message("Runtime Error".to_string(), e.clone());
-                        let mut ctx = DialogContext {
-                            editor: &mut self.widgets.editor,
-                            state: &mut self.state,
-                        };
-                        self.dialogs.message.open(&mut ctx);
-                    }
+                    Err(e) => self.show_runtime_error(e),
                 }
             }
             Err(e) => {
@@ -1218,6 +1464,16 @@ impl App {
                 self.state.run_state = RunState::Finished;
                 self.current_program = None;
             }
+            Ok(ExecutionResult::Exited) => {
+                for line in self.interpreter.take_output() {
+                    self.widgets.output.add_output(&line);
+                }
+                self.state.set_status("Program ended");
+                self.state.current_line = None;
+                self.state.show_output = false;
+                self.state.run_state = RunState::Editing;
+                self.current_program = None;
+            }
             Ok(ExecutionResult::Breakpoint(line)) => {
                 for output_line in self.interpreter.take_output() {
                     self.widgets.output.add_output(&output_line);
@@ -1225,7 +1481,7 @@ impl App {
                 self.state.current_line = Some(line);
                 self.state.run_state = RunState::Paused;
                 self.state.show_output = false;
-                self.state.set_status(format!("Breakpoint hit at line {}", line + 1));
+                self.show_breakpoint_status(line);
                 self.widgets.editor.go_to_line(line + 1);
             }
             Ok(ExecutionResult::Stepped(line)) => {
@@ -1241,59 +1497,35 @@ impl App {
             Ok(ExecutionResult::Running) => {
                 self.state.run_state = RunState::Running;
             }
-            Err(e) => {
-                self.state.show_output = false;
-                self.state.current_line = None;
-                self.state.run_state = RunState::Editing;
-                self.current_program = None;
-
-                self.dialogs.message.set_message("Runtime Error".to_string(), e.clone());
-                let mut ctx = DialogContext {
-                    editor: &mut self.widgets.editor,
-                    state: &mut self.state,
-                };
-                self.dialogs.message.open(&mut ctx);
-            }
+            Err(e) => self.show_runtime_error(e),
         }
     }
 
     fn execute_immediate(&mut self, cmd: &str) {
         // Try to parse and execute as expression or statement
         let source = cmd.trim();
-        let mut lexer = Lexer::new(source);
-        let tokens = lexer.tokenize();
-        let mut parser = Parser::new(tokens);
 
-        // Try as expression first
-        if let Ok(expr) = parser.parse_expression() {
-            match self.interpreter.eval_expr(&expr) {
-                Ok(value) => {
-                    self.widgets.output.add_output(&value.to_string());
-                }
-                Err(e) => {
-                    self.widgets.output.add_output(&format!("Error: {}", e));
-                }
-            }
-        } else {
-            // Try as statement
-            let mut lexer2 = Lexer::new(source);
-            let tokens2 = lexer2.tokenize();
-            let mut parser2 = Parser::new(tokens2);
-
-            match parser2.parse() {
-                Ok(stmts) => {
-                    if let Err(e) = self.interpreter.execute(&stmts) {
-                        self.widgets.output.add_output(&format!("Error: {}", e));
-                    } else {
-                        for line in self.interpreter.take_output() {
-                            self.widgets.output.add_output(&line);
-                        }
-                    }
+        // Statements first, so `x = 1` assigns rather than compares; they run
+        // against the live variables, which a paused program then sees. A
+        // lone expression (which may start with a number, read as a line
+        // label) has its value shown instead.
+        match Parser::new(Lexer::new(source).tokenize()).parse() {
+            Ok(stmts) if !matches!(&stmts[..], [basic::parser::Stmt::Expression(_)] | [basic::parser::Stmt::Label(_), ..]) => {
+                let result = self.interpreter.execute_immediate(&stmts);
+                for line in self.interpreter.take_output() {
+                    self.widgets.output.add_output(&line);
                 }
-                Err(e) => {
+                if let Err(e) = result {
                     self.widgets.output.add_output(&format!("Error: {}", e));
                 }
             }
+            parsed => match Parser::new(Lexer::new(source).tokenize()).parse_expression() {
+                Ok(expr) => match self.interpreter.eval_expr(&expr) {
+                    Ok(value) => self.widgets.output.add_output(&value.to_string()),
+                    Err(e) => self.widgets.output.add_output(&format!("Error: {}", e)),
+                },
+                Err(e) => self.widgets.output.add_output(&format!("Error: {}", parsed.err().unwrap_or(e))),
+            },
         }
     }
 
@@ -1334,102 +1566,40 @@ impl App {
         }
     }
 
-    /// Check syntax of current buffer
+    /// Check syntax of current buffer by parsing it, flagging the line of the
+    /// first error
     fn check_syntax(&mut self) {
-        self.state.syntax_errors.clear();
-
-        // Simple syntax checking - check for unclosed blocks
-        let mut open_fors = 0;
-        let mut open_ifs = 0;
-        let mut open_whiles = 0;
-        let mut open_subs = 0;
-        let mut open_functions = 0;
-
-        for (line_idx, line) in self.widgets.editor.buffer.lines.iter().enumerate() {
-            let upper = line.to_uppercase();
-            let trimmed = upper.trim();
-
-            // Track block starts
-            if trimmed.starts_with("FOR ") && !trimmed.contains(" NEXT") {
-                open_fors += 1;
-            }
-            if (trimmed.starts_with("IF ") || trimmed == "IF") && !trimmed.contains(" THEN ") && !trimmed.ends_with(" THEN") {
-                // Multi-line IF
-            } else if trimmed.starts_with("IF ") && (trimmed.contains(" THEN") && !trimmed.chars().filter(|&c| c != ' ').skip_while(|&c| c != 'N').take(4).collect::<String>().contains("THEN")) {
-                // This is getting complex - simplified version
-            }
-            if trimmed == "IF" || (trimmed.starts_with("IF ") && trimmed.ends_with("THEN")) {
-                open_ifs += 1;
-            }
-            if trimmed.starts_with("WHILE ") || trimmed == "WHILE" {
-                open_whiles += 1;
-            }
-            if trimmed.starts_with("SUB ") {
-                open_subs += 1;
-            }
-            if trimmed.starts_with("FUNCTION ") {
-                open_functions += 1;
-            }
+        let source = self.widgets.editor.content();
+        self.state.syntax_errors = basic::parser::syntax_error(&source).into_iter().collect();
+    }
 
-            // Track block ends
-            if trimmed == "NEXT" || trimmed.starts_with("NEXT ") {
-                if open_fors > 0 {
-                    open_fors -= 1;
-                } else {
-                    self.state.syntax_errors.push((line_idx, "NEXT without FOR".to_string()));
-                }
-            }
-            if trimmed == "END IF" || trimmed == "ENDIF" {
-                if open_ifs > 0 {
-                    open_ifs -= 1;
-                } else {
-                    self.state.syntax_errors.push((line_idx, "END IF without IF".to_string()));
-                }
-            }
-            if trimmed == "WEND" {
-                if open_whiles > 0 {
-                    open_whiles -= 1;
-                } else {
-                    self.state.syntax_errors.push((line_idx, "WEND without WHILE".to_string()));
-                }
-            }
-            if trimmed == "END SUB" {
-                if open_subs > 0 {
-                    open_subs -= 1;
-                } else {
-                    self.state.syntax_errors.push((line_idx, "END SUB without SUB".to_string()));
-                }
+    /// Re-indent and respace the program, or report the syntax error that
+    /// stops it from being formatted
+    fn format_program(&mut self) {
+        match self.widgets.editor.format_program(self.state.tab_stops) {
+            Ok(true) => {
+                self.state.set_modified(true);
+                self.state.set_status("Program formatted");
             }
-            if trimmed == "END FUNCTION" {
-                if open_functions > 0 {
-                    open_functions -= 1;
-                } else {
-                    self.state.syntax_errors.push((line_idx, "END FUNCTION without FUNCTION".to_string()));
-                }
+            Ok(false) => self.state.set_status("Program already formatted"),
+            Err((line, message)) => {
+                self.widgets.editor.go_to_line(line + 1);
+                self.state.syntax_errors = vec![(line, message.clone())];
+                let message = format!("{} in line {}", message, line + 1);
+                self.state.set_status(message.clone());
+                self.dialogs.message.set_message("Syntax Error".to_string(), message);
+                let mut ctx = DialogContext {
+                    editor: &mut self.widgets.editor,
+                    state: &mut self.state,
+                };
+                self.dialogs.message.open(&mut ctx);
             }
         }
-
-        // Check for unclosed blocks at end
-        let last_line = self.widgets.editor.buffer.line_count().saturating_sub(1);
-        if open_fors > 0 {
-            self.state.syntax_errors.push((last_line, format!("{} unclosed FOR loop(s)", open_fors)));
-        }
-        if open_ifs > 0 {
-            self.state.syntax_errors.push((last_line, format!("{} unclosed IF statement(s)", open_ifs)));
-        }
-        if open_whiles > 0 {
-            self.state.syntax_errors.push((last_line, format!("{} unclosed WHILE loop(s)", open_whiles)));
-        }
-        if open_subs > 0 {
-            self.state.syntax_errors.push((last_line, format!("{} unclosed SUB(s)", open_subs)));
-        }
-        if open_functions > 0 {
-            self.state.syntax_errors.push((last_line, format!("{} unclosed FUNCTION(s)", open_functions)));
-        }
     }
 
-    /// Repeat last search (F3)
-    fn repeat_find(&mut self) {
+    /// Repeat last search forward (F3) or backward (Shift+F3), continuing from
+    /// the current match
+    fn repeat_find(&mut self, backward: bool) {
         let search = self.state.last_search.clone();
         if search.is_empty() {
             // Open Find dialog if no previous search
@@ -1441,73 +1611,72 @@ impl App {
             return;
         }
 
-        if let Some((line, col)) = self.widgets.editor.find_text(&search, self.state.search_case_sensitive, self.state.search_whole_word) {
-            self.widgets.editor.go_to_and_select(line, col, search.len());
-            self.state.set_status(format!("Found at line {}", line + 1));
-        } else {
-            self.state.set_status("Match not found");
-        }
+        let status = self.widgets.editor.find_and_select(
+            &search,
+            self.state.search_case_sensitive,
+            self.state.search_whole_word,
+            self.state.search_regex,
+            backward,
+        );
+        self.state.set_status(status);
     }
 
-    /// Show list of SUBs and FUNCTIONs (F2)
+    /// Open the SUBs dialog listing the main module and its procedures
     fn show_subs_list(&mut self) {
-        // Parse the source to find SUB and FUNCTION definitions
+        let module_name = self.state.file_path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_uppercase())
+            .unwrap_or_else(|| "Untitled".to_string());
         let source = self.widgets.editor.content();
-        let mut subs: Vec<String> = Vec::new();
-
-        for (line_num, line) in source.lines().enumerate() {
-            let trimmed = line.trim().to_uppercase();
-            if trimmed.starts_with("SUB ") || trimmed.starts_with("FUNCTION ") {
-                // Extract the name
-                let parts: Vec<&str> = line.trim().split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let name = parts[1].split('(').next().unwrap_or(parts[1]);
-                    subs.push(format!("{}  (line {})", name, line_num + 1));
-                }
-            }
+        let call_stack = match self.state.run_state {
+            RunState::Paused | RunState::Stepping => self.interpreter.call_stack(),
+            _ => Vec::new(),
+        };
+        let (width, height) = self.screen.size();
+        self.modal = Some(Box::new(SubsDialog::new(&module_name, &source, &call_stack, width, height)));
+    }
+
+    /// Jump to the SUB or FUNCTION named under the cursor (Shift+F2)
+    fn go_to_definition(&mut self) {
+        let editor = &self.widgets.editor;
+        let line = editor.buffer.line(editor.cursor_line).unwrap_or("");
+        let chars: Vec<char> = line.chars().collect();
+        let is_word_char = |c: &char| c.is_alphanumeric() || *c == '_';
+        // Also accept the cursor just past the name, as in `Draw(` or at line end
+        let col = editor.cursor_col;
+        let on_word = chars.get(col).filter(|c| is_word_char(c)).map(|_| col)
+            .or_else(|| col.checked_sub(1).filter(|&c| chars.get(c).is_some_and(is_word_char)));
+        let name = on_word
+            .and_then(|col| editor.get_word_bounds(editor.cursor_line, col))
+            .map(|(start, end)| chars[start..end].iter().collect::<String>());
+
+        match name.and_then(|name| find_definition(&editor.content(), &name)) {
+            Some(line) => {
+                self.widgets.editor.go_to_line(line);
+                self.state.set_status(format!("Definition at line {}", line));
+            }
+            None => self.state.set_status("Definition not found"),
         }
+    }
 
-        if subs.is_empty() {
-            self.state.set_status("No SUBs or FUNCTIONs found");
-        } else {
-            // For now, just show in status. A proper implementation would show a dialog.
-            self.state.set_status(format!("Found: {}", subs.join(", ")));
+    /// Reload help files from a new Help Path and report the result
+    fn apply_help_path(&mut self, path: String) {
+        match self.dialogs.help.load_help_path(&path) {
+            Ok(count) => {
+                self.state.set_status(format!("Loaded {} help topics", count));
+                self.state.help_path = path;
+            }
+            Err(e) => {
+                self.state.set_status(format!("Help path not changed: {}", e));
+            }
         }
     }
 
     /// Show help for word under cursor
     fn show_help_for_word_under_cursor(&mut self) {
-        // Get the word at cursor position
-        let topic = if let Some(line) = self.widgets.editor.buffer.line(self.widgets.editor.cursor_line) {
-            let col = self.widgets.editor.cursor_col;
-            let chars: Vec<char> = line.chars().collect();
-            if col < chars.len() {
-                let mut start = col;
-                let mut end = col;
-
-                // Find start of word
-                while start > 0 && (chars[start - 1].is_alphanumeric() || chars[start - 1] == '$' || chars[start - 1] == '%') {
-                    start -= 1;
-                }
-
-                // Find end of word
-                while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '$' || chars[end] == '%') {
-                    end += 1;
-                }
-
-                if start < end {
-                    chars[start..end].iter().collect::<String>().to_uppercase()
-                } else {
-                    "General".to_string()
-                }
-            } else {
-                "General".to_string()
-            }
-        } else {
-            "General".to_string()
-        };
-
-        self.dialogs.help.set_topic(topic);
+        let line = self.widgets.editor.buffer.line(self.widgets.editor.cursor_line).unwrap_or("");
+        self.dialogs.help.set_topic_at(line, self.widgets.editor.cursor_col);
         let mut ctx = DialogContext {
             editor: &mut self.widgets.editor,
             state: &mut self.state,
@@ -1531,3 +1700,30 @@ impl App {
         let _ = self.terminal.flush();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_keeps_unsaved_program() {
+        for key in [InputEvent::Ctrl('o'), InputEvent::Ctrl('q')] {
+            let mut app = App::with_state(Terminal::headless(), AppState::new());
+            app.widgets.editor.load("PRINT \"unsaved\"");
+            app.state.set_modified(true);
+
+            app.handle_input(key.clone());
+            assert!(app.dialogs.confirm.is_open());
+            assert!(app.pending_unsaved.is_some());
+
+            // Escape cancels: nothing is opened, quit or discarded
+            app.handle_input(InputEvent::Escape);
+            assert!(!app.dialogs.confirm.is_open());
+            assert!(!app.dialogs.file_open.is_open());
+            assert!(!app.state.should_quit);
+            assert_eq!(app.pending_unsaved, None);
+            assert_eq!(app.widgets.editor.content(), "PRINT \"unsaved\"");
+            assert!(app.state.modified);
+        }
+    }
+}
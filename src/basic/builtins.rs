@@ -1,300 +1,498 @@
-//! Built-in BASIC functions
+//! Built-in BASIC string, math, conversion and clock functions
+//!
+//! These operate on already-evaluated arguments so both the program
+//! evaluator and the immediate window share one implementation. Positions
+//! are 1-based as in QBasic; out-of-range positions and counts yield empty
+//! strings (or 0 for INSTR) rather than errors.
 
-use crate::basic::interpreter::{Interpreter, Value};
-use crate::basic::parser::Expr;
+use crate::basic::interpreter::{cp437_to_unicode, Value};
 
-/// Call a built-in function
-pub fn call_builtin(interp: &mut Interpreter, name: &str, args: &[Expr]) -> Result<Value, String> {
-    let name_upper = name.to_uppercase();
-    let name_base = name_upper.trim_end_matches('$');
+/// Call a string or math built-in by name (without the `$` suffix).
+/// Returns None if `name` is not one of them.
+pub fn call_builtin(name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+    call_string_function(name, args)
+        .or_else(|| call_math_function(name, args))
+        .or_else(|| call_conversion_function(name, args))
+        .or_else(|| call_array_function(name, args))
+}
 
-    match name_base {
-        // Math functions
-        "ABS" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Float(v.abs()))
-        }
-        "INT" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Integer(v.floor() as i64))
-        }
-        "FIX" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Integer(v.trunc() as i64))
-        }
-        "SGN" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            let sign = if v > 0.0 { 1 } else if v < 0.0 { -1 } else { 0 };
-            Ok(Value::Integer(sign))
-        }
-        "SQR" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            if v < 0.0 {
-                Err("SQR of negative number".to_string())
+/// Call a string built-in by name (without the `$` suffix)
+fn call_string_function(name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+    let result = match name {
+        "LEN" => Ok(Value::Integer(str_arg(args, 0).chars().count() as i64)),
+        "LEFT" => Ok(Value::String(left(&str_arg(args, 0), int_arg(args, 1)))),
+        "RIGHT" => Ok(Value::String(right(&str_arg(args, 0), int_arg(args, 1)))),
+        "MID" => Ok(Value::String(mid(&str_arg(args, 0), int_arg(args, 1), args.get(2).map(Value::to_int)))),
+        "INSTR" => {
+            let pos = if args.len() >= 3 {
+                instr(int_arg(args, 0), &str_arg(args, 1), &str_arg(args, 2))
             } else {
-                Ok(Value::Float(v.sqrt()))
+                instr(1, &str_arg(args, 0), &str_arg(args, 1))
+            };
+            Ok(Value::Integer(pos))
+        }
+        "UCASE" => Ok(Value::String(str_arg(args, 0).to_uppercase())),
+        "LCASE" => Ok(Value::String(str_arg(args, 0).to_lowercase())),
+        "LTRIM" => Ok(Value::String(str_arg(args, 0).trim_start_matches(' ').to_string())),
+        "RTRIM" => Ok(Value::String(str_arg(args, 0).trim_end_matches(' ').to_string())),
+        "SPACE" => Ok(Value::String(" ".repeat(int_arg(args, 0).max(0) as usize))),
+        "STRING" => string_of(int_arg(args, 0), args.get(1)).map(Value::String),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Call a math built-in by name. RND needs interpreter state and is handled by `Rng`.
+fn call_math_function(name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+    let x = args.first().map(Value::to_float).unwrap_or(0.0);
+    let result = match name {
+        "SIN" => Ok(Value::Float(x.sin())),
+        "COS" => Ok(Value::Float(x.cos())),
+        "TAN" => Ok(Value::Float(x.tan())),
+        "ATN" => Ok(Value::Float(x.atan())),
+        "EXP" => Ok(Value::Float(x.exp())),
+        "SQR" if x < 0.0 => Err("Illegal function call".to_string()),
+        "SQR" => Ok(Value::Float(x.sqrt())),
+        "LOG" if x <= 0.0 => Err("Illegal function call".to_string()),
+        "LOG" => Ok(Value::Float(x.ln())),
+        "ABS" => Ok(match args.first() {
+            Some(Value::Integer(n)) => Value::Integer(n.abs()),
+            _ => Value::Float(x.abs()),
+        }),
+        "SGN" => Ok(Value::Integer(if x > 0.0 { 1 } else if x < 0.0 { -1 } else { 0 })),
+        "INT" => Ok(Value::Integer(x.floor() as i64)),
+        "FIX" => Ok(Value::Integer(x.trunc() as i64)),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// Call a conversion built-in (VAL, STR$, CHR$, ASC, CINT, CLNG, CSNG,
+/// CDBL, HEX$, OCT$) by name
+fn call_conversion_function(name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+    let result = match name {
+        "VAL" => Ok(val(&str_arg(args, 0))),
+        "STR" => match args.first() {
+            Some(Value::String(_)) => Err("Type mismatch".to_string()),
+            Some(n) if n.to_float() < 0.0 => Ok(Value::String(n.to_string())),
+            Some(n) => Ok(Value::String(format!(" {}", n.to_string()))),
+            None => Ok(Value::String(" 0".to_string())),
+        },
+        "CHR" => match u8::try_from(int_arg(args, 0)) {
+            Ok(code) => Ok(Value::String(cp437_to_unicode(code).to_string())),
+            Err(_) => Err("Illegal function call".to_string()),
+        },
+        "ASC" => match str_arg(args, 0).chars().next() {
+            Some(c) => Ok(Value::Integer(unicode_to_cp437(c))),
+            None => Err("Illegal function call".to_string()),
+        },
+        "CINT" => round_in_range(args, i16::MIN as f64, i16::MAX as f64),
+        "CLNG" => round_in_range(args, i32::MIN as f64, i32::MAX as f64),
+        "CSNG" | "CDBL" => match args.first() {
+            Some(Value::String(_)) => Err("Type mismatch".to_string()),
+            Some(n) if name == "CSNG" && n.to_float().abs() > f32::MAX as f64 => Err("Overflow".to_string()),
+            n => Ok(Value::Float(n.map_or(0.0, Value::to_float))),
+        },
+        "HEX" => Ok(Value::String(format!("{:X}", unsigned_arg(args)))),
+        "OCT" => Ok(Value::String(format!("{:o}", unsigned_arg(args)))),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// LBOUND(array[, dimension]) and UBOUND(array[, dimension])
+fn call_array_function(name: &str, args: &[Value]) -> Option<Result<Value, String>> {
+    let upper = match name {
+        "LBOUND" => false,
+        "UBOUND" => true,
+        _ => return None,
+    };
+    let result = match args {
+        [Value::Array(array)] | [Value::Array(array), _] => {
+            let dimension = args.get(1).map_or(1, Value::to_int);
+            match usize::try_from(dimension - 1).ok().and_then(|i| array.bounds.get(i)) {
+                Some(&(lower, upper_bound)) => Ok(Value::Integer(if upper { upper_bound } else { lower })),
+                None => Err("Subscript out of range".to_string()),
             }
         }
-        "SIN" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Float(v.sin()))
-        }
-        "COS" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Float(v.cos()))
-        }
-        "TAN" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Float(v.tan()))
-        }
-        "ATN" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Float(v.atan()))
+        [_] | [_, _] => Err("Array not defined".to_string()),
+        _ => Err("Argument-count mismatch".to_string()),
+    };
+    Some(result)
+}
+
+/// VAL(s$): the number at the start of `s`, ignoring spaces, or 0.
+/// Accepts a sign, decimal point and E/D exponent, or &H/&O radix prefixes.
+fn val(s: &str) -> Value {
+    let chars: Vec<char> = s.chars().filter(|c| !matches!(c, ' ' | '\t' | '\n')).collect();
+    let upper: String = chars.iter().collect::<String>().to_ascii_uppercase();
+
+    let radix = if upper.starts_with("&H") {
+        Some(16)
+    } else if upper.starts_with("&O") {
+        Some(8)
+    } else if upper.starts_with('&') {
+        Some(8).filter(|_| upper[1..].starts_with(|c: char| c.is_digit(8)))
+    } else {
+        None
+    };
+    if let Some(radix) = radix {
+        let digits: String = upper
+            .trim_start_matches('&')
+            .trim_start_matches(['H', 'O'])
+            .chars()
+            .take_while(|c| c.is_digit(radix))
+            .collect();
+        return Value::Integer(i64::from_str_radix(&digits, radix).unwrap_or(0));
+    }
+
+    // Longest prefix that forms a number: [sign] digits [. digits] [E|D [sign] digits]
+    let mut end = 0;
+    let mut i = 0;
+    if matches!(chars.first(), Some('+' | '-')) {
+        i += 1;
+    }
+    let mantissa_start = i;
+    while chars.get(i).is_some_and(|c| c.is_ascii_digit()) {
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') {
+        i += 1;
+        while chars.get(i).is_some_and(|c| c.is_ascii_digit()) {
+            i += 1;
         }
-        "LOG" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            if v <= 0.0 {
-                Err("LOG of non-positive number".to_string())
-            } else {
-                Ok(Value::Float(v.ln()))
+    }
+    if chars[mantissa_start..i].iter().any(|c| c.is_ascii_digit()) {
+        end = i;
+        if matches!(chars.get(i), Some('E' | 'e' | 'D' | 'd')) {
+            let mut j = i + 1;
+            if matches!(chars.get(j), Some('+' | '-')) {
+                j += 1;
+            }
+            let exponent_start = j;
+            while chars.get(j).is_some_and(|c| c.is_ascii_digit()) {
+                j += 1;
+            }
+            if j > exponent_start {
+                end = j;
             }
         }
-        "EXP" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Float(v.exp()))
-        }
-        "RND" => {
-            let r = interp.rnd();
-            Ok(Value::Float(r))
-        }
+    }
 
-        // String functions
-        "LEN" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            Ok(Value::Integer(s.len() as i64))
-        }
-        "LEFT" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            let n = interp.eval_expr(&args[1])?.to_int() as usize;
-            let result: String = s.chars().take(n).collect();
-            Ok(Value::String(result))
-        }
-        "RIGHT" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            let n = interp.eval_expr(&args[1])?.to_int() as usize;
-            let len = s.chars().count();
-            let result: String = s.chars().skip(len.saturating_sub(n)).collect();
-            Ok(Value::String(result))
-        }
-        "MID" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            let start = (interp.eval_expr(&args[1])?.to_int() as usize).saturating_sub(1);
-            let len = if args.len() > 2 {
-                interp.eval_expr(&args[2])?.to_int() as usize
-            } else {
-                s.len()
-            };
-            let result: String = s.chars().skip(start).take(len).collect();
-            Ok(Value::String(result))
-        }
-        "STR" => {
-            let v = interp.eval_expr(&args[0])?;
-            let s = match v {
-                Value::Integer(n) if n >= 0 => format!(" {}", n),
-                Value::Float(n) if n >= 0.0 => format!(" {}", n),
-                _ => v.to_string(),
-            };
-            Ok(Value::String(s))
-        }
-        "VAL" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            let s = s.trim();
-            if s.contains('.') || s.contains('E') || s.contains('e') {
-                Ok(Value::Float(s.parse().unwrap_or(0.0)))
-            } else {
-                Ok(Value::Integer(s.parse().unwrap_or(0)))
+    let text: String = chars[..end].iter().map(|c| if matches!(c, 'D' | 'd') { 'E' } else { *c }).collect();
+    Value::Float(text.parse().unwrap_or(0.0))
+}
+
+/// CINT/CLNG: round half to even, with "Overflow" outside `min..=max`
+fn round_in_range(args: &[Value], min: f64, max: f64) -> Result<Value, String> {
+    let n = match args.first() {
+        Some(Value::String(_)) => return Err("Type mismatch".to_string()),
+        n => n.map_or(0.0, Value::to_float).round_ties_even(),
+    };
+    if (min..=max).contains(&n) {
+        Ok(Value::Integer(n as i64))
+    } else {
+        Err("Overflow".to_string())
+    }
+}
+
+/// CP437 code of `c` (the reverse of CHR$), or its Unicode value if CP437 lacks it
+fn unicode_to_cp437(c: char) -> i64 {
+    if c.is_ascii() {
+        return c as i64;
+    }
+    (128..=255u8)
+        .find(|&code| cp437_to_unicode(code) == c)
+        .or_else(|| (1..32u8).find(|&code| cp437_to_unicode(code) == c))
+        .map_or(c as i64, i64::from)
+}
+
+/// Rounded integer argument of HEX$/OCT$, with negative numbers in 16-bit
+/// (or 32-bit, for long values) two's complement
+fn unsigned_arg(args: &[Value]) -> u64 {
+    let n = args.first().map_or(0, |v| v.to_float().round() as i64);
+    if n >= 0 {
+        n as u64
+    } else if n >= i16::MIN as i64 {
+        n as u16 as u64
+    } else {
+        n as u32 as u64
+    }
+}
+
+/// QBasic-compatible random number generator: a 24-bit linear congruential
+/// generator, so a given RANDOMIZE seed always yields the same sequence
+#[derive(Clone, Debug)]
+pub struct Rng {
+    seed: u32,
+    last: f64,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self { seed: 0x50000, last: 0.0 }
+    }
+}
+
+impl Rng {
+    /// RANDOMIZE n: mix the seed value into the generator state
+    pub fn randomize(&mut self, n: f64) {
+        let bits = n.to_bits();
+        let mixed = ((bits >> 48) ^ (bits >> 32)) as u32 & 0xFFFF;
+        self.seed = (mixed << 8) | (self.seed & 0xFF);
+    }
+
+    /// RND[(n)]: n < 0 reseeds from n, n = 0 repeats the last value,
+    /// otherwise returns the next value in [0, 1)
+    pub fn rnd(&mut self, n: Option<f64>) -> f64 {
+        match n {
+            Some(0.0) => return self.last,
+            Some(n) if n < 0.0 => {
+                let bits = n.to_bits();
+                self.seed = (bits as u32 ^ (bits >> 32) as u32) & 0xFF_FFFF;
             }
+            _ => {}
         }
-        "CHR" => {
-            let n = interp.eval_expr(&args[0])?.to_int() as u8;
-            let ch = cp437_to_unicode(n);
-            Ok(Value::String(ch.to_string()))
-        }
-        "ASC" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            let c = s.chars().next().unwrap_or('\0');
-            Ok(Value::Integer(c as i64))
-        }
-        "INSTR" => {
-            let (start, haystack, needle) = if args.len() == 2 {
-                let h = interp.eval_expr(&args[0])?.to_string();
-                let n = interp.eval_expr(&args[1])?.to_string();
-                (0, h, n)
-            } else {
-                let s = interp.eval_expr(&args[0])?.to_int() as usize;
-                let h = interp.eval_expr(&args[1])?.to_string();
-                let n = interp.eval_expr(&args[2])?.to_string();
-                (s.saturating_sub(1), h, n)
-            };
-            let result = haystack[start..].find(&needle)
-                .map(|i| i + start + 1)
-                .unwrap_or(0);
-            Ok(Value::Integer(result as i64))
-        }
-        "UCASE" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            Ok(Value::String(s.to_uppercase()))
-        }
-        "LCASE" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            Ok(Value::String(s.to_lowercase()))
-        }
-        "LTRIM" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            Ok(Value::String(s.trim_start().to_string()))
-        }
-        "RTRIM" => {
-            let s = interp.eval_expr(&args[0])?.to_string();
-            Ok(Value::String(s.trim_end().to_string()))
-        }
-        "SPACE" => {
-            let n = interp.eval_expr(&args[0])?.to_int() as usize;
-            Ok(Value::String(" ".repeat(n)))
-        }
-        "STRING" => {
-            let n = interp.eval_expr(&args[0])?.to_int() as usize;
-            let c = if args.len() > 1 {
-                let v = interp.eval_expr(&args[1])?;
-                match v {
-                    Value::String(s) => s.chars().next().unwrap_or(' '),
-                    Value::Integer(n) => (n as u8) as char,
-                    Value::Float(n) => (n as u8) as char,
-                    _ => ' ',
-                }
-            } else {
-                ' '
-            };
-            Ok(Value::String(c.to_string().repeat(n)))
-        }
+        self.seed = (self.seed.wrapping_mul(0x43FD43FD).wrapping_add(0xC39EC3)) & 0xFF_FFFF;
+        self.last = self.seed as f64 / 16_777_216.0;
+        self.last
+    }
+}
 
-        // Type conversion
-        "CINT" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Integer(v.round() as i64))
-        }
-        "CLNG" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Integer(v.round() as i64))
-        }
-        "CSNG" | "CDBL" => {
-            let v = interp.eval_expr(&args[0])?.to_float();
-            Ok(Value::Float(v))
-        }
+/// A local date and time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LocalTime {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    /// Seconds since midnight, with a fractional part
+    pub seconds: f64,
+}
 
-        // System
-        "TIMER" => {
-            let secs = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs_f64() % 86400.0;
-            Ok(Value::Float(secs))
-        }
-        "DATE" => {
-            let now = chrono_lite_date();
-            Ok(Value::String(now))
+/// Source of the time of day for TIMER, TIME$ and DATE$
+pub trait Clock {
+    fn now(&self) -> LocalTime;
+}
+
+/// The system clock in the local time zone
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> LocalTime {
+        let since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let secs = since_epoch.as_secs() as libc::time_t;
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe { libc::localtime_r(&secs, &mut tm) };
+        LocalTime {
+            year: tm.tm_year + 1900,
+            month: tm.tm_mon as u32 + 1,
+            day: tm.tm_mday as u32,
+            seconds: (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as f64
+                + since_epoch.subsec_nanos() as f64 / 1e9,
         }
+    }
+}
+
+/// Call a clock built-in (TIMER, TIME$ or DATE$, without the `$` suffix).
+/// Returns None if `name` is not one of them.
+pub fn call_clock_function(name: &str, clock: &dyn Clock) -> Option<Value> {
+    let result = match name {
+        "TIMER" => Value::Float(clock.now().seconds),
         "TIME" => {
-            let now = chrono_lite_time();
-            Ok(Value::String(now))
+            let secs = clock.now().seconds as u32;
+            Value::String(format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60))
         }
-        "INKEY" => {
-            // Would need keyboard input
-            Ok(Value::String(String::new()))
+        "DATE" => {
+            let now = clock.now();
+            Value::String(format!("{:02}-{:02}-{:04}", now.month, now.day, now.year))
         }
+        _ => return None,
+    };
+    Some(result)
+}
 
-        // Screen
-        "POS" => {
-            let col = interp.graphics.cursor_col;
-            Ok(Value::Integer(col as i64))
-        }
-        "CSRLIN" => {
-            let row = interp.graphics.cursor_row;
-            Ok(Value::Integer(row as i64))
-        }
-        "POINT" => {
-            let x = interp.eval_expr(&args[0])?.to_int() as i32;
-            let y = interp.eval_expr(&args[1])?.to_int() as i32;
-            let color = interp.graphics.point(x, y);
-            Ok(Value::Integer(color as i64))
-        }
+fn str_arg(args: &[Value], index: usize) -> String {
+    args.get(index).map(Value::to_string).unwrap_or_default()
+}
+
+fn int_arg(args: &[Value], index: usize) -> i64 {
+    args.get(index).map(Value::to_int).unwrap_or(0)
+}
 
-        _ => Err(format!("Unknown function: {}", name)),
+/// LEFT$(s, n)
+fn left(s: &str, n: i64) -> String {
+    s.chars().take(n.max(0) as usize).collect()
+}
+
+/// RIGHT$(s, n)
+fn right(s: &str, n: i64) -> String {
+    let len = s.chars().count();
+    s.chars().skip(len.saturating_sub(n.max(0) as usize)).collect()
+}
+
+/// MID$(s, start[, len])
+fn mid(s: &str, start: i64, len: Option<i64>) -> String {
+    if start < 1 {
+        return String::new();
+    }
+    let chars = s.chars().skip(start as usize - 1);
+    match len {
+        Some(n) => chars.take(n.max(0) as usize).collect(),
+        None => chars.collect(),
     }
 }
 
-fn chrono_lite_date() -> String {
-    // Simple date without chrono dependency
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    let days = secs / 86400;
-    // Approximate - not fully accurate but works for demo
-    let year = 1970 + (days / 365);
-    let day_of_year = days % 365;
-    let month = day_of_year / 30 + 1;
-    let day = day_of_year % 30 + 1;
-    format!("{:02}-{:02}-{:04}", month, day, year)
+/// INSTR([start,] haystack, needle): 1-based position of needle, or 0
+fn instr(start: i64, haystack: &str, needle: &str) -> i64 {
+    let hay: Vec<char> = haystack.chars().collect();
+    let needle: Vec<char> = needle.chars().collect();
+    if start < 1 || start as usize > hay.len() {
+        return 0;
+    }
+    let from = start as usize - 1;
+    if needle.is_empty() {
+        return start;
+    }
+    hay[from..]
+        .windows(needle.len())
+        .position(|w| w == needle.as_slice())
+        .map(|p| (from + p + 1) as i64)
+        .unwrap_or(0)
 }
 
-fn chrono_lite_time() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-    let day_secs = secs % 86400;
-    let hours = day_secs / 3600;
-    let minutes = (day_secs % 3600) / 60;
-    let seconds = day_secs % 60;
-    format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+/// STRING$(n, char$ | code)
+fn string_of(count: i64, fill: Option<&Value>) -> Result<String, String> {
+    let c = match fill {
+        Some(Value::String(s)) => s.chars().next().ok_or_else(|| "Illegal function call".to_string())?,
+        Some(v) => match u8::try_from(v.to_int()) {
+            Ok(code) => cp437_to_unicode(code),
+            Err(_) => return Err("Illegal function call".to_string()),
+        },
+        None => ' ',
+    };
+    Ok(c.to_string().repeat(count.max(0) as usize))
 }
 
-/// Convert CP437 (DOS) character code to Unicode
-fn cp437_to_unicode(code: u8) -> char {
-    // CP437 to Unicode mapping for codes 0-255
-    // Standard ASCII (32-126) maps directly
-    // Special characters for box drawing, etc.
-    match code {
-        // Control characters and special symbols (0-31)
-        0 => ' ',
-        1 => '☺', 2 => '☻', 3 => '♥', 4 => '♦', 5 => '♣', 6 => '♠', 7 => '•',
-        8 => '◘', 9 => '○', 10 => '◙', 11 => '♂', 12 => '♀', 13 => '♪', 14 => '♫', 15 => '☼',
-        16 => '►', 17 => '◄', 18 => '↕', 19 => '‼', 20 => '¶', 21 => '§', 22 => '▬', 23 => '↨',
-        24 => '↑', 25 => '↓', 26 => '→', 27 => '←', 28 => '∟', 29 => '↔', 30 => '▲', 31 => '▼',
-        // Standard ASCII (32-126)
-        32..=126 => code as char,
-        // Extended ASCII (127)
-        127 => '⌂',
-        // International characters (128-175)
-        128 => 'Ç', 129 => 'ü', 130 => 'é', 131 => 'â', 132 => 'ä', 133 => 'à', 134 => 'å', 135 => 'ç',
-        136 => 'ê', 137 => 'ë', 138 => 'è', 139 => 'ï', 140 => 'î', 141 => 'ì', 142 => 'Ä', 143 => 'Å',
-        144 => 'É', 145 => 'æ', 146 => 'Æ', 147 => 'ô', 148 => 'ö', 149 => 'ò', 150 => 'û', 151 => 'ù',
-        152 => 'ÿ', 153 => 'Ö', 154 => 'Ü', 155 => '¢', 156 => '£', 157 => '¥', 158 => '₧', 159 => 'ƒ',
-        160 => 'á', 161 => 'í', 162 => 'ó', 163 => 'ú', 164 => 'ñ', 165 => 'Ñ', 166 => 'ª', 167 => 'º',
-        168 => '¿', 169 => '⌐', 170 => '¬', 171 => '½', 172 => '¼', 173 => '¡', 174 => '«', 175 => '»',
-        // Box drawing - light shades (176-178)
-        176 => '░', 177 => '▒', 178 => '▓',
-        // Box drawing - single line (179-196)
-        179 => '│', 180 => '┤', 181 => '╡', 182 => '╢', 183 => '╖', 184 => '╕', 185 => '╣',
-        186 => '║', 187 => '╗', 188 => '╝', 189 => '╜', 190 => '╛', 191 => '┐',
-        192 => '└', 193 => '┴', 194 => '┬', 195 => '├', 196 => '─',
-        // Box drawing continued (197-218)
-        197 => '┼', 198 => '╞', 199 => '╟', 200 => '╚', 201 => '╔', 202 => '╩', 203 => '╦',
-        204 => '╠', 205 => '═', 206 => '╬', 207 => '╧', 208 => '╨', 209 => '╤', 210 => '╥',
-        211 => '╙', 212 => '╘', 213 => '╒', 214 => '╓', 215 => '╫', 216 => '╪', 217 => '┘', 218 => '┌',
-        // Block elements (219-223)
-        219 => '█', 220 => '▄', 221 => '▌', 222 => '▐', 223 => '▀',
-        // Greek letters and math symbols (224-239)
-        224 => 'α', 225 => 'ß', 226 => 'Γ', 227 => 'π', 228 => 'Σ', 229 => 'σ', 230 => 'µ', 231 => 'τ',
-        232 => 'Φ', 233 => 'Θ', 234 => 'Ω', 235 => 'δ', 236 => '∞', 237 => 'φ', 238 => 'ε', 239 => '∩',
-        // Math symbols continued (240-254)
-        240 => '≡', 241 => '±', 242 => '≥', 243 => '≤', 244 => '⌠', 245 => '⌡', 246 => '÷', 247 => '≈',
-        248 => '°', 249 => '∙', 250 => '·', 251 => '√', 252 => 'ⁿ', 253 => '²', 254 => '■', 255 => ' ',
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, args: &[Value]) -> Value {
+        call_builtin(name, args)
+            .expect("should be a builtin")
+            .expect("should succeed")
+    }
+
+    fn s(text: &str) -> Value {
+        Value::String(text.to_string())
+    }
+
+    #[test]
+    fn test_mid() {
+        assert_eq!(call("MID", &[s("QBasic"), Value::Integer(2), Value::Integer(3)]), s("Bas"));
+        assert_eq!(call("MID", &[s("QBasic"), Value::Integer(4)]), s("sic"));
+        assert_eq!(call("MID", &[s("QBasic"), Value::Integer(10), Value::Integer(2)]), s(""));
+        assert_eq!(call("MID", &[s("QBasic"), Value::Integer(0)]), s(""));
+    }
+
+    #[test]
+    fn test_instr() {
+        assert_eq!(call("INSTR", &[s("hello world"), s("o")]), Value::Integer(5));
+        assert_eq!(call("INSTR", &[Value::Integer(6), s("hello world"), s("o")]), Value::Integer(8));
+        assert_eq!(call("INSTR", &[s("hello"), s("z")]), Value::Integer(0));
+        assert_eq!(call("INSTR", &[Value::Integer(99), s("hello"), s("l")]), Value::Integer(0));
+        assert_eq!(call("INSTR", &[Value::Integer(2), s("abc"), s("")]), Value::Integer(2));
+    }
+
+    #[test]
+    fn test_trim() {
+        assert_eq!(call("LTRIM", &[s("  padded  ")]), s("padded  "));
+        assert_eq!(call("RTRIM", &[s("  padded  ")]), s("  padded"));
+    }
+
+    #[test]
+    fn test_string_and_space() {
+        assert_eq!(call("STRING", &[Value::Integer(3), s("xyz")]), s("xxx"));
+        assert_eq!(call("STRING", &[Value::Integer(2), Value::Integer(65)]), s("AA"));
+        assert_eq!(call("STRING", &[Value::Integer(-1), s("x")]), s(""));
+        assert!(call_builtin("STRING", &[Value::Integer(2), Value::Integer(300)])
+            .unwrap()
+            .is_err());
+        assert_eq!(call("SPACE", &[Value::Integer(3)]), s("   "));
+        assert_eq!(call("SPACE", &[Value::Integer(-2)]), s(""));
+    }
+
+    #[test]
+    fn test_left_right_out_of_range() {
+        assert_eq!(call("LEFT", &[s("abc"), Value::Integer(10)]), s("abc"));
+        assert_eq!(call("RIGHT", &[s("abc"), Value::Integer(-1)]), s(""));
+        assert!(call_builtin("LEFT", &[]).is_some());
+    }
+
+    #[test]
+    fn test_math_functions() {
+        assert_eq!(call("ABS", &[Value::Integer(-4)]), Value::Integer(4));
+        assert_eq!(call("SGN", &[Value::Float(-0.5)]), Value::Integer(-1));
+        assert_eq!(call("INT", &[Value::Float(-2.5)]), Value::Integer(-3));
+        assert_eq!(call("FIX", &[Value::Float(-2.5)]), Value::Integer(-2));
+        assert_eq!(call("SQR", &[Value::Integer(16)]), Value::Float(4.0));
+        assert_eq!(call("EXP", &[Value::Integer(0)]), Value::Float(1.0));
+        assert_eq!(call_builtin("SQR", &[Value::Integer(-1)]), Some(Err("Illegal function call".to_string())));
+        assert!(call_builtin("LOG", &[Value::Integer(0)]).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_conversion_functions() {
+        assert_eq!(call("STR", &[Value::Integer(5)]), s(" 5"));
+        assert_eq!(call("STR", &[Value::Float(-2.5)]), s("-2.5"));
+        assert_eq!(call("VAL", &[s("12abc")]), Value::Float(12.0));
+        assert_eq!(call("VAL", &[s(" -1 2.5e2x")]), Value::Float(-1250.0));
+        assert_eq!(call("VAL", &[s("abc")]), Value::Float(0.0));
+        assert_eq!(call("VAL", &[s("1E")]), Value::Float(1.0));
+        assert_eq!(call("VAL", &[s("&HFF")]), Value::Integer(255));
+        assert_eq!(call("CHR", &[Value::Integer(65)]), s("A"));
+        assert_eq!(call("ASC", &[s("Apple")]), Value::Integer(65));
+        assert_eq!(call("ASC", &[call("CHR", &[Value::Integer(200)])]), Value::Integer(200));
+        assert_eq!(call("HEX", &[Value::Integer(255)]), s("FF"));
+        assert_eq!(call("HEX", &[Value::Integer(-1)]), s("FFFF"));
+        assert_eq!(call("OCT", &[Value::Integer(8)]), s("10"));
+        assert_eq!(call_builtin("ASC", &[s("")]), Some(Err("Illegal function call".to_string())));
+        assert!(call_builtin("CHR", &[Value::Integer(256)]).unwrap().is_err());
+        assert!(call_builtin("STR", &[s("5")]).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_numeric_coercion_rounds_half_to_even() {
+        assert_eq!(call("CINT", &[Value::Float(2.5)]), Value::Integer(2));
+        assert_eq!(call("CINT", &[Value::Float(3.5)]), Value::Integer(4));
+        assert_eq!(call("CINT", &[Value::Float(-2.5)]), Value::Integer(-2));
+        assert_eq!(call("INT", &[Value::Float(2.5)]), Value::Integer(2));
+        assert_eq!(call("INT", &[Value::Float(3.5)]), Value::Integer(3));
+        assert_eq!(call("CLNG", &[Value::Float(100000.5)]), Value::Integer(100000));
+        assert_eq!(call("CDBL", &[Value::Integer(7)]), Value::Float(7.0));
+        assert_eq!(call_builtin("CINT", &[Value::Float(32767.5)]), Some(Err("Overflow".to_string())));
+        assert_eq!(call_builtin("CLNG", &[Value::Float(3e9)]), Some(Err("Overflow".to_string())));
+        assert_eq!(call_builtin("CSNG", &[Value::Float(1e300)]), Some(Err("Overflow".to_string())));
+        assert_eq!(call("CINT", &[Value::Float(-32768.0)]), Value::Integer(-32768));
+    }
+
+    #[test]
+    fn test_rnd_sequence_is_reproducible() {
+        let mut rng = Rng::default();
+        rng.randomize(42.0);
+        let first: Vec<f64> = (0..3).map(|_| rng.rnd(None)).collect();
+        assert!(first.iter().all(|v| (0.0..1.0).contains(v)));
+        assert_eq!(rng.rnd(Some(0.0)), first[2]);
+
+        let mut again = Rng::default();
+        again.randomize(42.0);
+        let second: Vec<f64> = (0..3).map(|_| again.rnd(None)).collect();
+        assert_eq!(first, second);
+
+        let mut other = Rng::default();
+        other.randomize(7.0);
+        assert_ne!(other.rnd(None), first[0]);
     }
 }
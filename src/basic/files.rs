@@ -0,0 +1,170 @@
+//! Sequential file I/O for OPEN/CLOSE, PRINT #, INPUT # and LINE INPUT #
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+/// Access mode given in OPEN ... FOR mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileMode {
+    Input,
+    Output,
+    Append,
+}
+
+/// A file opened with OPEN
+enum OpenFile {
+    Reader {
+        reader: BufReader<File>,
+        /// Fields left over from a partially consumed INPUT # line
+        pending: VecDeque<String>,
+    },
+    Writer(File),
+}
+
+/// Open files, keyed by BASIC file number
+#[derive(Default)]
+pub struct FileTable {
+    files: HashMap<i64, OpenFile>,
+}
+
+impl FileTable {
+    pub fn open(&mut self, path: &str, mode: FileMode, number: i64) -> Result<(), String> {
+        if !(1..=255).contains(&number) {
+            return Err("Bad file number".to_string());
+        }
+        if self.files.contains_key(&number) {
+            return Err("File already open".to_string());
+        }
+        let file = match mode {
+            FileMode::Input => File::open(path).map(|f| OpenFile::Reader {
+                reader: BufReader::new(f),
+                pending: VecDeque::new(),
+            }),
+            FileMode::Output => File::create(path).map(OpenFile::Writer),
+            FileMode::Append => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map(OpenFile::Writer),
+        }
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => "File not found".to_string(),
+            std::io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+            _ => format!("Path/File access error: {}", e),
+        })?;
+        self.files.insert(number, file);
+        Ok(())
+    }
+
+    /// Close one file, or every open file when `number` is None
+    pub fn close(&mut self, number: Option<i64>) {
+        match number {
+            Some(n) => {
+                self.files.remove(&n);
+            }
+            None => self.files.clear(),
+        }
+    }
+
+    /// Write text to a file opened for OUTPUT or APPEND
+    pub fn write(&mut self, number: i64, text: &str) -> Result<(), String> {
+        match self.files.get_mut(&number) {
+            Some(OpenFile::Writer(file)) => file
+                .write_all(text.as_bytes())
+                .map_err(|e| format!("Device I/O error: {}", e)),
+            Some(OpenFile::Reader { .. }) => Err("Bad file mode".to_string()),
+            None => Err("Bad file number".to_string()),
+        }
+    }
+
+    /// Read the rest of the current line (LINE INPUT #)
+    pub fn read_line(&mut self, number: i64) -> Result<String, String> {
+        let (reader, pending) = self.reader(number)?;
+        if !pending.is_empty() {
+            return Ok(pending.drain(..).collect::<Vec<_>>().join(","));
+        }
+        next_line(reader)?.ok_or_else(|| "Input past end of file".to_string())
+    }
+
+    /// Read the next comma-separated field (INPUT #), continuing onto following lines
+    pub fn read_field(&mut self, number: i64) -> Result<String, String> {
+        let (reader, pending) = self.reader(number)?;
+        while pending.is_empty() {
+            match next_line(reader)? {
+                Some(line) => pending.extend(split_input_fields(&line)),
+                None => return Err("Input past end of file".to_string()),
+            }
+        }
+        Ok(pending.pop_front().unwrap_or_default())
+    }
+
+    /// EOF(n): true when no more data can be read
+    pub fn eof(&mut self, number: i64) -> Result<bool, String> {
+        match self.files.get_mut(&number) {
+            Some(OpenFile::Reader { reader, pending }) => Ok(pending.is_empty()
+                && reader.fill_buf().map(|b| b.is_empty()).unwrap_or(true)),
+            Some(OpenFile::Writer(_)) => Ok(true),
+            None => Err("Bad file number".to_string()),
+        }
+    }
+
+    /// LOF(n): length of the file in bytes
+    pub fn length(&self, number: i64) -> Result<u64, String> {
+        let file = match self.files.get(&number) {
+            Some(OpenFile::Reader { reader, .. }) => reader.get_ref(),
+            Some(OpenFile::Writer(file)) => file,
+            None => return Err("Bad file number".to_string()),
+        };
+        file.metadata()
+            .map(|m| m.len())
+            .map_err(|e| format!("Device I/O error: {}", e))
+    }
+
+    fn reader(&mut self, number: i64) -> Result<(&mut BufReader<File>, &mut VecDeque<String>), String> {
+        match self.files.get_mut(&number) {
+            Some(OpenFile::Reader { reader, pending }) => Ok((reader, pending)),
+            Some(OpenFile::Writer(_)) => Err("Bad file mode".to_string()),
+            None => Err("Bad file number".to_string()),
+        }
+    }
+}
+
+/// Read one line without its terminator, or None at end of file
+fn next_line(reader: &mut BufReader<File>) -> Result<Option<String>, String> {
+    let mut line = String::new();
+    let read = reader
+        .read_line(&mut line)
+        .map_err(|e| format!("Device I/O error: {}", e))?;
+    if read == 0 {
+        return Ok(None);
+    }
+    let trimmed = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(trimmed);
+    Ok(Some(line))
+}
+
+/// Split an INPUT line on commas; double-quoted fields may contain commas
+pub fn split_input_fields(input: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut rest = input;
+    loop {
+        let trimmed = rest.trim_start();
+        let (field, remainder) = match trimmed.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let after = quoted.get(end + 1..).unwrap_or("");
+                (quoted[..end].to_string(), after.split_once(',').map(|(_, r)| r))
+            }
+            None => match trimmed.split_once(',') {
+                Some((f, r)) => (f.trim_end().to_string(), Some(r)),
+                None => (trimmed.trim_end().to_string(), None),
+            },
+        };
+        fields.push(field);
+        match remainder {
+            Some(r) => rest = r,
+            None => return fields,
+        }
+    }
+}
@@ -10,7 +10,9 @@
 //!
 //! Each terminal cell represents 2 vertical pixels in block mode
 
-use super::sixel::SixelEncoder;
+use std::borrow::Cow;
+
+use super::sixel::{SixelEncoder, PALETTE_16};
 
 
 /// A cell in the text screen buffer
@@ -31,11 +33,124 @@ impl Default for TextCell {
     }
 }
 
+/// Resolution and colours of a QBasic SCREEN mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScreenModeInfo {
+    pub width: u32,
+    pub height: u32,
+    pub text_cols: u32,
+    pub text_rows: u32,
+    pub colors: u16,
+}
+
+/// Logical resolution of SCREEN `mode`, or None if the mode isn't supported
+/// Index of the colour in `palette` nearest `rgb`
+fn nearest_color((r, g, b): (u8, u8, u8), palette: &[(u8, u8, u8); 16]) -> u8 {
+    let distance = |&(pr, pg, pb): &(u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, pr) + d(g, pg) + d(b, pb)
+    };
+    (0..16u8).min_by_key(|&i| distance(&palette[i as usize])).unwrap_or(0)
+}
+
+/// VGA's default colour for SCREEN 13 attributes 16-255: a grey ramp, then
+/// a 24-hue wheel at three intensities of three saturations each, then black
+fn vga_color(attr: u8) -> (u8, u8, u8) {
+    const GREYS: [u8; 16] = [0, 5, 8, 11, 14, 17, 20, 24, 28, 32, 36, 40, 45, 50, 56, 63];
+    // Channel levels from none to full of each hue, for each wheel
+    const LEVELS: [[u8; 5]; 9] = [
+        [0, 16, 31, 47, 63], [31, 39, 47, 55, 63], [45, 49, 54, 58, 63],
+        [0, 7, 14, 21, 28], [14, 17, 21, 24, 28], [20, 22, 24, 26, 28],
+        [0, 4, 8, 12, 16], [8, 10, 12, 14, 16], [11, 12, 13, 15, 16],
+    ];
+    let scale = |v: u8| (v as u32 * 255 / 63) as u8;
+    match attr {
+        0..=15 => PALETTE_16[attr as usize],
+        16..=31 => {
+            let v = scale(GREYS[attr as usize - 16]);
+            (v, v, v)
+        }
+        32..=247 => {
+            let levels = LEVELS[(attr as usize - 32) / 24];
+            let hue = (attr as i32 - 32) % 24;
+            // Each channel is full within 4 steps of its centre on the
+            // wheel (blue 0, red 8, green 16) and fades out over 4 more
+            let channel = |centre: i32| {
+                let d = (hue - centre).rem_euclid(24);
+                let d = d.min(24 - d);
+                scale(levels[(8 - d).clamp(0, 4) as usize])
+            };
+            (channel(8), channel(16), channel(0))
+        }
+        _ => (0, 0, 0),
+    }
+}
+
+pub fn screen_mode_info(mode: u8) -> Option<ScreenModeInfo> {
+    let (width, height, text_cols, text_rows, colors) = match mode {
+        0 => (640, 400, 80, 25, 16),
+        1 => (320, 200, 40, 25, 4),
+        2 => (640, 200, 80, 25, 2),
+        7 => (320, 200, 40, 25, 16),
+        8 => (640, 200, 80, 25, 16),
+        9 => (640, 350, 80, 25, 16),
+        12 => (640, 480, 80, 30, 16),
+        13 => (320, 200, 40, 25, 256),
+        _ => return None,
+    };
+    Some(ScreenModeInfo { width, height, text_cols, text_rows, colors })
+}
+
+/// Graphics viewport set by VIEW, in pixels (inclusive); drawing is
+/// clipped to it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x1: i32,
+    pub y1: i32,
+    pub x2: i32,
+    pub y2: i32,
+    /// Coordinates are measured from the viewport's corner (VIEW without SCREEN)
+    pub relative: bool,
+}
+
+/// Logical coordinates set by WINDOW, mapped onto the viewport
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogicalWindow {
+    pub x1: f64,
+    pub y1: f64,
+    pub x2: f64,
+    pub y2: f64,
+    /// WINDOW SCREEN: y grows downwards as on the screen, rather than up
+    pub screen: bool,
+}
+
+/// How PUT combines an image with the pixels already on screen
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PutAction {
+    Pset,
+    Preset,
+    And,
+    Or,
+    #[default]
+    Xor,
+}
+
 /// Graphics pixel buffer
 pub struct GraphicsMode {
     /// Current screen mode (0=text, 1/2/7/9/12/13=graphics)
     pub mode: u8,
 
+    /// PRINT, INPUT and the text cursor use the text screen buffer (the IDE's
+    /// output screen) rather than the interpreter's output stream
+    pub screen_output: bool,
+
+    /// Number of colours in the current mode's palette
+    pub colors: u16,
+
+    /// Buffer has the logical resolution set by SCREEN rather than the
+    /// terminal's size; it is scaled up to fit when rendered
+    fixed_resolution: bool,
+
     /// Pixel buffer width
     pub width: u32,
 
@@ -60,6 +175,9 @@ pub struct GraphicsMode {
     /// Cursor column (1-based)
     pub cursor_col: u16,
 
+    /// Text cursor shown while waiting for input (LOCATE's cursor argument)
+    pub cursor_visible: bool,
+
     /// Text screen dimensions
     pub text_cols: u16,
     pub text_rows: u16,
@@ -67,6 +185,18 @@ pub struct GraphicsMode {
     /// Text screen buffer for text-mode output
     pub text_screen: Vec<TextCell>,
 
+    /// Viewport set by VIEW, or None for the whole screen
+    view: Option<Viewport>,
+
+    /// Logical coordinates set by WINDOW, or None for pixels
+    window: Option<LogicalWindow>,
+
+    /// RGB colour shown for each attribute, remapped by PALETTE
+    palette: [(u8, u8, u8); 16],
+
+    /// Graphics cursor: the pixel last drawn at, read by POINT(0) and POINT(1)
+    pub last_point: (i32, i32),
+
     /// Graphics dirty flag - set when pixels change
     dirty: bool,
 
@@ -106,6 +236,9 @@ impl GraphicsMode {
         let pixel_count = (pixel_width * pixel_height) as usize;
         Self {
             mode: 12,
+            screen_output: false,
+            colors: 16,
+            fixed_resolution: false,
             width: pixel_width,
             height: pixel_height,
             pixels: vec![0; pixel_count],
@@ -114,9 +247,14 @@ impl GraphicsMode {
             background: 0,
             cursor_row: 1,
             cursor_col: 1,
+            cursor_visible: true,
             text_cols,
             text_rows,
             text_screen: vec![TextCell { char: ' ', fg: 15, bg: 0 }; (text_cols * text_rows) as usize],
+            view: None,
+            window: None,
+            palette: PALETTE_16,
+            last_point: (pixel_width as i32 / 2, pixel_height as i32 / 2),
             dirty: true,
             dirty_x_min: 0,
             dirty_y_min: 0,
@@ -133,6 +271,11 @@ impl GraphicsMode {
     /// cols/rows are character cell dimensions, pixel_width/pixel_height are actual pixel dimensions
     /// If pixel dimensions are 0, falls back to cols*8 / rows*16
     pub fn resize_pixels(&mut self, cols: u32, rows: u32, pixel_width: u32, pixel_height: u32) {
+        // A SCREEN mode keeps its own resolution
+        if self.fixed_resolution {
+            return;
+        }
+
         // Use provided pixel dimensions or fall back to assumed cell size
         let pixel_width = if pixel_width > 0 { pixel_width } else { cols * Self::CHAR_WIDTH };
         let pixel_height = if pixel_height > 0 { pixel_height } else { rows * Self::CHAR_HEIGHT };
@@ -173,6 +316,57 @@ impl GraphicsMode {
         self.needs_clear = true;
     }
 
+    /// Switch to SCREEN `mode`: resize to its logical resolution and text
+    /// size, reset the colours and clear the screen
+    pub fn set_screen(&mut self, mode: u8) -> Result<(), String> {
+        let info = screen_mode_info(mode).ok_or("Illegal function call")?;
+        self.mode = mode;
+        self.colors = info.colors;
+        self.foreground = if mode == 0 { 7 } else { 15 };
+        self.background = 0;
+        self.view = None;
+        self.window = None;
+        self.palette = PALETTE_16;
+        self.fixed_resolution = false;
+        self.resize_pixels(info.text_cols, info.text_rows, info.width, info.height);
+        self.fixed_resolution = true;
+        self.center_last_point();
+        self.cls();
+        self.needs_clear = true;
+        Ok(())
+    }
+
+    /// WIDTH: change the text screen to 40 or 80 columns and 25, 30, 43, 50
+    /// or 60 rows, clearing it. An omitted size keeps the current one when
+    /// that is a valid WIDTH, else 80 x 25. The pixel resolution is kept.
+    pub fn set_text_size(&mut self, cols: Option<u16>, rows: Option<u16>) -> Result<(), String> {
+        let valid_cols = |c: u16| matches!(c, 40 | 80);
+        let valid_rows = |r: u16| matches!(r, 25 | 30 | 43 | 50 | 60);
+        let cols = cols.unwrap_or(if valid_cols(self.text_cols) { self.text_cols } else { 80 });
+        let rows = rows.unwrap_or(if valid_rows(self.text_rows) { self.text_rows } else { 25 });
+        if !valid_cols(cols) || !valid_rows(rows) {
+            return Err("Illegal function call".to_string());
+        }
+
+        self.text_cols = cols;
+        self.text_rows = rows;
+        self.text_screen = vec![TextCell { char: ' ', fg: self.foreground, bg: self.background }; cols as usize * rows as usize];
+        // The terminal's size no longer decides the text screen's
+        self.fixed_resolution = true;
+        self.cls();
+        self.needs_clear = true;
+        Ok(())
+    }
+
+    /// Go back to following the terminal's size, the whole screen, pixel
+    /// coordinates and the standard palette (when a new program starts)
+    pub fn release_resolution(&mut self) {
+        self.fixed_resolution = false;
+        self.view = None;
+        self.window = None;
+        self.palette = PALETTE_16;
+    }
+
     /// Clear screen
     pub fn cls(&mut self) {
         self.pixels.fill(self.background);
@@ -194,8 +388,14 @@ impl GraphicsMode {
 
     /// Set colors
     pub fn set_color(&mut self, fg: u8, bg: u8) {
-        self.foreground = fg & 0x0F;
-        self.background = bg & 0x0F;
+        self.foreground = fg & self.color_mask();
+        self.background = bg & self.color_mask();
+    }
+
+    /// Bits of an attribute the current mode keeps: SCREEN 13 has 256
+    /// colours, the others at most 16
+    fn color_mask(&self) -> u8 {
+        if self.colors > 16 { 0xFF } else { 0x0F }
     }
 
     /// Set cursor position
@@ -278,13 +478,208 @@ impl GraphicsMode {
         TextCell::default()
     }
 
+    /// VIEW: clip drawing to `view`, or to the whole screen for None. The
+    /// viewport must lie on the screen.
+    pub fn set_view(&mut self, view: Option<Viewport>) -> Result<(), String> {
+        let view = view.map(|v| Viewport {
+            x1: v.x1.min(v.x2),
+            y1: v.y1.min(v.y2),
+            x2: v.x1.max(v.x2),
+            y2: v.y1.max(v.y2),
+            relative: v.relative,
+        });
+        if let Some(v) = view {
+            if v.x1 < 0 || v.y1 < 0 || v.x2 as u32 >= self.width || v.y2 as u32 >= self.height {
+                return Err("Illegal function call".to_string());
+            }
+        }
+        self.view = view;
+        self.center_last_point();
+        Ok(())
+    }
+
+    /// WINDOW: use `window`'s logical coordinates, or pixels for None. The
+    /// window can't be empty in either direction.
+    pub fn set_window(&mut self, window: Option<LogicalWindow>) -> Result<(), String> {
+        let window = window.map(|w| LogicalWindow {
+            x1: w.x1.min(w.x2),
+            y1: w.y1.min(w.y2),
+            x2: w.x1.max(w.x2),
+            y2: w.y1.max(w.y2),
+            screen: w.screen,
+        });
+        if window.is_some_and(|w| w.x1 == w.x2 || w.y1 == w.y2) {
+            return Err("Illegal function call".to_string());
+        }
+        self.window = window;
+        self.center_last_point();
+        Ok(())
+    }
+
+    /// Move the graphics cursor to the middle of the viewport, as SCREEN,
+    /// VIEW and WINDOW do
+    fn center_last_point(&mut self) {
+        let (x1, y1, x2, y2) = self.view_bounds();
+        self.last_point = (((x1 + x2) / 2.0) as i32, ((y1 + y2) / 2.0) as i32);
+    }
+
+    /// Fill the viewport with `color`, as VIEW's fill argument does
+    pub fn fill_view(&mut self, color: u8) {
+        if let Some(v) = self.view {
+            self.fill_box(v.x1, v.y1, v.x2, v.y2, color);
+        }
+    }
+
+    /// Frame the viewport with a line just outside it, as VIEW's border
+    /// argument does
+    pub fn frame_view(&mut self, color: u8) {
+        if let Some(v) = self.view.take() {
+            self.draw_box(v.x1 - 1, v.y1 - 1, v.x2 + 1, v.y2 + 1, color);
+            self.view = Some(v);
+        }
+    }
+
+    /// PALETTE attr, color: show `attr` in another colour. In the VGA modes
+    /// (SCREEN 12 and 13) `color` is 65536 * blue + 256 * green + red with
+    /// each part 0-63; elsewhere it is one of the 64 EGA colours. Pixels
+    /// already drawn change too.
+    pub fn set_palette(&mut self, attr: i64, color: i64) -> Result<(), String> {
+        let attr = usize::try_from(attr).ok().filter(|&a| a < 16).ok_or("Illegal function call")?;
+        // -1 leaves the attribute as it is
+        if color == -1 {
+            return Ok(());
+        }
+        let scale = |level: i64| (level * 255 / 63) as u8;
+        let rgb = match (self.mode, color) {
+            (12 | 13, 0..=0x3F3F3F) if color & 0xC0C0C0 == 0 => {
+                (scale(color & 0x3F), scale((color >> 8) & 0x3F), scale((color >> 16) & 0x3F))
+            }
+            (12 | 13, _) => return Err("Illegal function call".to_string()),
+            // EGA colours are rgbRGB: the low bits give 2/3 of each channel,
+            // the high bits the other 1/3
+            (_, 0..=63) => {
+                let channel = |bit: i64| ((color >> bit) & 1) as u8 * 0xAA + ((color >> (bit + 3)) & 1) as u8 * 0x55;
+                (channel(2), channel(1), channel(0))
+            }
+            _ => return Err("Illegal function call".to_string()),
+        };
+        self.palette[attr] = rgb;
+        self.sixel_cache.clear();
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// PALETTE with no arguments: put every attribute back to its own colour
+    pub fn reset_palette(&mut self) {
+        self.palette = PALETTE_16;
+        self.sixel_cache.clear();
+        self.dirty = true;
+    }
+
+    /// RGB colour shown for each attribute
+    pub fn palette(&self) -> &[(u8, u8, u8); 16] {
+        &self.palette
+    }
+
+    /// The standard attribute nearest the colour `attr` is shown in, for
+    /// text drawn with the terminal's 16 colours
+    pub fn display_attr(&self, attr: u8) -> u8 {
+        nearest_color(self.attr_rgb(attr), &PALETTE_16)
+    }
+
+    /// RGB colour of `attr`: its palette entry, or for SCREEN 13's
+    /// attributes above 15 the VGA default
+    fn attr_rgb(&self, attr: u8) -> (u8, u8, u8) {
+        self.palette.get(attr as usize).copied().unwrap_or_else(|| vga_color(attr))
+    }
+
+    /// The pixel buffer as the sixel encoder's 16 colours: SCREEN 13's
+    /// attributes above 15 become the nearest palette entry
+    fn display_pixels(&self) -> Cow<'_, [u8]> {
+        if !self.pixels.iter().any(|&p| p > 15) {
+            return Cow::Borrowed(&self.pixels);
+        }
+        let mut map = [0u8; 256];
+        for (attr, slot) in map.iter_mut().enumerate() {
+            *slot = if attr < 16 { attr as u8 } else { nearest_color(vga_color(attr as u8), &self.palette) };
+        }
+        Cow::Owned(self.pixels.iter().map(|&p| map[p as usize]).collect())
+    }
+
+    /// The area WINDOW maps onto: the viewport, else the whole screen
+    fn view_bounds(&self) -> (f64, f64, f64, f64) {
+        match self.view {
+            Some(v) => (v.x1 as f64, v.y1 as f64, v.x2 as f64, v.y2 as f64),
+            None => (0.0, 0.0, self.width.saturating_sub(1) as f64, self.height.saturating_sub(1) as f64),
+        }
+    }
+
+    /// Map a point given to a drawing statement to a pixel, through WINDOW's
+    /// logical coordinates and VIEW's origin
+    pub fn to_physical(&self, x: f64, y: f64) -> (i32, i32) {
+        let (vx1, vy1, vx2, vy2) = self.view_bounds();
+        match self.window {
+            Some(w) => {
+                let px = vx1 + (x - w.x1) * (vx2 - vx1) / (w.x2 - w.x1);
+                let py = if w.screen {
+                    vy1 + (y - w.y1) * (vy2 - vy1) / (w.y2 - w.y1)
+                } else {
+                    vy2 - (y - w.y1) * (vy2 - vy1) / (w.y2 - w.y1)
+                };
+                (px.round() as i32, py.round() as i32)
+            }
+            None => match self.view {
+                Some(v) if v.relative => ((x + vx1).round() as i32, (y + vy1).round() as i32),
+                _ => (x.round() as i32, y.round() as i32),
+            },
+        }
+    }
+
+    /// Map a pixel back to the coordinates a drawing statement would give
+    /// for it: the inverse of `to_physical`
+    pub fn to_logical(&self, px: i32, py: i32) -> (f64, f64) {
+        let (vx1, vy1, vx2, vy2) = self.view_bounds();
+        let (px, py) = (px as f64, py as f64);
+        match self.window {
+            Some(w) => {
+                let x = w.x1 + (px - vx1) * (w.x2 - w.x1) / (vx2 - vx1).max(1.0);
+                let y = if w.screen {
+                    w.y1 + (py - vy1) * (w.y2 - w.y1) / (vy2 - vy1).max(1.0)
+                } else {
+                    w.y1 + (vy2 - py) * (w.y2 - w.y1) / (vy2 - vy1).max(1.0)
+                };
+                (x, y)
+            }
+            None => match self.view {
+                Some(v) if v.relative => (px - vx1, py - vy1),
+                _ => (px, py),
+            },
+        }
+    }
+
+    /// Map a horizontal distance, such as a CIRCLE radius, to pixels
+    pub fn to_physical_length(&self, length: f64) -> i32 {
+        match self.window {
+            Some(w) => {
+                let (vx1, _, vx2, _) = self.view_bounds();
+                (length * (vx2 - vx1) / (w.x2 - w.x1)).round() as i32
+            }
+            None => length.round() as i32,
+        }
+    }
+
     /// Set a pixel
     pub fn pset(&mut self, x: i32, y: i32, color: u8) {
+        if let Some(v) = self.view {
+            if x < v.x1 || x > v.x2 || y < v.y1 || y > v.y2 {
+                return;
+            }
+        }
         if x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
             let xu = x as u32;
             let yu = y as u32;
             let idx = (yu * self.width + xu) as usize;
-            let new_color = color & 0x0F;
+            let new_color = color & self.color_mask();
 
             // Only mark dirty if pixel actually changed
             if self.pixels[idx] != new_color {
@@ -309,6 +704,21 @@ impl GraphicsMode {
         }
     }
 
+    /// POINT(x, y): the attribute of a pixel, or None if it is off the
+    /// screen or outside the viewport
+    pub fn point_in_view(&self, x: i32, y: i32) -> Option<u8> {
+        if let Some(v) = self.view {
+            if x < v.x1 || x > v.x2 || y < v.y1 || y > v.y2 {
+                return None;
+            }
+        }
+        if x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
+            Some(self.point(x, y))
+        } else {
+            None
+        }
+    }
+
     /// Get pixel color
     pub fn point(&self, x: i32, y: i32) -> u8 {
         if x >= 0 && y >= 0 && (x as u32) < self.width && (y as u32) < self.height {
@@ -523,58 +933,118 @@ impl GraphicsMode {
         self.pset(cx - y, cy - x, color);
     }
 
-    /// Flood fill (simple recursive - limited for large areas)
-    pub fn paint(&mut self, x: i32, y: i32, fill_color: u8) {
+    /// Flood fill outwards from (x, y) up to pixels of `border_color`
+    pub fn paint(&mut self, x: i32, y: i32, fill_color: u8, border_color: u8) {
         if x < 0 || y < 0 || (x as u32) >= self.width || (y as u32) >= self.height {
             return;
         }
 
-        let target_color = self.point(x, y);
-        if target_color == fill_color {
+        // Pixels are stored masked to the mode's colours, like pset
+        let border = border_color & self.color_mask();
+        if self.point(x, y) == border {
             return;
         }
 
-        // Use a simple scanline fill instead of recursive
-        self.scanline_fill(x, y, target_color, fill_color);
+        self.scanline_fill(x, y, fill_color, border);
     }
 
-    fn scanline_fill(&mut self, start_x: i32, start_y: i32, target: u8, fill: u8) {
+    /// Fill the area around (start_x, start_y) bounded by `border` pixels.
+    /// Uses an explicit stack of seed points so large regions can't overflow
+    /// the call stack, and a visited map so the fill color never stops it.
+    fn scanline_fill(&mut self, start_x: i32, start_y: i32, fill: u8, border: u8) {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let index = |x: i32, y: i32| (y * width + x) as usize;
+        let mut visited = vec![false; self.pixels.len()];
         let mut stack = vec![(start_x, start_y)];
 
         while let Some((x, y)) = stack.pop() {
-            if x < 0 || y < 0 || (x as u32) >= self.width || (y as u32) >= self.height {
-                continue;
-            }
-
-            if self.point(x, y) != target {
+            if visited[index(x, y)] || self.point(x, y) == border {
                 continue;
             }
 
-            // Find left edge
+            // Extend the span left and right to the border
+            let open = |this: &Self, visited: &[bool], x: i32| {
+                !visited[index(x, y)] && this.point(x, y) != border
+            };
             let mut left = x;
-            while left > 0 && self.point(left - 1, y) == target {
+            while left > 0 && open(self, &visited, left - 1) {
                 left -= 1;
             }
-
-            // Find right edge and fill
             let mut right = x;
-            while (right as u32) < self.width && self.point(right, y) == target {
-                self.pset(right, y, fill);
+            while right < width - 1 && open(self, &visited, right + 1) {
                 right += 1;
             }
 
-            // Add spans above and below
-            for scan_x in left..right {
-                if y > 0 && self.point(scan_x, y - 1) == target {
-                    stack.push((scan_x, y - 1));
-                }
-                if (y as u32) < self.height - 1 && self.point(scan_x, y + 1) == target {
-                    stack.push((scan_x, y + 1));
+            // Fill it and seed the rows above and below
+            for scan_x in left..=right {
+                self.pset(scan_x, y, fill);
+                visited[index(scan_x, y)] = true;
+                for next_y in [y - 1, y + 1] {
+                    if next_y >= 0 && next_y < height && !visited[index(scan_x, next_y)]
+                        && self.point(scan_x, next_y) != border
+                    {
+                        stack.push((scan_x, next_y));
+                    }
                 }
             }
         }
     }
 
+    /// Capture the rectangle (x1, y1)-(x2, y2) in GET array layout: the width
+    /// in bits, the height, then one byte per pixel packed two to a word (the
+    /// SCREEN 13 format). None if the rectangle leaves the screen.
+    pub fn get_image(&self, x1: i32, y1: i32, x2: i32, y2: i32) -> Option<Vec<i64>> {
+        let (left, right) = (x1.min(x2), x1.max(x2));
+        let (top, bottom) = (y1.min(y2), y1.max(y2));
+        if left < 0 || top < 0 || right as u32 >= self.width || bottom as u32 >= self.height {
+            return None;
+        }
+
+        let (width, height) = (right - left + 1, bottom - top + 1);
+        let mut data = vec![(width * 8) as i64, height as i64];
+        let bytes: Vec<u8> = (top..=bottom)
+            .flat_map(|y| (left..=right).map(move |x| (x, y)))
+            .map(|(x, y)| self.point(x, y))
+            .collect();
+        data.extend(bytes.chunks(2).map(|pair| {
+            let word = pair[0] as u16 | (pair.get(1).copied().unwrap_or(0) as u16) << 8;
+            word as i16 as i64
+        }));
+        Some(data)
+    }
+
+    /// Draw GET array `data` with its top left corner at (x, y), combining it
+    /// with the screen according to `action`
+    pub fn put_image(&mut self, x: i32, y: i32, data: &[i64], action: PutAction) -> Result<(), String> {
+        let illegal = || "Illegal function call".to_string();
+        let (width, height) = match data {
+            [bits, rows, ..] if *bits > 0 && *rows > 0 => ((*bits / 8) as i32, *rows as i32),
+            _ => return Err(illegal()),
+        };
+        let pixel_count = (width * height) as usize;
+        if data.len() < 2 + pixel_count.div_ceil(2) {
+            return Err(illegal());
+        }
+        if x < 0 || y < 0 || (x + width) as u32 > self.width || (y + height) as u32 > self.height {
+            return Err(illegal());
+        }
+
+        for i in 0..pixel_count {
+            let byte = (data[2 + i / 2] as u16 >> (8 * (i % 2))) as u8;
+            let (px, py) = (x + i as i32 % width, y + i as i32 / width);
+            let screen = self.point(px, py);
+            let color = match action {
+                PutAction::Pset => byte,
+                PutAction::Preset => !byte,
+                PutAction::And => screen & byte,
+                PutAction::Or => screen | byte,
+                PutAction::Xor => screen ^ byte,
+            };
+            self.pset(px, py, color);
+        }
+        Ok(())
+    }
+
 }
 
 impl Default for GraphicsMode {
@@ -589,8 +1059,8 @@ impl GraphicsMode {
     /// Returns the sixel escape sequence string that can be written to terminal.
     /// The `scale` parameter controls the pixel size (1 = native, 2 = 2x, etc.)
     pub fn render_sixel(&self, scale: u32) -> String {
-        let mut encoder = SixelEncoder::new();
-        encoder.encode(&self.pixels, self.width, self.height, scale).to_string()
+        let mut encoder = SixelEncoder::with_palette(self.palette);
+        encoder.encode(&self.display_pixels(), self.width, self.height, scale).to_string()
     }
 
     /// Render the pixel buffer as sixel graphics
@@ -603,8 +1073,8 @@ impl GraphicsMode {
         }
 
         // Render at native size (1:1) since buffer is already sized to terminal
-        let mut encoder = SixelEncoder::new();
-        self.sixel_cache = encoder.encode(&self.pixels, self.width, self.height, 1).to_string();
+        let mut encoder = SixelEncoder::with_palette(self.palette);
+        self.sixel_cache = encoder.encode(&self.display_pixels(), self.width, self.height, 1).to_string();
         self.cached_term_size = term_size;
         self.dirty = false;
         self.generation = self.generation.wrapping_add(1);
@@ -641,6 +1111,14 @@ impl GraphicsMode {
         }
     }
 
+    /// Render the whole screen again on the next draw, e.g. after something
+    /// else has been shown over it
+    pub fn force_redraw(&mut self) {
+        self.sixel_cache.clear();
+        self.dirty = true;
+        self.needs_clear = true;
+    }
+
     /// Check if screen needs clearing (mode changed, first render, etc.)
     /// This consumes the flag - subsequent calls return false until set again.
     pub fn take_needs_clear(&mut self) -> bool {
@@ -683,8 +1161,8 @@ impl GraphicsMode {
     pub fn render_dirty_region(&mut self) -> Option<(String, u32, u32, u32, u32)> {
         let (x, y, w, h) = self.get_dirty_region()?;
 
-        let mut encoder = SixelEncoder::new();
-        let sixel = encoder.encode_region(&self.pixels, self.width, self.height, x, y, w, h).to_string();
+        let mut encoder = SixelEncoder::with_palette(self.palette);
+        let sixel = encoder.encode_region(&self.display_pixels(), self.width, self.height, x, y, w, h).to_string();
 
         // Reset dirty region
         self.reset_dirty_region();
@@ -719,14 +1197,24 @@ impl GraphicsMode {
             return updates;
         }
 
-        // Check if we need full redraw (first render, resize, or large dirty area)
-        let full_redraw = self.cached_term_size != term_size || self.is_full_redraw_needed() || self.sixel_cache.is_empty();
+        // A SCREEN mode's logical resolution is scaled up by a whole factor to fit
+        let scale = if self.fixed_resolution {
+            let fit_w = term_cols as u32 * char_width / self.width.max(1);
+            let fit_h = term_rows as u32 * char_height / self.height.max(1);
+            fit_w.min(fit_h).max(1)
+        } else {
+            1
+        };
+
+        // Check if we need full redraw (first render, resize, scaling, or large dirty area)
+        let full_redraw = self.cached_term_size != term_size || scale > 1
+            || self.is_full_redraw_needed() || self.sixel_cache.is_empty();
 
         if full_redraw {
             // Full screen render
-            let mut encoder = SixelEncoder::new();
-            self.sixel_cache = encoder.encode(&self.pixels, self.width, self.height, 1).to_string();
-            updates.push((self.sixel_cache.clone(), 0, 0, self.width, self.height));
+            let mut encoder = SixelEncoder::with_palette(self.palette);
+            self.sixel_cache = encoder.encode(&self.display_pixels(), self.width, self.height, scale).to_string();
+            updates.push((self.sixel_cache.clone(), 0, 0, self.width * scale, self.height * scale));
         } else if let Some((x, y, w, h)) = self.get_dirty_region() {
             // Align dirty region to character cell boundaries for correct sixel positioning
             // Calculate which character cells are affected
@@ -758,8 +1246,8 @@ impl GraphicsMode {
 
             if aligned_w > 0 && aligned_h > 0 {
                 // Encode the aligned region
-                let mut encoder = SixelEncoder::new();
-                let sixel = encoder.encode_region(&self.pixels, self.width, self.height,
+                let mut encoder = SixelEncoder::with_palette(self.palette);
+                let sixel = encoder.encode_region(&self.display_pixels(), self.width, self.height,
                                                   aligned_x, aligned_y, aligned_w, aligned_h).to_string();
                 updates.push((sixel, aligned_x, aligned_y, aligned_w, aligned_h));
             }
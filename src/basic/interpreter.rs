@@ -1,46 +1,21 @@
 //! BASIC interpreter with generator-based execution for clean yield/resume semantics
 
-use crate::basic::graphics::GraphicsMode;
-use crate::basic::parser::{BinOp, DimVar, Expr, PrintItem, Stmt, UnaryOp};
+use crate::basic::builtins::{call_builtin, call_clock_function, Clock, Rng, SystemClock};
+use crate::basic::files::{split_input_fields, FileTable};
+use crate::basic::graphics::{GraphicsMode, LogicalWindow, Viewport};
+use crate::basic::parser::{BinOp, Block, DimVar, ExitKind, Expr, Param, PrintItem, Stmt, UnaryOp, VarType};
+use crate::basic::print_using::format_using;
+use crate::basic::sound::{PlayState, SoundEvent, TICKS_PER_SECOND};
 use async_recursion::async_recursion;
 use genawaiter::rc::{Co, Gen};
 use genawaiter::GeneratorState;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::time::Instant;
 
-/// Random number generator state (simple linear congruential generator)
-fn rnd() -> f64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    static mut SEED: u64 = 0;
-    static mut INITIALIZED: bool = false;
-
-    unsafe {
-        if !INITIALIZED {
-            SEED = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos() as u64;
-            INITIALIZED = true;
-        }
-        // LCG parameters
-        SEED = SEED.wrapping_mul(6364136223846793005).wrapping_add(1);
-        (SEED >> 33) as f64 / (1u64 << 31) as f64
-    }
-}
-
-/// Format a number for STR$ function
-fn format_number(n: f64) -> String {
-    if n == n.trunc() && n.abs() < 1e10 {
-        format!("{}", n as i64)
-    } else {
-        format!("{}", n)
-    }
-}
-
 /// Convert CP437 (DOS) character code to Unicode
-fn cp437_to_unicode(code: u8) -> char {
+pub(crate) fn cp437_to_unicode(code: u8) -> char {
     match code {
         0 => ' ',
         1 => '☺', 2 => '☻', 3 => '♥', 4 => '♦', 5 => '♣', 6 => '♠', 7 => '•',
@@ -76,9 +51,92 @@ pub enum Value {
     String(String),
     Float(f64),
     Integer(i64),
-    IntArray(Vec<i64>),
-    FloatArray(Vec<f64>),
-    StringArray(Vec<String>),
+    Record(Record),
+    Array(ArrayValue),
+}
+
+/// A dimensioned array of any rank, stored in row-major order
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArrayValue {
+    /// (lower, upper) bounds for each dimension, inclusive
+    pub bounds: Vec<(i64, i64)>,
+    pub elements: Vec<Value>,
+    pub elem_type: VarType,
+}
+
+impl ArrayValue {
+    fn new(bounds: Vec<(i64, i64)>, elem_type: VarType, default: Value) -> Self {
+        let size = Self::size_of(&bounds);
+        Self { bounds, elements: vec![default; size], elem_type }
+    }
+
+    fn size_of(bounds: &[(i64, i64)]) -> usize {
+        bounds.iter().map(|&(lo, hi)| (hi - lo + 1).max(0) as usize).product()
+    }
+
+    /// Flat offset of an element, or "Subscript out of range"
+    fn offset(&self, indices: &[i64]) -> Result<usize, String> {
+        if indices.len() != self.bounds.len() {
+            return Err("Wrong number of dimensions".to_string());
+        }
+        let mut offset = 0usize;
+        for (&idx, &(lo, hi)) in indices.iter().zip(&self.bounds) {
+            if idx < lo || idx > hi {
+                return Err("Subscript out of range".to_string());
+            }
+            offset = offset * (hi - lo + 1) as usize + (idx - lo) as usize;
+        }
+        Ok(offset)
+    }
+
+    fn get(&self, indices: &[i64]) -> Result<&Value, String> {
+        let offset = self.offset(indices)?;
+        Ok(&self.elements[offset])
+    }
+
+    fn get_mut(&mut self, indices: &[i64]) -> Result<&mut Value, String> {
+        let offset = self.offset(indices)?;
+        Ok(&mut self.elements[offset])
+    }
+
+    /// Resize to new bounds, keeping values whose indices exist in both shapes
+    fn resize_preserve(&mut self, bounds: Vec<(i64, i64)>, default: Value) {
+        let mut resized = ArrayValue::new(bounds, self.elem_type.clone(), default);
+        if resized.bounds.len() == self.bounds.len() {
+            let mut indices: Vec<i64> = self.bounds.iter().map(|&(lo, _)| lo).collect();
+            for value in std::mem::take(&mut self.elements) {
+                if let Ok(slot) = resized.get_mut(&indices) {
+                    *slot = value;
+                }
+                // Advance the index odometer, last dimension fastest
+                for (idx, &(lo, hi)) in indices.iter_mut().zip(&self.bounds).rev() {
+                    if *idx < hi {
+                        *idx += 1;
+                        break;
+                    }
+                    *idx = lo;
+                }
+            }
+        }
+        *self = resized;
+    }
+}
+
+/// An instance of a TYPE ... END TYPE record
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub type_name: String,
+    pub fields: Vec<(String, Value)>,
+}
+
+impl Record {
+    fn field(&self, name: &str) -> Option<&Value> {
+        self.fields.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v)
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.fields.iter_mut().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v)
+    }
 }
 
 impl Value {
@@ -130,6 +188,8 @@ impl Value {
 pub enum ExecutionResult {
     /// Program completed successfully
     Completed,
+    /// Program ran SYSTEM: leave without waiting for a key
+    Exited,
     /// Program was stopped by user
     Stopped,
     /// Hit a breakpoint at line number
@@ -146,9 +206,111 @@ pub enum ExecutionResult {
 #[derive(Clone, Debug)]
 pub struct Procedure {
     pub name: String,
-    pub params: Vec<String>,
-    pub body: Vec<Stmt>,
+    pub params: Vec<Param>,
+    pub body: Block,
     pub is_function: bool,
+    /// SUB/FUNCTION ... STATIC: every local keeps its value between calls
+    pub is_static: bool,
+}
+
+/// Local variables of a SUB, FUNCTION or DEF FN call
+#[derive(Default)]
+struct Frame {
+    vars: HashMap<String, Value>,
+    /// Module-level variables made visible with SHARED
+    shared: HashSet<String>,
+    /// DEF FN functions see every module-level variable
+    sees_globals: bool,
+    /// Parameters passed by reference, bound to the caller's storage
+    refs: HashMap<String, Slot>,
+    /// Uppercase name of the SUB or FUNCTION being run
+    procedure: Option<String>,
+    /// Locals declared STATIC, kept in the procedure's STATIC table
+    statics: HashSet<String>,
+    /// SUB/FUNCTION ... STATIC: every local is kept in the STATIC table
+    static_locals: bool,
+    /// Line of the statement that made the call
+    call_line: usize,
+}
+
+/// A variable or array element in a particular scope: where a parameter
+/// passed by reference reads and writes its value
+#[derive(Clone, Debug)]
+struct Slot {
+    scope: Scope,
+    name: String,
+    /// Subscripts when the slot is an array element
+    indices: Vec<i64>,
+}
+
+/// Where a variable is stored
+#[derive(Clone, Debug)]
+enum Scope {
+    Module,
+    /// Locals of the frame at this position in the call stack
+    Local(usize),
+    /// STATIC table of the procedure with this uppercase name
+    Static(String),
+}
+
+/// The value stored in `slot`
+fn slot_value<'a>(state: &'a InterpreterState, slot: &Slot) -> Option<&'a Value> {
+    let scope = match &slot.scope {
+        Scope::Module => &state.variables,
+        Scope::Local(i) => &state.call_stack.get(*i)?.vars,
+        Scope::Static(key) => state.static_vars.get(key)?,
+    };
+    match scope.get(&slot.name)? {
+        Value::Array(arr) if !slot.indices.is_empty() => arr.get(&slot.indices).ok(),
+        value => Some(value),
+    }
+}
+
+/// Mutable variant of `slot_value`
+fn slot_value_mut<'a>(
+    call_stack: &'a mut [Frame],
+    variables: &'a mut HashMap<String, Value>,
+    static_vars: &'a mut HashMap<String, HashMap<String, Value>>,
+    slot: &Slot,
+) -> Option<&'a mut Value> {
+    let scope = match &slot.scope {
+        Scope::Module => variables,
+        Scope::Local(i) => &mut call_stack.get_mut(*i)?.vars,
+        Scope::Static(key) => static_vars.get_mut(key)?,
+    };
+    let value = scope.get_mut(&slot.name)?;
+    if slot.indices.is_empty() {
+        return Some(value);
+    }
+    match value {
+        Value::Array(arr) => arr.get_mut(&slot.indices).ok(),
+        _ => None,
+    }
+}
+
+/// A DEF FN single-line function
+#[derive(Clone, Debug)]
+pub struct DefFn {
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+/// A TYPE ... END TYPE definition
+#[derive(Clone, Debug)]
+pub struct UserType {
+    pub name: String,
+    pub fields: Vec<(String, VarType)>,
+}
+
+/// Where RESUME carries on after a runtime error caught by ON ERROR GOTO
+#[derive(Clone, Copy, Debug)]
+enum Resume {
+    /// RESUME [0]: run the statement that raised the error again
+    Retry,
+    /// RESUME NEXT: carry on after that statement
+    Next,
+    /// RESUME label: jump to this top-level position
+    At(usize),
 }
 
 /// Pending INPUT statement state
@@ -185,31 +347,90 @@ pub struct InterpreterState {
     // Subroutine/function support
     gosub_stack: Vec<usize>,
     procedures: HashMap<String, Procedure>,
-    call_stack: Vec<HashMap<String, Value>>,
+    // Parameters given by DECLARE, keyed by uppercase procedure name
+    declarations: HashMap<String, Vec<Param>>,
+    // DEF FN functions, keyed by uppercase name
+    def_fns: HashMap<String, DefFn>,
+    call_stack: Vec<Frame>,
+    // Module-level variables shared with every procedure (DIM SHARED, COMMON SHARED)
+    shared_globals: HashSet<String>,
+    // Values of STATIC locals between calls, keyed by uppercase procedure name
+    static_vars: HashMap<String, HashMap<String, Value>>,
     return_value: Option<Value>,
 
+    // User-defined TYPEs, keyed by uppercase name
+    types: HashMap<String, UserType>,
+
+    // Lower bound for arrays dimensioned without an explicit one
+    option_base: i64,
+
+    // Default types set by DEFINT etc., keyed by uppercase first letter
+    def_types: HashMap<char, VarType>,
+
+    // Types of scalar variables declared with DIM ... AS
+    var_types: HashMap<String, VarType>,
+
+    // CONST bindings, keyed by uppercase name and visible in every scope
+    constants: HashMap<String, Value>,
+
+    // Files opened with OPEN, keyed by file number
+    files: FileTable,
+
+    // RND generator, reseeded by RANDOMIZE
+    rng: Rng,
+
+    // Time of day for TIMER, TIME$ and DATE$
+    clock: Box<dyn Clock>,
+
+    // Error trapping (ON ERROR GOTO / RESUME)
+    error_handler: Option<usize>,
+    /// An ON ERROR handler is running and has not reached RESUME
+    handling_error: bool,
+    err_code: i64,
+    err_line: i64,
+
     // Graphics
     pub graphics: GraphicsMode,
 
     // I/O
     output_buffer: Vec<String>,
+    // The last output line was left open by a trailing ; or , and the next
+    // PRINT continues it
+    output_open: bool,
     input_buffer: String,
     input_ready: bool,
     pending_input: Option<PendingInput>,
-    last_key: Option<char>,
+    /// Next INKEY$ result: one character, or CHR$(0) and a scan code
+    last_key: Option<String>,
+    // When a timed SLEEP ends, unless a key ends it first
+    sleep_deadline: Option<Instant>,
+
+    // Sound
+    sound_events: Vec<SoundEvent>,
+    play_state: PlayState,
 
     // Execution control
     running: bool,
     stop_requested: bool,
+    // END or SYSTEM ran (as opposed to the user stopping the program)
+    ended: bool,
+    system_exit: bool,
     breakpoints: Vec<usize>,
+    // Conditions on breakpoints, which only stop when theirs is true
+    breakpoint_conditions: HashMap<usize, Expr>,
+    // Why a breakpoint condition couldn't be evaluated
+    breakpoint_error: Option<String>,
     step_mode: bool,
+    // Procedure Step: only stop at statements at most this many calls deep
+    step_depth: Option<usize>,
 
     // Timing
     last_yield_time: Instant,
-    start_time: Instant,
 
     // Error state
     error: Option<String>,
+    // The line the unhandled error stopped the program at
+    error_line: Option<usize>,
 }
 
 impl InterpreterState {
@@ -222,25 +443,50 @@ impl InterpreterState {
             data_pointer: 0,
             gosub_stack: Vec::new(),
             procedures: HashMap::new(),
+            declarations: HashMap::new(),
+            def_fns: HashMap::new(),
             call_stack: Vec::new(),
+            shared_globals: HashSet::new(),
+            static_vars: HashMap::new(),
             return_value: None,
+            types: HashMap::new(),
+            option_base: 0,
+            def_types: HashMap::new(),
+            var_types: HashMap::new(),
+            constants: HashMap::new(),
+            files: FileTable::default(),
+            rng: Rng::default(),
+            clock: Box::new(SystemClock),
+            error_handler: None,
+            handling_error: false,
+            err_code: 0,
+            err_line: 0,
             graphics: {
                 let mut g = GraphicsMode::new(80, 25);
                 g.mode = 0;  // Start in text mode
                 g
             },
             output_buffer: Vec::new(),
+            output_open: false,
             input_buffer: String::new(),
             input_ready: false,
             pending_input: None,
             last_key: None,
+            sleep_deadline: None,
+            sound_events: Vec::new(),
+            play_state: PlayState::default(),
             running: false,
             stop_requested: false,
+            ended: false,
+            system_exit: false,
             breakpoints: Vec::new(),
+            breakpoint_conditions: HashMap::new(),
+            breakpoint_error: None,
             step_mode: false,
+            step_depth: None,
             last_yield_time: Instant::now(),
-            start_time: Instant::now(),
             error: None,
+            error_line: None,
         }
     }
 
@@ -252,21 +498,340 @@ impl InterpreterState {
         self.data_pointer = 0;
         self.gosub_stack.clear();
         self.procedures.clear();
+        self.declarations.clear();
+        self.def_fns.clear();
         self.call_stack.clear();
+        self.shared_globals.clear();
+        self.static_vars.clear();
         self.return_value = None;
+        self.types.clear();
+        self.option_base = 0;
+        self.def_types.clear();
+        self.var_types.clear();
+        self.constants.clear();
+        self.files.close(None);
+        self.rng = Rng::default();
+        self.clear_error_trap();
         self.output_buffer.clear();
+        self.output_open = false;
         self.input_buffer.clear();
         self.input_ready = false;
         self.pending_input = None;
         self.last_key = None;
+        self.sleep_deadline = None;
+        self.sound_events.clear();
+        self.play_state = PlayState::default();
         self.running = false;
         self.stop_requested = false;
+        self.ended = false;
+        self.system_exit = false;
         self.step_mode = false;
+        self.step_depth = None;
         self.error = None;
-        self.start_time = Instant::now();
+        self.error_line = None;
         self.last_yield_time = Instant::now();
     }
 
+    /// Add text to the text output, continuing an open line and leaving the
+    /// last line open when `newline` is false. Embedded '\n's start new lines.
+    fn write_output(&mut self, text: &str, newline: bool) {
+        let mut lines = text.split('\n');
+        let first = lines.next().unwrap_or_default();
+        match self.output_buffer.last_mut() {
+            Some(last) if self.output_open => last.push_str(first),
+            _ => self.output_buffer.push(first.to_string()),
+        }
+        self.output_buffer.extend(lines.map(str::to_string));
+        self.output_open = !newline;
+    }
+
+    /// 0-based column the next PRINT starts at
+    fn print_column(&self) -> usize {
+        if self.graphics.screen_output {
+            self.graphics.cursor_col.saturating_sub(1) as usize
+        } else if self.output_open {
+            self.output_buffer.last().map_or(0, |line| line.chars().count())
+        } else {
+            0
+        }
+    }
+
+    fn clear_error_trap(&mut self) {
+        self.error_handler = None;
+        self.handling_error = false;
+        self.err_code = 0;
+        self.err_line = 0;
+    }
+
+    /// Whether `name` refers to a module-level variable in the current scope:
+    /// always outside procedures, and inside one only if it is shared
+    fn is_global(&self, name: &str) -> bool {
+        match self.call_stack.last() {
+            None => true,
+            Some(frame) => frame.sees_globals || frame.shared.contains(name) || self.shared_globals.contains(name),
+        }
+    }
+
+    /// Push the frame for a call to `procedure`
+    fn enter_procedure(&mut self, procedure: &Procedure, mut frame: Frame) {
+        frame.procedure = Some(procedure.name.to_uppercase());
+        frame.static_locals = procedure.is_static;
+        frame.call_line = self.current_line;
+        self.call_stack.push(frame);
+    }
+
+    /// Pop a procedure's frame
+    fn leave_procedure(&mut self) -> Frame {
+        self.call_stack.pop().unwrap_or_default()
+    }
+
+    /// The procedure whose STATIC table holds `name` in the current scope.
+    /// Every call of the procedure, recursive ones included, shares it.
+    fn static_key(&self, name: &str) -> Option<&String> {
+        let frame = self.call_stack.last()?;
+        let is_static = frame.statics.contains(name)
+            || (frame.static_locals
+                && !frame.vars.contains_key(name)
+                && !frame.refs.contains_key(name)
+                && !self.is_global(name));
+        frame.procedure.as_ref().filter(|_| is_static)
+    }
+
+    /// Look up a variable in the current procedure scope, then globals
+    fn var(&self, name: &str) -> Option<&Value> {
+        if let Some(slot) = self.bound_slot(name) {
+            return slot_value(self, slot);
+        }
+        if let Some(key) = self.static_key(name) {
+            return self.static_vars.get(key)?.get(name);
+        }
+        match self.call_stack.last().and_then(|frame| frame.vars.get(name)) {
+            Some(v) => Some(v),
+            None if self.is_global(name) => self.variables.get(name),
+            None => None,
+        }
+    }
+
+    /// Mutable variant of `var`
+    fn var_mut(&mut self, name: &str) -> Option<&mut Value> {
+        if let Some(slot) = self.bound_slot(name).cloned() {
+            return slot_value_mut(&mut self.call_stack, &mut self.variables, &mut self.static_vars, &slot);
+        }
+        if let Some(key) = self.static_key(name).cloned() {
+            return self.static_vars.get_mut(&key)?.get_mut(name);
+        }
+        let global = self.is_global(name);
+        match self.call_stack.last_mut() {
+            Some(frame) if frame.vars.contains_key(name) => frame.vars.get_mut(name),
+            _ if global => self.variables.get_mut(name),
+            _ => None,
+        }
+    }
+
+    /// Where the current procedure's parameter `name` is bound, if it was
+    /// passed by reference
+    fn bound_slot(&self, name: &str) -> Option<&Slot> {
+        self.call_stack.last().and_then(|frame| frame.refs.get(name))
+    }
+
+    /// The storage of a variable or array element named in the current
+    /// scope, creating the variable or dimensioning the array if needed
+    fn slot_for(&mut self, reference: ArgRef) -> Result<Slot, String> {
+        let (name, indices) = match reference {
+            ArgRef::Var(name) => (name, Vec::new()),
+            ArgRef::Element(name, indices) => (name, indices),
+        };
+        if !indices.is_empty() {
+            self.array_mut(&name, indices.len())?.get(&indices)?;
+        }
+        // Passing on a parameter passes on what it is bound to
+        if let Some(slot) = self.bound_slot(&name) {
+            let mut slot = slot.clone();
+            if !indices.is_empty() {
+                slot.indices = indices;
+            }
+            return Ok(slot);
+        }
+        if indices.is_empty() && self.var(&name).is_none() {
+            let value = self.default_var(&name);
+            self.new_var_scope(&name).insert(name.clone(), value);
+        }
+        let scope = match (self.static_key(&name), self.call_stack.last()) {
+            (Some(key), _) => Scope::Static(key.clone()),
+            (None, Some(frame)) if frame.vars.contains_key(&name) => Scope::Local(self.call_stack.len() - 1),
+            _ => Scope::Module,
+        };
+        Ok(Slot { scope, name, indices })
+    }
+
+    /// The scope a new variable called `name` is created in: the current
+    /// procedure's locals, unless the name is shared
+    fn new_var_scope(&mut self, name: &str) -> &mut HashMap<String, Value> {
+        if self.is_global(name) {
+            return &mut self.variables;
+        }
+        if let Some(key) = self.static_key(name).cloned() {
+            return self.static_vars.entry(key).or_default();
+        }
+        match self.call_stack.last_mut() {
+            Some(frame) => &mut frame.vars,
+            None => &mut self.variables,
+        }
+    }
+
+    /// Type of a scalar variable: its suffix, else its DIM AS type, else the DEFtype default
+    fn var_type(&self, name: &str) -> VarType {
+        if name.ends_with(['$', '%', '&', '!', '#']) {
+            return element_type(name, None);
+        }
+        if let Some(t) = self.var_types.get(name) {
+            return t.clone();
+        }
+        name.chars()
+            .next()
+            .and_then(|c| self.def_types.get(&c.to_ascii_uppercase()))
+            .cloned()
+            .unwrap_or(VarType::Single)
+    }
+
+    /// Whether an integer expression is LONG rather than INTEGER, which sets
+    /// the range its arithmetic overflows at
+    fn is_long_expr(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Integer(n) => i16::try_from(*n).is_err(),
+            Expr::Variable(name) => {
+                let param = self
+                    .call_stack
+                    .last()
+                    .and_then(|frame| frame.procedure.as_ref())
+                    .and_then(|key| self.procedures.get(key))
+                    .and_then(|procedure| procedure.params.iter().find(|p| p.name == *name))
+                    .and_then(|param| param.var_type.clone());
+                param.unwrap_or_else(|| self.var_type(name)) == VarType::Long
+            }
+            Expr::ArrayAccess(name, _) => match self.var(name) {
+                Some(Value::Array(arr)) => arr.elem_type == VarType::Long,
+                _ => self.var_type(name) == VarType::Long,
+            },
+            Expr::FunctionCall(name, _) => name.eq_ignore_ascii_case("CLNG") || self.var_type(name) == VarType::Long,
+            Expr::BinaryOp(left, BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::IntDiv | BinOp::Mod, right) => {
+                self.is_long_expr(left) || self.is_long_expr(right)
+            }
+            Expr::UnaryOp(op, operand) => match (op, &**operand) {
+                // -32768 is an INTEGER
+                (UnaryOp::Neg, Expr::Integer(n)) => i16::try_from(-n).is_err(),
+                _ => self.is_long_expr(operand),
+            },
+            Expr::Paren(operand) => self.is_long_expr(operand),
+            _ => false,
+        }
+    }
+
+    fn is_constant(&self, name: &str) -> bool {
+        self.constants.contains_key(&name.to_uppercase())
+    }
+
+    /// Value of a variable that has not been assigned yet
+    fn default_var(&self, name: &str) -> Value {
+        default_value(&self.types, &self.var_type(name)).unwrap_or(Value::Integer(0))
+    }
+
+    /// Assign a scalar variable, coercing to its type. Writes to the local
+    /// scope when the variable exists there, else to globals.
+    fn set_var(&mut self, name: &str, value: Value) -> Result<(), String> {
+        if self.is_constant(name) {
+            return Err("Duplicate definition".to_string());
+        }
+        let var_type = match self.bound_slot(name) {
+            Some(slot) => self.slot_type(slot),
+            None => self.var_type(name),
+        };
+        let value = coerce_value(value, &var_type)?;
+        match self.var_mut(name) {
+            Some(slot) => *slot = value,
+            None => {
+                self.new_var_scope(name).insert(name.to_string(), value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Type a value written through `slot` is converted to
+    fn slot_type(&self, slot: &Slot) -> VarType {
+        let whole = Slot { indices: Vec::new(), ..slot.clone() };
+        match slot_value(self, &whole) {
+            Some(Value::Array(arr)) if !slot.indices.is_empty() => arr.elem_type.clone(),
+            _ => self.var_type(&slot.name),
+        }
+    }
+
+    /// Assign an array element, coercing to the array's element type
+    fn set_element(&mut self, name: &str, indices: &[i64], value: Value) -> Result<(), String> {
+        let arr = self.array_mut(name, indices.len())?;
+        let value = coerce_value(value, &arr.elem_type)?;
+        *arr.get_mut(indices)? = value;
+        Ok(())
+    }
+
+    /// Assign a field of a record variable or record array element
+    fn set_record_field(&mut self, name: &str, indices: &[i64], fields: &[String], value: Value) -> Result<(), String> {
+        let global = self.is_global(name);
+        let slot = match (self.bound_slot(name), self.static_key(name)) {
+            (Some(slot), _) => Some(slot.clone()),
+            (None, Some(key)) => Some(Slot { scope: Scope::Static(key.clone()), name: name.to_string(), indices: Vec::new() }),
+            (None, None) => None,
+        };
+        let types = &self.types;
+        let target = match slot {
+            Some(slot) => slot_value_mut(&mut self.call_stack, &mut self.variables, &mut self.static_vars, &slot),
+            None => match self.call_stack.last_mut() {
+                Some(frame) if frame.vars.contains_key(name) => frame.vars.get_mut(name),
+                _ if global => self.variables.get_mut(name),
+                _ => None,
+            },
+        };
+        let record = match target {
+            Some(Value::Record(record)) if indices.is_empty() => record,
+            Some(Value::Array(arr)) if !indices.is_empty() => match arr.get_mut(indices)? {
+                Value::Record(record) => record,
+                _ => return Err("Type mismatch".to_string()),
+            },
+            _ => return Err("Type mismatch".to_string()),
+        };
+        set_field(types, record, fields, value)
+    }
+
+    /// Fetch an array for element access, auto-dimensioning it (0..10 per
+    /// subscript) the first time an undeclared array is used
+    fn array_mut(&mut self, name: &str, rank: usize) -> Result<&mut ArrayValue, String> {
+        if self.var(name).is_none() {
+            let elem_type = element_type(name, None);
+            let default = default_value(&self.types, &elem_type)?;
+            let bounds = vec![(self.option_base, 10); rank];
+            self.new_var_scope(name)
+                .insert(name.to_string(), Value::Array(ArrayValue::new(bounds, elem_type, default)));
+        }
+        match self.var_mut(name) {
+            Some(Value::Array(arr)) => Ok(arr),
+            _ => Err("Type mismatch".to_string()),
+        }
+    }
+
+    /// The array holding GET/PUT image data and the offset of its first
+    /// element - element `indices`, or the start of the array
+    fn image_array(&mut self, name: &str, indices: &[i64]) -> Result<(&mut ArrayValue, usize), String> {
+        let arr = if indices.is_empty() {
+            match self.var_mut(name) {
+                Some(Value::Array(arr)) => arr,
+                _ => return Err("Type mismatch".to_string()),
+            }
+        } else {
+            self.array_mut(name, indices.len())?
+        };
+        let start = if indices.is_empty() { 0 } else { arr.offset(indices)? };
+        Ok((arr, start))
+    }
+
     fn should_yield_for_ui(&self) -> bool {
         self.last_yield_time.elapsed().as_millis() >= 16
     }
@@ -343,14 +908,38 @@ impl Interpreter {
         self.generator = None;
     }
 
+    /// Replace the clock read by TIMER, TIME$ and DATE$
+    #[allow(dead_code)] // The IDE and headless runner use the system clock
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.state.borrow_mut().clock = clock;
+    }
+
     pub fn set_breakpoints(&mut self, breakpoints: &[usize]) {
         self.state.borrow_mut().breakpoints = breakpoints.to_vec();
     }
 
+    /// Make the breakpoints at these lines conditional
+    pub fn set_breakpoint_conditions(&mut self, conditions: HashMap<usize, Expr>) {
+        self.state.borrow_mut().breakpoint_conditions = conditions;
+    }
+
+    /// The error from the last breakpoint condition that couldn't be
+    /// evaluated (the breakpoint stops anyway)
+    pub fn take_breakpoint_error(&mut self) -> Option<String> {
+        self.state.borrow_mut().breakpoint_error.take()
+    }
+
     pub fn set_step_mode(&mut self, enabled: bool) {
         self.state.borrow_mut().step_mode = enabled;
     }
 
+    /// Make the next steps go over SUB and FUNCTION calls made from the
+    /// current statement (Procedure Step) or into them (Step)
+    pub fn set_step_over(&mut self, over: bool) {
+        let mut s = self.state.borrow_mut();
+        s.step_depth = over.then(|| s.call_stack.len());
+    }
+
     pub fn request_stop(&mut self) {
         self.state.borrow_mut().stop_requested = true;
     }
@@ -359,8 +948,28 @@ impl Interpreter {
         self.state.borrow().running
     }
 
+    /// Take the finished output lines. While the program runs, a line left
+    /// open by a trailing ; stays behind to be continued (unless INPUT is
+    /// waiting on it).
     pub fn take_output(&mut self) -> Vec<String> {
-        std::mem::take(&mut self.state.borrow_mut().output_buffer)
+        let mut s = self.state.borrow_mut();
+        let mut lines = std::mem::take(&mut s.output_buffer);
+        if s.running && s.output_open && s.pending_input.is_none() {
+            if let Some(open) = lines.pop() {
+                s.output_buffer.push(open);
+            }
+        }
+        lines
+    }
+
+    /// Take the sounds queued by BEEP, SOUND and PLAY since the last call
+    pub fn take_sound_events(&mut self) -> Vec<SoundEvent> {
+        std::mem::take(&mut self.state.borrow_mut().sound_events)
+    }
+
+    /// Whether the last line of output is unfinished (ends in ; or an INPUT prompt)
+    pub fn output_line_open(&self) -> bool {
+        self.state.borrow().output_open
     }
 
     pub fn pending_input(&self) -> Option<PendingInput> {
@@ -399,16 +1008,21 @@ impl Interpreter {
     }
 
     pub fn set_last_key(&mut self, key: Option<char>) {
-        self.state.borrow_mut().last_key = key;
+        self.state.borrow_mut().last_key = key.map(String::from);
     }
 
-    /// Set pending key from string (for INKEY$ with escape sequences)
+    /// Set pending key from string (for INKEY$ extended keys like CHR$(0) + "H")
     pub fn set_pending_key(&mut self, key: Option<String>) {
-        self.state.borrow_mut().last_key = key.and_then(|s| s.chars().next());
+        self.state.borrow_mut().last_key = key;
     }
 
-    pub fn get_last_key(&self) -> Option<char> {
-        self.state.borrow().last_key
+    /// When the running SLEEP times out, if it is timed
+    pub fn sleep_deadline(&self) -> Option<Instant> {
+        self.state.borrow().sleep_deadline
+    }
+
+    pub fn get_last_key(&self) -> Option<String> {
+        self.state.borrow().last_key.clone()
     }
 
     /// Direct access to graphics for mutation (needed by app.rs)
@@ -424,20 +1038,70 @@ impl Interpreter {
         self.state.borrow().error.clone()
     }
 
+    /// The line the program stopped at with an unhandled runtime error
+    pub fn error_line(&self) -> Option<usize> {
+        self.state.borrow().error_line
+    }
+
+    /// The SUBs and FUNCTIONs being run, outermost first, each with the line
+    /// it was called from
+    pub fn call_stack(&self) -> Vec<(String, usize)> {
+        let s = self.state.borrow();
+        s.call_stack
+            .iter()
+            .filter_map(|frame| {
+                let key = frame.procedure.as_ref()?;
+                let name = s.procedures.get(key).map_or_else(|| key.clone(), |p| p.name.clone());
+                Some((name, frame.call_line))
+            })
+            .collect()
+    }
+
+    /// Names of the variables visible where the program is paused, sorted
+    pub fn variable_names(&self) -> Vec<String> {
+        let s = self.state.borrow();
+        let locals = s.call_stack.last().into_iter().flat_map(|frame| frame.vars.keys().chain(frame.refs.keys()));
+        let statics = s.call_stack.last().and_then(|frame| frame.procedure.as_ref()).and_then(|key| s.static_vars.get(key));
+        let statics = statics.into_iter().flat_map(|table| table.keys()).filter(|name| s.static_key(name).is_some());
+        let globals = s.variables.keys().filter(|name| s.is_global(name));
+        let mut names: Vec<String> = locals
+            .chain(statics)
+            .chain(globals)
+            .filter(|name| name.starts_with(|c: char| c.is_alphabetic()))
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
     /// Execute program synchronously (for tests and simple usage)
-    pub fn execute(&mut self, program: &[Stmt]) -> Result<(), String> {
+    pub fn execute(&mut self, program: &Block) -> Result<(), String> {
         // Reset state
         {
             let mut s = self.state.borrow_mut();
             s.running = true;
             s.stop_requested = false;
+            s.ended = false;
+            s.system_exit = false;
             s.error = None;
-            s.start_time = Instant::now();
+            s.error_line = None;
             s.last_yield_time = Instant::now();
             s.labels.clear();
             s.data_values.clear();
             s.data_pointer = 0;
             s.procedures.clear();
+            s.declarations.clear();
+            s.def_fns.clear();
+            s.output_open = false;
+            s.graphics.cursor_visible = true;
+            s.types.clear();
+            s.option_base = 0;
+            s.def_types.clear();
+            s.var_types.clear();
+            s.constants.clear();
+            s.files.close(None);
+            s.clear_error_trap();
         }
 
         // Pre-process
@@ -457,7 +1121,7 @@ impl Interpreter {
                             state.data_values.push(value);
                         }
                     }
-                    Stmt::Sub { name, params, body } => {
+                    Stmt::Sub { name, params, body, is_static } => {
                         state.procedures.insert(
                             name.to_uppercase(),
                             Procedure {
@@ -465,10 +1129,11 @@ impl Interpreter {
                                 params: params.clone(),
                                 body: body.clone(),
                                 is_function: false,
+                                is_static: *is_static,
                             },
                         );
                     }
-                    Stmt::Function { name, params, body } => {
+                    Stmt::Function { name, params, body, is_static } => {
                         state.procedures.insert(
                             name.to_uppercase(),
                             Procedure {
@@ -476,6 +1141,25 @@ impl Interpreter {
                                 params: params.clone(),
                                 body: body.clone(),
                                 is_function: true,
+                                is_static: *is_static,
+                            },
+                        );
+                    }
+                    Stmt::Declare { name, params, .. } => {
+                        state.declarations.insert(name.to_uppercase(), params.clone());
+                    }
+                    Stmt::DefFn { name, params, body } => {
+                        state.def_fns.insert(
+                            name.to_uppercase(),
+                            DefFn { params: params.clone(), body: body.clone() },
+                        );
+                    }
+                    Stmt::TypeDef { name, fields } => {
+                        state.types.insert(
+                            name.to_uppercase(),
+                            UserType {
+                                name: name.clone(),
+                                fields: fields.clone(),
                             },
                         );
                     }
@@ -485,7 +1169,7 @@ impl Interpreter {
         }
 
         // Create generator and consume all yields
-        let gen = create_execution_generator(self.state.clone(), program.to_vec());
+        let gen = create_execution_generator(self.state.clone(), program.clone());
         let mut wrapper = GenWrapper { gen };
         while wrapper.resume_gen().is_some() {
             // Consume all yields
@@ -499,19 +1183,32 @@ impl Interpreter {
     }
 
     /// Execute with debug support - stores generator for later continuation
-    pub fn execute_with_debug(&mut self, program: &[Stmt]) -> Result<ExecutionResult, String> {
+    pub fn execute_with_debug(&mut self, program: &Block) -> Result<ExecutionResult, String> {
         // Reset state for new execution
         {
             let mut s = self.state.borrow_mut();
             s.running = true;
             s.stop_requested = false;
+            s.ended = false;
+            s.system_exit = false;
             s.error = None;
-            s.start_time = Instant::now();
+            s.error_line = None;
             s.last_yield_time = Instant::now();
             s.labels.clear();
             s.data_values.clear();
             s.data_pointer = 0;
             s.procedures.clear();
+            s.declarations.clear();
+            s.def_fns.clear();
+            s.output_open = false;
+            s.graphics.cursor_visible = true;
+            s.types.clear();
+            s.option_base = 0;
+            s.def_types.clear();
+            s.var_types.clear();
+            s.constants.clear();
+            s.files.close(None);
+            s.clear_error_trap();
         }
 
         // Pre-process: collect labels, DATA statements, and procedures
@@ -531,7 +1228,7 @@ impl Interpreter {
                             state.data_values.push(value);
                         }
                     }
-                    Stmt::Sub { name, params, body } => {
+                    Stmt::Sub { name, params, body, is_static } => {
                         state.procedures.insert(
                             name.to_uppercase(),
                             Procedure {
@@ -539,10 +1236,11 @@ impl Interpreter {
                                 params: params.clone(),
                                 body: body.clone(),
                                 is_function: false,
+                                is_static: *is_static,
                             },
                         );
                     }
-                    Stmt::Function { name, params, body } => {
+                    Stmt::Function { name, params, body, is_static } => {
                         state.procedures.insert(
                             name.to_uppercase(),
                             Procedure {
@@ -550,6 +1248,25 @@ impl Interpreter {
                                 params: params.clone(),
                                 body: body.clone(),
                                 is_function: true,
+                                is_static: *is_static,
+                            },
+                        );
+                    }
+                    Stmt::Declare { name, params, .. } => {
+                        state.declarations.insert(name.to_uppercase(), params.clone());
+                    }
+                    Stmt::DefFn { name, params, body } => {
+                        state.def_fns.insert(
+                            name.to_uppercase(),
+                            DefFn { params: params.clone(), body: body.clone() },
+                        );
+                    }
+                    Stmt::TypeDef { name, fields } => {
+                        state.types.insert(
+                            name.to_uppercase(),
+                            UserType {
+                                name: name.clone(),
+                                fields: fields.clone(),
                             },
                         );
                     }
@@ -560,7 +1277,7 @@ impl Interpreter {
 
         // Create and store the generator (clone state to avoid borrowing self)
         let state_clone = self.state.clone();
-        let program_owned = program.to_vec();
+        let program_owned = program.clone();
         let gen = create_execution_generator(state_clone, program_owned);
         self.generator = Some(Box::new(GenWrapper { gen }));
 
@@ -591,7 +1308,9 @@ impl Interpreter {
                     self.generator = None;
                     if let Some(err) = self.state.borrow().error.clone() {
                         return Err(err);
-                    } else if self.state.borrow().stop_requested {
+                    } else if self.state.borrow().system_exit {
+                        return Ok(ExecutionResult::Exited);
+                    } else if self.state.borrow().stop_requested && !self.state.borrow().ended {
                         return Ok(ExecutionResult::Stopped);
                     } else {
                         return Ok(ExecutionResult::Completed);
@@ -601,13 +1320,71 @@ impl Interpreter {
         }
     }
 
-    /// Evaluate an expression (for immediate window)
-    pub fn eval_expr(&mut self, expr: &Expr) -> Result<Value, String> {
-        // Use a synchronous evaluation - create a minimal generator context
+    /// Run statements typed in the Immediate window against the current
+    /// variables, in the scope of the procedure the program is paused in.
+    /// Nothing is reset, so assignments stay for the rest of the run.
+    pub fn execute_immediate(&mut self, stmts: &Block) -> Result<(), String> {
         let state = self.state.clone();
+        let body = stmts.clone();
+        let (paused_line, depth) = {
+            let s = self.state.borrow();
+            (s.current_line, s.call_stack.len())
+        };
+        // Breakpoints in called procedures are passed over: the program is
+        // already paused
+        let mut gen = Gen::new(|co: Co<YieldReason>| async move {
+            execute_block(&co, &state, &body, &Block::default()).await
+        });
+        let result = loop {
+            match gen.resume() {
+                GeneratorState::Yielded(YieldReason::NeedsInput) if self.state.borrow().pending_input.is_some() => {
+                    self.state.borrow_mut().pending_input = None;
+                    break StmtResult::Error("INPUT is not supported in the Immediate window".to_string());
+                }
+                GeneratorState::Yielded(_) => {}
+                GeneratorState::Complete(result) => break result,
+            }
+        };
+        {
+            // An INPUT refused inside a SUB leaves its frame behind
+            let mut s = self.state.borrow_mut();
+            s.current_line = paused_line;
+            s.call_stack.truncate(depth);
+        }
+        match result {
+            StmtResult::Continue | StmtResult::End => Ok(()),
+            StmtResult::Error(e) => Err(e),
+            StmtResult::Jump(_) => Err("Label not defined".to_string()),
+            StmtResult::Exit(kind) => Err(misplaced_exit(kind)),
+            StmtResult::Resume(_) => Err("RESUME is not supported in the Immediate window".to_string()),
+        }
+    }
 
-        // We can't easily use async here, so do a simpler synchronous eval
-        eval_expr_sync(&state, expr)
+    /// Evaluate an expression (for immediate window), in the same scope as
+    /// `execute_immediate`
+    pub fn eval_expr(&mut self, expr: &Expr) -> Result<Value, String> {
+        let state = self.state.clone();
+        let expr = expr.clone();
+        let (paused_line, depth) = {
+            let s = self.state.borrow();
+            (s.current_line, s.call_stack.len())
+        };
+        // Breakpoints in called FUNCTIONs are passed over, as for statements
+        let mut gen = Gen::new(|co: Co<YieldReason>| async move { eval_expr_core(&co, &state, &expr, &Block::default()).await });
+        let result = loop {
+            match gen.resume() {
+                GeneratorState::Yielded(YieldReason::NeedsInput) if self.state.borrow().pending_input.is_some() => {
+                    self.state.borrow_mut().pending_input = None;
+                    break Err("INPUT is not supported in the Immediate window".to_string());
+                }
+                GeneratorState::Yielded(_) => {}
+                GeneratorState::Complete(result) => break result,
+            }
+        };
+        let mut s = self.state.borrow_mut();
+        s.current_line = paused_line;
+        s.call_stack.truncate(depth);
+        result
     }
 
 }
@@ -615,11 +1392,13 @@ impl Interpreter {
 /// Create a generator for program execution (standalone to avoid borrow issues)
 fn create_execution_generator(
     state: Rc<RefCell<InterpreterState>>,
-    program: Vec<Stmt>,
+    program: Block,
 ) -> Gen<YieldReason, (), impl std::future::Future<Output = ()>> {
     Gen::new(|co: Co<YieldReason>| async move {
         execute_program(&co, &state, &program).await;
-        state.borrow_mut().running = false;
+        let mut s = state.borrow_mut();
+        s.running = false;
+        s.files.close(None);
     })
 }
 
@@ -641,57 +1420,452 @@ fn eval_const_expr(expr: &Expr) -> Value {
     }
 }
 
-// For backwards compatibility with tests that access graphics directly
-impl std::ops::Deref for Interpreter {
-    type Target = Rc<RefCell<InterpreterState>>;
-    fn deref(&self) -> &Self::Target {
-        &self.state
+/// QBasic error number for a runtime error message, as reported by ERR
+fn error_code(message: &str) -> i64 {
+    const CODES: &[(&str, i64)] = &[
+        ("RETURN without GOSUB", 3),
+        ("Out of DATA", 4),
+        ("Illegal function call", 5),
+        ("Overflow", 6),
+        ("Label not found", 8),
+        ("Subscript out of range", 9),
+        ("Division by zero", 11),
+        ("Type mismatch", 13),
+        ("No RESUME", 19),
+        ("RESUME without error", 20),
+        ("Bad file number", 52),
+        ("File not found", 53),
+        ("Bad file mode", 54),
+        ("File already open", 55),
+        ("Device I/O error", 57),
+        ("Input past end of file", 62),
+        ("Permission denied", 70),
+        ("Path/File access error", 75),
+    ];
+    CODES
+        .iter()
+        .find(|(prefix, _)| message.starts_with(prefix))
+        .map(|&(_, code)| code)
+        .unwrap_or(51) // Internal error
+}
+
+/// Line number (ERL) of the nearest numbered line at or before source line
+/// `line`, inside blocks and procedures too, or 0
+fn line_number_at(program: &Block, line: usize) -> i64 {
+    fn nearest(block: &Block, line: usize) -> Option<(usize, i64)> {
+        block
+            .with_lines()
+            .filter(|&(_, stmt_line)| stmt_line <= line)
+            .filter_map(|(stmt, stmt_line)| match stmt {
+                Stmt::Label(n) => Some((stmt_line, *n)),
+                Stmt::If { then_branch, else_branch, .. } => {
+                    nearest(then_branch, line).max(else_branch.as_ref().and_then(|b| nearest(b, line)))
+                }
+                Stmt::For { body, .. } | Stmt::While { body, .. } | Stmt::DoLoop { body, .. } |
+                Stmt::Sub { body, .. } | Stmt::Function { body, .. } => nearest(body, line),
+                _ => None,
+            })
+            .max()
     }
+    nearest(program, line).map_or(0, |(_, n)| n)
 }
 
-/// Main program execution loop
-async fn execute_program(
-    co: &Co<YieldReason>,
-    state: &Rc<RefCell<InterpreterState>>,
-    program: &[Stmt],
-) {
-    let mut pos = 0;
+/// Element type of an array: the declared AS type, else the name's suffix
+fn element_type(name: &str, declared: Option<&VarType>) -> VarType {
+    if let Some(t) = declared {
+        return t.clone();
+    }
+    match name.chars().last() {
+        Some('$') => VarType::String,
+        Some('%') => VarType::Integer,
+        Some('&') => VarType::Long,
+        Some('#') => VarType::Double,
+        _ => VarType::Single,
+    }
+}
 
-    while pos < program.len() {
-        // Check stop request
-        if state.borrow().stop_requested {
-            return;
+/// The initial value of a variable declared with DIM or STATIC
+async fn dim_value(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, var: &DimVar, program: &Block) -> Result<Value, String> {
+    let elem_type = element_type(&var.name, var.var_type.as_ref());
+    let default = default_value(&state.borrow().types, &elem_type)?;
+    if var.dimensions.is_empty() {
+        if let Some(t) = &var.var_type {
+            state.borrow_mut().var_types.insert(var.name.clone(), t.clone());
         }
+        return Ok(default);
+    }
+    let bounds = eval_bounds(co, state, &var.dimensions, program).await?;
+    Ok(Value::Array(ArrayValue::new(bounds, elem_type, default)))
+}
 
-        // Update current line and check breakpoints
-        state.borrow_mut().current_line = pos;
-
-        let should_break = {
-            let s = state.borrow();
-            s.breakpoints.contains(&pos) || s.step_mode
+/// Evaluate DIM/REDIM dimension expressions into (lower, upper) bounds
+async fn eval_bounds(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, dims: &[(Option<Expr>, Expr)], program: &Block) -> Result<Vec<(i64, i64)>, String> {
+    let base = state.borrow().option_base;
+    let mut bounds = Vec::new();
+    for (lower, upper) in dims {
+        let lower = match lower {
+            Some(lower) => eval_expr_core(co, state, lower, program).await?.to_int(),
+            None => base,
         };
-
-        if should_break {
-            if state.borrow().breakpoints.contains(&pos) {
-                co.yield_(YieldReason::Breakpoint(pos)).await;
-            } else {
-                co.yield_(YieldReason::Stepped(pos)).await;
-            }
-            // Check if stop was requested during the yield
-            if state.borrow().stop_requested {
-                return;
-            }
+        let upper = eval_expr_core(co, state, upper, program).await?.to_int();
+        if upper < lower {
+            return Err("Subscript out of range".to_string());
+        }
+        bounds.push((lower, upper));
+    }
+    Ok(bounds)
+}
+
+/// Read an array element, auto-dimensioning undeclared arrays
+fn read_array_element(
+    state: &Rc<RefCell<InterpreterState>>,
+    name: &str,
+    indices: &[i64],
+) -> Result<Value, String> {
+    let mut s = state.borrow_mut();
+    s.array_mut(name, indices.len())?.get(indices).cloned()
+}
+
+/// Show a prompt and wait for the user to enter a line of input.
+/// Returns None if the program was stopped while waiting.
+async fn read_input_line(
+    co: &Co<YieldReason>,
+    state: &Rc<RefCell<InterpreterState>>,
+    prompt: &str,
+    vars: &[String],
+) -> Option<String> {
+    {
+        let mut s = state.borrow_mut();
+        if s.graphics.screen_output {
+            s.graphics.print_text(prompt, false);
+        } else if !prompt.is_empty() || s.output_open {
+            s.write_output(prompt, false);
+        }
+        s.pending_input = Some(PendingInput {
+            prompt: prompt.to_string(),
+            var_names: vars.to_vec(),
+        });
+        s.input_buffer.clear();
+        s.input_ready = false;
+    }
+
+    loop {
+        co.yield_(YieldReason::NeedsInput).await;
+
+        let (ready, stop) = {
+            let s = state.borrow();
+            (s.input_ready, s.stop_requested)
+        };
+
+        if stop {
+            return None;
+        }
+
+        if ready {
+            break;
+        }
+    }
+
+    let mut s = state.borrow_mut();
+    let input = std::mem::take(&mut s.input_buffer);
+    s.pending_input = None;
+    s.input_ready = false;
+    // Enter ends the prompt line
+    s.output_open = false;
+
+    // Echo input to graphics if active
+    if s.graphics.screen_output {
+        s.graphics.print_text(&input, true);
+    }
+    Some(input)
+}
+
+/// Convert one INPUT field to the variable's type, or None if it doesn't fit
+fn parse_input_field(var_type: &VarType, field: &str) -> Option<Value> {
+    if *var_type == VarType::String {
+        return Some(Value::String(field.to_string()));
+    }
+    let number = if field.is_empty() {
+        Value::Integer(0)
+    } else if let Ok(n) = field.parse::<i64>() {
+        Value::Integer(n)
+    } else {
+        Value::Float(field.parse::<f64>().ok()?)
+    };
+    let range = match var_type {
+        VarType::Integer => i16::MIN as f64..=i16::MAX as f64,
+        VarType::Long => i32::MIN as f64..=i32::MAX as f64,
+        _ => f64::MIN..=f64::MAX,
+    };
+    if !range.contains(&number.to_float().round()) {
+        return None;
+    }
+    coerce_value(number, var_type).ok()
+}
+
+/// Text PRINT shows for a value: numbers get a leading space for the sign
+/// when not negative and a trailing space
+fn print_value_text(value: &Value) -> String {
+    match value {
+        Value::Integer(_) | Value::Float(_) => {
+            let text = value.to_string();
+            if text.starts_with('-') {
+                format!("{} ", text)
+            } else {
+                format!(" {} ", text)
+            }
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// Build the text of a PRINT statement, returning it and whether the newline is suppressed.
+/// `start_col` is the 0-based column printing starts at; TAB past the current
+/// column continues on the next line.
+async fn format_print_items(
+    co: &Co<YieldReason>,
+    state: &Rc<RefCell<InterpreterState>>,
+    items: &[PrintItem],
+    start_col: usize,
+    program: &Block,
+) -> Result<(String, bool), String> {
+    let mut line = String::new();
+    let mut no_newline = false;
+    let column = |line: &String| match line.rfind('\n') {
+        Some(pos) => line[pos + 1..].chars().count(),
+        None => start_col + line.chars().count(),
+    };
+
+    for item in items {
+        match item {
+            PrintItem::Semicolon => no_newline = true,
+            PrintItem::Comma => {
+                // Tab to next 14-column zone
+                let spaces = 14 - (column(&line) % 14);
+                line.push_str(&" ".repeat(spaces));
+                no_newline = true;
+            }
+            PrintItem::Tab(expr) => {
+                let col = eval_expr_core(co, state, expr, program).await?.to_int().max(1) as usize - 1;
+                if column(&line) > col {
+                    line.push('\n');
+                }
+                let pad = col - column(&line);
+                line.push_str(&" ".repeat(pad));
+                no_newline = true;
+            }
+            PrintItem::Spc(expr) => {
+                let n = eval_expr_core(co, state, expr, program).await?.to_int().max(0) as usize;
+                line.push_str(&" ".repeat(n));
+                no_newline = true;
+            }
+            PrintItem::Expr(expr) => {
+                line.push_str(&print_value_text(&eval_expr_core(co, state, expr, program).await?));
+                no_newline = false;
+            }
+        }
+    }
+
+    Ok((line, no_newline))
+}
+
+/// Send a finished PRINT line to the graphics screen or the text output
+fn emit_print_line(state: &Rc<RefCell<InterpreterState>>, line: String, no_newline: bool) {
+    let mut s = state.borrow_mut();
+    if s.graphics.screen_output {
+        s.graphics.print_text(&line, !no_newline);
+    } else {
+        s.write_output(&line, !no_newline);
+    }
+}
+
+/// Default value for a variable declared AS the given type
+fn default_value(types: &HashMap<String, UserType>, var_type: &VarType) -> Result<Value, String> {
+    match var_type {
+        VarType::Integer | VarType::Long => Ok(Value::Integer(0)),
+        VarType::Single | VarType::Double => Ok(Value::Float(0.0)),
+        VarType::String => Ok(Value::String(String::new())),
+        VarType::FixedString(n) => Ok(Value::String(" ".repeat(*n))),
+        VarType::UserType(name) => {
+            let user_type = types
+                .get(&name.to_uppercase())
+                .ok_or_else(|| format!("Type not defined: {}", name))?;
+            let mut fields = Vec::new();
+            for (field, field_type) in &user_type.fields {
+                fields.push((field.clone(), default_value(types, field_type)?));
+            }
+            Ok(Value::Record(Record { type_name: user_type.name.clone(), fields }))
+        }
+    }
+}
+
+/// Coerce a value for assignment to a variable or field of the given type
+fn coerce_value(value: Value, var_type: &VarType) -> Result<Value, String> {
+    match (var_type, value) {
+        (VarType::Integer | VarType::Long, v @ (Value::Integer(_) | Value::Float(_))) => {
+            let n = v.to_float().round_ties_even();
+            let (min, max) = match var_type {
+                VarType::Integer => (i16::MIN as f64, i16::MAX as f64),
+                _ => (i32::MIN as f64, i32::MAX as f64),
+            };
+            if !(min..=max).contains(&n) {
+                return Err("Overflow".to_string());
+            }
+            Ok(Value::Integer(n as i64))
+        }
+        (VarType::Single | VarType::Double, v @ (Value::Integer(_) | Value::Float(_))) => {
+            Ok(Value::Float(v.to_float()))
+        }
+        (VarType::String, Value::String(s)) => Ok(Value::String(s)),
+        (VarType::FixedString(n), Value::String(s)) => {
+            let mut fixed: String = s.chars().take(*n).collect();
+            let len = fixed.chars().count();
+            fixed.push_str(&" ".repeat(n - len));
+            Ok(Value::String(fixed))
+        }
+        (VarType::UserType(name), Value::Record(r)) if r.type_name.eq_ignore_ascii_case(name) => {
+            Ok(Value::Record(r))
+        }
+        _ => Err("Type mismatch".to_string()),
+    }
+}
+
+/// Assign to a variable, array element or record field expression
+async fn assign_lvalue(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, target: &Expr, value: Value, program: &Block) -> Result<(), String> {
+    match target {
+        Expr::Variable(name) => state.borrow_mut().set_var(name, value),
+        Expr::ArrayAccess(name, indices) => {
+            let indices = eval_indices(co, state, indices, program).await?;
+            state.borrow_mut().set_element(name, &indices, value)
+        }
+        Expr::FieldAccess(name, indices, fields) => {
+            let indices = eval_indices(co, state, indices, program).await?;
+            state.borrow_mut().set_record_field(name, &indices, fields, value)
+        }
+        _ => Err("Expected variable".to_string()),
+    }
+}
+
+/// SWAP: exchange the values of two lvalues of compatible type
+async fn swap_lvalues(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, first: &Expr, second: &Expr, program: &Block) -> Result<(), String> {
+    let a = eval_expr_core(co, state, first, program).await?;
+    let b = eval_expr_core(co, state, second, program).await?;
+    let compatible = match (&a, &b) {
+        (Value::String(_), Value::String(_)) => true,
+        (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_)) => true,
+        (Value::Record(x), Value::Record(y)) => x.type_name.eq_ignore_ascii_case(&y.type_name),
+        _ => false,
+    };
+    if !compatible {
+        return Err("Type mismatch".to_string());
+    }
+    assign_lvalue(co, state, first, b, program).await?;
+    assign_lvalue(co, state, second, a, program).await
+}
+
+/// Read a field path out of a record
+fn get_field(record: &Record, fields: &[String]) -> Result<Value, String> {
+    let (field, rest) = fields.split_first().ok_or_else(|| "Type mismatch".to_string())?;
+    let value = record
+        .field(field)
+        .ok_or_else(|| format!("Element not defined: {}", field))?;
+    match (value, rest.is_empty()) {
+        (v, true) => Ok(v.clone()),
+        (Value::Record(inner), false) => get_field(inner, rest),
+        _ => Err("Type mismatch".to_string()),
+    }
+}
+
+/// Store a value at a field path inside a record, coercing to the field's declared type
+fn set_field(
+    types: &HashMap<String, UserType>,
+    record: &mut Record,
+    fields: &[String],
+    value: Value,
+) -> Result<(), String> {
+    let (field, rest) = fields.split_first().ok_or_else(|| "Type mismatch".to_string())?;
+    let field_type = types
+        .get(&record.type_name.to_uppercase())
+        .and_then(|t| t.fields.iter().find(|(n, _)| n.eq_ignore_ascii_case(field)))
+        .map(|(_, t)| t.clone())
+        .ok_or_else(|| format!("Element not defined: {}", field))?;
+    let slot = record
+        .field_mut(field)
+        .ok_or_else(|| format!("Element not defined: {}", field))?;
+    match (slot, rest.is_empty()) {
+        (slot, true) => {
+            *slot = coerce_value(value, &field_type)?;
+            Ok(())
+        }
+        (Value::Record(inner), false) => set_field(types, inner, rest, value),
+        _ => Err("Type mismatch".to_string()),
+    }
+}
+
+/// Evaluate record field access: name[(index)].field...
+fn read_field(
+    state: &Rc<RefCell<InterpreterState>>,
+    name: &str,
+    indices: &[i64],
+    fields: &[String],
+) -> Result<Value, String> {
+    let s = state.borrow();
+    let record = match s.var(name) {
+        Some(Value::Record(record)) if indices.is_empty() => record,
+        Some(Value::Array(arr)) if !indices.is_empty() => match arr.get(indices)? {
+            Value::Record(record) => record,
+            _ => return Err("Type mismatch".to_string()),
+        },
+        _ => return Err("Type mismatch".to_string()),
+    };
+    get_field(record, fields)
+}
+
+// For backwards compatibility with tests that access graphics directly
+impl std::ops::Deref for Interpreter {
+    type Target = Rc<RefCell<InterpreterState>>;
+    fn deref(&self) -> &Self::Target {
+        &self.state
+    }
+}
+
+/// Main program execution loop
+async fn execute_program(
+    co: &Co<YieldReason>,
+    state: &Rc<RefCell<InterpreterState>>,
+    program: &Block,
+) {
+    let mut pos = 0;
+
+    while pos < program.len() {
+        // Check stop request
+        if state.borrow().stop_requested {
+            return;
+        }
+
+        // Update current line and check breakpoints
+        if pause_for_debugger(co, state, program.line(pos), program).await {
+            return;
         }
 
         // Execute statement
-        let result = execute_stmt(co, state, &program[pos], program).await;
+        let result = run_stmt(co, state, &program[pos], program).await;
 
         match result {
             StmtResult::Continue => pos += 1,
             StmtResult::Jump(new_pos) => pos = new_pos,
             StmtResult::End => return,
-            StmtResult::Error(e) => {
-                state.borrow_mut().error = Some(e);
+            StmtResult::Error(_) | StmtResult::Exit(_) | StmtResult::Resume(_) => {
+                let e = match result {
+                    StmtResult::Exit(kind) => misplaced_exit(kind),
+                    StmtResult::Error(e) => e,
+                    _ => "RESUME without error".to_string(),
+                };
+                let mut s = state.borrow_mut();
+                if !s.stop_requested {
+                    s.error = Some(e);
+                    s.error_line = Some(s.current_line);
+                }
                 return;
             }
         }
@@ -710,6 +1884,187 @@ enum StmtResult {
     Jump(usize),
     End,
     Error(String),
+    /// EXIT statement unwinding to the nearest enclosing construct of this kind
+    Exit(ExitKind),
+    /// RESUME ending an error handler
+    Resume(Resume),
+}
+
+/// Run one statement. A runtime error it raises while an ON ERROR handler is
+/// set runs the handler, and the handler's RESUME decides what happens here:
+/// the statement runs again, the one after it is next, or a jump unwinds.
+async fn run_stmt(
+    co: &Co<YieldReason>,
+    state: &Rc<RefCell<InterpreterState>>,
+    stmt: &Stmt,
+    program: &Block,
+) -> StmtResult {
+    let line = state.borrow().current_line;
+    loop {
+        let e = match execute_stmt(co, state, stmt, program).await {
+            StmtResult::Error(e) => e,
+            other => return other,
+        };
+        let handler = {
+            let mut s = state.borrow_mut();
+            // Trap the error unless there is no handler or we're already handling one
+            match s.error_handler {
+                Some(handler) if !s.handling_error && !s.stop_requested => {
+                    s.err_code = error_code(&e);
+                    s.err_line = line_number_at(program, s.current_line);
+                    s.handling_error = true;
+                    handler
+                }
+                _ => return StmtResult::Error(e),
+            }
+        };
+        let resume = match run_error_handler(co, state, program, handler).await {
+            StmtResult::Resume(resume) => resume,
+            other => return other,
+        };
+        state.borrow_mut().current_line = line;
+        match resume {
+            Resume::Retry => {}
+            Resume::Next => return StmtResult::Continue,
+            Resume::At(pos) => return StmtResult::Jump(pos),
+        }
+    }
+}
+
+/// Run the ON ERROR handler at `pos` at module level, with the frames of the
+/// SUBs and FUNCTIONs being run set aside, until it reaches RESUME. END
+/// stops the program, and an error in the handler isn't trapped.
+#[async_recursion(?Send)]
+async fn run_error_handler(
+    co: &Co<YieldReason>,
+    state: &Rc<RefCell<InterpreterState>>,
+    program: &Block,
+    mut pos: usize,
+) -> StmtResult {
+    let frames = std::mem::take(&mut state.borrow_mut().call_stack);
+    let result = loop {
+        let Some(stmt) = program.get(pos) else {
+            break StmtResult::Error("No RESUME".to_string());
+        };
+        if state.borrow().stop_requested || pause_for_debugger(co, state, program.line(pos), program).await {
+            break StmtResult::End;
+        }
+        match execute_stmt(co, state, stmt, program).await {
+            StmtResult::Continue => pos += 1,
+            StmtResult::Jump(new_pos) => pos = new_pos,
+            other => break other,
+        }
+    };
+    let mut s = state.borrow_mut();
+    s.call_stack = frames;
+    if matches!(result, StmtResult::Resume(_)) {
+        s.handling_error = false;
+    }
+    result
+}
+
+/// Error that unwinds an expression whose FUNCTION call ran END or was
+/// stopped; it ends the program without being reported
+const PROGRAM_STOPPED: &str = "Program stopped";
+
+/// Error for an EXIT that reached the top level without finding its construct
+fn misplaced_exit(kind: ExitKind) -> String {
+    match kind {
+        ExitKind::For => "EXIT FOR not within FOR...NEXT",
+        ExitKind::Do => "EXIT DO not within DO...LOOP",
+        ExitKind::While => "EXIT WHILE not within WHILE...WEND",
+        ExitKind::Sub => "EXIT SUB not within SUB",
+        ExitKind::Function => "EXIT FUNCTION not within FUNCTION",
+    }
+    .to_string()
+}
+
+/// Make `line` the current line and stop there if it has a breakpoint or
+/// we're stepping. Returns whether the user stopped the program meanwhile.
+async fn pause_for_debugger(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, line: usize, program: &Block) -> bool {
+    let condition = {
+        let mut s = state.borrow_mut();
+        s.current_line = line;
+        s.breakpoint_conditions.get(&line).cloned()
+    };
+    // A condition that can't be evaluated counts as true, so the user sees why
+    let condition_met = match condition {
+        Some(condition) => eval_expr_core(co, state, &condition, program).await.map_or_else(
+            |e| {
+                state.borrow_mut().breakpoint_error = Some(e);
+                true
+            },
+            |value| value.is_truthy(),
+        ),
+        None => true,
+    };
+    let reason = {
+        let s = state.borrow();
+        if s.breakpoints.contains(&line) && condition_met {
+            YieldReason::Breakpoint(line)
+        } else if s.step_mode && s.step_depth.is_none_or(|depth| s.call_stack.len() <= depth) {
+            YieldReason::Stepped(line)
+        } else {
+            return false;
+        }
+    };
+    co.yield_(reason).await;
+    state.borrow().stop_requested
+}
+
+/// Run a SUB or FUNCTION body in the frame its caller pushed. EXIT SUB or
+/// EXIT FUNCTION ends it early; jumps, END and errors go back to the caller.
+async fn execute_procedure(
+    co: &Co<YieldReason>,
+    state: &Rc<RefCell<InterpreterState>>,
+    procedure: &Procedure,
+    program: &Block,
+) -> StmtResult {
+    for (stmt, line) in procedure.body.with_lines() {
+        if state.borrow().stop_requested {
+            return StmtResult::End;
+        }
+        if pause_for_debugger(co, state, line, program).await {
+            return StmtResult::End;
+        }
+        match run_stmt(co, state, stmt, program).await {
+            StmtResult::Continue => {}
+            StmtResult::Exit(ExitKind::Sub | ExitKind::Function) => break,
+            StmtResult::Exit(kind) => return StmtResult::Error(misplaced_exit(kind)),
+            other => return other,
+        }
+    }
+    StmtResult::Continue
+}
+
+/// Run the statements of an IF branch or loop body, making each one's line
+/// the current line and stopping there for breakpoints and steps. Unless
+/// one fails, the line of the statement holding the block is current again
+/// afterwards.
+async fn execute_block(
+    co: &Co<YieldReason>,
+    state: &Rc<RefCell<InterpreterState>>,
+    body: &Block,
+    program: &Block,
+) -> StmtResult {
+    let outer_line = state.borrow().current_line;
+    for (stmt, line) in body.with_lines() {
+        if state.borrow().stop_requested {
+            return StmtResult::End;
+        }
+        if pause_for_debugger(co, state, line, program).await {
+            return StmtResult::End;
+        }
+        let result = run_stmt(co, state, stmt, program).await;
+        if !matches!(result, StmtResult::Continue) {
+            if !matches!(result, StmtResult::Error(_)) {
+                state.borrow_mut().current_line = outer_line;
+            }
+            return result;
+        }
+    }
+    state.borrow_mut().current_line = outer_line;
+    StmtResult::Continue
 }
 
 /// Execute a single statement
@@ -718,20 +2073,17 @@ async fn execute_stmt(
     co: &Co<YieldReason>,
     state: &Rc<RefCell<InterpreterState>>,
     stmt: &Stmt,
-    program: &[Stmt],
+    program: &Block,
 ) -> StmtResult {
     match stmt {
         Stmt::Empty | Stmt::Label(_) | Stmt::TextLabel(_) | Stmt::Data(_) | Stmt::Rem(_) |
-        Stmt::Sub { .. } | Stmt::Function { .. } => {
+        Stmt::Sub { .. } | Stmt::Function { .. } | Stmt::DefFn { .. } | Stmt::TypeDef { .. } | Stmt::Declare { .. } => {
             StmtResult::Continue
         }
 
         Stmt::Let(name, value) => {
-            match eval_expr_core(state, value) {
-                Ok(v) => {
-                    state.borrow_mut().variables.insert(name.clone(), v);
-                    StmtResult::Continue
-                }
+            match eval_expr_core(co, state, value, program).await.and_then(|v| state.borrow_mut().set_var(name, v)) {
+                Ok(()) => StmtResult::Continue,
                 Err(e) => StmtResult::Error(e),
             }
         }
@@ -740,7 +2092,7 @@ async fn execute_stmt(
             let idx_values: Result<Vec<i64>, String> = {
                 let mut results = Vec::new();
                 for idx in indices {
-                    match eval_expr_core(state, idx) {
+                    match eval_expr_core(co, state, idx, program).await {
                         Ok(v) => results.push(v.to_int()),
                         Err(e) => return StmtResult::Error(e),
                     }
@@ -748,219 +2100,242 @@ async fn execute_stmt(
                 Ok(results)
             };
 
-            match idx_values {
-                Ok(indices) => {
-                    match eval_expr_core(state, value) {
-                        Ok(val) => {
-                            let mut s = state.borrow_mut();
-                            if indices.len() == 1 {
-                                let idx = indices[0] as usize;
-                                if let Some(arr) = s.variables.get_mut(name) {
-                                    match (arr, &val) {
-                                        (Value::IntArray(ref mut a), Value::Integer(v)) => {
-                                            if idx < a.len() { a[idx] = *v; }
-                                        }
-                                        (Value::IntArray(ref mut a), Value::Float(v)) => {
-                                            if idx < a.len() { a[idx] = *v as i64; }
-                                        }
-                                        (Value::FloatArray(ref mut a), Value::Float(v)) => {
-                                            if idx < a.len() { a[idx] = *v; }
-                                        }
-                                        (Value::FloatArray(ref mut a), Value::Integer(v)) => {
-                                            if idx < a.len() { a[idx] = *v as f64; }
-                                        }
-                                        (Value::StringArray(ref mut a), Value::String(v)) => {
-                                            if idx < a.len() { a[idx] = v.clone(); }
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                            }
-                            StmtResult::Continue
-                        }
-                        Err(e) => StmtResult::Error(e),
-                    }
-                }
+            let result = match idx_values {
+                Ok(indices) => match eval_expr_core(co, state, value, program).await {
+                    Ok(val) => state.borrow_mut().set_element(name, &indices, val),
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => StmtResult::Continue,
                 Err(e) => StmtResult::Error(e),
             }
         }
 
-        Stmt::Print(items) => {
-            let mut line = String::new();
-            let mut no_newline = false;
-
-            for item in items {
-                match item {
-                    PrintItem::Semicolon => no_newline = true,
-                    PrintItem::Comma => {
-                        // Tab to next 14-column zone
-                        let spaces = 14 - (line.len() % 14);
-                        line.push_str(&" ".repeat(spaces));
-                        no_newline = true;
-                    }
-                    PrintItem::Tab(expr) => {
-                        let col = match eval_expr_core(state, expr) {
-                            Ok(v) => v.to_int().max(1) as usize - 1,
-                            Err(e) => return StmtResult::Error(e),
-                        };
-                        while line.len() < col {
-                            line.push(' ');
-                        }
-                        no_newline = true;
-                    }
-                    PrintItem::Spc(expr) => {
-                        let n = match eval_expr_core(state, expr) {
-                            Ok(v) => v.to_int().max(0) as usize,
-                            Err(e) => return StmtResult::Error(e),
-                        };
-                        line.push_str(&" ".repeat(n));
-                        no_newline = true;
-                    }
-                    PrintItem::Expr(expr) => {
-                        match eval_expr_core(state, expr) {
-                            Ok(v) => {
-                                line.push_str(&v.to_string());
-                                no_newline = false;
-                            }
-                            Err(e) => return StmtResult::Error(e),
-                        }
-                    }
+        Stmt::FieldLet(name, indices, fields, value) => {
+            let mut idx_values = Vec::new();
+            for idx in indices {
+                match eval_expr_core(co, state, idx, program).await {
+                    Ok(v) => idx_values.push(v.to_int()),
+                    Err(e) => return StmtResult::Error(e),
                 }
             }
+            let result = eval_expr_core(co, state, value, program).await
+                .and_then(|val| state.borrow_mut().set_record_field(name, &idx_values, fields, val));
+            match result {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
+            }
+        }
 
-            // Handle graphics mode printing
-            {
+        Stmt::Const(constants) => {
+            for (name, expr) in constants {
+                // Without a suffix the constant takes the type of its value
+                let value = eval_expr_core(co, state, expr, program).await.and_then(|v| {
+                    if name.ends_with(['$', '%', '&', '!', '#']) {
+                        coerce_value(v, &element_type(name, None))
+                    } else {
+                        Ok(v)
+                    }
+                });
                 let mut s = state.borrow_mut();
-                if s.graphics.mode > 0 {
-                    s.graphics.print_text(&line, !no_newline);
-                } else {
-                    s.output_buffer.push(line);
+                match value {
+                    Ok(_) if s.is_constant(name) => return StmtResult::Error("Duplicate definition".to_string()),
+                    Ok(v) => {
+                        s.constants.insert(name.to_uppercase(), v);
+                    }
+                    Err(e) => return StmtResult::Error(e),
                 }
             }
+            StmtResult::Continue
+        }
+
+        Stmt::Swap(first, second) => match swap_lvalues(co, state, first, second, program).await {
+            Ok(()) => StmtResult::Continue,
+            Err(e) => StmtResult::Error(e),
+        },
 
+        Stmt::Print(items) => {
+            let start_col = state.borrow().print_column();
+            let (line, no_newline) = match format_print_items(co, state, items, start_col, program).await {
+                Ok(r) => r,
+                Err(e) => return StmtResult::Error(e),
+            };
+            emit_print_line(state, line, no_newline);
             StmtResult::Continue
         }
 
-        Stmt::Input(prompt, vars) => {
-            // Output prompt
-            if let Some(p) = prompt {
-                let mut s = state.borrow_mut();
-                if s.graphics.mode > 0 {
-                    s.graphics.print_text(p, false);
-                    s.graphics.print_text("? ", false);
-                } else {
-                    s.output_buffer.push(format!("{}? ", p));
+        Stmt::PrintUsing(format, values, no_newline) => {
+            let format = match eval_expr_core(co, state, format, program).await {
+                Ok(Value::String(f)) => f,
+                Ok(_) => return StmtResult::Error("Type mismatch".to_string()),
+                Err(e) => return StmtResult::Error(e),
+            };
+            let mut args = Vec::new();
+            for value in values {
+                match eval_expr_core(co, state, value, program).await {
+                    Ok(v) => args.push(v),
+                    Err(e) => return StmtResult::Error(e),
                 }
-            } else {
-                let mut s = state.borrow_mut();
-                if s.graphics.mode > 0 {
-                    s.graphics.print_text("? ", false);
-                } else {
-                    s.output_buffer.push("? ".to_string());
+            }
+            match format_using(&format, &args) {
+                Ok(line) => {
+                    emit_print_line(state, line, *no_newline);
+                    StmtResult::Continue
                 }
+                Err(e) => StmtResult::Error(e),
             }
+        }
 
-            // Set up pending input
-            {
-                let mut s = state.borrow_mut();
-                s.pending_input = Some(PendingInput {
-                    prompt: prompt.clone().unwrap_or_default(),
-                    var_names: vars.clone(),
-                });
-                s.input_buffer.clear();
-                s.input_ready = false;
+        Stmt::Input { prompt, question_mark, vars } => {
+            let mut prompt_text = prompt.clone().unwrap_or_default();
+            if *question_mark {
+                prompt_text.push_str("? ");
             }
 
-            // Wait for input
             loop {
-                co.yield_(YieldReason::NeedsInput).await;
+                let input = match read_input_line(co, state, &prompt_text, vars).await {
+                    Some(input) => input,
+                    None => return StmtResult::End,
+                };
 
-                let (ready, stop) = {
+                // Split on commas and coerce each field; mismatches re-prompt
+                let fields = split_input_fields(&input);
+                let values: Option<Vec<Value>> = if fields.len() == vars.len() {
                     let s = state.borrow();
-                    (s.input_ready, s.stop_requested)
+                    vars.iter()
+                        .zip(&fields)
+                        .map(|(var, field)| parse_input_field(&s.var_type(var), field))
+                        .collect()
+                } else {
+                    None
                 };
 
-                if stop {
-                    return StmtResult::End;
-                }
-
-                if ready {
-                    break;
-                }
-            }
-
-            // Process input
-            {
-                let mut s = state.borrow_mut();
-                let input = s.input_buffer.clone();
-                s.input_buffer.clear();
-                s.pending_input = None;
-
-                // Echo input to graphics if active
-                if s.graphics.mode > 0 {
-                    s.graphics.print_text(&input, true);
-                }
-
-                // Split input by commas for multiple variables
-                let parts: Vec<&str> = input.split(',').collect();
-                for (i, var) in vars.iter().enumerate() {
-                    let part = parts.get(i).map(|s| s.trim()).unwrap_or("");
-                    let is_numeric = !var.ends_with('$');
-                    let value = if is_numeric {
-                        if let Ok(n) = part.parse::<i64>() {
-                            Value::Integer(n)
-                        } else if let Ok(f) = part.parse::<f64>() {
-                            Value::Float(f)
-                        } else {
-                            Value::Integer(0)
+                match values {
+                    Some(values) => {
+                        let mut s = state.borrow_mut();
+                        for (var, value) in vars.iter().zip(values) {
+                            if let Err(e) = s.set_var(var, value) {
+                                return StmtResult::Error(e);
+                            }
                         }
-                    } else {
-                        Value::String(part.to_string())
-                    };
-                    s.variables.insert(var.clone(), value);
+                        break;
+                    }
+                    None => emit_print_line(state, "Redo from start".to_string(), false),
                 }
             }
 
             StmtResult::Continue
         }
 
-        Stmt::If { condition, then_branch, else_branch } => {
-            match eval_expr_core(state, condition) {
-                Ok(cond) => {
-                    if cond.is_truthy() {
-                        for stmt in then_branch {
-                            let result = execute_stmt(co, state, stmt, program).await;
-                            match result {
-                                StmtResult::Continue => {}
-                                other => return other,
-                            }
-                        }
-                    } else if let Some(else_stmts) = else_branch {
-                        for stmt in else_stmts {
-                            let result = execute_stmt(co, state, stmt, program).await;
-                            match result {
-                                StmtResult::Continue => {}
-                                other => return other,
-                            }
-                        }
-                    }
-                    StmtResult::Continue
+        Stmt::LineInput(prompt, var) => {
+            let prompt = prompt.clone().unwrap_or_default();
+            match read_input_line(co, state, &prompt, std::slice::from_ref(var)).await {
+                Some(input) => match state.borrow_mut().set_var(var, Value::String(input)) {
+                    Ok(()) => StmtResult::Continue,
+                    Err(e) => StmtResult::Error(e),
+                },
+                None => StmtResult::End,
+            }
+        }
+
+        Stmt::Open { path, mode, file_num } => {
+            let result = match (eval_expr_core(co, state, path, program).await, eval_expr_core(co, state, file_num, program).await) {
+                (Ok(path), Ok(number)) => state.borrow_mut().files.open(&path.to_string(), *mode, number.to_int()),
+                (Err(e), _) | (_, Err(e)) => Err(e),
+            };
+            match result {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
+            }
+        }
+
+        Stmt::Close(file_nums) => {
+            if file_nums.is_empty() {
+                state.borrow_mut().files.close(None);
+            }
+            for file_num in file_nums {
+                match eval_expr_core(co, state, file_num, program).await {
+                    Ok(n) => state.borrow_mut().files.close(Some(n.to_int())),
+                    Err(e) => return StmtResult::Error(e),
+                }
+            }
+            StmtResult::Continue
+        }
+
+        Stmt::PrintFile(file_num, items) => {
+            let result = match (eval_expr_core(co, state, file_num, program).await, format_print_items(co, state, items, 0, program).await) {
+                (Ok(number), Ok((mut line, no_newline))) => {
+                    if !no_newline {
+                        line.push('\n');
+                    }
+                    state.borrow_mut().files.write(number.to_int(), &line)
+                }
+                (Err(e), _) | (_, Err(e)) => Err(e),
+            };
+            match result {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
+            }
+        }
+
+        Stmt::InputFile(file_num, vars) => {
+            let number = match eval_expr_core(co, state, file_num, program).await {
+                Ok(n) => n.to_int(),
+                Err(e) => return StmtResult::Error(e),
+            };
+            let mut s = state.borrow_mut();
+            for var in vars {
+                let var_type = s.var_type(var);
+                let value = s.files.read_field(number).and_then(|field| {
+                    parse_input_field(&var_type, &field).ok_or_else(|| "Type mismatch".to_string())
+                });
+                if let Err(e) = value.and_then(|v| s.set_var(var, v)) {
+                    return StmtResult::Error(e);
+                }
+            }
+            StmtResult::Continue
+        }
+
+        Stmt::LineInputFile(file_num, var) => {
+            let result = eval_expr_core(co, state, file_num, program).await
+                .and_then(|number| state.borrow_mut().files.read_line(number.to_int()));
+            match result {
+                Ok(line) => match state.borrow_mut().set_var(var, Value::String(line)) {
+                    Ok(()) => StmtResult::Continue,
+                    Err(e) => StmtResult::Error(e),
+                },
+                Err(e) => StmtResult::Error(e),
+            }
+        }
+
+        Stmt::If { condition, then_branch, else_branch } => {
+            match eval_expr_core(co, state, condition, program).await {
+                Ok(cond) => {
+                    if cond.is_truthy() {
+                        execute_block(co, state, then_branch, program).await
+                    } else if let Some(else_stmts) = else_branch {
+                        execute_block(co, state, else_stmts, program).await
+                    } else {
+                        StmtResult::Continue
+                    }
                 }
                 Err(e) => StmtResult::Error(e),
             }
         }
 
         Stmt::For { var, start, end, step, body } => {
-            let start_val = match eval_expr_core(state, start) {
+            let start_val = match eval_expr_core(co, state, start, program).await {
                 Ok(v) => v.to_float(),
                 Err(e) => return StmtResult::Error(e),
             };
-            let end_val = match eval_expr_core(state, end) {
+            let end_val = match eval_expr_core(co, state, end, program).await {
                 Ok(v) => v.to_float(),
                 Err(e) => return StmtResult::Error(e),
             };
             let step_val = match step {
-                Some(s) => match eval_expr_core(state, s) {
+                Some(s) => match eval_expr_core(co, state, s, program).await {
                     Ok(v) => v.to_float(),
                     Err(e) => return StmtResult::Error(e),
                 },
@@ -982,25 +2357,19 @@ async fn execute_stmt(
                 }
 
                 // Set loop variable
-                state.borrow_mut().variables.insert(var.clone(), Value::Float(current));
+                if let Err(e) = state.borrow_mut().set_var(var, Value::Float(current)) {
+                    return StmtResult::Error(e);
+                }
 
                 // Execute body
-                for stmt in body {
-                    if state.borrow().stop_requested {
-                        return StmtResult::End;
-                    }
-
-                    let result = execute_stmt(co, state, stmt, program).await;
-                    match result {
-                        StmtResult::Continue => {}
-                        StmtResult::Jump(pos) => return StmtResult::Jump(pos),
-                        StmtResult::End => return StmtResult::End,
-                        StmtResult::Error(e) => return StmtResult::Error(e),
-                    }
+                match execute_block(co, state, body, program).await {
+                    StmtResult::Continue => {}
+                    StmtResult::Exit(ExitKind::For) => return StmtResult::Continue,
+                    other => return other,
                 }
 
-                // Increment
-                current += step_val;
+                // Increment, picking up any change the body made to the variable
+                current = state.borrow().var(var).map_or(current, Value::to_float) + step_val;
 
                 // Periodic yield for UI updates
                 if state.borrow().should_yield_for_ui() {
@@ -1010,14 +2379,16 @@ async fn execute_stmt(
             }
 
             // Set final value
-            state.borrow_mut().variables.insert(var.clone(), Value::Float(current));
-            StmtResult::Continue
+            match state.borrow_mut().set_var(var, Value::Float(current)) {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
+            }
         }
 
         Stmt::While { condition, body } => {
             loop {
                 // Check condition
-                let cond = match eval_expr_core(state, condition) {
+                let cond = match eval_expr_core(co, state, condition, program).await {
                     Ok(v) => v.is_truthy(),
                     Err(e) => return StmtResult::Error(e),
                 };
@@ -1027,18 +2398,10 @@ async fn execute_stmt(
                 }
 
                 // Execute body
-                for stmt in body {
-                    if state.borrow().stop_requested {
-                        return StmtResult::End;
-                    }
-
-                    let result = execute_stmt(co, state, stmt, program).await;
-                    match result {
-                        StmtResult::Continue => {}
-                        StmtResult::Jump(pos) => return StmtResult::Jump(pos),
-                        StmtResult::End => return StmtResult::End,
-                        StmtResult::Error(e) => return StmtResult::Error(e),
-                    }
+                match execute_block(co, state, body, program).await {
+                    StmtResult::Continue => {}
+                    StmtResult::Exit(ExitKind::While) => return StmtResult::Continue,
+                    other => return other,
                 }
 
                 // Periodic yield
@@ -1056,7 +2419,7 @@ async fn execute_stmt(
                 // Check condition at start (if pre-test)
                 if *is_pre_test {
                     if let Some(cond_expr) = condition {
-                        let cond = match eval_expr_core(state, cond_expr) {
+                        let cond = match eval_expr_core(co, state, cond_expr, program).await {
                             Ok(v) => v.is_truthy(),
                             Err(e) => return StmtResult::Error(e),
                         };
@@ -1070,24 +2433,16 @@ async fn execute_stmt(
                 }
 
                 // Execute body
-                for stmt in body {
-                    if state.borrow().stop_requested {
-                        return StmtResult::End;
-                    }
-
-                    let result = execute_stmt(co, state, stmt, program).await;
-                    match result {
-                        StmtResult::Continue => {}
-                        StmtResult::Jump(pos) => return StmtResult::Jump(pos),
-                        StmtResult::End => return StmtResult::End,
-                        StmtResult::Error(e) => return StmtResult::Error(e),
-                    }
+                match execute_block(co, state, body, program).await {
+                    StmtResult::Continue => {}
+                    StmtResult::Exit(ExitKind::Do) => return StmtResult::Continue,
+                    other => return other,
                 }
 
                 // Check condition at end (if post-test)
                 if !*is_pre_test {
                     if let Some(cond_expr) = condition {
-                        let cond = match eval_expr_core(state, cond_expr) {
+                        let cond = match eval_expr_core(co, state, cond_expr, program).await {
                             Ok(v) => v.is_truthy(),
                             Err(e) => return StmtResult::Error(e),
                         };
@@ -1127,6 +2482,46 @@ async fn execute_stmt(
             }
         }
 
+        Stmt::Exit(kind) => StmtResult::Exit(*kind),
+
+        Stmt::DefType(var_type, ranges) => {
+            let mut s = state.borrow_mut();
+            for &(first, last) in ranges {
+                for letter in first..=last {
+                    s.def_types.insert(letter, var_type.clone());
+                }
+            }
+            StmtResult::Continue
+        }
+
+        Stmt::OnErrorGoTo(label) => {
+            let mut s = state.borrow_mut();
+            match label {
+                Some(label) => match s.labels.get(label).copied() {
+                    Some(pos) => s.error_handler = Some(pos),
+                    None => return StmtResult::Error(format!("Label not found: {}", label)),
+                },
+                None => s.error_handler = None,
+            }
+            StmtResult::Continue
+        }
+
+        Stmt::Resume | Stmt::ResumeNext | Stmt::ResumeLabel(_) => {
+            let s = state.borrow();
+            if !s.handling_error {
+                return StmtResult::Error("RESUME without error".to_string());
+            }
+            match stmt {
+                Stmt::Resume => StmtResult::Resume(Resume::Retry),
+                Stmt::ResumeNext => StmtResult::Resume(Resume::Next),
+                Stmt::ResumeLabel(label) => match s.labels.get(label).copied() {
+                    Some(pos) => StmtResult::Resume(Resume::At(pos)),
+                    None => StmtResult::Error(format!("Label not found: {}", label)),
+                },
+                _ => unreachable!(),
+            }
+        }
+
         Stmt::GoSub(line) => {
             let label = line.to_string();
             let (target, current) = {
@@ -1173,99 +2568,160 @@ async fn execute_stmt(
             StmtResult::Continue
         }
 
-        Stmt::Dim(dim_vars) => {
-            for dim_var in dim_vars {
-                let DimVar { name, dimensions, var_type: _ } = dim_var;
-                let sizes: Result<Vec<usize>, String> = {
-                    let mut results = Vec::new();
-                    for dim in dimensions {
-                        match eval_expr_core(state, dim) {
-                            Ok(v) => results.push((v.to_int() + 1) as usize), // BASIC arrays are 0 to N
-                            Err(e) => return StmtResult::Error(e),
-                        }
-                    }
-                    Ok(results)
+        Stmt::Dim { shared, vars } => {
+            for var in vars {
+                let name = &var.name;
+                if state.borrow().is_constant(name) {
+                    return StmtResult::Error("Duplicate definition".to_string());
+                }
+                let value = match dim_value(co, state, var, program).await {
+                    Ok(v) => v,
+                    Err(e) => return StmtResult::Error(e),
                 };
+                let mut s = state.borrow_mut();
+                if *shared {
+                    s.shared_globals.insert(name.clone());
+                }
+                s.new_var_scope(name).insert(name.clone(), value);
+            }
+            StmtResult::Continue
+        }
 
-                match sizes {
-                    Ok(dims) => {
-                        let size = dims.iter().product();
-                        let is_string = name.ends_with('$');
-
-                        let array = if is_string {
-                            Value::StringArray(vec![String::new(); size])
-                        } else if name.ends_with('%') {
-                            Value::IntArray(vec![0; size])
-                        } else {
-                            Value::FloatArray(vec![0.0; size])
-                        };
-
-                        state.borrow_mut().variables.insert(name.clone(), array);
+        Stmt::Static(vars) => {
+            for var in vars {
+                if state.borrow().is_constant(&var.name) {
+                    return StmtResult::Error("Duplicate definition".to_string());
+                }
+                // The variable is created once, in the procedure's STATIC
+                // table, and every later call uses it from there
+                let (key, exists) = {
+                    let mut s = state.borrow_mut();
+                    let s = &mut *s;
+                    let key = s.call_stack.last().and_then(|frame| frame.procedure.clone());
+                    let exists = key
+                        .as_ref()
+                        .and_then(|key| s.static_vars.get(key))
+                        .is_some_and(|table| table.contains_key(&var.name));
+                    if let (Some(frame), true) = (s.call_stack.last_mut(), exists) {
+                        frame.statics.insert(var.name.clone());
+                        frame.vars.remove(&var.name);
                     }
+                    (key, exists)
+                };
+                if exists {
+                    continue;
+                }
+                let value = match dim_value(co, state, var, program).await {
+                    Ok(v) => v,
                     Err(e) => return StmtResult::Error(e),
+                };
+                let mut s = state.borrow_mut();
+                match (key, s.call_stack.last_mut()) {
+                    (Some(key), Some(frame)) => {
+                        frame.statics.insert(var.name.clone());
+                        frame.vars.remove(&var.name);
+                        s.static_vars.entry(key).or_default().insert(var.name.clone(), value);
+                    }
+                    (None, Some(frame)) => {
+                        frame.vars.insert(var.name.clone(), value);
+                    }
+                    (_, None) => {
+                        s.variables.insert(var.name.clone(), value);
+                    }
                 }
             }
             StmtResult::Continue
         }
 
-        Stmt::Call(name, args) => {
-            // Evaluate arguments
-            let arg_values: Result<Vec<Value>, String> = {
-                let mut results = Vec::new();
-                for arg in args {
-                    match eval_expr_core(state, arg) {
-                        Ok(v) => results.push(v),
-                        Err(e) => return StmtResult::Error(e),
+        Stmt::Shared(names) => {
+            let mut s = state.borrow_mut();
+            match s.call_stack.last_mut() {
+                Some(frame) => frame.shared.extend(names.iter().cloned()),
+                None => s.shared_globals.extend(names.iter().cloned()),
+            }
+            StmtResult::Continue
+        }
+
+        Stmt::ReDim { preserve, vars } => {
+            for DimVar { name, dimensions, var_type } in vars {
+                let bounds = match eval_bounds(co, state, dimensions, program).await {
+                    Ok(b) => b,
+                    Err(e) => return StmtResult::Error(e),
+                };
+                let mut guard = state.borrow_mut();
+                let s = &mut *guard;
+                let existing_type = match s.var(name) {
+                    Some(Value::Array(arr)) => Some(arr.elem_type.clone()),
+                    _ => None,
+                };
+                let elem_type = element_type(name, var_type.as_ref().or(existing_type.as_ref()));
+                let default = match default_value(&s.types, &elem_type) {
+                    Ok(v) => v,
+                    Err(e) => return StmtResult::Error(e),
+                };
+                match s.var_mut(name) {
+                    Some(Value::Array(arr)) if *preserve => {
+                        if arr.bounds.len() != bounds.len() {
+                            return StmtResult::Error("Wrong number of dimensions".to_string());
+                        }
+                        arr.resize_preserve(bounds, default);
+                    }
+                    Some(slot) => *slot = Value::Array(ArrayValue::new(bounds, elem_type, default)),
+                    None => {
+                        s.new_var_scope(name).insert(name.clone(), Value::Array(ArrayValue::new(bounds, elem_type, default)));
                     }
                 }
-                Ok(results)
-            };
+            }
+            StmtResult::Continue
+        }
 
-            let arg_values = match arg_values {
-                Ok(v) => v,
-                Err(e) => return StmtResult::Error(e),
-            };
+        Stmt::OptionBase(base) => {
+            state.borrow_mut().option_base = *base;
+            StmtResult::Continue
+        }
 
-            // Look up procedure
+        Stmt::Call(name, args) => {
             let proc = state.borrow().procedures.get(&name.to_uppercase()).cloned();
-
             match proc {
                 Some(procedure) => {
-                    // Set up local scope with parameters
-                    let mut local_scope = HashMap::new();
-                    for (param, value) in procedure.params.iter().zip(arg_values) {
-                        local_scope.insert(param.clone(), value);
-                    }
-
-                    // Push scope
-                    state.borrow_mut().call_stack.push(local_scope);
-
-                    // Execute procedure body
-                    for stmt in &procedure.body {
-                        if state.borrow().stop_requested {
-                            state.borrow_mut().call_stack.pop();
-                            return StmtResult::End;
-                        }
-
-                        let result = execute_stmt(co, state, stmt, program).await;
-                        match result {
-                            StmtResult::Continue => {}
-                            other => {
-                                state.borrow_mut().call_stack.pop();
-                                return other;
-                            }
-                        }
+                    let frame = match bind_arguments(co, state, &procedure, args, program).await {
+                        Ok(frame) => frame,
+                        Err(e) => return StmtResult::Error(e),
+                    };
+                    state.borrow_mut().enter_procedure(&procedure, frame);
+                    let result = execute_procedure(co, state, &procedure, program).await;
+                    let frame = state.borrow_mut().leave_procedure();
+                    if !matches!(result, StmtResult::Error(_)) {
+                        state.borrow_mut().current_line = frame.call_line;
                     }
-
-                    // Pop scope
-                    state.borrow_mut().call_stack.pop();
-                    StmtResult::Continue
+                    result
                 }
                 None => StmtResult::Error(format!("SUB not found: {}", name)),
             }
         }
 
-        Stmt::End | Stmt::Stop => StmtResult::End,
+        Stmt::End => {
+            state.borrow_mut().ended = true;
+            StmtResult::End
+        }
+
+        Stmt::System => {
+            let mut s = state.borrow_mut();
+            s.ended = true;
+            s.system_exit = true;
+            StmtResult::End
+        }
+
+        Stmt::Stop => {
+            // Pause like a breakpoint; continuing picks up after the STOP
+            let line = state.borrow().current_line;
+            co.yield_(YieldReason::Breakpoint(line)).await;
+            if state.borrow().stop_requested {
+                StmtResult::End
+            } else {
+                StmtResult::Continue
+            }
+        }
 
         Stmt::Cls => {
             state.borrow_mut().graphics.cls();
@@ -1273,23 +2729,41 @@ async fn execute_stmt(
         }
 
         Stmt::Screen(mode) => {
-            let mode_val = match eval_expr_core(state, mode) {
+            let mode_val = match eval_expr_core(co, state, mode, program).await {
                 Ok(v) => v.to_int() as u8,
                 Err(e) => return StmtResult::Error(e),
             };
 
-            state.borrow_mut().graphics.set_mode(mode_val);
-            StmtResult::Continue
+            match state.borrow_mut().graphics.set_screen(mode_val) {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
+            }
+        }
+
+        Stmt::Width(cols, rows) => {
+            let mut size = [None, None];
+            for (value, expr) in size.iter_mut().zip([cols, rows]) {
+                if let Some(expr) = expr {
+                    match eval_expr_core(co, state, expr, program).await {
+                        Ok(v) => *value = Some(v.to_int().clamp(0, u16::MAX as i64) as u16),
+                        Err(e) => return StmtResult::Error(e),
+                    }
+                }
+            }
+            match state.borrow_mut().graphics.set_text_size(size[0], size[1]) {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
+            }
         }
 
         Stmt::Color(fg, bg) => {
-            let fg_val = match eval_expr_core(state, fg) {
+            let fg_val = match eval_expr_core(co, state, fg, program).await {
                 Ok(v) => v.to_int() as u8,
                 Err(e) => return StmtResult::Error(e),
             };
 
             let bg_val = if let Some(bg_expr) = bg {
-                match eval_expr_core(state, bg_expr) {
+                match eval_expr_core(co, state, bg_expr, program).await {
                     Ok(v) => v.to_int() as u8,
                     Err(e) => return StmtResult::Error(e),
                 }
@@ -1301,66 +2775,62 @@ async fn execute_stmt(
             StmtResult::Continue
         }
 
-        Stmt::Locate(row, col) => {
-            let r = match eval_expr_core(state, row) {
-                Ok(v) => v.to_int() as u16,
-                Err(e) => return StmtResult::Error(e),
-            };
-
-            let c = match eval_expr_core(state, col) {
-                Ok(v) => v.to_int() as u16,
-                Err(e) => return StmtResult::Error(e),
+        Stmt::Locate { row, col, cursor } => {
+            let (r, c, cursor) = match (
+                eval_optional(co, state, row.as_ref(), program).await,
+                eval_optional(co, state, col.as_ref(), program).await,
+                eval_optional(co, state, cursor.as_ref(), program).await,
+            ) {
+                (Ok(r), Ok(c), Ok(cursor)) => (r.map(|v| v.to_int()), c.map(|v| v.to_int()), cursor.map(|v| v.to_int())),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return StmtResult::Error(e),
             };
 
+            let mut s = state.borrow_mut();
+            let r = r.map_or(s.graphics.cursor_row, |r| r as u16);
+            let c = c.map_or(s.graphics.cursor_col, |c| c as u16);
+            if let Some(cursor) = cursor {
+                s.graphics.cursor_visible = cursor != 0;
+            }
             // BASIC LOCATE is 1-based, locate() expects 1-based
-            state.borrow_mut().graphics.locate(r, c);
+            s.graphics.locate(r, c);
             StmtResult::Continue
         }
 
-        Stmt::Pset(x, y, color) => {
-            let x_val = match eval_expr_core(state, x) {
-                Ok(v) => v.to_int() as i32,
-                Err(e) => return StmtResult::Error(e),
-            };
-
-            let y_val = match eval_expr_core(state, y) {
-                Ok(v) => v.to_int() as i32,
+        Stmt::Pset(x, y, color) | Stmt::Preset(x, y, color) => {
+            let (x_val, y_val) = match eval_point(co, state, x, y, program).await {
+                Ok(point) => point,
                 Err(e) => return StmtResult::Error(e),
             };
 
             let color_val = if let Some(c) = color {
-                match eval_expr_core(state, c) {
+                match eval_expr_core(co, state, c, program).await {
                     Ok(v) => v.to_int() as u8,
                     Err(e) => return StmtResult::Error(e),
                 }
+            } else if matches!(stmt, Stmt::Preset(..)) {
+                state.borrow().graphics.background
             } else {
                 state.borrow().graphics.foreground
             };
 
-            state.borrow_mut().graphics.pset(x_val, y_val, color_val);
+            let mut s = state.borrow_mut();
+            s.graphics.pset(x_val, y_val, color_val);
+            s.graphics.last_point = (x_val, y_val);
             StmtResult::Continue
         }
 
         Stmt::Line { x1, y1, x2, y2, color, box_fill } => {
-            let x1_val = match eval_expr_core(state, x1) {
-                Ok(v) => v.to_int() as i32,
-                Err(e) => return StmtResult::Error(e),
-            };
-            let y1_val = match eval_expr_core(state, y1) {
-                Ok(v) => v.to_int() as i32,
-                Err(e) => return StmtResult::Error(e),
-            };
-            let x2_val = match eval_expr_core(state, x2) {
-                Ok(v) => v.to_int() as i32,
+            let (x1_val, y1_val) = match eval_point(co, state, x1, y1, program).await {
+                Ok(point) => point,
                 Err(e) => return StmtResult::Error(e),
             };
-            let y2_val = match eval_expr_core(state, y2) {
-                Ok(v) => v.to_int() as i32,
+            let (x2_val, y2_val) = match eval_point(co, state, x2, y2, program).await {
+                Ok(point) => point,
                 Err(e) => return StmtResult::Error(e),
             };
 
             let color_val = if let Some(c) = color {
-                match eval_expr_core(state, c) {
+                match eval_expr_core(co, state, c, program).await {
                     Ok(v) => v.to_int() as u8,
                     Err(e) => return StmtResult::Error(e),
                 }
@@ -1374,25 +2844,22 @@ async fn execute_stmt(
                 Some(false) => s.graphics.draw_box(x1_val, y1_val, x2_val, y2_val, color_val),
                 None => s.graphics.line(x1_val, y1_val, x2_val, y2_val, color_val),
             }
+            s.graphics.last_point = (x2_val, y2_val);
             StmtResult::Continue
         }
 
         Stmt::Circle { x, y, radius, color, start_angle, end_angle, aspect } => {
-            let x_val = match eval_expr_core(state, x) {
-                Ok(v) => v.to_int() as i32,
+            let (x_val, y_val) = match eval_point(co, state, x, y, program).await {
+                Ok(point) => point,
                 Err(e) => return StmtResult::Error(e),
             };
-            let y_val = match eval_expr_core(state, y) {
-                Ok(v) => v.to_int() as i32,
-                Err(e) => return StmtResult::Error(e),
-            };
-            let radius_val = match eval_expr_core(state, radius) {
-                Ok(v) => v.to_int() as i32,
+            let radius_val = match eval_expr_core(co, state, radius, program).await {
+                Ok(v) => state.borrow().graphics.to_physical_length(v.to_float()),
                 Err(e) => return StmtResult::Error(e),
             };
 
             let color_val = if let Some(c) = color {
-                match eval_expr_core(state, c) {
+                match eval_expr_core(co, state, c, program).await {
                     Ok(v) => v.to_int() as u8,
                     Err(e) => return StmtResult::Error(e),
                 }
@@ -1401,7 +2868,7 @@ async fn execute_stmt(
             };
 
             let start_val = if let Some(s) = start_angle {
-                match eval_expr_core(state, s) {
+                match eval_expr_core(co, state, s, program).await {
                     Ok(v) => Some(v.to_float()),
                     Err(e) => return StmtResult::Error(e),
                 }
@@ -1410,7 +2877,7 @@ async fn execute_stmt(
             };
 
             let end_val = if let Some(e) = end_angle {
-                match eval_expr_core(state, e) {
+                match eval_expr_core(co, state, e, program).await {
                     Ok(v) => Some(v.to_float()),
                     Err(e) => return StmtResult::Error(e),
                 }
@@ -1419,7 +2886,7 @@ async fn execute_stmt(
             };
 
             let aspect_val = if let Some(a) = aspect {
-                match eval_expr_core(state, a) {
+                match eval_expr_core(co, state, a, program).await {
                     Ok(v) => Some(v.to_float()),
                     Err(e) => return StmtResult::Error(e),
                 }
@@ -1427,38 +2894,40 @@ async fn execute_stmt(
                 None
             };
 
-            state.borrow_mut().graphics.circle_arc(x_val, y_val, radius_val, color_val, start_val, end_val, aspect_val);
+            let mut s = state.borrow_mut();
+            s.graphics.circle_arc(x_val, y_val, radius_val, color_val, start_val, end_val, aspect_val);
+            s.graphics.last_point = (x_val, y_val);
             StmtResult::Continue
         }
 
         Stmt::Bezier { x1, y1, cx, cy, x2, y2, color, thickness } => {
-            let x1_val = match eval_expr_core(state, x1) {
+            let x1_val = match eval_expr_core(co, state, x1, program).await {
                 Ok(v) => v.to_int() as i32,
                 Err(e) => return StmtResult::Error(e),
             };
-            let y1_val = match eval_expr_core(state, y1) {
+            let y1_val = match eval_expr_core(co, state, y1, program).await {
                 Ok(v) => v.to_int() as i32,
                 Err(e) => return StmtResult::Error(e),
             };
-            let cx_val = match eval_expr_core(state, cx) {
+            let cx_val = match eval_expr_core(co, state, cx, program).await {
                 Ok(v) => v.to_int() as i32,
                 Err(e) => return StmtResult::Error(e),
             };
-            let cy_val = match eval_expr_core(state, cy) {
+            let cy_val = match eval_expr_core(co, state, cy, program).await {
                 Ok(v) => v.to_int() as i32,
                 Err(e) => return StmtResult::Error(e),
             };
-            let x2_val = match eval_expr_core(state, x2) {
+            let x2_val = match eval_expr_core(co, state, x2, program).await {
                 Ok(v) => v.to_int() as i32,
                 Err(e) => return StmtResult::Error(e),
             };
-            let y2_val = match eval_expr_core(state, y2) {
+            let y2_val = match eval_expr_core(co, state, y2, program).await {
                 Ok(v) => v.to_int() as i32,
                 Err(e) => return StmtResult::Error(e),
             };
 
             let color_val = if let Some(c) = color {
-                match eval_expr_core(state, c) {
+                match eval_expr_core(co, state, c, program).await {
                     Ok(v) => v.to_int() as u8,
                     Err(e) => return StmtResult::Error(e),
                 }
@@ -1467,7 +2936,7 @@ async fn execute_stmt(
             };
 
             let thickness_val = if let Some(t) = thickness {
-                match eval_expr_core(state, t) {
+                match eval_expr_core(co, state, t, program).await {
                     Ok(v) => v.to_int() as i32,
                     Err(e) => return StmtResult::Error(e),
                 }
@@ -1479,94 +2948,247 @@ async fn execute_stmt(
             StmtResult::Continue
         }
 
-        Stmt::Paint(x, y, color) => {
-            let x_val = match eval_expr_core(state, x) {
-                Ok(v) => v.to_int() as i32,
-                Err(e) => return StmtResult::Error(e),
-            };
-            let y_val = match eval_expr_core(state, y) {
-                Ok(v) => v.to_int() as i32,
-                Err(e) => return StmtResult::Error(e),
-            };
-            let color_val = match eval_expr_core(state, color) {
-                Ok(v) => v.to_int() as u8,
-                Err(e) => return StmtResult::Error(e),
+        Stmt::View { coords, screen, fill, border } => {
+            let mut values = Vec::new();
+            let exprs = coords.iter().flat_map(|(x1, y1, x2, y2)| [x1, y1, x2, y2]);
+            for expr in exprs.chain(fill).chain(border) {
+                match eval_expr_core(co, state, expr, program).await {
+                    Ok(v) => values.push(v.to_int() as i32),
+                    Err(e) => return StmtResult::Error(e),
+                }
+            }
+            let view = match values[..] {
+                [x1, y1, x2, y2, ..] => Some(Viewport { x1, y1, x2, y2, relative: !screen }),
+                _ => None,
             };
 
-            state.borrow_mut().graphics.paint(x_val, y_val, color_val);
-            StmtResult::Continue
-        }
-
-        Stmt::Beep => {
-            // Beep is a no-op in terminal mode
+            let mut s = state.borrow_mut();
+            if let Err(e) = s.graphics.set_view(view) {
+                return StmtResult::Error(e);
+            }
+            let mut colors = values.iter().skip(4).map(|&c| c as u8);
+            if let Some(color) = fill.as_ref().and_then(|_| colors.next()) {
+                s.graphics.fill_view(color);
+            }
+            if let Some(color) = border.as_ref().and_then(|_| colors.next()) {
+                s.graphics.frame_view(color);
+            }
             StmtResult::Continue
         }
 
-        Stmt::Sound(_, _) => {
-            // Sound is a no-op in terminal mode
+        Stmt::Palette(None) => {
+            state.borrow_mut().graphics.reset_palette();
             StmtResult::Continue
         }
 
-        Stmt::Sleep(duration) => {
-            if let Some(dur_expr) = duration {
-                let dur = match eval_expr_core(state, dur_expr) {
-                    Ok(v) => v.to_float(),
+        Stmt::Palette(Some((attr, color))) => {
+            let mut values = [0; 2];
+            for (value, expr) in values.iter_mut().zip([attr, color]) {
+                match eval_expr_core(co, state, expr, program).await {
+                    Ok(v) => *value = v.to_int(),
                     Err(e) => return StmtResult::Error(e),
-                };
-
-                let target = std::time::Instant::now() + std::time::Duration::from_secs_f64(dur);
-                while std::time::Instant::now() < target {
-                    if state.borrow().stop_requested {
-                        return StmtResult::End;
-                    }
-                    co.yield_(YieldReason::UiUpdate).await;
-                }
-            } else {
-                // Sleep with no duration waits for keypress
-                loop {
-                    co.yield_(YieldReason::NeedsInput).await;
-                    if state.borrow().stop_requested || state.borrow().last_key.is_some() {
-                        state.borrow_mut().last_key = None;
-                        break;
-                    }
                 }
             }
-            StmtResult::Continue
+            match state.borrow_mut().graphics.set_palette(values[0], values[1]) {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
+            }
         }
 
-        Stmt::Randomize(seed) => {
-            if let Some(seed_expr) = seed {
-                match eval_expr_core(state, seed_expr) {
-                    Ok(_) => {
-                        // Seed is currently ignored
+        Stmt::Window { coords, screen } => {
+            let window = match coords {
+                Some((x1, y1, x2, y2)) => {
+                    let mut values = [0.0; 4];
+                    for (value, expr) in values.iter_mut().zip([x1, y1, x2, y2]) {
+                        match eval_expr_core(co, state, expr, program).await {
+                            Ok(v) => *value = v.to_float(),
+                            Err(e) => return StmtResult::Error(e),
+                        }
                     }
-                    Err(e) => return StmtResult::Error(e),
+                    let [x1, y1, x2, y2] = values;
+                    Some(LogicalWindow { x1, y1, x2, y2, screen: *screen })
                 }
+                None => None,
+            };
+            match state.borrow_mut().graphics.set_window(window) {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
             }
+        }
+
+        Stmt::Paint(x, y, color, border) => {
+            let (x_val, y_val) = match eval_point(co, state, x, y, program).await {
+                Ok(point) => point,
+                Err(e) => return StmtResult::Error(e),
+            };
+            let color_val = match eval_expr_core(co, state, color, program).await {
+                Ok(v) => v.to_int() as u8,
+                Err(e) => return StmtResult::Error(e),
+            };
+            let border_val = match eval_optional(co, state, border.as_ref(), program).await {
+                Ok(v) => v.map_or(color_val, |v| v.to_int() as u8),
+                Err(e) => return StmtResult::Error(e),
+            };
+
+            let mut s = state.borrow_mut();
+            s.graphics.paint(x_val, y_val, color_val, border_val);
+            s.graphics.last_point = (x_val, y_val);
             StmtResult::Continue
         }
 
-        Stmt::Read(vars) => {
-            for var in vars {
-                let value = {
-                    let mut s = state.borrow_mut();
-                    if s.data_pointer < s.data_values.len() {
-                        let v = s.data_values[s.data_pointer].clone();
-                        s.data_pointer += 1;
-                        v
-                    } else {
-                        return StmtResult::Error("Out of DATA".to_string());
+        Stmt::GetImage { x1, y1, x2, y2, array, indices } => {
+            let mut values = Vec::new();
+            for expr in [x1, y1, x2, y2].into_iter().chain(indices) {
+                match eval_expr_core(co, state, expr, program).await {
+                    Ok(v) => values.push(v.to_int()),
+                    Err(e) => return StmtResult::Error(e),
+                }
+            }
+            let (coords, indices) = values.split_at(4);
+            let mut s = state.borrow_mut();
+            let result = s
+                .graphics
+                .get_image(coords[0] as i32, coords[1] as i32, coords[2] as i32, coords[3] as i32)
+                .ok_or_else(|| "Illegal function call".to_string())
+                .and_then(|data| {
+                    let (arr, start) = s.image_array(array, indices)?;
+                    let slots = arr.elements.get_mut(start..start + data.len())
+                        .ok_or_else(|| "Illegal function call".to_string())?;
+                    for (slot, word) in slots.iter_mut().zip(data) {
+                        *slot = coerce_value(Value::Integer(word), &arr.elem_type)?;
                     }
-                };
-                state.borrow_mut().variables.insert(var.clone(), value);
+                    Ok(())
+                });
+            match result {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
             }
-            StmtResult::Continue
         }
 
-        Stmt::Restore(line) => {
-            if let Some(n) = line {
-                let label = n.to_string();
-                let target = state.borrow().labels.get(&label).copied();
+        Stmt::PutImage { x, y, array, indices, action } => {
+            let mut values = Vec::new();
+            for expr in [x, y].into_iter().chain(indices) {
+                match eval_expr_core(co, state, expr, program).await {
+                    Ok(v) => values.push(v.to_int()),
+                    Err(e) => return StmtResult::Error(e),
+                }
+            }
+            let (coords, indices) = values.split_at(2);
+            let mut s = state.borrow_mut();
+            let result = s
+                .image_array(array, indices)
+                .map(|(arr, start)| arr.elements[start..].iter().map(Value::to_int).collect::<Vec<i64>>())
+                .and_then(|data| s.graphics.put_image(coords[0] as i32, coords[1] as i32, &data, *action));
+            match result {
+                Ok(()) => StmtResult::Continue,
+                Err(e) => StmtResult::Error(e),
+            }
+        }
+
+        Stmt::Beep => {
+            state.borrow_mut().sound_events.push(SoundEvent::Beep);
+            StmtResult::Continue
+        }
+
+        Stmt::Sound(freq, duration) => {
+            let frequency = match eval_expr_core(co, state, freq, program).await {
+                Ok(v) => v.to_float(),
+                Err(e) => return StmtResult::Error(e),
+            };
+            let ticks = match eval_expr_core(co, state, duration, program).await {
+                Ok(v) => v.to_float(),
+                Err(e) => return StmtResult::Error(e),
+            };
+            if !(37.0..=32767.0).contains(&frequency) || !(0.0..=65535.0).contains(&ticks) {
+                return StmtResult::Error("Illegal function call".to_string());
+            }
+            let seconds = ticks / TICKS_PER_SECOND;
+            state.borrow_mut().sound_events.push(SoundEvent::Tone { frequency, seconds });
+            StmtResult::Continue
+        }
+
+        Stmt::Play(commands) => {
+            let mml = match eval_expr_core(co, state, commands, program).await {
+                Ok(Value::String(s)) => s,
+                Ok(_) => return StmtResult::Error("Type mismatch".to_string()),
+                Err(e) => return StmtResult::Error(e),
+            };
+            let mut s = state.borrow_mut();
+            match s.play_state.play(&mml) {
+                Ok(events) => {
+                    s.sound_events.extend(events);
+                    StmtResult::Continue
+                }
+                Err(e) => StmtResult::Error(e),
+            }
+        }
+
+        Stmt::Sleep(duration) => {
+            let seconds = match eval_optional(co, state, duration.as_ref(), program).await {
+                Ok(v) => v.map_or(0.0, |v| v.to_float()),
+                Err(e) => return StmtResult::Error(e),
+            };
+
+            // Wait for a key, or until the time runs out when there is one.
+            // Yielding NeedsInput lets the host poll the keyboard meanwhile.
+            let deadline = (seconds > 0.0).then(|| Instant::now() + std::time::Duration::from_secs_f64(seconds));
+            state.borrow_mut().sleep_deadline = deadline;
+            let result = loop {
+                co.yield_(YieldReason::NeedsInput).await;
+                let mut s = state.borrow_mut();
+                if s.stop_requested {
+                    break StmtResult::End;
+                }
+                if s.last_key.take().is_some() || deadline.is_some_and(|d| Instant::now() >= d) {
+                    break StmtResult::Continue;
+                }
+            };
+            state.borrow_mut().sleep_deadline = None;
+            result
+        }
+
+        Stmt::Randomize(seed) => {
+            let seed = match seed {
+                Some(seed_expr) => match eval_expr_core(co, state, seed_expr, program).await {
+                    Ok(v) => v.to_float(),
+                    Err(e) => return StmtResult::Error(e),
+                },
+                // Without a seed, use the time of day like RANDOMIZE TIMER
+                None => state.borrow().clock.now().seconds,
+            };
+            state.borrow_mut().rng.randomize(seed);
+            StmtResult::Continue
+        }
+
+        Stmt::Read(vars) => {
+            for var in vars {
+                let value = {
+                    let mut s = state.borrow_mut();
+                    if s.data_pointer < s.data_values.len() {
+                        let v = s.data_values[s.data_pointer].clone();
+                        s.data_pointer += 1;
+                        v
+                    } else {
+                        return StmtResult::Error("Out of DATA".to_string());
+                    }
+                };
+                let mut s = state.borrow_mut();
+                // DATA items are text, so numbers can be read into strings
+                let value = match (s.var_type(var), value) {
+                    (VarType::String, v @ (Value::Integer(_) | Value::Float(_))) => Value::String(v.to_string()),
+                    (_, v) => v,
+                };
+                if let Err(e) = s.set_var(var, value) {
+                    return StmtResult::Error(e);
+                }
+            }
+            StmtResult::Continue
+        }
+
+        Stmt::Restore(line) => {
+            if let Some(n) = line {
+                let label = n.to_string();
+                let target = state.borrow().labels.get(&label).copied();
                 if let Some(pos) = target {
                     // Count DATA values before this position
                     let mut count = 0;
@@ -1588,7 +3210,7 @@ async fn execute_stmt(
 
         Stmt::Expression(expr) => {
             // Evaluate the expression for side effects (like function calls)
-            match eval_expr_core(state, expr) {
+            match eval_expr_core(co, state, expr, program).await {
                 Ok(_) => StmtResult::Continue,
                 Err(e) => StmtResult::Error(e),
             }
@@ -1601,7 +3223,7 @@ async fn execute_stmt(
 async fn execute_subroutine(
     co: &Co<YieldReason>,
     state: &Rc<RefCell<InterpreterState>>,
-    program: &[Stmt],
+    program: &Block,
     start_pos: usize,
 ) -> StmtResult {
     let mut pos = start_pos;
@@ -1618,25 +3240,287 @@ async fn execute_subroutine(
             return StmtResult::Continue;
         }
 
-        state.borrow_mut().current_line = pos;
+        state.borrow_mut().current_line = program.line(pos);
 
-        let result = execute_stmt(co, state, stmt, program).await;
+        let result = run_stmt(co, state, stmt, program).await;
 
         match result {
             StmtResult::Continue => pos += 1,
             StmtResult::Jump(new_pos) => pos = new_pos,
-            StmtResult::End => return StmtResult::End,
-            StmtResult::Error(e) => return StmtResult::Error(e),
+            other => return other,
         }
     }
 
     StmtResult::Continue
 }
 
-/// Evaluate an expression (sync version - doesn't need async since no yields)
-fn eval_expr_core(
+/// Apply `op` when either operand is a string: strings concatenate with `+`
+/// and compare by character code; mixing a string with a number, or any
+/// other operator on strings, is a type mismatch. None for two numbers.
+fn string_operation(op: &BinOp, l: &Value, r: &Value) -> Option<Result<Value, String>> {
+    let (a, b) = match (l, r) {
+        (Value::String(a), Value::String(b)) => (a, b),
+        (Value::String(_), _) | (_, Value::String(_)) => return Some(Err("Type mismatch".to_string())),
+        _ => return None,
+    };
+    let truth = |cond: bool| Ok(Value::Integer(if cond { -1 } else { 0 }));
+    Some(match op {
+        BinOp::Add => Ok(Value::String(format!("{}{}", a, b))),
+        BinOp::Eq => truth(a == b),
+        BinOp::Ne => truth(a != b),
+        BinOp::Lt => truth(a < b),
+        BinOp::Gt => truth(a > b),
+        BinOp::Le => truth(a <= b),
+        BinOp::Ge => truth(a >= b),
+        _ => Err("Type mismatch".to_string()),
+    })
+}
+
+/// Integer division (`\\`) or MOD: operands are rounded to integers first,
+/// the quotient truncates toward zero and the remainder takes the dividend's sign
+fn integer_divide(op: &BinOp, l: &Value, r: &Value, long: bool) -> Result<Value, String> {
+    let a = l.to_float().round_ties_even();
+    let b = r.to_float().round_ties_even();
+    if !(i32::MIN as f64..=i32::MAX as f64).contains(&a) || !(i32::MIN as f64..=i32::MAX as f64).contains(&b) {
+        return Err("Overflow".to_string());
+    }
+    let (a, b) = (a as i64, b as i64);
+    if b == 0 {
+        return Err("Division by zero".to_string());
+    }
+    let long = long || i16::try_from(a).is_err() || i16::try_from(b).is_err();
+    integer_result(if matches!(op, BinOp::Mod) { a % b } else { a / b }, long)
+}
+
+/// Add, subtract or multiply integers, which overflow outside the INTEGER
+/// range, or the LONG range when either operand is LONG
+fn integer_arithmetic(op: &BinOp, a: i64, b: i64, long: bool) -> Result<Value, String> {
+    let n = match op {
+        BinOp::Add => a.checked_add(b),
+        BinOp::Sub => a.checked_sub(b),
+        _ => a.checked_mul(b),
+    };
+    let long = long || i16::try_from(a).is_err() || i16::try_from(b).is_err();
+    integer_result(n.ok_or_else(|| "Overflow".to_string())?, long)
+}
+
+/// An integer result, if it is in the INTEGER range or, for `long`, the LONG range
+fn integer_result(n: i64, long: bool) -> Result<Value, String> {
+    let fits = if long { i32::try_from(n).is_ok() } else { i16::try_from(n).is_ok() };
+    if fits {
+        Ok(Value::Integer(n))
+    } else {
+        Err("Overflow".to_string())
+    }
+}
+
+/// Call a DEF FN function: bind the arguments, coerced to the parameter
+/// types, in a fresh scope and evaluate the stored expression there
+async fn call_def_fn(
+    co: &Co<YieldReason>,
+    state: &Rc<RefCell<InterpreterState>>,
+    name: &str,
+    def_fn: &DefFn,
+    args: Vec<Value>,
+    program: &Block,
+) -> Result<Value, String> {
+    if args.len() != def_fn.params.len() {
+        return Err("Argument-count mismatch".to_string());
+    }
+    let mut frame = Frame { sees_globals: true, ..Frame::default() };
+    for (param, value) in def_fn.params.iter().zip(args) {
+        let value = coerce_value(value, &state.borrow().var_type(param))?;
+        frame.vars.insert(param.clone(), value);
+    }
+
+    state.borrow_mut().call_stack.push(frame);
+    let result = eval_expr_core(co, state, &def_fn.body, program).await;
+    state.borrow_mut().call_stack.pop();
+    let value = result?;
+    let return_type = state.borrow().var_type(name);
+    coerce_value(value, &return_type)
+}
+
+/// Check a call's arguments against the procedure's parameters and, when it
+/// was declared, its DECLARE: the counts must match, and strings may only be
+/// passed to string parameters
+fn check_arguments(state: &Rc<RefCell<InterpreterState>>, procedure: &Procedure, args: &[Value]) -> Result<(), String> {
+    let s = state.borrow();
+    let declared = s.declarations.get(&procedure.name.to_uppercase());
+    for params in std::iter::once(&procedure.params).chain(declared) {
+        if args.len() != params.len() {
+            return Err("Argument-count mismatch".to_string());
+        }
+        for (param, arg) in params.iter().zip(args) {
+            let var_type = param.var_type.clone().unwrap_or_else(|| s.var_type(&param.name));
+            let wants_string = matches!(var_type, VarType::String | VarType::FixedString(_));
+            let is_number = matches!(arg, Value::Integer(_) | Value::Float(_));
+            if (wants_string && is_number) || (!wants_string && matches!(arg, Value::String(_))) {
+                return Err("Parameter type mismatch".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Evaluate a call's arguments, each once, into the frame for `procedure`.
+/// A variable or array element is passed by reference: the parameter is
+/// bound to the caller's storage unless its type differs, when it gets a
+/// converted copy as in QBasic.
+async fn bind_arguments(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, procedure: &Procedure, args: &[Expr], program: &Block) -> Result<Frame, String> {
+    let mut slots = Vec::new();
+    let mut values = Vec::new();
+    for arg in args {
+        let slot = match argument_reference(co, state, arg, program).await? {
+            Some(reference) => Some(state.borrow_mut().slot_for(reference)?),
+            None => None,
+        };
+        let value = match &slot {
+            Some(slot) => {
+                let s = state.borrow();
+                slot_value(&s, slot).cloned().ok_or("Type mismatch")?
+            }
+            None => eval_expr_core(co, state, arg, program).await?,
+        };
+        slots.push(slot);
+        values.push(value);
+    }
+    check_arguments(state, procedure, &values)?;
+
+    let s = state.borrow();
+    let mut frame = Frame::default();
+    for ((param, value), slot) in procedure.params.iter().zip(values).zip(slots) {
+        let var_type = param.var_type.clone().unwrap_or_else(|| s.var_type(&param.name));
+        match slot {
+            Some(slot) if matches!(value, Value::Array(_)) || passes_by_reference(&var_type, &value) => {
+                frame.refs.insert(param.name.clone(), slot);
+            }
+            _ => {
+                frame.vars.insert(param.name.clone(), coerce_value(value, &var_type)?);
+            }
+        }
+    }
+    Ok(frame)
+}
+
+/// Whether a parameter of `var_type` can share the storage of `value`
+/// rather than take a converted copy
+fn passes_by_reference(var_type: &VarType, value: &Value) -> bool {
+    match (var_type, value) {
+        (VarType::Integer | VarType::Long, Value::Integer(_)) => true,
+        (VarType::Single | VarType::Double, Value::Float(_)) => true,
+        (VarType::String | VarType::FixedString(_), Value::String(_)) => true,
+        (VarType::UserType(name), Value::Record(record)) => record.type_name.eq_ignore_ascii_case(name),
+        _ => false,
+    }
+}
+
+/// Caller's variable or array element that an argument is passed by reference as
+enum ArgRef {
+    Var(String),
+    Element(String, Vec<i64>),
+}
+
+/// The variable or array element an argument names, with its subscripts
+/// evaluated now. Any other expression, constants and function calls
+/// included, is passed by value.
+async fn argument_reference(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, arg: &Expr, program: &Block) -> Result<Option<ArgRef>, String> {
+    match arg {
+        Expr::Variable(name) if !state.borrow().is_constant(name) && user_function(state, name).is_none() => {
+            Ok(Some(ArgRef::Var(name.clone())))
+        }
+        Expr::ArrayAccess(name, indices)
+            if user_function(state, name).is_none() && !state.borrow().def_fns.contains_key(&name.to_uppercase()) =>
+        {
+            let mut values = Vec::new();
+            for index in indices {
+                values.push(eval_expr_core(co, state, index, program).await?.to_int());
+            }
+            Ok(Some(ArgRef::Element(name.clone(), values)))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The FUNCTION called `name`, if there is one
+fn user_function(state: &Rc<RefCell<InterpreterState>>, name: &str) -> Option<Procedure> {
+    state.borrow().procedures.get(&name.to_uppercase()).filter(|p| p.is_function).cloned()
+}
+
+/// Call a FUNCTION from an expression, in a fresh frame where the function's
+/// name holds the return value. The body runs in the caller's generator, so
+/// it can wait for input, pause in the debugger and be stopped.
+async fn call_function(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, procedure: Procedure, args: &[Expr], program: &Block) -> Result<Value, String> {
+    let mut frame = bind_arguments(co, state, &procedure, args, program).await?;
+    let return_type = {
+        let s = state.borrow();
+        let return_type = s.var_type(&procedure.name);
+        frame.vars.insert(procedure.name.clone(), default_value(&s.types, &return_type)?);
+        return_type
+    };
+    state.borrow_mut().enter_procedure(&procedure, frame);
+    let result = execute_procedure(co, state, &procedure, program).await;
+    let frame = state.borrow_mut().leave_procedure();
+    if !matches!(result, StmtResult::Error(_)) {
+        state.borrow_mut().current_line = frame.call_line;
+    }
+
+    let name = &procedure.name;
+    match result {
+        StmtResult::Continue => {}
+        // END ran or the user stopped the program: abandon the expression
+        StmtResult::End => {
+            state.borrow_mut().stop_requested = true;
+            return Err(PROGRAM_STOPPED.to_string());
+        }
+        StmtResult::Error(e) => return Err(e),
+        StmtResult::Jump(_) | StmtResult::Exit(_) => return Err("Label not defined".to_string()),
+        StmtResult::Resume(_) => return Err("RESUME without error".to_string()),
+    }
+    let value = frame
+        .vars
+        .into_iter()
+        .find(|(var, _)| var.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value);
+    match value {
+        Some(value) => coerce_value(value, &return_type),
+        None => default_value(&state.borrow().types, &return_type),
+    }
+}
+
+/// Evaluate array subscripts
+async fn eval_indices(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, indices: &[Expr], program: &Block) -> Result<Vec<i64>, String> {
+    let mut values = Vec::new();
+    for index in indices {
+        values.push(eval_expr_core(co, state, index, program).await?.to_int());
+    }
+    Ok(values)
+}
+
+/// Evaluate an argument that may be left out
+async fn eval_optional(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, expr: Option<&Expr>, program: &Block) -> Result<Option<Value>, String> {
+    match expr {
+        Some(expr) => eval_expr_core(co, state, expr, program).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Evaluate a point given to a graphics statement and map it to a pixel
+/// through WINDOW and VIEW
+async fn eval_point(co: &Co<YieldReason>, state: &Rc<RefCell<InterpreterState>>, x: &Expr, y: &Expr, program: &Block) -> Result<(i32, i32), String> {
+    let x = eval_expr_core(co, state, x, program).await?.to_float();
+    let y = eval_expr_core(co, state, y, program).await?.to_float();
+    Ok(state.borrow().graphics.to_physical(x, y))
+}
+
+/// Evaluate an expression. Only a FUNCTION call can yield, when its body
+/// waits for input, pauses in the debugger or lets the screen update.
+#[async_recursion(?Send)]
+async fn eval_expr_core(
+    co: &Co<YieldReason>,
     state: &Rc<RefCell<InterpreterState>>,
     expr: &Expr,
+    program: &Block,
 ) -> Result<Value, String> {
     match expr {
         Expr::Integer(n) => Ok(Value::Integer(*n)),
@@ -1651,7 +3535,7 @@ fn eval_expr_core(
             // Special case for INKEY$
             if name_upper == "INKEY$" {
                 let key = state.borrow_mut().last_key.take();
-                return Ok(Value::String(key.map_or(String::new(), |c| c.to_string())));
+                return Ok(Value::String(key.unwrap_or_default()));
             }
 
             // Special case for screen dimension pseudo-variables
@@ -1665,69 +3549,51 @@ fn eval_expr_core(
             // Check local scope first
             {
                 let s = state.borrow();
-                if let Some(local_scope) = s.call_stack.last() {
-                    if let Some(v) = local_scope.get(name) {
-                        return Ok(v.clone());
-                    }
+                if let Some(v) = s.call_stack.last().and_then(|frame| frame.vars.get(name)) {
+                    return Ok(v.clone());
+                }
+                if let Some(v) = s.constants.get(&name_upper) {
+                    return Ok(v.clone());
+                }
+                if let Some(v) = s.var(name) {
+                    return Ok(v.clone());
                 }
             }
 
-            // Then check global scope
-            let var = state.borrow().variables.get(name).cloned();
-            match var {
-                Some(v) => Ok(v),
-                None => {
-                    // Return default value based on type suffix
-                    if name.ends_with('$') {
-                        Ok(Value::String(String::new()))
-                    } else {
-                        Ok(Value::Integer(0))
-                    }
-                }
+            // A FUNCTION without parameters can be called without parentheses
+            if let Some(procedure) = user_function(state, name).filter(|p| p.params.is_empty()) {
+                return call_function(co, state, procedure, &[], program).await;
             }
+            let s = state.borrow();
+            Ok(s.default_var(name))
         }
 
         Expr::ArrayAccess(name, indices) => {
+            if let Some(procedure) = user_function(state, name) {
+                return call_function(co, state, procedure, indices, program).await;
+            }
+
             let idx_values: Vec<i64> = {
                 let mut results = Vec::new();
                 for idx in indices {
-                    results.push(eval_expr_core(state, idx)?.to_int());
+                    results.push(eval_expr_core(co, state, idx, program).await?.to_int());
                 }
                 results
             };
 
-            let arr = state.borrow().variables.get(name).cloned();
-            match arr {
-                Some(Value::IntArray(arr)) => {
-                    let idx = idx_values[0] as usize;
-                    Ok(arr.get(idx).map(|&v| Value::Integer(v)).unwrap_or(Value::Integer(0)))
-                }
-                Some(Value::FloatArray(arr)) => {
-                    let idx = idx_values[0] as usize;
-                    Ok(arr.get(idx).map(|&v| Value::Float(v)).unwrap_or(Value::Float(0.0)))
-                }
-                Some(Value::StringArray(arr)) => {
-                    let idx = idx_values[0] as usize;
-                    Ok(arr.get(idx).map(|v| Value::String(v.clone())).unwrap_or(Value::String(String::new())))
-                }
-                _ => {
-                    // Auto-create array
-                    let size = (idx_values[0] + 11) as usize;
-                    if name.ends_with('$') {
-                        let arr = vec![String::new(); size];
-                        state.borrow_mut().variables.insert(name.clone(), Value::StringArray(arr));
-                        Ok(Value::String(String::new()))
-                    } else {
-                        let arr = vec![0.0; size];
-                        state.borrow_mut().variables.insert(name.clone(), Value::FloatArray(arr));
-                        Ok(Value::Float(0.0))
-                    }
-                }
+            read_array_element(state, name, &idx_values)
+        }
+
+        Expr::FieldAccess(name, indices, fields) => {
+            let mut idx_values = Vec::new();
+            for idx in indices {
+                idx_values.push(eval_expr_core(co, state, idx, program).await?.to_int());
             }
+            read_field(state, name, &idx_values, fields)
         }
 
         Expr::BinaryOp(left, op, right) => {
-            let l = eval_expr_core(state, left)?;
+            let l = eval_expr_core(co, state, left, program).await?;
 
             // Short-circuit AND/OR
             match op {
@@ -1735,65 +3601,46 @@ fn eval_expr_core(
                     if !l.is_truthy() {
                         return Ok(Value::Integer(0));
                     }
-                    let r = eval_expr_core(state, right)?;
+                    let r = eval_expr_core(co, state, right, program).await?;
                     return Ok(Value::Integer(if r.is_truthy() { -1 } else { 0 }));
                 }
                 BinOp::Or => {
                     if l.is_truthy() {
                         return Ok(Value::Integer(-1));
                     }
-                    let r = eval_expr_core(state, right)?;
+                    let r = eval_expr_core(co, state, right, program).await?;
                     return Ok(Value::Integer(if r.is_truthy() { -1 } else { 0 }));
                 }
                 _ => {}
             }
 
-            let r = eval_expr_core(state, right)?;
+            let r = eval_expr_core(co, state, right, program).await?;
+            if let Some(result) = string_operation(op, &l, &r) {
+                return result;
+            }
+
+            let long = || {
+                let s = state.borrow();
+                s.is_long_expr(left) || s.is_long_expr(right)
+            };
+            if let (BinOp::Add | BinOp::Sub | BinOp::Mul, Value::Integer(a), Value::Integer(b)) = (op, &l, &r) {
+                return integer_arithmetic(op, *a, *b, long());
+            }
 
             match op {
-                BinOp::Add => match (&l, &r) {
-                    (Value::String(a), Value::String(b)) => Ok(Value::String(format!("{}{}", a, b))),
-                    (Value::String(a), b) => Ok(Value::String(format!("{}{}", a, b.to_string()))),
-                    (a, Value::String(b)) => Ok(Value::String(format!("{}{}", a.to_string(), b))),
-                    (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a + b)),
-                    _ => Ok(Value::Float(l.to_float() + r.to_float())),
-                },
-                BinOp::Sub => match (&l, &r) {
-                    (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a - b)),
-                    _ => Ok(Value::Float(l.to_float() - r.to_float())),
-                },
-                BinOp::Mul => match (&l, &r) {
-                    (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a * b)),
-                    _ => Ok(Value::Float(l.to_float() * r.to_float())),
-                },
+                BinOp::Add => Ok(Value::Float(l.to_float() + r.to_float())),
+                BinOp::Sub => Ok(Value::Float(l.to_float() - r.to_float())),
+                BinOp::Mul => Ok(Value::Float(l.to_float() * r.to_float())),
+                BinOp::Div if r.to_float() == 0.0 => Err("Division by zero".to_string()),
                 BinOp::Div => Ok(Value::Float(l.to_float() / r.to_float())),
-                BinOp::IntDiv => Ok(Value::Integer(l.to_int() / r.to_int().max(1))),
-                BinOp::Mod => Ok(Value::Integer(l.to_int() % r.to_int().max(1))),
+                BinOp::IntDiv | BinOp::Mod => integer_divide(op, &l, &r, long()),
                 BinOp::Pow => Ok(Value::Float(l.to_float().powf(r.to_float()))),
-                BinOp::Eq => match (&l, &r) {
-                    (Value::String(a), Value::String(b)) => Ok(Value::Integer(if a == b { -1 } else { 0 })),
-                    _ => Ok(Value::Integer(if (l.to_float() - r.to_float()).abs() < f64::EPSILON { -1 } else { 0 })),
-                },
-                BinOp::Ne => match (&l, &r) {
-                    (Value::String(a), Value::String(b)) => Ok(Value::Integer(if a != b { -1 } else { 0 })),
-                    _ => Ok(Value::Integer(if (l.to_float() - r.to_float()).abs() >= f64::EPSILON { -1 } else { 0 })),
-                },
-                BinOp::Lt => match (&l, &r) {
-                    (Value::String(a), Value::String(b)) => Ok(Value::Integer(if a < b { -1 } else { 0 })),
-                    _ => Ok(Value::Integer(if l.to_float() < r.to_float() { -1 } else { 0 })),
-                },
-                BinOp::Gt => match (&l, &r) {
-                    (Value::String(a), Value::String(b)) => Ok(Value::Integer(if a > b { -1 } else { 0 })),
-                    _ => Ok(Value::Integer(if l.to_float() > r.to_float() { -1 } else { 0 })),
-                },
-                BinOp::Le => match (&l, &r) {
-                    (Value::String(a), Value::String(b)) => Ok(Value::Integer(if a <= b { -1 } else { 0 })),
-                    _ => Ok(Value::Integer(if l.to_float() <= r.to_float() { -1 } else { 0 })),
-                },
-                BinOp::Ge => match (&l, &r) {
-                    (Value::String(a), Value::String(b)) => Ok(Value::Integer(if a >= b { -1 } else { 0 })),
-                    _ => Ok(Value::Integer(if l.to_float() >= r.to_float() { -1 } else { 0 })),
-                },
+                BinOp::Eq => Ok(Value::Integer(if (l.to_float() - r.to_float()).abs() < f64::EPSILON { -1 } else { 0 })),
+                BinOp::Ne => Ok(Value::Integer(if (l.to_float() - r.to_float()).abs() >= f64::EPSILON { -1 } else { 0 })),
+                BinOp::Lt => Ok(Value::Integer(if l.to_float() < r.to_float() { -1 } else { 0 })),
+                BinOp::Gt => Ok(Value::Integer(if l.to_float() > r.to_float() { -1 } else { 0 })),
+                BinOp::Le => Ok(Value::Integer(if l.to_float() <= r.to_float() { -1 } else { 0 })),
+                BinOp::Ge => Ok(Value::Integer(if l.to_float() >= r.to_float() { -1 } else { 0 })),
                 BinOp::And | BinOp::Or => unreachable!(), // handled above
                 BinOp::Xor => {
                     let a = l.is_truthy();
@@ -1814,7 +3661,7 @@ fn eval_expr_core(
         }
 
         Expr::UnaryOp(op, operand) => {
-            let v = eval_expr_core(state, operand)?;
+            let v = eval_expr_core(co, state, operand, program).await?;
             match op {
                 UnaryOp::Neg => match v {
                     Value::Integer(i) => Ok(Value::Integer(-i)),
@@ -1827,343 +3674,139 @@ fn eval_expr_core(
         Expr::FunctionCall(name, args) => {
             let name_upper = name.to_uppercase();
 
-            // Check for user-defined functions
-            let func = state.borrow().procedures.get(&name_upper).cloned();
-            if let Some(procedure) = func {
-                if procedure.is_function {
-                    // User-defined functions can't be called from sync eval context
-                    return Err(format!("Cannot call function {} in sync context", name));
-                }
+            if let Some(procedure) = user_function(state, name) {
+                return call_function(co, state, procedure, args, program).await;
             }
 
-            // Evaluate arguments for built-in functions
             let arg_values: Vec<Value> = {
                 let mut results = Vec::new();
                 for arg in args {
-                    results.push(eval_expr_core(state, arg)?);
+                    results.push(eval_expr_core(co, state, arg, program).await?);
                 }
                 results
             };
 
-            // Built-in functions
-            match name_upper.trim_end_matches('$') {
-                "ABS" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().abs()).unwrap_or(0.0))),
-                "INT" => Ok(Value::Integer(arg_values.first().map(|v| v.to_float().floor() as i64).unwrap_or(0))),
-                "FIX" => Ok(Value::Integer(arg_values.first().map(|v| v.to_float().trunc() as i64).unwrap_or(0))),
-                "SGN" => {
-                    let f = arg_values.first().map(|v| v.to_float()).unwrap_or(0.0);
-                    Ok(Value::Integer(if f > 0.0 { 1 } else if f < 0.0 { -1 } else { 0 }))
-                },
-                "SQR" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().sqrt()).unwrap_or(0.0))),
-                "SIN" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().sin()).unwrap_or(0.0))),
-                "COS" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().cos()).unwrap_or(0.0))),
-                "TAN" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().tan()).unwrap_or(0.0))),
-                "ATN" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().atan()).unwrap_or(0.0))),
-                "LOG" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().ln()).unwrap_or(0.0))),
-                "EXP" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().exp()).unwrap_or(0.0))),
-
-                "LEN" => Ok(Value::Integer(arg_values.first().map(|v| v.to_string().len() as i64).unwrap_or(0))),
-                "LEFT" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    let n = arg_values.get(1).map(|v| v.to_int()).unwrap_or(0) as usize;
-                    Ok(Value::String(s.chars().take(n).collect()))
-                },
-                "RIGHT" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    let n = arg_values.get(1).map(|v| v.to_int()).unwrap_or(0) as usize;
-                    let len = s.chars().count();
-                    Ok(Value::String(s.chars().skip(len.saturating_sub(n)).collect()))
-                },
-                "MID" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    let start = (arg_values.get(1).map(|v| v.to_int()).unwrap_or(1) - 1).max(0) as usize;
-                    let len = arg_values.get(2).map(|v| v.to_int() as usize);
-                    let chars: Vec<char> = s.chars().collect();
-                    let result: String = match len {
-                        Some(l) => chars.iter().skip(start).take(l).collect(),
-                        None => chars.iter().skip(start).collect(),
-                    };
-                    Ok(Value::String(result))
-                },
-                "INSTR" => {
-                    let (start, s1, s2) = if arg_values.len() >= 3 {
-                        (
-                            arg_values[0].to_int().max(1) as usize - 1,
-                            arg_values[1].to_string(),
-                            arg_values[2].to_string(),
-                        )
-                    } else {
-                        (
-                            0,
-                            arg_values.first().map(|v| v.to_string()).unwrap_or_default(),
-                            arg_values.get(1).map(|v| v.to_string()).unwrap_or_default(),
-                        )
-                    };
-                    let haystack = &s1[start.min(s1.len())..];
-                    let pos = haystack.find(&s2).map(|p| p + start + 1).unwrap_or(0);
-                    Ok(Value::Integer(pos as i64))
-                },
-                "CHR" => {
-                    let code = arg_values.first().map(|v| v.to_int()).unwrap_or(0);
-                    let ch = cp437_to_unicode(code as u8);
-                    Ok(Value::String(ch.to_string()))
-                },
-                "ASC" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    let code = s.chars().next().map(|c| c as i64).unwrap_or(0);
-                    Ok(Value::Integer(code))
-                },
-                "STR" => {
-                    let n = arg_values.first().map(|v| v.to_float()).unwrap_or(0.0);
-                    let s = if n >= 0.0 {
-                        format!(" {}", format_number(n))
-                    } else {
-                        format_number(n)
-                    };
-                    Ok(Value::String(s))
-                },
-                "VAL" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    let n: f64 = s.trim().parse().unwrap_or(0.0);
-                    Ok(Value::Float(n))
-                },
-                "UCASE" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    Ok(Value::String(s.to_uppercase()))
-                },
-                "LCASE" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    Ok(Value::String(s.to_lowercase()))
-                },
-                "LTRIM" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    Ok(Value::String(s.trim_start().to_string()))
-                },
-                "RTRIM" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    Ok(Value::String(s.trim_end().to_string()))
-                },
-                "SPACE" => {
-                    let n = arg_values.first().map(|v| v.to_int()).unwrap_or(0);
-                    Ok(Value::String(" ".repeat(n.max(0) as usize)))
-                },
-                "STRING" => {
-                    let count = arg_values.first().map(|v| v.to_int()).unwrap_or(0).max(0) as usize;
-                    let c = match arg_values.get(1) {
-                        Some(Value::String(s)) => s.chars().next().unwrap_or(' '),
-                        Some(v) => char::from_u32(v.to_int() as u32).unwrap_or(' '),
-                        None => ' ',
-                    };
-                    Ok(Value::String(c.to_string().repeat(count)))
-                },
-
-                "RND" => Ok(Value::Float(rnd())),
-                "TIMER" => Ok(Value::Float(state.borrow().start_time.elapsed().as_secs_f64())),
-
-                "CINT" => Ok(Value::Integer(arg_values.first().map(|v| v.to_float().round() as i64).unwrap_or(0))),
-                "CLNG" => Ok(Value::Integer(arg_values.first().map(|v| v.to_float().round() as i64).unwrap_or(0))),
-                "CSNG" | "CDBL" => Ok(Value::Float(arg_values.first().map(|v| v.to_float()).unwrap_or(0.0))),
+            let def_fn = state.borrow().def_fns.get(&name_upper).cloned();
+            if let Some(def_fn) = def_fn {
+                return call_def_fn(co, state, name, &def_fn, arg_values, program).await;
+            }
 
-                "HEX" => {
-                    let n = arg_values.first().map(|v| v.to_int()).unwrap_or(0);
-                    Ok(Value::String(format!("{:X}", n)))
-                },
-                "OCT" => {
-                    let n = arg_values.first().map(|v| v.to_int()).unwrap_or(0);
-                    Ok(Value::String(format!("{:o}", n)))
-                },
+            // Built-in functions
+            let base_name = name_upper.trim_end_matches('$');
+            if let Some(result) = call_builtin(base_name, &arg_values) {
+                return result;
+            }
+            if let Some(value) = call_clock_function(base_name, state.borrow().clock.as_ref()) {
+                return Ok(value);
+            }
+            match base_name {
+                "RND" => {
+                    let n = arg_values.first().map(|v| v.to_float());
+                    Ok(Value::Float(state.borrow_mut().rng.rnd(n)))
+                }
+                "INKEY" => {
+                    let key = state.borrow_mut().last_key.take();
+                    Ok(Value::String(key.unwrap_or_default()))
+                }
+                "ERR" => Ok(Value::Integer(state.borrow().err_code)),
+                "ERL" => Ok(Value::Integer(state.borrow().err_line)),
 
                 "POINT" => {
-                    let x = arg_values.first().map(|v| v.to_int()).unwrap_or(0) as i32;
-                    let y = arg_values.get(1).map(|v| v.to_int()).unwrap_or(0) as i32;
-                    let color = state.borrow().graphics.point(x, y);
-                    Ok(Value::Integer(color as i64))
+                    let g = &state.borrow().graphics;
+                    match arg_values.as_slice() {
+                        // POINT(n): the graphics cursor, as pixels (0, 1) or
+                        // in WINDOW coordinates (2, 3)
+                        [n] => {
+                            let (px, py) = g.last_point;
+                            let (lx, ly) = g.to_logical(px, py);
+                            match n.to_int() {
+                                0 => Ok(Value::Integer(px as i64)),
+                                1 => Ok(Value::Integer(py as i64)),
+                                2 => Ok(Value::Float(lx)),
+                                3 => Ok(Value::Float(ly)),
+                                _ => Err("Illegal function call".to_string()),
+                            }
+                        }
+                        [x, y] => {
+                            let (x, y) = g.to_physical(x.to_float(), y.to_float());
+                            Ok(Value::Integer(g.point_in_view(x, y).map_or(-1, |color| color as i64)))
+                        }
+                        _ => Err("Argument-count mismatch".to_string()),
+                    }
                 },
 
                 "CSRLIN" => Ok(Value::Integer(state.borrow().graphics.cursor_row as i64)),
-                "POS" => Ok(Value::Integer(state.borrow().graphics.cursor_col as i64)),
+                // The column the next PRINT starts at, on the screen or in the output
+                "POS" => Ok(Value::Integer(state.borrow().print_column() as i64 + 1)),
 
                 "SCREENWIDTH" => Ok(Value::Integer(state.borrow().graphics.width as i64)),
                 "SCREENHEIGHT" => Ok(Value::Integer(state.borrow().graphics.height as i64)),
 
-                "LBOUND" => Ok(Value::Integer(0)),
-                "UBOUND" => {
-                    if let Some(arr_name) = args.first() {
-                        if let Expr::Variable(name) = arr_name {
-                            let arr = state.borrow().variables.get(name).cloned();
-                            match arr {
-                                Some(Value::IntArray(a)) => Ok(Value::Integer((a.len() - 1) as i64)),
-                                Some(Value::FloatArray(a)) => Ok(Value::Integer((a.len() - 1) as i64)),
-                                Some(Value::StringArray(a)) => Ok(Value::Integer((a.len() - 1) as i64)),
-                                _ => Ok(Value::Integer(0)),
-                            }
-                        } else {
-                            Ok(Value::Integer(0))
-                        }
-                    } else {
-                        Ok(Value::Integer(0))
-                    }
-                },
+                "EOF" => {
+                    let number = arg_values.first().map(|v| v.to_int()).unwrap_or(0);
+                    let eof = state.borrow_mut().files.eof(number)?;
+                    Ok(Value::Integer(if eof { -1 } else { 0 }))
+                }
+                "LOF" => {
+                    let number = arg_values.first().map(|v| v.to_int()).unwrap_or(0);
+                    Ok(Value::Integer(state.borrow().files.length(number)? as i64))
+                }
 
                 _ => Err(format!("Unknown function: {}", name)),
             }
         }
 
-        Expr::Paren(inner) => eval_expr_core(state, inner),
+        Expr::Paren(inner) => eval_expr_core(co, state, inner, program).await,
     }
 }
 
-/// Synchronous expression evaluation (for immediate window)
-fn eval_expr_sync(state: &Rc<RefCell<InterpreterState>>, expr: &Expr) -> Result<Value, String> {
-    match expr {
-        Expr::Integer(n) => Ok(Value::Integer(*n)),
-        Expr::Float(n) => Ok(Value::Float(*n)),
-        Expr::String(s) => Ok(Value::String(s.clone())),
-
-        Expr::Variable(name) => {
-            let name_upper = name.to_uppercase();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::basic::lexer::Lexer;
+    use crate::basic::parser::Parser;
+    use crate::basic::builtins::LocalTime;
 
-            if name_upper == "INKEY$" {
-                let key = state.borrow_mut().last_key.take();
-                return Ok(Value::String(key.map_or(String::new(), |c| c.to_string())));
-            }
+    fn run_basic(code: &str) -> Result<String, String> {
+        let (mut interp, result) = run_with_interpreter(code);
+        result?;
+        Ok(interp.take_output().join("\n"))
+    }
 
-            if name_upper == "SCREENWIDTH" {
-                return Ok(Value::Integer(state.borrow().graphics.width as i64));
-            }
-            if name_upper == "SCREENHEIGHT" {
-                return Ok(Value::Integer(state.borrow().graphics.height as i64));
-            }
+    /// Run a program, keeping the interpreter to inspect afterwards
+    fn run_with_interpreter(code: &str) -> (Interpreter, Result<(), String>) {
+        let mut interp = Interpreter::new();
+        let result = Parser::new(Lexer::new(code).tokenize()).parse()
+            .and_then(|stmts| interp.execute(&stmts));
+        (interp, result)
+    }
 
-            let var = state.borrow().variables.get(name).cloned();
-            match var {
-                Some(v) => Ok(v),
-                None => {
-                    if name.ends_with('$') {
-                        Ok(Value::String(String::new()))
-                    } else {
-                        Ok(Value::Integer(0))
-                    }
-                }
-            }
-        }
+    /// Parse a program that must be valid
+    fn parse_basic(code: &str) -> Block {
+        Parser::new(Lexer::new(code).tokenize()).parse().expect("Should parse")
+    }
 
-        Expr::ArrayAccess(name, indices) => {
-            let idx_values: Vec<i64> = indices
-                .iter()
-                .map(|idx| eval_expr_sync(state, idx).map(|v| v.to_int()))
-                .collect::<Result<_, _>>()?;
-
-            let arr = state.borrow().variables.get(name).cloned();
-            match arr {
-                Some(Value::IntArray(arr)) => {
-                    let idx = idx_values[0] as usize;
-                    Ok(arr.get(idx).map(|&v| Value::Integer(v)).unwrap_or(Value::Integer(0)))
-                }
-                Some(Value::FloatArray(arr)) => {
-                    let idx = idx_values[0] as usize;
-                    Ok(arr.get(idx).map(|&v| Value::Float(v)).unwrap_or(Value::Float(0.0)))
-                }
-                Some(Value::StringArray(arr)) => {
-                    let idx = idx_values[0] as usize;
-                    Ok(arr.get(idx).map(|v| Value::String(v.clone())).unwrap_or(Value::String(String::new())))
-                }
-                _ => Ok(Value::Integer(0)),
-            }
-        }
-
-        Expr::BinaryOp(left, op, right) => {
-            let l = eval_expr_sync(state, left)?;
-            let r = eval_expr_sync(state, right)?;
-
-            match op {
-                BinOp::Add => match (&l, &r) {
-                    (Value::String(a), Value::String(b)) => Ok(Value::String(format!("{}{}", a, b))),
-                    (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a + b)),
-                    _ => Ok(Value::Float(l.to_float() + r.to_float())),
-                },
-                BinOp::Sub => match (&l, &r) {
-                    (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a - b)),
-                    _ => Ok(Value::Float(l.to_float() - r.to_float())),
-                },
-                BinOp::Mul => match (&l, &r) {
-                    (Value::Integer(a), Value::Integer(b)) => Ok(Value::Integer(a * b)),
-                    _ => Ok(Value::Float(l.to_float() * r.to_float())),
-                },
-                BinOp::Div => Ok(Value::Float(l.to_float() / r.to_float())),
-                BinOp::IntDiv => Ok(Value::Integer(l.to_int() / r.to_int().max(1))),
-                BinOp::Mod => Ok(Value::Integer(l.to_int() % r.to_int().max(1))),
-                BinOp::Pow => Ok(Value::Float(l.to_float().powf(r.to_float()))),
-                BinOp::Eq => Ok(Value::Integer(if (l.to_float() - r.to_float()).abs() < f64::EPSILON { -1 } else { 0 })),
-                BinOp::Ne => Ok(Value::Integer(if (l.to_float() - r.to_float()).abs() >= f64::EPSILON { -1 } else { 0 })),
-                BinOp::Lt => Ok(Value::Integer(if l.to_float() < r.to_float() { -1 } else { 0 })),
-                BinOp::Gt => Ok(Value::Integer(if l.to_float() > r.to_float() { -1 } else { 0 })),
-                BinOp::Le => Ok(Value::Integer(if l.to_float() <= r.to_float() { -1 } else { 0 })),
-                BinOp::Ge => Ok(Value::Integer(if l.to_float() >= r.to_float() { -1 } else { 0 })),
-                BinOp::And => Ok(Value::Integer(if l.is_truthy() && r.is_truthy() { -1 } else { 0 })),
-                BinOp::Or => Ok(Value::Integer(if l.is_truthy() || r.is_truthy() { -1 } else { 0 })),
-                BinOp::Xor => Ok(Value::Integer(if l.is_truthy() != r.is_truthy() { -1 } else { 0 })),
-                BinOp::Eqv => Ok(Value::Integer(if l.is_truthy() == r.is_truthy() { -1 } else { 0 })),
-                BinOp::Imp => Ok(Value::Integer(if !l.is_truthy() || r.is_truthy() { -1 } else { 0 })),
-            }
-        }
-
-        Expr::UnaryOp(op, operand) => {
-            let v = eval_expr_sync(state, operand)?;
-            match op {
-                UnaryOp::Neg => match v {
-                    Value::Integer(i) => Ok(Value::Integer(-i)),
-                    _ => Ok(Value::Float(-v.to_float())),
-                },
-                UnaryOp::Not => Ok(Value::Integer(if !v.is_truthy() { -1 } else { 0 })),
-            }
-        }
-
-        Expr::FunctionCall(name, args) => {
-            let name_upper = name.to_uppercase();
-            let arg_values: Vec<Value> = args
-                .iter()
-                .map(|arg| eval_expr_sync(state, arg))
-                .collect::<Result<_, _>>()?;
-
-            match name_upper.trim_end_matches('$') {
-                "ABS" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().abs()).unwrap_or(0.0))),
-                "INT" => Ok(Value::Integer(arg_values.first().map(|v| v.to_float().floor() as i64).unwrap_or(0))),
-                "SQR" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().sqrt()).unwrap_or(0.0))),
-                "SIN" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().sin()).unwrap_or(0.0))),
-                "COS" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().cos()).unwrap_or(0.0))),
-                "TAN" => Ok(Value::Float(arg_values.first().map(|v| v.to_float().tan()).unwrap_or(0.0))),
-                "LEN" => Ok(Value::Integer(arg_values.first().map(|v| v.to_string().len() as i64).unwrap_or(0))),
-                "VAL" => {
-                    let s = arg_values.first().map(|v| v.to_string()).unwrap_or_default();
-                    Ok(Value::Float(s.trim().parse().unwrap_or(0.0)))
-                },
-                "STR" => {
-                    let n = arg_values.first().map(|v| v.to_float()).unwrap_or(0.0);
-                    Ok(Value::String(format_number(n)))
-                },
-                "RND" => Ok(Value::Float(rnd())),
-                _ => Err(format!("Unknown function: {}", name)),
-            }
-        }
-
-        Expr::Paren(inner) => eval_expr_sync(state, inner),
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::basic::lexer::Lexer;
-    use crate::basic::parser::Parser;
-
-    fn run_basic(code: &str) -> Result<String, String> {
+    /// Run a program, answering each INPUT with the next line from `inputs`
+    fn run_basic_with_input(code: &str, inputs: &[&str]) -> Result<String, String> {
         let mut lexer = Lexer::new(code);
         let tokens = lexer.tokenize();
         let mut parser = Parser::new(tokens);
         let stmts = parser.parse()?;
         let mut interp = Interpreter::new();
-        interp.execute(&stmts)?;
+        let mut inputs = inputs.iter();
+        let mut result = interp.execute_with_debug(&stmts)?;
+        loop {
+            match result {
+                ExecutionResult::NeedsInput => {
+                    let line = inputs.next().ok_or("Ran out of input")?;
+                    line.chars().for_each(|c| interp.add_input_char(c));
+                    interp.complete_input();
+                }
+                ExecutionResult::Running => {}
+                _ => break,
+            }
+            result = interp.continue_execution(&stmts)?;
+        }
         Ok(interp.take_output().join("\n"))
     }
 
@@ -2176,25 +3819,42 @@ mod tests {
     #[test]
     fn test_variable_assignment() {
         let output = run_basic("x = 42\nPRINT x").expect("Should run");
-        assert_eq!(output, "42");
+        assert_eq!(output, " 42 ");
     }
 
     #[test]
     fn test_arithmetic() {
         let output = run_basic("PRINT 2 + 3 * 4").expect("Should run");
-        assert_eq!(output, "14");
+        assert_eq!(output, " 14 ");
     }
 
     #[test]
     fn test_for_loop() {
         let output = run_basic("FOR i = 1 TO 3\nPRINT i\nNEXT i").expect("Should run");
-        assert_eq!(output, "1\n2\n3");
+        assert_eq!(output, " 1 \n 2 \n 3 ");
+    }
+
+    #[test]
+    fn test_for_loop_step_direction() {
+        let output = run_basic("FOR i = 10 TO 1 STEP -3\nPRINT i;\nNEXT\nPRINT i").expect("Should run");
+        assert_eq!(output, " 10  7  4  1 -2 ");
+
+        let output = run_basic("n = 0\nFOR x = 0 TO 2 STEP 0.5\nn = n + 1\nNEXT\nPRINT n; x").expect("Should run");
+        assert_eq!(output, " 5  2.5 ");
+
+        let output = run_basic("FOR i = 1 TO 0\nPRINT \"body\"\nNEXT\nFOR j = 0 TO 1 STEP -1\nPRINT \"body\"\nNEXT\nPRINT i; j")
+            .expect("Should run");
+        assert_eq!(output, " 1  0 ");
+
+        // Assigning the counter in the body moves the loop on
+        let output = run_basic("FOR i = 1 TO 10\nPRINT i;\ni = i + 2\nNEXT").expect("Should run");
+        assert_eq!(output, " 1  4  7  10 ");
     }
 
     #[test]
     fn test_while_loop() {
         let output = run_basic("x = 0\nWHILE x < 3\nx = x + 1\nPRINT x\nWEND").expect("Should run");
-        assert_eq!(output, "1\n2\n3");
+        assert_eq!(output, " 1 \n 2 \n 3 ");
     }
 
     #[test]
@@ -2212,7 +3872,7 @@ mod tests {
     #[test]
     fn test_array() {
         let output = run_basic("DIM a(5)\na(0) = 10\na(1) = 20\nPRINT a(0) + a(1)").expect("Should run");
-        assert_eq!(output, "30");
+        assert_eq!(output, " 30 ");
     }
 
     #[test]
@@ -2321,7 +3981,7 @@ RETURN
         let mut interp = Interpreter::new();
         interp.with_graphics_mut(|g| {
             g.resize(80, 25);
-            g.mode = 12;  // Enable graphics mode
+            g.screen_output = true;  // PRINT to the text screen
         });
         interp.execute(&stmts).expect("Should execute");
 
@@ -2348,4 +4008,1204 @@ NEXT i
         let unique: std::collections::HashSet<&str> = lines.iter().cloned().collect();
         assert!(unique.len() > 1, "Expected varied RND values, got: {}", output);
     }
+
+    #[test]
+    fn test_user_defined_type_round_trip() {
+        let code = r#"
+TYPE Point
+    x AS INTEGER
+    y AS INTEGER
+    label AS STRING * 4
+END TYPE
+DIM p AS Point
+p.x = 5
+p.y = 2.6
+p.label = "origin"
+PRINT p.x; p.y
+PRINT p.label
+"#;
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 5  3 \norig");
+    }
+
+    #[test]
+    fn test_user_defined_type_array() {
+        let code = r#"
+TYPE Point
+    x AS INTEGER
+    y AS INTEGER
+END TYPE
+DIM pts(3) AS Point
+FOR i = 0 TO 3
+    pts(i).x = i * 10
+NEXT i
+PRINT pts(2).x + pts(3).x
+"#;
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 50 ");
+    }
+
+    #[test]
+    fn test_user_defined_type_undefined_field() {
+        let code = "TYPE Point\nx AS INTEGER\nEND TYPE\nDIM p AS Point\np.z = 1";
+        let err = run_basic(code).expect_err("Should fail");
+        assert!(err.contains("Element not defined"), "Error: {}", err);
+    }
+
+    #[test]
+    fn test_two_dimensional_array() {
+        let code = "DIM g(3, 4)\nFOR i = 0 TO 3\nFOR j = 0 TO 4\ng(i, j) = i * 10 + j\nNEXT j\nNEXT i\nPRINT g(2, 3)\nPRINT g(3, 4)";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 23 \n 34 ");
+    }
+
+    #[test]
+    fn test_option_base_and_subscript_out_of_range() {
+        let code = "OPTION BASE 1\nDIM a(3)\na(1) = 5\nPRINT a(1)\nPRINT a(0)";
+        let err = run_basic(code).expect_err("Should fail");
+        assert!(err.contains("Subscript out of range"), "Error: {}", err);
+    }
+
+    #[test]
+    fn test_redim_preserve_grows_last_dimension() {
+        let code = "DIM m(1, 1)\nm(0, 0) = 1\nm(0, 1) = 2\nm(1, 0) = 3\nm(1, 1) = 4\nREDIM PRESERVE m(1, 3)\nm(1, 3) = 9\nPRINT m(0, 0)\nPRINT m(0, 1)\nPRINT m(1, 0)\nPRINT m(1, 1)\nPRINT m(0, 2)\nPRINT m(1, 3)";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 1 \n 2 \n 3 \n 4 \n 0 \n 9 ");
+    }
+
+    #[test]
+    fn test_redim_clears_values() {
+        let code = "DIM a(2)\na(1) = 7\nREDIM a(5)\nPRINT a(1)";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output.trim(), "0");
+    }
+
+    #[test]
+    fn test_print_using() {
+        let code = "PRINT USING \"$$#,###.##\"; 1234.5\nPRINT USING \"\\  \\ ###\"; \"Widget\", 7";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " $1,234.50\nWidg   7");
+    }
+
+    #[test]
+    fn test_input_prompts() {
+        let output = run_basic_with_input("INPUT \"Name\"; n$\nINPUT \"Age: \", a\nPRINT n$; a", &["Ann", "42"])
+            .expect("Should run");
+        assert_eq!(output, "Name? \nAge: \nAnn 42 ");
+    }
+
+    #[test]
+    fn test_input_multiple_variables_redo() {
+        let code = "INPUT a, b%, c$\nPRINT a + b%; c$";
+        let output = run_basic_with_input(code, &["1, 2", "1, x, y", "1.5, 2, \"hi, there\""])
+            .expect("Should run");
+        assert_eq!(output, "? \nRedo from start\n? \nRedo from start\n? \n 3.5 hi, there");
+    }
+
+    #[test]
+    fn test_line_input_reads_whole_line() {
+        let output = run_basic_with_input("LINE INPUT x$\nPRINT \"[\"; x$; \"]\"", &["  a, b, c"])
+            .expect("Should run");
+        assert_eq!(output, "[  a, b, c]");
+    }
+
+    #[test]
+    fn test_sequential_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("qbasic-rs-test-{}.txt", std::process::id()));
+        let path_str = path.to_string_lossy().replace('\\', "/");
+        let code = format!(
+            "OPEN \"{p}\" FOR OUTPUT AS #1\n\
+             PRINT #1, \"first, line\"\n\
+             PRINT #1, 42\n\
+             PRINT #1, \"third\"\n\
+             CLOSE #1\n\
+             OPEN \"{p}\" FOR INPUT AS #2\n\
+             PRINT LOF(2)\n\
+             LINE INPUT #2, a$\n\
+             INPUT #2, n\n\
+             INPUT #2, c$\n\
+             PRINT a$\n\
+             PRINT n + 1\n\
+             PRINT c$\n\
+             PRINT EOF(2)\n\
+             CLOSE",
+            p = path_str
+        );
+        let output = run_basic(&code);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(output.expect("Should run"), " 23 \nfirst, line\n 43 \nthird\n-1 ");
+    }
+
+    #[test]
+    fn test_randomize_gives_known_sequence() {
+        let code = "RANDOMIZE 42\nFOR i = 1 TO 3\nPRINT INT(RND * 1000)\nNEXT i\nPRINT INT(RND(0) * 1000)";
+        let first = run_basic(code).expect("Should run");
+        assert_eq!(first, run_basic(code).expect("Should run"));
+        assert_eq!(first, " 339 \n 648 \n 187 \n 187 ");
+    }
+
+    #[test]
+    fn test_sqr_of_negative_is_illegal() {
+        let err = run_basic("PRINT SQR(-1)").expect_err("Should fail");
+        assert_eq!(err, "Illegal function call");
+    }
+
+    #[test]
+    fn test_on_error_resume_next() {
+        let code = "ON ERROR GOTO Handler\n\
+                    flag = 0\n\
+                    10 x = 1 / 0\n\
+                    PRINT \"after\"; flag\n\
+                    END\n\
+                    Handler:\n\
+                    flag = 1\n\
+                    PRINT ERR; ERL\n\
+                    RESUME NEXT";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 11  10 \nafter 1 ");
+    }
+
+    #[test]
+    fn test_resume_returns_into_loops_and_subs() {
+        // RESUME NEXT carries on with the loop body after the failing statement
+        let code = "ON ERROR GOTO Handler\n\
+                    FOR i = 1 TO 3\n\
+                    30 PRINT 6 / (2 - i)\n\
+                    PRINT \"i\"; i\n\
+                    NEXT\n\
+                    END\n\
+                    Handler:\n\
+                    PRINT \"error\"; ERR; ERL\n\
+                    RESUME NEXT";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 6 \ni 1 \nerror 11  30 \ni 2 \n-6 \ni 3 ");
+
+        // An error inside a FUNCTION called from the loop runs the handler
+        // too, and RESUME NEXT carries on in the FUNCTION's body
+        let code = "ON ERROR GOTO Handler\n\
+                    FOR i = 1 TO 3\n\
+                    40 PRINT Inverse(2 - i)\n\
+                    NEXT\n\
+                    END\n\
+                    Handler:\n\
+                    PRINT \"error\"; ERR; ERL\n\
+                    RESUME NEXT\n\
+                    FUNCTION Inverse (d)\n\
+                    Inverse = 1 / d\n\
+                    END FUNCTION";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 1 \nerror 11  40 \n 0 \n-1 ");
+
+        // RESUME retries the statement inside the SUB, in the SUB's scope,
+        // once the handler (which sees module-level variables) fixes things
+        let code = "ON ERROR GOTO Handler\n\
+                    d = 0\n\
+                    CALL Divide(12)\n\
+                    PRINT \"back\"\n\
+                    END\n\
+                    Handler:\n\
+                    d = 4\n\
+                    RESUME\n\
+                    SUB Divide (n)\n\
+                    SHARED d\n\
+                    d2 = 1\n\
+                    PRINT n / d\n\
+                    PRINT d2\n\
+                    END SUB";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 3 \n 1 \nback");
+
+        // RESUME label leaves the SUB
+        let code = "ON ERROR GOTO Handler\n\
+                    CALL Fail\n\
+                    PRINT \"skipped\"\n\
+                    20 PRINT \"done\"\n\
+                    END\n\
+                    Handler:\n\
+                    RESUME 20\n\
+                    SUB Fail\n\
+                    x = 1 / 0\n\
+                    END SUB";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, "done");
+
+        // An error in the handler itself is not trapped
+        let code = "ON ERROR GOTO Handler\nFOR i = 1 TO 2\nx = 1 / 0\nNEXT\nEND\nHandler:\nPRINT 1 / 0\nRESUME NEXT";
+        let (_, result) = run_with_interpreter(code);
+        assert_eq!(result, Err("Division by zero".to_string()));
+    }
+
+    #[test]
+    fn test_on_error_goto_zero_disables_trapping() {
+        let code = "ON ERROR GOTO Handler\nON ERROR GOTO 0\nx = 1 / 0\nEND\nHandler:\nRESUME NEXT";
+        let err = run_basic(code).expect_err("Should fail");
+        assert_eq!(err, "Division by zero");
+    }
+
+    #[test]
+    fn test_exit_for_leaves_inner_loop_only() {
+        let code = "FOR i = 1 TO 3\n\
+                    FOR j = 1 TO 3\n\
+                    IF j = 2 THEN EXIT FOR\n\
+                    PRINT i; j\n\
+                    NEXT j\n\
+                    NEXT i\n\
+                    PRINT \"done\"";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 1  1 \n 2  1 \n 3  1 \ndone");
+    }
+
+    #[test]
+    fn test_exit_do() {
+        let code = "n = 0\nDO\nn = n + 1\nIF n = 4 THEN EXIT DO\nLOOP\nPRINT n";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 4 ");
+    }
+
+    #[test]
+    fn test_exit_sub_returns_early() {
+        let code = "SUB Greet\nPRINT \"a\"\nEXIT SUB\nPRINT \"b\"\nEND SUB\nCALL Greet\nPRINT \"c\"";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, "a\nc");
+    }
+
+    #[test]
+    fn test_exit_while_leaves_innermost_loop() {
+        // The inner loop reuses the outer counter and leaves early; the
+        // outer condition is only re-checked when its own WEND is reached
+        let code = "i = 0\nWHILE i < 3\ni = i + 1\nj = 0\nWHILE 1\nj = j + 1\n\
+                    WHILE j < 10\nIF j = i THEN EXIT WHILE\nj = j + 1\nWEND\n\
+                    IF j >= i THEN EXIT WHILE\nWEND\ni = i + 10\nPRINT i; j\ni = i - 10\nWEND\nPRINT \"done\"; i";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 11  1 \n 12  2 \n 13  3 \ndone 3 ");
+
+        let err = run_basic("FOR i = 1 TO 2\nEXIT WHILE\nNEXT").expect_err("Should fail");
+        assert_eq!(err, "EXIT WHILE not within WHILE...WEND");
+    }
+
+    #[test]
+    fn test_exit_for_outside_loop_is_error() {
+        let err = run_basic("PRINT 1\nEXIT FOR").expect_err("Should fail");
+        assert_eq!(err, "EXIT FOR not within FOR...NEXT");
+    }
+
+    #[test]
+    fn test_integer_division_and_mod() {
+        let code = "PRINT 7 \\ 2\nPRINT 7 MOD 3\nPRINT -7 \\ 2\nPRINT -7 MOD 3\nPRINT 7 MOD -3\n\
+                    PRINT 7.6 \\ 2.4\nPRINT 10 - 7 MOD 4 * 2";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 3 \n 1 \n-3 \n-1 \n 1 \n 4 \n 3 ");
+    }
+
+    #[test]
+    fn test_integer_division_by_zero() {
+        assert_eq!(run_basic("PRINT 5 \\ 0").expect_err("Should fail"), "Division by zero");
+        assert_eq!(run_basic("PRINT 5 MOD 0.4").expect_err("Should fail"), "Division by zero");
+    }
+
+    #[test]
+    fn test_type_suffixes_are_distinct_variables() {
+        let code = "A% = 3.7\nA$ = \"text\"\nA# = 1.5\nPRINT A%\nPRINT A$\nPRINT A#\nB& = 2.5\nPRINT B&";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 4 \ntext\n 1.5 \n 2 ");
+    }
+
+    #[test]
+    fn test_defint_sets_default_type() {
+        let code = "DEFINT I-K\nDEFSTR S\ni = 2.6\nx = 2.6\nPRINT i\nPRINT x\nPRINT \"[\"; s; \"]\"";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 3 \n 2.6 \n[]");
+    }
+
+    #[test]
+    fn test_string_into_numeric_variable_is_type_mismatch() {
+        let err = run_basic("x% = \"abc\"").expect_err("Should fail");
+        assert_eq!(err, "Type mismatch");
+    }
+
+    #[test]
+    fn test_swap_in_bubble_sort() {
+        let code = "DIM a(4)\n\
+                    a(0) = 5\na(1) = 3\na(2) = 9\na(3) = 1\na(4) = 4\n\
+                    FOR i = 0 TO 3\n\
+                    FOR j = 0 TO 3 - i\n\
+                    IF a(j) > a(j + 1) THEN SWAP a(j), a(j + 1)\n\
+                    NEXT j\n\
+                    NEXT i\n\
+                    PRINT a(0); a(1); a(2); a(3); a(4)";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 1  3  4  5  9 ");
+    }
+
+    #[test]
+    fn test_swap_variables_and_type_mismatch() {
+        let output = run_basic("a$ = \"x\"\nb$ = \"y\"\nSWAP a$, b$\nPRINT a$; b$").expect("Should run");
+        assert_eq!(output, "yx");
+        let err = run_basic("a$ = \"x\"\nSWAP a$, n").expect_err("Should fail");
+        assert_eq!(err, "Type mismatch");
+    }
+
+    #[test]
+    fn test_const_in_for_bound_and_sub() {
+        let code = "CONST LAST = 3, GREETING$ = \"hi\"\n\
+                    SUB Show\nPRINT GREETING$; last\nEND SUB\n\
+                    FOR i = 1 TO LAST\nPRINT i\nNEXT i\n\
+                    CALL Show";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 1 \n 2 \n 3 \nhi 3 ");
+    }
+
+    #[test]
+    fn test_const_cannot_be_redefined() {
+        assert_eq!(run_basic("CONST MAX = 1\nMAX = 2").expect_err("Should fail"), "Duplicate definition");
+        assert_eq!(run_basic("CONST MAX = 1\nDIM MAX").expect_err("Should fail"), "Duplicate definition");
+        assert_eq!(run_basic("CONST MAX = 1, MAX = 2").expect_err("Should fail"), "Duplicate definition");
+    }
+
+    #[test]
+    fn test_open_missing_file_for_input() {
+        let err = run_basic("OPEN \"/nonexistent/qbasic-rs.txt\" FOR INPUT AS #1").expect_err("Should fail");
+        assert!(err.contains("File not found"), "Error: {}", err);
+    }
+
+    #[test]
+    fn test_def_fn() {
+        let code = "DEF FNsquare(x) = x * x
+                    x = 7
+                    PRINT FNsquare(3)
+                    PRINT x
+                    DEF FN half%(n%) = n% / 2
+                    PRINT FNhalf%(7.4)
+                    DEF FNgreet$(n$) = \"Hi \" + n$
+                    PRINT FNgreet$(\"Ann\")";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 9 \n 7 \n 4 \nHi Ann");
+        let err = run_basic("DEF FNf$(a$) = a$\nPRINT FNf$(1)").expect_err("Should fail");
+        assert_eq!(err, "Type mismatch");
+    }
+
+    #[test]
+    fn test_print_tab_and_spc() {
+        let output = run_basic("PRINT TAB(10); \"x\"").expect("Should run");
+        assert_eq!(output, "         x");
+        assert_eq!(output.find('x'), Some(9));
+        let output = run_basic("PRINT \"a\"; SPC(3); \"b\"; SPC(0); \"c\"").expect("Should run");
+        assert_eq!(output, "a   bc");
+        // TAB to a column already passed continues on the next line
+        let output = run_basic("PRINT \"abcdef\"; TAB(3); \"x\"\nPRINT \"y\"").expect("Should run");
+        assert_eq!(output, "abcdef\n  x\ny");
+    }
+
+    #[test]
+    fn test_print_comma_zones() {
+        let output = run_basic("PRINT 1, \"ab\", -2\nPRINT , \"z\"").expect("Should run");
+        assert_eq!(output, format!(" 1 {}ab{}-2 \n{}z", " ".repeat(11), " ".repeat(12), " ".repeat(14)));
+    }
+
+    #[test]
+    fn test_print_trailing_semicolon_continues_line() {
+        let output = run_basic("PRINT \"a\";\nPRINT 5;\nPRINT \"b\",\nPRINT \"c\"\nPRINT \"d\"").expect("Should run");
+        assert_eq!(output, format!("a 5 b{}c\nd", " ".repeat(9)));
+        // An INPUT prompt continues the open line, and Enter ends it
+        let output = run_basic_with_input("PRINT \"Size\";\nINPUT n\nPRINT n", &["7"]).expect("Should run");
+        assert_eq!(output, "Size? \n 7 ");
+    }
+
+    #[test]
+    fn test_locate_cursor_argument() {
+        let run = |code: &str| {
+            let (interp, result) = run_with_interpreter(code);
+            result.expect("Should run");
+            let g = interp.graphics();
+            (g.cursor_row, g.cursor_col, g.cursor_visible)
+        };
+        assert_eq!(run("LOCATE 5, 10, 0"), (5, 10, false));
+        // Omitted row/col keep the position; scanline arguments are ignored
+        assert_eq!(run("LOCATE 4, 2, 0\nLOCATE , , 1, 6, 7"), (4, 2, true));
+        assert_eq!(run("LOCATE 7"), (7, 1, true));
+    }
+
+    #[test]
+    fn test_screen_sets_mode_resolution() {
+        let (interp, result) = run_with_interpreter("SCREEN 13");
+        assert_eq!(result, Ok(()));
+        let g = interp.graphics();
+        assert_eq!((g.mode, g.width, g.height, g.colors), (13, 320, 200, 256));
+        assert_eq!((g.text_cols, g.text_rows), (40, 25));
+        drop(g);
+
+        // Switching modes clears the screen
+        let (interp, result) = run_with_interpreter("SCREEN 13\nPSET (10, 10), 4\nSCREEN 12");
+        assert_eq!(result, Ok(()));
+        let g = interp.graphics();
+        assert_eq!((g.width, g.height, g.text_rows), (640, 480, 30));
+        assert_eq!(g.point(10, 10), 0);
+        drop(g);
+
+        assert_eq!(run_with_interpreter("SCREEN 5").1, Err("Illegal function call".to_string()));
+    }
+
+    #[test]
+    fn test_screen_13_keeps_256_colors() {
+        let output = run_basic("SCREEN 13\nPSET (0, 0), 200\nCOLOR 100\nPSET (1, 0)\nPRINT POINT(0, 0); POINT(1, 0)")
+            .expect("Should run");
+        assert!(output.contains(" 200  100 "), "Got: {:?}", output);
+
+        // The 16-colour modes keep the low four bits
+        let output = run_basic("SCREEN 12\nPSET (0, 0), 200\nPRINT POINT(0, 0)").expect("Should run");
+        assert!(output.contains(" 8 "), "Got: {:?}", output);
+    }
+
+    #[test]
+    fn test_window_and_view_map_logical_points() {
+        // WINDOW puts y = 0 at the bottom; WINDOW SCREEN keeps it at the top
+        let (interp, result) = run_with_interpreter("SCREEN 13\nWINDOW (0, 0)-(319, 199)\nPSET (10, 0), 4");
+        assert_eq!(result, Ok(()));
+        assert_eq!(interp.graphics().point(10, 199), 4);
+        let (interp, result) = run_with_interpreter("SCREEN 13\nWINDOW SCREEN (0, 0)-(31.9, 19.9)\nPSET (1, 2), 4");
+        assert_eq!(result, Ok(()));
+        assert_eq!(interp.graphics().point(10, 20), 4);
+        let (interp, result) = run_with_interpreter("SCREEN 13\nWINDOW (-1, -1)-(1, 1)\nWINDOW\nPSET (5, 6), 4");
+        assert_eq!(result, Ok(()));
+        assert_eq!(interp.graphics().point(5, 6), 4);
+
+        // VIEW moves the origin to the viewport's corner and clips to it
+        let (interp, result) = run_with_interpreter("SCREEN 13\nVIEW (100, 50)-(199, 149)\nPSET (0, 0), 4\nPSET (150, 10), 4");
+        assert_eq!(result, Ok(()));
+        let g = interp.graphics();
+        assert_eq!((g.point(100, 50), g.point(250, 60)), (4, 0));
+        drop(g);
+
+        // WINDOW maps onto the viewport, and circles scale with it
+        let code = "SCREEN 13\nVIEW (100, 50)-(199, 149), , 2\nWINDOW (0, 0)-(9.9, 9.9)\nPSET (0, 0), 4\nCIRCLE (5, 5), 1, 3";
+        let (interp, result) = run_with_interpreter(code);
+        assert_eq!(result, Ok(()));
+        let g = interp.graphics();
+        assert_eq!(g.point(100, 149), 4);
+        assert_eq!(g.point(150 + 10, 99), 3);
+        assert_eq!(g.point(99, 49), 2);
+        drop(g);
+
+        assert_eq!(run_with_interpreter("SCREEN 13\nWINDOW (0, 0)-(0, 10)").1, Err("Illegal function call".to_string()));
+        assert_eq!(run_with_interpreter("SCREEN 13\nVIEW (0, 0)-(400, 10)").1, Err("Illegal function call".to_string()));
+    }
+
+    #[test]
+    fn test_palette_remaps_attribute_colors() {
+        // VGA colours are 65536 * blue + 256 * green + red
+        let (interp, result) = run_with_interpreter("SCREEN 13\nPSET (0, 0), 1\nPALETTE 1, 63");
+        assert_eq!(result, Ok(()));
+        let g = interp.graphics();
+        assert_eq!(g.palette()[1], (255, 0, 0));
+        assert!(g.render_sixel(1).contains("#1;2;100;0;0"));
+        assert_eq!(g.display_attr(1), 4);
+        drop(g);
+
+        // EGA colours are rgbRGB; PALETTE alone resets
+        let (interp, result) = run_with_interpreter("SCREEN 9\nPALETTE 1, 20");
+        assert_eq!(result, Ok(()));
+        assert_eq!(interp.graphics().palette()[1], (170, 85, 0));
+        let (interp, result) = run_with_interpreter("SCREEN 13\nPALETTE 1, 63\nPALETTE");
+        assert_eq!(result, Ok(()));
+        assert_eq!(interp.graphics().palette()[1], (0, 0, 170));
+
+        assert_eq!(run_with_interpreter("PALETTE 16, 1").1, Err("Illegal function call".to_string()));
+        assert_eq!(run_with_interpreter("SCREEN 13\nPALETTE 1, 64").1, Err("Illegal function call".to_string()));
+    }
+
+    #[test]
+    fn test_point_reads_pixels_and_graphics_cursor() {
+        let code = "SCREEN 13\nPSET (10, 20), 9\nPRINT POINT(10, 20); POINT(11, 20); POINT(-1, 5); POINT(320, 0)\n\
+                    LINE (0, 0)-(30, 40), 2\nPRINT POINT(0); POINT(1)\n\
+                    WINDOW (0, 0)-(319, 199)\nPSET (5, 0), 3\nPRINT POINT(5, 0); POINT(2); POINT(3); POINT(1)\n\
+                    WINDOW\nVIEW (10, 10)-(20, 20)\nPRINT POINT(0, 10); POINT(20, 20)";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output.lines().collect::<Vec<_>>(), [" 9  0 -1 -1 ", " 30  40 ", " 3  5  0  199 ", " 9 -1 "]);
+        assert!(run_basic("PRINT POINT(4)").is_err());
+    }
+
+    #[test]
+    fn test_csrlin_and_pos_after_locate() {
+        let stmts = parse_basic("LOCATE 5, 10\nr = CSRLIN: c = POS(0)\nPRINT \"ab\";\nc2 = POS(0)");
+        let mut interp = Interpreter::new();
+        interp.with_graphics_mut(|g| g.screen_output = true);
+        interp.execute(&stmts).expect("Should execute");
+        let s = interp.state.borrow();
+        let value = |name: &str| s.variables.get(name).map(Value::to_int);
+        assert_eq!((value("r"), value("c"), value("c2")), (Some(5), Some(10), Some(12)));
+        drop(s);
+
+        // Without the text screen POS follows the output line
+        let output = run_basic("PRINT \"abc\";\np = POS(0)\nPRINT p\nPRINT POS(0)").expect("Should run");
+        assert_eq!(output.lines().collect::<Vec<_>>(), ["abc 4 ", " 1 "]);
+    }
+
+    #[test]
+    fn test_width_sets_text_size() {
+        let (interp, result) = run_with_interpreter("WIDTH 40");
+        assert_eq!(result, Ok(()));
+        let g = interp.graphics();
+        assert_eq!((g.text_cols, g.text_rows), (40, 25));
+        assert_eq!(g.text_screen.len(), 40 * 25);
+        drop(g);
+
+        let (interp, result) = run_with_interpreter("WIDTH 80, 50\nWIDTH , 43");
+        assert_eq!(result, Ok(()));
+        assert_eq!((interp.graphics().text_cols, interp.graphics().text_rows), (80, 43));
+
+        assert_eq!(run_with_interpreter("WIDTH 64").1, Err("Illegal function call".to_string()));
+        // Still usable as a variable name
+        assert!(run_with_interpreter("WIDTH = 78\nPRINT WIDTH / 2").1.is_ok());
+    }
+
+    #[test]
+    fn test_graphics_primitives_set_pixels() {
+        let code = "SCREEN 13\n\
+                    LINE (0, 0)-(9, 9), 4\n\
+                    LINE (20, 20)-(24, 22), 2, B\n\
+                    LINE (30, 30)-(32, 32), , BF\n\
+                    PSET (50, 50), 5\n\
+                    PSET (51, 50), 5\n\
+                    PRESET (51, 50)\n\
+                    CIRCLE (100, 100), 10, 3";
+        let stmts = parse_basic(code);
+        let mut interp = Interpreter::new();
+        interp.execute(&stmts).expect("Should run");
+        let g = interp.graphics();
+        // Diagonal line
+        assert!((0..10).all(|i| g.point(i, i) == 4));
+        assert_eq!(g.point(1, 0), 0);
+        // Box outline only, filled box in the foreground color
+        assert_eq!((g.point(20, 20), g.point(24, 22), g.point(22, 20), g.point(22, 21)), (2, 2, 2, 0));
+        assert!((30..=32).all(|i| g.point(i, 31) == 15));
+        // PRESET clears to the background
+        assert_eq!((g.point(50, 50), g.point(51, 50)), (5, 0));
+        // Circle points on the axes, centre untouched
+        assert_eq!((g.point(110, 100), g.point(100, 100)), (3, 0));
+    }
+
+    #[test]
+    fn test_paint_fills_bounded_area() {
+        let code = "SCREEN 13\n\
+                    LINE (10, 10)-(30, 30), 2, B\n\
+                    PSET (20, 20), 4\n\
+                    PAINT (15, 15), 4, 2\n\
+                    LINE (50, 10)-(70, 30), 6, B\n\
+                    PAINT (60, 20), 6\n\
+                    SCREEN 12\n\
+                    PAINT (0, 0), 1";
+        let stmts = parse_basic(code);
+        let mut interp = Interpreter::new();
+        let first = parse_basic(&code.lines().take(6).collect::<Vec<_>>().join("\n"));
+        interp.execute(&first).expect("Should run");
+        {
+            let g = interp.graphics();
+            // Interior filled (even past a pixel already in the fill color), border and outside kept
+            assert_eq!((g.point(11, 11), g.point(29, 29), g.point(25, 20)), (4, 4, 4));
+            assert_eq!((g.point(10, 20), g.point(5, 5), g.point(31, 20)), (2, 0, 0));
+            // Without a border color the fill color bounds the area
+            assert_eq!((g.point(60, 20), g.point(45, 20)), (6, 0));
+        }
+
+        // A whole-screen fill uses the explicit stack rather than recursion
+        interp.execute(&stmts).expect("Should run");
+        let g = interp.graphics();
+        assert_eq!((g.point(0, 0), g.point(639, 479)), (1, 1));
+    }
+
+    #[test]
+    fn test_get_put_copies_image() {
+        let code = "SCREEN 13\n\
+                    DIM sprite%(20)\n\
+                    LINE (0, 0)-(3, 3), 5, BF\n\
+                    PSET (1, 2), 9\n\
+                    GET (0, 0)-(3, 3), sprite%\n\
+                    PUT (100, 50), sprite%, PSET\n\
+                    PUT (200, 50), sprite%\n\
+                    PUT (200, 50), sprite%";
+        let stmts = parse_basic(code);
+        let mut interp = Interpreter::new();
+        let first = parse_basic(&code.lines().take(7).collect::<Vec<_>>().join("\n"));
+        interp.execute(&first).expect("Should run");
+        {
+            let g = interp.graphics();
+            for y in 0..4 {
+                for x in 0..4 {
+                    assert_eq!(g.point(100 + x, 50 + y), g.point(x, y));
+                }
+            }
+            assert_eq!((g.point(101, 52), g.point(103, 53), g.point(104, 50)), (9, 5, 0));
+            assert_eq!(g.point(201, 52), 9);
+        }
+
+        // Drawing the same image twice with XOR restores the background
+        interp.execute(&stmts).expect("Should run");
+        let g = interp.graphics();
+        assert_eq!((g.point(200, 50), g.point(201, 52)), (0, 0));
+    }
+
+    #[test]
+    fn test_sound_statements_run_to_completion() {
+        let code = "BEEP\n\
+                    SOUND 440, 18.2\n\
+                    PLAY \"T120 O2 L4 C D E F G\"\n\
+                    PRINT \"done\"";
+        let stmts = parse_basic(code);
+        let mut interp = Interpreter::new();
+        interp.execute(&stmts).expect("Should run to completion");
+        assert_eq!(interp.take_output(), vec!["done"]);
+
+        let events = interp.take_sound_events();
+        assert_eq!(events[0], SoundEvent::Beep);
+        assert_eq!(events[1], SoundEvent::Tone { frequency: 440.0, seconds: 1.0 });
+        // Five notes, each followed by the gap of normal articulation
+        assert_eq!(events.len(), 12);
+        assert!(interp.take_sound_events().is_empty());
+
+        assert_eq!(run_basic("PLAY \"Z\"").expect_err("Should fail"), "Illegal function call");
+        assert_eq!(run_basic("SOUND 10, 1").expect_err("Should fail"), "Illegal function call");
+    }
+
+    struct FixedClock(LocalTime);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> LocalTime {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_clock_functions() {
+        let code = "PRINT TIME$\nPRINT DATE$\nPRINT TIMER";
+        let stmts = parse_basic(code);
+        let mut interp = Interpreter::new();
+        let now = LocalTime { year: 1991, month: 6, day: 3, seconds: 9.0 * 3600.0 + 5.0 * 60.0 + 7.25 };
+        interp.set_clock(Box::new(FixedClock(now)));
+        interp.execute(&stmts).expect("Should run");
+        assert_eq!(interp.take_output().join("\n"), "09:05:07\n06-03-1991\n 32707.25 ");
+    }
+
+    #[test]
+    fn test_sleep_resumes_after_timeout_or_key() {
+        let code = "PRINT 1\nSLEEP 0.01\nPRINT 2";
+        let stmts = parse_basic(code);
+        let mut interp = Interpreter::new();
+        let start = Instant::now();
+        let mut result = interp.execute_with_debug(&stmts).expect("Should run");
+        while matches!(result, ExecutionResult::NeedsInput | ExecutionResult::Running) {
+            assert!(interp.sleep_deadline().is_some());
+            result = interp.continue_execution(&stmts).expect("Should run");
+        }
+        assert!(matches!(result, ExecutionResult::Completed));
+        assert!(start.elapsed() >= std::time::Duration::from_millis(10));
+        assert_eq!(interp.take_output(), vec![" 1 ", " 2 "]);
+
+        // A key press ends a long SLEEP, or one without a duration
+        for code in ["SLEEP 100\nPRINT 3", "SLEEP\nPRINT 3"] {
+            let stmts = parse_basic(code);
+            let mut interp = Interpreter::new();
+            let result = interp.execute_with_debug(&stmts).expect("Should run");
+            assert!(matches!(result, ExecutionResult::NeedsInput));
+            interp.set_last_key(Some('x'));
+            let result = interp.continue_execution(&stmts).expect("Should run");
+            assert!(matches!(result, ExecutionResult::Completed), "{}", code);
+            assert_eq!(interp.take_output(), vec![" 3 "]);
+        }
+    }
+
+    #[test]
+    fn test_function_bodies_wait_for_keys_and_input() {
+        let code = "PRINT \"Got \"; WaitKey$\nFUNCTION WaitKey$\nDO\nk$ = INKEY$\nLOOP UNTIL k$ <> \"\"\nWaitKey$ = k$\nEND FUNCTION";
+        let stmts = parse_basic(code);
+
+        // The host gets control back while the FUNCTION polls the keyboard
+        let mut interp = Interpreter::new();
+        assert!(matches!(interp.execute_with_debug(&stmts), Ok(ExecutionResult::Running)));
+        interp.set_last_key(Some('q'));
+        let mut result = interp.continue_execution(&stmts);
+        while matches!(result, Ok(ExecutionResult::Running)) {
+            result = interp.continue_execution(&stmts);
+        }
+        assert!(matches!(result, Ok(ExecutionResult::Completed)));
+        assert_eq!(interp.take_output(), vec!["Got q"]);
+
+        // and can stop it there, without the rest of the statement running
+        let mut interp = Interpreter::new();
+        assert!(matches!(interp.execute_with_debug(&stmts), Ok(ExecutionResult::Running)));
+        interp.request_stop();
+        assert!(matches!(interp.continue_execution(&stmts), Ok(ExecutionResult::Stopped)));
+        assert!(interp.take_output().is_empty());
+        assert_eq!(interp.get_error(), None);
+
+        let code = "n = Twice(0)\nPRINT n\nFUNCTION Twice (x)\nINPUT \"Number\"; x\nTwice = x * 2\nEND FUNCTION";
+        assert_eq!(run_basic_with_input(code, &["21"]).expect("Should run"), "Number? \n 42 ");
+    }
+
+    #[test]
+    fn test_function_bodies_trap_errors_and_gosub() {
+        // The module's error handler runs for an error inside a FUNCTION, and
+        // RESUME NEXT carries on in its body
+        let code = "ON ERROR GOTO Handler\n\
+                    PRINT Safe(0)\n\
+                    END\n\
+                    Handler:\n\
+                    PRINT \"trapped\"; ERR\n\
+                    RESUME NEXT\n\
+                    FUNCTION Safe (d)\n\
+                    Safe = 1 / d\n\
+                    Safe = 2\n\
+                    END FUNCTION";
+        assert_eq!(run_basic(code).expect("Should run"), "trapped 11 \n 2 ");
+
+        // GOSUB finds its label and RETURNs into the FUNCTION
+        let code = "PRINT Twice(4)\n\
+                    END\n\
+                    Grow:\n\
+                    n = n * 2\n\
+                    RETURN\n\
+                    FUNCTION Twice (n)\n\
+                    GOSUB Grow\n\
+                    Twice = n\n\
+                    END FUNCTION";
+        assert_eq!(run_basic(code).expect("Should run"), " 8 ");
+    }
+
+    #[test]
+    fn test_single_line_if_else() {
+        let code = "a = 2: b = 1\n\
+                    IF a > b THEN PRINT \"hi\": PRINT \"there\" ELSE PRINT \"lo\"\n\
+                    IF a < b THEN PRINT \"hi\" ELSE PRINT \"lo\": PRINT \"again\"\n\
+                    IF a < b THEN PRINT \"never\"\n\
+                    IF a > b THEN 100\n\
+                    PRINT \"skipped\"\n\
+                    100 IF a < b THEN PRINT \"never\" ELSE 200\n\
+                    PRINT \"skipped\"\n\
+                    200 PRINT \"done\"";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, "hi\nthere\nlo\nagain\ndone");
+    }
+
+    #[test]
+    fn test_recursive_function() {
+        let code = "PRINT Fact#(10)\n\
+                    PRINT Fact#(5) + 1\n\
+                    FUNCTION Fact# (n)\n\
+                    IF n <= 1 THEN Fact# = 1: EXIT FUNCTION\n\
+                    Fact# = n * Fact#(n - 1)\n\
+                    END FUNCTION";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 3628800 \n 121 ");
+    }
+
+    #[test]
+    fn test_procedure_locals_and_shared() {
+        let code = "DIM SHARED total\n\
+                    x = 5\n\
+                    y = 7\n\
+                    CALL Bump(3)\n\
+                    CALL Bump(4)\n\
+                    PRINT x; y; total; n\n\
+                    SUB Bump (n)\n\
+                    SHARED y\n\
+                    PRINT x;\n\
+                    x = n\n\
+                    y = y + 1\n\
+                    total = total + n\n\
+                    END SUB";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 0  0  5  9  7  0 ");
+    }
+
+    #[test]
+    fn test_sub_updates_shared_counter() {
+        let code = "COMMON SHARED hits\n\
+                    DIM log$(3)\n\
+                    count = 10\n\
+                    FOR i = 1 TO 3\n\
+                    CALL Tally(i)\n\
+                    NEXT\n\
+                    PRINT count; hits; log$(2)\n\
+                    SUB Tally (k)\n\
+                    SHARED count, log$()\n\
+                    count = count + k\n\
+                    hits = hits + 1\n\
+                    log$(k) = \"#\" + LTRIM$(STR$(k))\n\
+                    END SUB";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 16  3 #2");
+    }
+
+    #[test]
+    fn test_static_locals_persist() {
+        let code = "FOR i = 1 TO 3\n\
+                    CALL Counter\n\
+                    PRINT NextId; Total(i)\n\
+                    NEXT\n\
+                    SUB Counter\n\
+                    STATIC calls\n\
+                    temp = temp + 1\n\
+                    calls = calls + 1\n\
+                    PRINT calls; temp;\n\
+                    END SUB\n\
+                    FUNCTION NextId STATIC\n\
+                    id = id + 1\n\
+                    NextId = id\n\
+                    END FUNCTION\n\
+                    FUNCTION Total (n) STATIC\n\
+                    sum = sum + n\n\
+                    Total = sum\n\
+                    END FUNCTION";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, " 1  1  1  1 \n 2  1  2  3 \n 3  1  3  6 ");
+    }
+
+    #[test]
+    fn test_static_locals_are_shared_by_recursive_calls() {
+        // The inner call's changes are seen by the outer call still running
+        let code = "Deep 1\nPRINT\nCount 1\n\
+                    SUB Deep (d) STATIC\nc = c + 1\nIF d > 0 THEN Deep d - 1\nc = c + 1\nPRINT c;\nEND SUB\n\
+                    SUB Count (d)\nSTATIC c\nc = c + 1\nIF d > 0 THEN Count d - 1\nc = c + 1\nPRINT c;\nEND SUB";
+        assert_eq!(run_basic(code).expect("Should run"), " 3  4 \n 3  4 ");
+    }
+
+    #[test]
+    fn test_end_stop_and_system() {
+        let output = run_basic("PRINT 1\nIF 1 THEN END\nPRINT 2").expect("Should run");
+        assert_eq!(output, " 1 ");
+
+        let run = |code: &str| {
+            let stmts = parse_basic(code);
+            let mut interp = Interpreter::new();
+            let result = interp.execute_with_debug(&stmts).expect("Should run");
+            (interp, stmts, result)
+        };
+
+        // STOP pauses like a breakpoint and CONTINUE carries on after it
+        let (mut interp, stmts, result) = run("PRINT 1\nSTOP\nPRINT 2");
+        assert!(matches!(result, ExecutionResult::Breakpoint(1)));
+        assert_eq!(interp.take_output(), vec![" 1 "]);
+        let result = interp.continue_execution(&stmts).expect("Should run");
+        assert!(matches!(result, ExecutionResult::Completed));
+        assert_eq!(interp.take_output(), vec![" 2 "]);
+
+        // END inside a FUNCTION still completes normally
+        let (_, _, result) = run("x = F\nPRINT x\nFUNCTION F\nEND\nEND FUNCTION");
+        assert!(matches!(result, ExecutionResult::Completed));
+
+        let (mut interp, _, result) = run("PRINT 1\nSYSTEM\nPRINT 2");
+        assert!(matches!(result, ExecutionResult::Exited));
+        assert_eq!(interp.take_output(), vec![" 1 "]);
+    }
+
+    #[test]
+    fn test_step_over_and_into_procedures() {
+        let code = "PRINT 1\nCALL Greet\nPRINT 3\nSUB Greet\nPRINT 2\nEND SUB";
+        let stmts = parse_basic(code);
+        let sub_line = stmts.line(stmts.iter().position(|s| matches!(s, Stmt::Sub { .. })).expect("SUB"));
+
+        let start = |interp: &mut Interpreter| {
+            interp.set_step_mode(true);
+            assert!(matches!(interp.execute_with_debug(&stmts), Ok(ExecutionResult::Stepped(0))));
+            assert!(matches!(interp.continue_execution(&stmts), Ok(ExecutionResult::Stepped(1))));
+        };
+
+        // Procedure Step runs the whole SUB and stops on the line after the call
+        let mut interp = Interpreter::new();
+        start(&mut interp);
+        interp.set_step_over(true);
+        assert!(matches!(interp.continue_execution(&stmts), Ok(ExecutionResult::Stepped(2))));
+        assert_eq!(interp.take_output(), vec![" 1 ", " 2 "]);
+
+        // Step stops inside it first
+        let mut interp = Interpreter::new();
+        start(&mut interp);
+        interp.set_step_over(false);
+        let result = interp.continue_execution(&stmts);
+        assert!(matches!(result, Ok(ExecutionResult::Stepped(line)) if line == sub_line + 1));
+        assert_eq!(interp.take_output(), vec![" 1 "]);
+        // Then at its END SUB, then back after the call
+        let result = interp.continue_execution(&stmts);
+        assert!(matches!(result, Ok(ExecutionResult::Stepped(line)) if line == sub_line + 2));
+        assert!(matches!(interp.continue_execution(&stmts), Ok(ExecutionResult::Stepped(2))));
+        assert_eq!(interp.take_output(), vec![" 2 "]);
+    }
+
+    #[test]
+    fn test_steps_stop_inside_loop_bodies() {
+        let code = "FOR i = 1 TO 2\nPRINT i\nNEXT\nCALL Count\nSUB Count\nDO WHILE n < 1\nn = n + 1\nLOOP\nEND SUB";
+        let stmts = parse_basic(code);
+        let steps = |over: bool| {
+            let mut interp = Interpreter::new();
+            interp.set_step_mode(true);
+            interp.set_step_over(over);
+            let mut lines = Vec::new();
+            let mut result = interp.execute_with_debug(&stmts);
+            while let Ok(ExecutionResult::Stepped(line)) = result {
+                lines.push(line);
+                result = interp.continue_execution(&stmts);
+            }
+            assert!(matches!(result, Ok(ExecutionResult::Completed)));
+            lines
+        };
+
+        // Step stops on every statement of the FOR body, and in the SUB's loop
+        assert_eq!(steps(false), vec![0, 1, 1, 3, 5, 6, 8, 4]);
+        // Procedure Step still stops in the body, but runs the SUB in one go
+        assert_eq!(steps(true), vec![0, 1, 1, 3, 4]);
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let code = "FOR i = 1 TO 10\nPRINT i\nNEXT";
+        let stmts = parse_basic(code);
+        let print_line = 1;
+        let condition = |text: &str| Parser::new(Lexer::new(text).tokenize()).parse_expression().expect("condition");
+
+        // Only stops once i reaches 5
+        let mut interp = Interpreter::new();
+        interp.set_breakpoints(&[print_line]);
+        interp.set_breakpoint_conditions(HashMap::from([(print_line, condition("i = 5"))]));
+        let result = interp.execute_with_debug(&stmts);
+        assert!(matches!(result, Ok(ExecutionResult::Breakpoint(line)) if line == print_line));
+        assert_eq!(interp.take_output(), vec![" 1 ", " 2 ", " 3 ", " 4 "]);
+        assert_eq!(interp.take_breakpoint_error(), None);
+        assert!(matches!(interp.continue_execution(&stmts), Ok(ExecutionResult::Completed)));
+        assert_eq!(interp.take_output(), vec![" 5 ", " 6 ", " 7 ", " 8 ", " 9 ", " 10 "]);
+
+        // A condition that can't be evaluated stops and reports why
+        let mut interp = Interpreter::new();
+        interp.set_breakpoints(&[print_line]);
+        interp.set_breakpoint_conditions(HashMap::from([(print_line, condition("1 / (i - 1) > 0"))]));
+        let result = interp.execute_with_debug(&stmts);
+        assert!(matches!(result, Ok(ExecutionResult::Breakpoint(line)) if line == print_line));
+        assert!(interp.take_breakpoint_error().is_some());
+    }
+
+    #[test]
+    fn test_call_stack_reports_nested_calls() {
+        let code = "CALL Outer\nSUB Outer\nCALL Inner\nEND SUB\nSUB Inner\nSTOP\nEND SUB";
+        let stmts = parse_basic(code);
+        let outer_line = stmts.iter().position(|s| matches!(s, Stmt::Sub { name, .. } if name == "Outer")).expect("Outer");
+
+        let mut interp = Interpreter::new();
+        assert!(matches!(interp.execute_with_debug(&stmts), Ok(ExecutionResult::Breakpoint(_))));
+        assert_eq!(interp.call_stack(), vec![("Outer".to_string(), 0), ("Inner".to_string(), outer_line + 1)]);
+
+        assert!(matches!(interp.continue_execution(&stmts), Ok(ExecutionResult::Completed)));
+        assert!(interp.call_stack().is_empty());
+    }
+
+    #[test]
+    fn test_breakpoints_and_steps_in_a_later_sub_use_source_lines() {
+        let code = "CALL First\nCALL Second\nEND\n\nSUB First\n  PRINT 1\nEND SUB\n\n                    SUB Second\n  ' note\n\n  PRINT 2: PRINT 3\n  PRINT 4\nEND SUB";
+        let stmts = parse_basic(code);
+
+        // A breakpoint on the second SUB's PRINT 4 line stops there
+        let mut interp = Interpreter::new();
+        interp.set_breakpoints(&[12]);
+        assert!(matches!(interp.execute_with_debug(&stmts), Ok(ExecutionResult::Breakpoint(12))));
+        assert_eq!(interp.take_output(), vec![" 1 ", " 2 ", " 3 "]);
+        assert_eq!(interp.call_stack(), vec![("Second".to_string(), 1)]);
+        assert!(matches!(interp.continue_execution(&stmts), Ok(ExecutionResult::Completed)));
+
+        // Stepping into it visits its lines, then its END SUB
+        let mut interp = Interpreter::new();
+        interp.set_step_mode(true);
+        let mut lines = vec![];
+        let mut result = interp.execute_with_debug(&stmts);
+        while let Ok(ExecutionResult::Stepped(line)) = result {
+            lines.push(line);
+            result = interp.continue_execution(&stmts);
+        }
+        assert_eq!(lines, vec![0, 5, 6, 1, 11, 11, 12, 13, 2]);
+    }
+
+    #[test]
+    fn test_immediate_statements_keep_variables() {
+        let immediate = |interp: &mut Interpreter, code: &str| {
+            let stmts = parse_basic(code);
+            interp.execute_immediate(&stmts)
+        };
+
+        let mut interp = Interpreter::new();
+        immediate(&mut interp, "x = 5").unwrap();
+        immediate(&mut interp, "x = x + 1").unwrap();
+        immediate(&mut interp, "? x;: PRINT x * 2").unwrap();
+        assert_eq!(interp.take_output(), vec![" 6  12 "]);
+
+        // Changes made while paused are seen when the program carries on
+        let stmts = parse_basic("x = 1\nSTOP\nPRINT x");
+        assert!(matches!(interp.execute_with_debug(&stmts), Ok(ExecutionResult::Breakpoint(_))));
+        immediate(&mut interp, "x = x + 41").unwrap();
+        assert!(matches!(interp.continue_execution(&stmts), Ok(ExecutionResult::Completed)));
+        assert_eq!(interp.take_output(), vec![" 42 "]);
+    }
+
+    #[test]
+    fn test_variable_names_in_scope() {
+        let code = "total = 1\nname$ = \"a\"\nCALL Work\nSUB Work\n  count = 2\n  STOP\nEND SUB";
+        let stmts = parse_basic(code);
+        let mut interp = Interpreter::new();
+        assert!(matches!(interp.execute_with_debug(&stmts), Ok(ExecutionResult::Breakpoint(_))));
+        assert_eq!(interp.variable_names(), vec!["count"]);
+
+        assert!(matches!(interp.continue_execution(&stmts), Ok(ExecutionResult::Completed)));
+        assert_eq!(interp.variable_names(), vec!["name$", "total"]);
+    }
+
+    #[test]
+    fn test_lbound_and_ubound() {
+        let code = "DIM a(1 TO 10)\nPRINT LBOUND(a); UBOUND(a)\n\
+                    DIM b(-2 TO 3, 5)\nPRINT LBOUND(b, 1); UBOUND(b, 1); LBOUND(b, 2); UBOUND(b, 2)\n\
+                    OPTION BASE 1\nDIM c(4)\nPRINT LBOUND(c); UBOUND(c)";
+        let output = run_basic(code).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines, vec![" 1  10 ", "-2  3  0  5 ", " 1  4 "]);
+
+        let err = run_basic("DIM b(5, 5)\nPRINT UBOUND(b, 3)").unwrap_err();
+        assert!(err.contains("Subscript out of range"), "Error: {}", err);
+        let err = run_basic("PRINT LBOUND(z)").unwrap_err();
+        assert!(err.contains("Array not defined"), "Error: {}", err);
+    }
+
+    #[test]
+    fn test_dim_with_explicit_lower_bounds() {
+        let code = "DIM m(0 TO 5, -3 TO 3)\nm(5, -3) = 7\nm(0, 3) = 2\nPRINT m(5, -3) + m(0, 3)\n\
+                    REDIM PRESERVE m(0 TO 5, -3 TO 4)\nPRINT m(5, -3); m(0, 4)";
+        let output = run_basic(code).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines, vec![" 9 ", " 7  0 "]);
+
+        let err = run_basic("DIM a(1 TO 5)\na(5) = 1\nPRINT a(0)").unwrap_err();
+        assert!(err.contains("Subscript out of range"), "Error: {}", err);
+        let err = run_basic("DIM a(5 TO 1)").unwrap_err();
+        assert!(err.contains("Subscript out of range"), "Error: {}", err);
+    }
+
+    #[test]
+    fn test_string_comparison_and_concatenation() {
+        let code = "PRINT \"abc\" < \"abd\"; \"abc\" > \"ab\"; \"B\" < \"a\"; \"x\" = \"X\"; \"a\" <> \"b\"\n\
+                    s$ = \"\"\nFOR i = 1 TO 3\ns$ = s$ + CHR$(64 + i) + \"-\"\nNEXT\nPRINT s$";
+        let output = run_basic(code).expect("Should run");
+        assert_eq!(output, "-1 -1 -1  0 -1 \nA-B-C-");
+
+        for code in ["PRINT \"1\" = 1", "PRINT 2 < \"3\"", "PRINT \"a\" + 1", "PRINT \"a\" - \"b\""] {
+            let err = run_basic(code).expect_err("Should fail");
+            assert_eq!(err, "Type mismatch", "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_integer_arithmetic_overflows() {
+        for code in ["a& = 2147483647\nPRINT a& * a& * 4", "PRINT 300 * 300", "PRINT -32768 \\ -1", "a% = -32768\nPRINT a% - 1"] {
+            assert_eq!(run_basic(code).expect_err("Should fail"), "Overflow", "{}", code);
+        }
+
+        // LONG operands widen the range
+        let code = "x% = 200\na& = 300\n\
+                    PRINT x% * 2; a& * 300; 40000 - 1; -32768 \\ 1\n\
+                    PRINT Square&(1000)\n\
+                    FUNCTION Square& (n AS LONG)\nSquare& = n * n\nEND FUNCTION";
+        assert_eq!(run_basic(code).expect("Should run"), " 400  90000  39999 -32768 \n 1000000 ");
+    }
+
+    #[test]
+    fn test_runtime_errors_record_their_line() {
+        let run = |code: &str| {
+            let (interp, result) = run_with_interpreter(code);
+            (result.expect_err("Should fail"), interp.error_line())
+        };
+        assert_eq!(run("a% = 32767\nPRINT a%\na% = a% + 1"), ("Overflow".to_string(), Some(2)));
+        assert_eq!(run("b& = 40000\nc% = b&"), ("Overflow".to_string(), Some(1)));
+        assert_eq!(run("x = 0\nPRINT 1 / x\nPRINT 2"), ("Division by zero".to_string(), Some(1)));
+        assert_eq!(run("PRINT 7 MOD 0"), ("Division by zero".to_string(), Some(0)));
+
+        // Resetting for the next run forgets it
+        let (mut interp, _) = run_with_interpreter("PRINT 1\nPRINT 1 / 0");
+        assert_eq!(interp.error_line(), Some(1));
+        interp.reset();
+        assert_eq!(interp.error_line(), None);
+
+        // A trapped error doesn't stop the program
+        let code = "ON ERROR GOTO handler\nPRINT 1 / 0\nEND\nhandler:\nRESUME NEXT";
+        let stmts = parse_basic(code);
+        let mut interp = Interpreter::new();
+        assert!(interp.execute(&stmts).is_ok());
+        assert_eq!(interp.error_line(), None);
+    }
+
+    #[test]
+    fn test_error_line_is_the_source_line() {
+        let line = |code: &str| run_with_interpreter(code).0.error_line();
+        // Blank lines, comments and colon-separated statements
+        assert_eq!(line("' c\n\nx = 0\n\nPRINT 1 / x"), Some(4));
+        assert_eq!(line("REM start\nx = 0: y = 1\n\nPRINT y: PRINT y / x"), Some(3));
+        // Inside a loop body and an IF block
+        assert_eq!(line("FOR i = 1 TO 3\n\n  PRINT i\n  PRINT 1 / (2 - i)\nNEXT"), Some(3));
+        assert_eq!(line("x = 0\nIF x = 0 THEN\n  PRINT x\n\n  PRINT 1 / x\nEND IF"), Some(4));
+        // Inside a SUB body, and in the calling line once the SUB returns
+        let code = "CALL Work(0)\nEND\n\nSUB Work (n)\n  PRINT n\n\n  PRINT 1 / n\nEND SUB";
+        assert_eq!(line(code), Some(6));
+        let code = "CALL Work(0): PRINT 1 / 0\nEND\nSUB Work (n)\n  PRINT n\nEND SUB";
+        assert_eq!(line(code), Some(0));
+    }
+
+    #[test]
+    fn test_declare_checks_calls() {
+        let code = "DECLARE SUB Total (a AS INTEGER, b AS INTEGER)\nDECLARE FUNCTION Twice (x)\n\
+                    CALL Total(2.6, 1)\nPRINT Twice(4)\nCALL Hello\n\
+                    SUB Total (a AS INTEGER, b AS INTEGER)\nPRINT a + b\nEND SUB\n\
+                    FUNCTION Twice (x)\nTwice = x * 2\nEND FUNCTION\n\
+                    SUB Hello\nPRINT \"hi\"\nEND SUB";
+        // Parameters are converted to their AS type; Hello needs no DECLARE
+        assert_eq!(run_basic(code).expect("Should run"), " 4 \n 8 \nhi");
+
+        let program = |call: &str| {
+            format!("DECLARE SUB Show (n AS STRING)\nDECLARE FUNCTION Twice (x)\n{}\n\
+                     SUB Show (n AS STRING)\nPRINT n\nEND SUB\n\
+                     FUNCTION Twice (x)\nTwice = x * 2\nEND FUNCTION", call)
+        };
+        assert_eq!(run_basic(&program("CALL Show(\"a\", \"b\")")).expect_err("Should fail"), "Argument-count mismatch");
+        assert_eq!(run_basic(&program("PRINT Twice(1, 2)")).expect_err("Should fail"), "Argument-count mismatch");
+        assert_eq!(run_basic(&program("CALL Show(5)")).expect_err("Should fail"), "Parameter type mismatch");
+
+        // A call must also match a DECLARE that disagrees with the definition
+        let err = run_basic("DECLARE SUB Pair (a)\nCALL Pair(1, 2)\nSUB Pair (a, b)\nEND SUB").expect_err("Should fail");
+        assert_eq!(err, "Argument-count mismatch");
+    }
+
+    #[test]
+    fn test_sub_arguments_pass_by_reference() {
+        let code = "DIM a(3)\nn = 5\na(2) = 7\ni = 2\n\
+                    CALL Twice(n)\nTwice a(i)\nTwice n + 1\nTwice (n)\n\
+                    PRINT n; a(2)\nShow n, \"x\"\n\
+                    SUB Twice (v)\nv = v * 2\nEND SUB\n\
+                    SUB Show (p, q$)\nPRINT \"show\"; p; q$\nEND SUB";
+        // Variables and array elements are updated; expressions, including a
+        // parenthesized variable, pass a copy
+        assert_eq!(run_basic(code).expect("Should run"), " 10  14 \nshow 10 x");
+
+        let nested = "x = 1\nOuter x\nPRINT x\n\
+                      SUB Outer (a)\nInner a\nEND SUB\n\
+                      SUB Inner (b)\nb = b + 41\nEND SUB";
+        assert_eq!(run_basic(nested).expect("Should run"), " 42 ");
+    }
+
+    #[test]
+    fn test_arguments_share_the_callers_variable() {
+        // The parameter is the caller's variable, not a copy made at the call
+        let shared = "x = 1\nBump x\nPRINT x\n\
+                      SUB Bump (a)\nSHARED x\nx = 5\na = a + 1\nEND SUB";
+        assert_eq!(run_basic(shared).expect("Should run"), " 6 ");
+
+        let function = "x = 1\ny = Inc(x)\nPRINT x; y\n\
+                        FUNCTION Inc (a)\na = a + 1\nInc = a * 10\nEND FUNCTION";
+        assert_eq!(run_basic(function).expect("Should run"), " 2  20 ");
+
+        // A subscript with a side effect is evaluated once
+        let once = "DIM a(5)\na(1) = 3\nTwice a(Nxt)\nPRINT n; a(1)\n\
+                    SUB Twice (v)\nv = v * 2\nEND SUB\n\
+                    FUNCTION Nxt\nSHARED n\nn = n + 1\nNxt = n\nEND FUNCTION";
+        assert_eq!(run_basic(once).expect("Should run"), " 1  6 ");
+    }
 }
@@ -55,10 +55,12 @@ pub enum Keyword {
     Exit,
 
     // Declarations
-    Dim, As, Let,
+    Dim, ReDim, Preserve, As, Let,
+    Option, Base,
+    DefInt, DefLng, DefSng, DefDbl, DefStr,
     Const,
     Sub, Function,
-    Shared, Static,
+    Shared, Static, Common,
     Type,
 
     // Data types
@@ -68,7 +70,7 @@ pub enum Keyword {
     Data, Read, Restore,
 
     // I/O
-    Print, Input, Open, Close, Write,
+    Print, Using, Input, Open, Close, Write,
     Line, Get, Put,
     Append, Output, Random, Binary,
 
@@ -85,12 +87,12 @@ pub enum Keyword {
     Rem,
     Def, Fn,
     On, Error, Resume,
-    Call,
+    Call, Declare,
     Swap,
     Beep, Sound, Play,
     Sleep,
     Randomize,
-    Stop,
+    Stop, System,
 }
 
 /// A token with position info
@@ -127,17 +129,19 @@ impl<'a> Lexer<'a> {
         lexer
     }
 
+    /// Move to the next character; `line` and `column` are always those of
+    /// `current_char`, so a newline counts as the end of its own line
     fn advance(&mut self) -> Option<char> {
         let prev = self.current_char;
-        self.current_char = self.input.next();
-        if let Some(c) = self.current_char {
-            if c == '\n' {
+        match prev {
+            Some('\n') => {
                 self.line += 1;
                 self.column = 1;
-            } else {
-                self.column += 1;
             }
+            Some(_) => self.column += 1,
+            None => {}
         }
+        self.current_char = self.input.next();
         prev
     }
 
@@ -238,6 +242,40 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Read a `&H` (hex), `&O` or bare `&` (octal) literal; the leading `&` is current.
+    /// Like QBasic, values that fit in 16 bits are INTEGER and wrap negative
+    /// (`&HFFFF` is -1) unless the trailing `&` suffix makes them LONG.
+    fn read_radix_number(&mut self) -> TokenKind {
+        self.advance(); // Skip '&'
+        let radix = match self.peek() {
+            Some('H' | 'h') => {
+                self.advance();
+                16
+            }
+            Some('O' | 'o') => {
+                self.advance();
+                8
+            }
+            _ => 8,
+        };
+
+        let mut value: u64 = 0;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(radix)) {
+            value = (value * u64::from(radix) + u64::from(digit)) & 0xFFFF_FFFF;
+            self.advance();
+        }
+
+        let long = self.peek() == Some('&');
+        if long {
+            self.advance();
+        }
+        if long || value > 0xFFFF {
+            TokenKind::Integer(i64::from(value as u32 as i32))
+        } else {
+            TokenKind::Integer(i64::from(value as u16 as i16))
+        }
+    }
+
     fn read_string(&mut self) -> TokenKind {
         self.advance(); // Skip opening quote
         let mut s = String::new();
@@ -312,6 +350,15 @@ impl<'a> Lexer<'a> {
 
             // Declarations
             "DIM" => Some(Keyword::Dim),
+            "REDIM" => Some(Keyword::ReDim),
+            "PRESERVE" => Some(Keyword::Preserve),
+            "OPTION" => Some(Keyword::Option),
+            "BASE" => Some(Keyword::Base),
+            "DEFINT" => Some(Keyword::DefInt),
+            "DEFLNG" => Some(Keyword::DefLng),
+            "DEFSNG" => Some(Keyword::DefSng),
+            "DEFDBL" => Some(Keyword::DefDbl),
+            "DEFSTR" => Some(Keyword::DefStr),
             "AS" => Some(Keyword::As),
             "LET" => Some(Keyword::Let),
             "CONST" => Some(Keyword::Const),
@@ -319,6 +366,7 @@ impl<'a> Lexer<'a> {
             "FUNCTION" => Some(Keyword::Function),
             "SHARED" => Some(Keyword::Shared),
             "STATIC" => Some(Keyword::Static),
+            "COMMON" => Some(Keyword::Common),
             "TYPE" => Some(Keyword::Type),
 
             // Data types
@@ -335,6 +383,7 @@ impl<'a> Lexer<'a> {
 
             // I/O
             "PRINT" => Some(Keyword::Print),
+            "USING" => Some(Keyword::Using),
             "INPUT" => Some(Keyword::Input),
             "OPEN" => Some(Keyword::Open),
             "CLOSE" => Some(Keyword::Close),
@@ -379,6 +428,7 @@ impl<'a> Lexer<'a> {
             "ERROR" => Some(Keyword::Error),
             "RESUME" => Some(Keyword::Resume),
             "CALL" => Some(Keyword::Call),
+            "DECLARE" => Some(Keyword::Declare),
             "SWAP" => Some(Keyword::Swap),
             "BEEP" => Some(Keyword::Beep),
             "SOUND" => Some(Keyword::Sound),
@@ -386,6 +436,7 @@ impl<'a> Lexer<'a> {
             "SLEEP" => Some(Keyword::Sleep),
             "RANDOMIZE" => Some(Keyword::Randomize),
             "STOP" => Some(Keyword::Stop),
+            "SYSTEM" => Some(Keyword::System),
 
             _ => None,
         }
@@ -425,6 +476,10 @@ impl<'a> Lexer<'a> {
 
             Some(c) if c.is_ascii_digit() => self.read_number(),
 
+            Some('&') if matches!(self.peek_next(), Some('H' | 'h' | 'O' | 'o' | '0'..='7')) => {
+                self.read_radix_number()
+            }
+
             Some('.') => {
                 if let Some(next) = self.peek_next() {
                     if next.is_ascii_digit() {
@@ -450,6 +505,12 @@ impl<'a> Lexer<'a> {
                 }
             }
 
+            // ? is shorthand for PRINT
+            Some('?') => {
+                self.advance();
+                TokenKind::Keyword(Keyword::Print)
+            }
+
             Some('+') => {
                 self.advance();
                 TokenKind::Plus
@@ -549,3 +610,44 @@ impl<'a> Lexer<'a> {
         tokens
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first_kind(source: &str) -> TokenKind {
+        Lexer::new(source).next_token().kind
+    }
+
+    #[test]
+    fn test_hex_literals() {
+        assert_eq!(first_kind("&HFF"), TokenKind::Integer(255));
+        assert_eq!(first_kind("&h1f"), TokenKind::Integer(31));
+        assert_eq!(first_kind("&HFFFF"), TokenKind::Integer(-1));
+        assert_eq!(first_kind("&HFFFF&"), TokenKind::Integer(65535));
+        assert_eq!(first_kind("&H10000"), TokenKind::Integer(65536));
+    }
+
+    #[test]
+    fn test_octal_literals() {
+        assert_eq!(first_kind("&O10"), TokenKind::Integer(8));
+        assert_eq!(first_kind("&o17"), TokenKind::Integer(15));
+        assert_eq!(first_kind("&17"), TokenKind::Integer(15));
+    }
+
+    #[test]
+    fn test_radix_literal_in_expression() {
+        let kinds: Vec<TokenKind> = Lexer::new("x = &H0F + 1").tokenize().into_iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Identifier("x".to_string()),
+                TokenKind::Equal,
+                TokenKind::Integer(15),
+                TokenKind::Plus,
+                TokenKind::Integer(1),
+                TokenKind::Eof,
+            ]
+        );
+    }
+}
@@ -3,8 +3,12 @@
 pub mod lexer;
 pub mod parser;
 pub mod interpreter;
+pub mod builtins;
 pub mod graphics;
+pub mod files;
+pub mod print_using;
 pub mod sixel;
+pub mod sound;
 
 pub use lexer::Lexer;
 pub use parser::Parser;
@@ -1,6 +1,8 @@
 //! BASIC parser - produces an AST
 
-use crate::basic::lexer::{Token, TokenKind, Keyword};
+use crate::basic::files::FileMode;
+use crate::basic::graphics::PutAction;
+use crate::basic::lexer::{Lexer, Token, TokenKind, Keyword};
 
 /// Expression types
 #[derive(Clone, Debug)]
@@ -15,6 +17,8 @@ pub enum Expr {
     Variable(String),
     /// Array access: name(indices)
     ArrayAccess(String, Vec<Expr>),
+    /// Record field access: name[(indices)].field[.field...]
+    FieldAccess(String, Vec<Expr>, Vec<String>),
     /// Binary operation
     BinaryOp(Box<Expr>, BinOp, Box<Expr>),
     /// Unary operation
@@ -39,6 +43,9 @@ pub enum UnaryOp {
     Neg, Not,
 }
 
+/// The (x1, y1)-(x2, y2) corners given to VIEW and WINDOW
+pub type BoxCoords = (Expr, Expr, Expr, Expr);
+
 /// Statement types
 #[derive(Clone, Debug)]
 #[allow(dead_code)]
@@ -55,20 +62,59 @@ pub enum Stmt {
     /// LET assignment: [LET] var = expr
     Let(String, Expr),
 
+    /// CONST name = expr[, name = expr...]
+    Const(Vec<(String, Expr)>),
+
     /// Array assignment: arr(indices) = expr
     ArrayLet(String, Vec<Expr>, Expr),
 
+    /// Record field assignment: var[(indices)].field[.field...] = expr
+    FieldLet(String, Vec<Expr>, Vec<String>, Expr),
+
+    /// SWAP a, b - each side is a variable, array element or record field
+    Swap(Expr, Expr),
+
     /// PRINT statement
     Print(Vec<PrintItem>),
 
-    /// INPUT statement: INPUT ["prompt";] var [, var...]
-    Input(Option<String>, Vec<String>),
+    /// PRINT USING format; values, with a trailing separator suppressing the newline
+    PrintUsing(Expr, Vec<Expr>, bool),
+
+    /// INPUT statement: INPUT ["prompt"{;|,}] var [, var...]
+    /// A semicolon after the prompt adds "? ", a comma suppresses it
+    Input {
+        prompt: Option<String>,
+        question_mark: bool,
+        vars: Vec<String>,
+    },
+
+    /// LINE INPUT [;] ["prompt";] var$ - reads a whole line verbatim
+    LineInput(Option<String>, String),
+
+    /// OPEN file FOR mode AS #n
+    Open {
+        path: Expr,
+        mode: FileMode,
+        file_num: Expr,
+    },
+
+    /// CLOSE [#n, ...] - no file numbers closes every file
+    Close(Vec<Expr>),
+
+    /// PRINT #n, items
+    PrintFile(Expr, Vec<PrintItem>),
+
+    /// INPUT #n, var [, var...]
+    InputFile(Expr, Vec<String>),
+
+    /// LINE INPUT #n, var$
+    LineInputFile(Expr, String),
 
     /// IF/THEN/ELSE
     If {
         condition: Expr,
-        then_branch: Vec<Stmt>,
-        else_branch: Option<Vec<Stmt>>,
+        then_branch: Block,
+        else_branch: Option<Block>,
     },
 
     /// FOR/NEXT loop
@@ -77,13 +123,13 @@ pub enum Stmt {
         start: Expr,
         end: Expr,
         step: Option<Expr>,
-        body: Vec<Stmt>,
+        body: Block,
     },
 
     /// WHILE/WEND loop
     While {
         condition: Expr,
-        body: Vec<Stmt>,
+        body: Block,
     },
 
     /// DO/LOOP
@@ -91,7 +137,7 @@ pub enum Stmt {
         condition: Option<Expr>,
         is_while: bool,    // WHILE or UNTIL
         is_pre_test: bool, // Condition at DO or at LOOP
-        body: Vec<Stmt>,
+        body: Block,
     },
 
     /// GOTO line number
@@ -100,6 +146,21 @@ pub enum Stmt {
     /// GOTO text label
     GoToLabel(String),
 
+    /// EXIT FOR / EXIT DO / EXIT SUB / EXIT FUNCTION
+    Exit(ExitKind),
+
+    /// ON ERROR GOTO label - None (GOTO 0) disables error trapping
+    OnErrorGoTo(Option<String>),
+
+    /// RESUME [0] - retry the statement that raised the error
+    Resume,
+
+    /// RESUME NEXT - continue after the statement that raised the error
+    ResumeNext,
+
+    /// RESUME label
+    ResumeLabel(String),
+
     /// GOSUB line number
     GoSub(i64),
 
@@ -109,21 +170,66 @@ pub enum Stmt {
     /// RETURN
     Return(Option<Expr>),
 
-    /// DIM statement
-    Dim(Vec<DimVar>),
+    /// DIM [SHARED] statement
+    Dim {
+        shared: bool,
+        vars: Vec<DimVar>,
+    },
+
+    /// STATIC vars inside a SUB/FUNCTION - locals that keep their values
+    /// between calls
+    Static(Vec<DimVar>),
 
-    /// SUB definition
+    /// SHARED vars inside a SUB/FUNCTION, or COMMON SHARED vars at module
+    /// level - lets procedures use those module-level variables
+    Shared(Vec<String>),
+
+    /// REDIM [PRESERVE] statement
+    ReDim {
+        preserve: bool,
+        vars: Vec<DimVar>,
+    },
+
+    /// OPTION BASE 0|1
+    OptionBase(i64),
+
+    /// DEFINT/DEFLNG/DEFSNG/DEFDBL/DEFSTR letter ranges, e.g. DEFINT A-Z
+    DefType(VarType, Vec<(char, char)>),
+
+    /// TYPE name ... END TYPE user-defined record type
+    TypeDef {
+        name: String,
+        fields: Vec<(String, VarType)>,
+    },
+
+    /// SUB definition; `is_static` when every local keeps its value between calls
     Sub {
         name: String,
-        params: Vec<String>,
-        body: Vec<Stmt>,
+        params: Vec<Param>,
+        body: Block,
+        is_static: bool,
     },
 
-    /// FUNCTION definition
+    /// FUNCTION definition; `is_static` as for SUB
     Function {
+        name: String,
+        params: Vec<Param>,
+        body: Block,
+        is_static: bool,
+    },
+
+    /// DEF FNname[(params)] = expr single-line function
+    DefFn {
         name: String,
         params: Vec<String>,
-        body: Vec<Stmt>,
+        body: Expr,
+    },
+
+    /// DECLARE SUB/FUNCTION name [(params)]: the parameters calls must match
+    Declare {
+        name: String,
+        is_function: bool,
+        params: Vec<Param>,
     },
 
     /// CALL sub
@@ -132,24 +238,39 @@ pub enum Stmt {
     /// END
     End,
 
-    /// STOP
+    /// STOP - pause as if at a breakpoint
     Stop,
 
+    /// SYSTEM - end the program and leave straight away
+    System,
+
     /// CLS
     Cls,
 
     /// SCREEN mode
     Screen(Expr),
 
+    /// WIDTH [cols] [, rows] - omitted arguments keep the current size
+    Width(Option<Expr>, Option<Expr>),
+
     /// COLOR fg [, bg]
     Color(Expr, Option<Expr>),
 
     /// LOCATE row, col
-    Locate(Expr, Expr),
+    /// LOCATE [row], [col], [cursor], [start], [stop] - omitted arguments
+    /// leave the position unchanged; the scanline arguments are ignored
+    Locate {
+        row: Option<Expr>,
+        col: Option<Expr>,
+        cursor: Option<Expr>,
+    },
 
     /// PSET (x, y) [, color]
     Pset(Expr, Expr, Option<Expr>),
 
+    /// PRESET (x, y) [, color] - like PSET but defaults to the background color
+    Preset(Expr, Expr, Option<Expr>),
+
     /// LINE (x1,y1)-(x2,y2) [, color] [, B[F]]
     Line {
         x1: Expr,
@@ -184,8 +305,47 @@ pub enum Stmt {
         thickness: Option<Expr>,
     },
 
-    /// PAINT (x, y), color
-    Paint(Expr, Expr, Expr),
+    /// VIEW [[SCREEN] (x1, y1)-(x2, y2) [, [fill] [, border]]] - clip
+    /// graphics to a viewport; VIEW alone restores the whole screen
+    View {
+        coords: Option<BoxCoords>,
+        screen: bool,
+        fill: Option<Expr>,
+        border: Option<Expr>,
+    },
+
+    /// WINDOW [[SCREEN] (x1, y1)-(x2, y2)] - logical coordinates for
+    /// graphics; WINDOW alone goes back to pixels
+    Window {
+        coords: Option<BoxCoords>,
+        screen: bool,
+    },
+
+    /// PALETTE [attr, color] - show an attribute in another colour; PALETTE
+    /// alone restores the defaults
+    Palette(Option<(Expr, Expr)>),
+
+    /// PAINT (x, y), color [, border] - border defaults to the fill color
+    Paint(Expr, Expr, Expr, Option<Expr>),
+
+    /// GET (x1, y1)-(x2, y2), array[(index)] - capture a screen region
+    GetImage {
+        x1: Expr,
+        y1: Expr,
+        x2: Expr,
+        y2: Expr,
+        array: String,
+        indices: Vec<Expr>,
+    },
+
+    /// PUT (x, y), array[(index)] [, PSET|PRESET|AND|OR|XOR] - draw a GET image
+    PutImage {
+        x: Expr,
+        y: Expr,
+        array: String,
+        indices: Vec<Expr>,
+        action: PutAction,
+    },
 
     /// BEEP
     Beep,
@@ -193,6 +353,9 @@ pub enum Stmt {
     /// SOUND freq, duration
     Sound(Expr, Expr),
 
+    /// PLAY commands$ - music macro language
+    Play(Expr),
+
     /// SLEEP [seconds]
     Sleep(Option<Expr>),
 
@@ -215,6 +378,16 @@ pub enum Stmt {
     Expression(Expr),
 }
 
+/// Construct left by an EXIT statement
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExitKind {
+    For,
+    Do,
+    While,
+    Sub,
+    Function,
+}
+
 /// Print item (can be expression, separator, or TAB/SPC)
 #[derive(Clone, Debug)]
 #[allow(dead_code)]
@@ -231,18 +404,76 @@ pub enum PrintItem {
 #[allow(dead_code)]
 pub struct DimVar {
     pub name: String,
-    pub dimensions: Vec<Expr>,
+    /// Optional lower bound (`lower TO upper`) and upper bound per dimension
+    pub dimensions: Vec<(Option<Expr>, Expr)>,
+    pub var_type: Option<VarType>,
+}
+
+/// SUB or FUNCTION parameter, with the type given by `AS`, if any
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
     pub var_type: Option<VarType>,
 }
 
 /// Variable types
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum VarType {
     Integer,
     Long,
     Single,
     Double,
     String,
+    /// STRING * n
+    FixedString(usize),
+    /// Name of a TYPE ... END TYPE record
+    UserType(String),
+}
+
+/// Statements in source order, each with the line (counted from 0) it
+/// starts on. Derefs to the statements themselves.
+#[derive(Clone, Debug, Default)]
+pub struct Block {
+    stmts: Vec<Stmt>,
+    lines: Vec<usize>,
+}
+
+impl Block {
+    pub fn push(&mut self, stmt: Stmt, line: usize) {
+        self.stmts.push(stmt);
+        self.lines.push(line);
+    }
+
+    /// Add the statements of `other` to the end
+    pub fn append(&mut self, other: Block) {
+        self.stmts.extend(other.stmts);
+        self.lines.extend(other.lines);
+    }
+
+    /// Source line of the statement at `pos`
+    pub fn line(&self, pos: usize) -> usize {
+        self.lines.get(pos).copied().unwrap_or(0)
+    }
+
+    /// The statements with their source lines
+    pub fn with_lines(&self) -> impl Iterator<Item = (&Stmt, usize)> {
+        self.stmts.iter().zip(self.lines.iter().copied())
+    }
+}
+
+impl std::ops::Deref for Block {
+    type Target = [Stmt];
+    fn deref(&self) -> &[Stmt] {
+        &self.stmts
+    }
+}
+
+impl<'a> IntoIterator for &'a Block {
+    type Item = &'a Stmt;
+    type IntoIter = std::slice::Iter<'a, Stmt>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.stmts.iter()
+    }
 }
 
 /// Parser for BASIC
@@ -270,6 +501,11 @@ impl Parser {
         &self.current().kind
     }
 
+    /// Source line of the current token, counted from 0
+    fn line(&self) -> usize {
+        self.current().line.saturating_sub(1)
+    }
+
     fn advance(&mut self) -> &Token {
         if self.pos < self.tokens.len() {
             self.pos += 1;
@@ -291,6 +527,13 @@ impl Parser {
         err
     }
 
+    /// Error for a block whose opening keyword is the token at `opened_at`
+    /// and which the end of the program left unclosed
+    fn unclosed_block(&self, opened_at: usize, msg: &str) -> String {
+        let (line, column) = self.tokens.get(opened_at).map_or((0, 0), |t| (t.line, t.column));
+        format!("Line {}, col {}: {}", line, column, msg)
+    }
+
     /// Push parsing context for error messages
     fn push_context(&mut self, ctx: &'static str) {
         self.context_stack.push(ctx);
@@ -309,8 +552,130 @@ impl Parser {
         }
     }
 
-    fn skip_newlines(&mut self) {
-        while matches!(self.peek(), TokenKind::Newline) {
+    /// Parse DEF FNname[(param, ...)] = expr after DEF
+    fn parse_def_fn(&mut self) -> Result<Stmt, String> {
+        let name = match self.peek().clone() {
+            TokenKind::Keyword(Keyword::Fn) => {
+                self.advance();
+                self.parse_fn_name()?
+            }
+            TokenKind::Identifier(name) if is_def_fn_name(&name) => {
+                self.advance();
+                name
+            }
+            _ => return Err(self.error("Expected FN name after DEF")),
+        };
+
+        let mut params = Vec::new();
+        if matches!(self.peek(), TokenKind::LeftParen) {
+            self.advance();
+            loop {
+                match self.peek().clone() {
+                    TokenKind::Identifier(param) => {
+                        self.advance();
+                        params.push(param);
+                    }
+                    _ => return Err(self.error("Expected parameter name")),
+                }
+                if !matches!(self.peek(), TokenKind::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+            self.expect(TokenKind::RightParen)?;
+        }
+
+        if !matches!(self.peek(), TokenKind::Equal) {
+            return Err(self.error("Multi-line DEF FN is not supported"));
+        }
+        self.advance();
+        let body = self.parse_expression()?;
+        Ok(Stmt::DefFn { name, params, body })
+    }
+
+    /// Parse the name after a separate FN keyword, giving FNname
+    fn parse_fn_name(&mut self) -> Result<String, String> {
+        match self.peek().clone() {
+            TokenKind::Identifier(name) => {
+                self.advance();
+                Ok(format!("FN{}", name))
+            }
+            _ => Err(self.error("Expected function name after FN")),
+        }
+    }
+
+    /// Parse optional parenthesized DEF FN call arguments
+    fn parse_fn_args(&mut self) -> Result<Vec<Expr>, String> {
+        let mut args = Vec::new();
+        if !matches!(self.peek(), TokenKind::LeftParen) {
+            return Ok(args);
+        }
+        self.advance();
+        if !matches!(self.peek(), TokenKind::RightParen) {
+            loop {
+                args.push(self.parse_expression()?);
+                if !matches!(self.peek(), TokenKind::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        self.expect(TokenKind::RightParen)?;
+        Ok(args)
+    }
+
+    /// Parse a variable, array element or record field reference
+    fn parse_lvalue(&mut self) -> Result<Expr, String> {
+        match self.parse_primary()? {
+            expr @ (Expr::Variable(_) | Expr::ArrayAccess(..) | Expr::FieldAccess(..)) => Ok(expr),
+            _ => Err(self.error("Expected variable")),
+        }
+    }
+
+    /// Parse a DEFtype letter list such as `A-C, X, Z`
+    fn parse_letter_ranges(&mut self) -> Result<Vec<(char, char)>, String> {
+        let mut ranges = Vec::new();
+        loop {
+            let first = self.parse_letter()?;
+            let last = if matches!(self.peek(), TokenKind::Minus) {
+                self.advance();
+                self.parse_letter()?
+            } else {
+                first
+            };
+            if last < first {
+                return Err(self.error("Invalid letter range"));
+            }
+            ranges.push((first, last));
+            if !matches!(self.peek(), TokenKind::Comma) {
+                return Ok(ranges);
+            }
+            self.advance();
+        }
+    }
+
+    fn parse_letter(&mut self) -> Result<char, String> {
+        if let TokenKind::Identifier(name) = self.peek().clone() {
+            let mut chars = name.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                if c.is_ascii_alphabetic() {
+                    self.advance();
+                    return Ok(c.to_ascii_uppercase());
+                }
+            }
+        }
+        Err(self.error("Expected letter"))
+    }
+
+    /// Whether the current statement ends here: at the end of the line, a
+    /// colon, or the ELSE of a single-line IF
+    fn at_statement_end(&self) -> bool {
+        matches!(self.peek(), TokenKind::Newline | TokenKind::Eof | TokenKind::Colon | TokenKind::Keyword(Keyword::Else))
+    }
+
+    /// Skip newlines and the colons separating statements on one line
+    fn skip_separators(&mut self) {
+        while matches!(self.peek(), TokenKind::Newline | TokenKind::Colon) {
             self.advance();
         }
     }
@@ -329,24 +694,34 @@ impl Parser {
     }
 
     /// Parse the entire program
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, String> {
-        let mut statements = Vec::new();
+    pub fn parse(&mut self) -> Result<Block, String> {
+        let mut statements = Block::default();
 
         while !matches!(self.peek(), TokenKind::Eof) {
-            self.skip_newlines();
+            self.skip_separators();
             if matches!(self.peek(), TokenKind::Eof) {
                 break;
             }
 
             let stmts = self.parse_statement()?;
-            statements.extend(stmts);
+            statements.append(stmts);
         }
 
         Ok(statements)
     }
 
+    /// Parse a single statement, with the line it starts on
+    fn parse_statement(&mut self) -> Result<Block, String> {
+        let line = self.line();
+        let mut block = Block::default();
+        for stmt in self.parse_labelled_statement()? {
+            block.push(stmt, line);
+        }
+        Ok(block)
+    }
+
     /// Parse a single statement, returning one or two statements if there's a line label
-    fn parse_statement(&mut self) -> Result<Vec<Stmt>, String> {
+    fn parse_labelled_statement(&mut self) -> Result<Vec<Stmt>, String> {
         // Check for line number label
         if let TokenKind::Integer(n) = self.peek().clone() {
             self.advance();
@@ -391,8 +766,30 @@ impl Parser {
             }
             TokenKind::Keyword(Keyword::Input) => {
                 self.advance();
+                if matches!(self.peek(), TokenKind::Hash) {
+                    let file_num = self.parse_file_number()?;
+                    self.expect(TokenKind::Comma)?;
+                    return Ok(Stmt::InputFile(file_num, self.parse_input_vars()));
+                }
                 self.parse_input()
             }
+            TokenKind::Keyword(Keyword::Open) => {
+                self.advance();
+                self.parse_open()
+            }
+            TokenKind::Keyword(Keyword::Close) => {
+                self.advance();
+                let mut file_nums = Vec::new();
+                while !self.at_statement_end() {
+                    file_nums.push(self.parse_file_number()?);
+                    if matches!(self.peek(), TokenKind::Comma) {
+                        self.advance();
+                    } else {
+                        break;
+                    }
+                }
+                Ok(Stmt::Close(file_nums))
+            }
             TokenKind::Keyword(Keyword::If) => {
                 self.advance();
                 self.parse_if()
@@ -413,6 +810,95 @@ impl Parser {
                 self.advance();
                 self.parse_goto()
             }
+            TokenKind::Keyword(Keyword::Const) => {
+                self.advance();
+                let mut constants = Vec::new();
+                loop {
+                    let name = match self.peek().clone() {
+                        TokenKind::Identifier(name) => {
+                            self.advance();
+                            name
+                        }
+                        _ => return Err(self.error("Expected constant name")),
+                    };
+                    self.expect(TokenKind::Equal)?;
+                    constants.push((name, self.parse_expression()?));
+                    if !matches!(self.peek(), TokenKind::Comma) {
+                        break;
+                    }
+                    self.advance();
+                }
+                Ok(Stmt::Const(constants))
+            }
+            TokenKind::Keyword(Keyword::Def) => {
+                self.advance();
+                self.parse_def_fn()
+            }
+            TokenKind::Keyword(Keyword::Swap) => {
+                self.advance();
+                let first = self.parse_lvalue()?;
+                self.expect(TokenKind::Comma)?;
+                let second = self.parse_lvalue()?;
+                Ok(Stmt::Swap(first, second))
+            }
+            TokenKind::Keyword(Keyword::Exit) => {
+                self.advance();
+                let kind = match self.peek() {
+                    TokenKind::Keyword(Keyword::For) => ExitKind::For,
+                    TokenKind::Keyword(Keyword::Do) => ExitKind::Do,
+                    TokenKind::Keyword(Keyword::While) => ExitKind::While,
+                    TokenKind::Keyword(Keyword::Sub) => ExitKind::Sub,
+                    TokenKind::Keyword(Keyword::Function) => ExitKind::Function,
+                    _ => return Err(self.error("Expected FOR, DO, WHILE, SUB or FUNCTION after EXIT")),
+                };
+                self.advance();
+                Ok(Stmt::Exit(kind))
+            }
+            TokenKind::Keyword(Keyword::On) => {
+                self.advance();
+                self.expect(TokenKind::Keyword(Keyword::Error))?;
+                self.expect(TokenKind::Keyword(Keyword::GoTo))?;
+                match self.peek().clone() {
+                    TokenKind::Integer(0) => {
+                        self.advance();
+                        Ok(Stmt::OnErrorGoTo(None))
+                    }
+                    TokenKind::Integer(n) => {
+                        self.advance();
+                        Ok(Stmt::OnErrorGoTo(Some(n.to_string())))
+                    }
+                    TokenKind::Identifier(name) => {
+                        self.advance();
+                        Ok(Stmt::OnErrorGoTo(Some(name)))
+                    }
+                    _ => Err(self.error("Expected line number or label after ON ERROR GOTO")),
+                }
+            }
+            TokenKind::Keyword(Keyword::Next) => Err(self.error("NEXT without FOR")),
+            TokenKind::Keyword(Keyword::Wend) => Err(self.error("WEND without WHILE")),
+            TokenKind::Keyword(Keyword::Loop) => Err(self.error("LOOP without DO")),
+            TokenKind::Keyword(Keyword::Resume) => {
+                self.advance();
+                match self.peek().clone() {
+                    TokenKind::Keyword(Keyword::Next) => {
+                        self.advance();
+                        Ok(Stmt::ResumeNext)
+                    }
+                    TokenKind::Integer(0) => {
+                        self.advance();
+                        Ok(Stmt::Resume)
+                    }
+                    TokenKind::Integer(n) => {
+                        self.advance();
+                        Ok(Stmt::ResumeLabel(n.to_string()))
+                    }
+                    TokenKind::Identifier(name) => {
+                        self.advance();
+                        Ok(Stmt::ResumeLabel(name))
+                    }
+                    _ => Ok(Stmt::Resume),
+                }
+            }
             TokenKind::Keyword(Keyword::GoSub) => {
                 self.advance();
                 self.parse_gosub()
@@ -425,6 +911,70 @@ impl Parser {
                 self.advance();
                 self.parse_dim()
             }
+            TokenKind::Keyword(Keyword::Shared) => {
+                self.advance();
+                Ok(Stmt::Shared(self.parse_shared_names()?))
+            }
+            TokenKind::Keyword(Keyword::Static) => {
+                self.advance();
+                Ok(Stmt::Static(self.parse_dim_vars()?))
+            }
+            TokenKind::Keyword(Keyword::Common) => {
+                // COMMON [SHARED] [/block/] vars - only SHARED matters with one module
+                self.advance();
+                let shared = matches!(self.peek(), TokenKind::Keyword(Keyword::Shared));
+                if shared {
+                    self.advance();
+                }
+                if matches!(self.peek(), TokenKind::Slash) {
+                    self.advance();
+                    if let TokenKind::Identifier(_) = self.peek() {
+                        self.advance();
+                    }
+                    self.expect(TokenKind::Slash)?;
+                }
+                let names = self.parse_shared_names()?;
+                Ok(if shared { Stmt::Shared(names) } else { Stmt::Empty })
+            }
+            TokenKind::Keyword(Keyword::ReDim) => {
+                self.advance();
+                let preserve = if matches!(self.peek(), TokenKind::Keyword(Keyword::Preserve)) {
+                    self.advance();
+                    true
+                } else {
+                    false
+                };
+                let vars = self.parse_dim_vars()?;
+                Ok(Stmt::ReDim { preserve, vars })
+            }
+            TokenKind::Keyword(Keyword::Option) => {
+                self.advance();
+                self.expect(TokenKind::Keyword(Keyword::Base))?;
+                match self.peek().clone() {
+                    TokenKind::Integer(n @ (0 | 1)) => {
+                        self.advance();
+                        Ok(Stmt::OptionBase(n))
+                    }
+                    _ => Err(self.error("Expected 0 or 1 after OPTION BASE")),
+                }
+            }
+            TokenKind::Keyword(
+                kw @ (Keyword::DefInt | Keyword::DefLng | Keyword::DefSng | Keyword::DefDbl | Keyword::DefStr),
+            ) => {
+                self.advance();
+                let var_type = match kw {
+                    Keyword::DefInt => VarType::Integer,
+                    Keyword::DefLng => VarType::Long,
+                    Keyword::DefSng => VarType::Single,
+                    Keyword::DefDbl => VarType::Double,
+                    _ => VarType::String,
+                };
+                Ok(Stmt::DefType(var_type, self.parse_letter_ranges()?))
+            }
+            TokenKind::Keyword(Keyword::Type) => {
+                self.advance();
+                self.parse_type_def()
+            }
             TokenKind::Keyword(Keyword::End) => {
                 self.advance();
                 Ok(Stmt::End)
@@ -433,6 +983,10 @@ impl Parser {
                 self.advance();
                 Ok(Stmt::Stop)
             }
+            TokenKind::Keyword(Keyword::System) => {
+                self.advance();
+                Ok(Stmt::System)
+            }
             TokenKind::Keyword(Keyword::Cls) => {
                 self.advance();
                 Ok(Stmt::Cls)
@@ -454,22 +1008,63 @@ impl Parser {
                 self.advance();
                 self.parse_pset()
             }
+            TokenKind::Keyword(Keyword::Preset) => {
+                self.advance();
+                match self.parse_pset()? {
+                    Stmt::Pset(x, y, color) => Ok(Stmt::Preset(x, y, color)),
+                    stmt => Ok(stmt),
+                }
+            }
             TokenKind::Keyword(Keyword::Line) => {
                 self.advance();
-                self.parse_line()
+                if matches!(self.peek(), TokenKind::Keyword(Keyword::Input)) {
+                    self.advance();
+                    self.parse_line_input()
+                } else {
+                    self.parse_line()
+                }
             }
             TokenKind::Keyword(Keyword::Circle) => {
                 self.advance();
                 self.parse_circle()
             }
+            TokenKind::Keyword(Keyword::Palette) => {
+                self.advance();
+                if self.at_statement_end() {
+                    return Ok(Stmt::Palette(None));
+                }
+                if matches!(self.peek(), TokenKind::Keyword(Keyword::Using)) {
+                    return Err(self.error("PALETTE USING is not supported"));
+                }
+                let attr = self.parse_expression()?;
+                self.expect(TokenKind::Comma)?;
+                let color = self.parse_expression()?;
+                Ok(Stmt::Palette(Some((attr, color))))
+            }
             TokenKind::Keyword(Keyword::Bezier) => {
                 self.advance();
                 self.parse_bezier()
             }
+            TokenKind::Keyword(Keyword::View) => {
+                self.advance();
+                self.parse_view()
+            }
+            TokenKind::Keyword(Keyword::Window) => {
+                self.advance();
+                self.parse_window()
+            }
             TokenKind::Keyword(Keyword::Paint) => {
                 self.advance();
                 self.parse_paint()
             }
+            TokenKind::Keyword(Keyword::Get) => {
+                self.advance();
+                self.parse_get_image()
+            }
+            TokenKind::Keyword(Keyword::Put) => {
+                self.advance();
+                self.parse_put_image()
+            }
             TokenKind::Keyword(Keyword::Beep) => {
                 self.advance();
                 Ok(Stmt::Beep)
@@ -481,9 +1076,13 @@ impl Parser {
                 let dur = self.parse_expression()?;
                 Ok(Stmt::Sound(freq, dur))
             }
+            TokenKind::Keyword(Keyword::Play) => {
+                self.advance();
+                Ok(Stmt::Play(self.parse_expression()?))
+            }
             TokenKind::Keyword(Keyword::Sleep) => {
                 self.advance();
-                let secs = if !matches!(self.peek(), TokenKind::Newline | TokenKind::Eof | TokenKind::Colon) {
+                let secs = if !self.at_statement_end() {
                     Some(self.parse_expression()?)
                 } else {
                     None
@@ -492,7 +1091,7 @@ impl Parser {
             }
             TokenKind::Keyword(Keyword::Randomize) => {
                 self.advance();
-                let seed = if !matches!(self.peek(), TokenKind::Newline | TokenKind::Eof | TokenKind::Colon) {
+                let seed = if !self.at_statement_end() {
                     Some(self.parse_expression()?)
                 } else {
                     None
@@ -530,6 +1129,10 @@ impl Parser {
                 self.advance();
                 self.parse_sub()
             }
+            TokenKind::Keyword(Keyword::Declare) => {
+                self.advance();
+                self.parse_declare()
+            }
             TokenKind::Keyword(Keyword::Function) => {
                 self.advance();
                 self.parse_function()
@@ -538,6 +1141,14 @@ impl Parser {
                 self.advance();
                 self.parse_call()
             }
+            // WIDTH isn't reserved, so programs that use it as a variable
+            // still run; it is a statement unless assigned to
+            TokenKind::Identifier(name) if name.eq_ignore_ascii_case("WIDTH")
+                && !matches!(self.tokens.get(self.pos + 1).map(|t| &t.kind), Some(TokenKind::Equal | TokenKind::LeftParen)) =>
+            {
+                self.advance();
+                self.parse_width()
+            }
             TokenKind::Identifier(_) => {
                 // Could be assignment or procedure call
                 self.parse_identifier_statement()
@@ -579,10 +1190,21 @@ impl Parser {
                 }
             }
             self.expect(TokenKind::RightParen)?;
+            let fields = self.parse_field_path()?;
             self.expect(TokenKind::Equal)?;
             let value = self.parse_expression()?;
-            Ok(Stmt::ArrayLet(name, indices, value))
+            if fields.is_empty() {
+                Ok(Stmt::ArrayLet(name, indices, value))
+            } else {
+                Ok(Stmt::FieldLet(name, indices, fields, value))
+            }
         } else {
+            let fields = self.parse_field_path()?;
+            if !fields.is_empty() {
+                self.expect(TokenKind::Equal)?;
+                let value = self.parse_expression()?;
+                return Ok(Stmt::FieldLet(name, Vec::new(), fields, value));
+            }
             self.expect(TokenKind::Equal)?;
             let value = self.parse_expression()?;
             Ok(Stmt::Let(name, value))
@@ -590,14 +1212,69 @@ impl Parser {
     }
 
     fn parse_identifier_statement(&mut self) -> Result<Stmt, String> {
-        // This handles both assignments and procedure calls
-        self.parse_assignment()
+        // A name not assigned to is a SUB call without CALL: `Name arg, arg`.
+        // After a subscript, look past the closing bracket for the =.
+        let mut next = self.pos + 1;
+        if matches!(self.tokens.get(next).map(|t| &t.kind), Some(TokenKind::LeftParen)) {
+            let mut depth = 0;
+            while let Some(token) = self.tokens.get(next) {
+                match token.kind {
+                    TokenKind::LeftParen => depth += 1,
+                    TokenKind::RightParen => depth -= 1,
+                    TokenKind::Newline | TokenKind::Eof => break,
+                    _ => {}
+                }
+                next += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+        }
+        let is_call = match self.tokens.get(next).map(|t| &t.kind) {
+            Some(TokenKind::Equal) => false,
+            Some(TokenKind::Identifier(s)) => s != ".",
+            _ => true,
+        };
+        if !is_call {
+            return self.parse_assignment();
+        }
+
+        let TokenKind::Identifier(name) = self.peek().clone() else {
+            return Err(format!("Expected variable name, got {:?}", self.peek()));
+        };
+        self.advance();
+        let mut args = Vec::new();
+        if !self.at_statement_end() {
+            loop {
+                args.push(self.parse_expression()?);
+                if !matches!(self.peek(), TokenKind::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        Ok(Stmt::Call(name, args))
     }
 
     fn parse_print(&mut self) -> Result<Stmt, String> {
+        if matches!(self.peek(), TokenKind::Keyword(Keyword::Using)) {
+            self.advance();
+            return self.parse_print_using();
+        }
+
+        if matches!(self.peek(), TokenKind::Hash) {
+            let file_num = self.parse_file_number()?;
+            self.expect(TokenKind::Comma)?;
+            return Ok(Stmt::PrintFile(file_num, self.parse_print_items()?));
+        }
+
+        Ok(Stmt::Print(self.parse_print_items()?))
+    }
+
+    fn parse_print_items(&mut self) -> Result<Vec<PrintItem>, String> {
         let mut items = Vec::new();
 
-        while !matches!(self.peek(), TokenKind::Newline | TokenKind::Eof | TokenKind::Colon) {
+        while !self.at_statement_end() {
             match self.peek() {
                 TokenKind::Comma => {
                     self.advance();
@@ -607,6 +1284,17 @@ impl Parser {
                     self.advance();
                     items.push(PrintItem::Semicolon);
                 }
+                TokenKind::Identifier(name)
+                    if (name.eq_ignore_ascii_case("TAB") || name.eq_ignore_ascii_case("SPC"))
+                        && matches!(self.tokens.get(self.pos + 1).map(|t| &t.kind), Some(TokenKind::LeftParen)) =>
+                {
+                    let is_tab = name.eq_ignore_ascii_case("TAB");
+                    self.advance();
+                    self.advance();
+                    let expr = self.parse_expression()?;
+                    self.expect(TokenKind::RightParen)?;
+                    items.push(if is_tab { PrintItem::Tab(expr) } else { PrintItem::Spc(expr) });
+                }
                 _ => {
                     let expr = self.parse_expression()?;
                     items.push(PrintItem::Expr(expr));
@@ -614,40 +1302,133 @@ impl Parser {
             }
         }
 
-        Ok(Stmt::Print(items))
+        Ok(items)
     }
 
-    fn parse_input(&mut self) -> Result<Stmt, String> {
-        let prompt = if let TokenKind::String(s) = self.peek().clone() {
+    /// Parse a file number: [#]expr
+    fn parse_file_number(&mut self) -> Result<Expr, String> {
+        if matches!(self.peek(), TokenKind::Hash) {
+            self.advance();
+        }
+        self.parse_expression()
+    }
+
+    fn parse_open(&mut self) -> Result<Stmt, String> {
+        let path = self.parse_expression()?;
+        self.expect(TokenKind::Keyword(Keyword::For))?;
+        let mode = match self.peek() {
+            TokenKind::Keyword(Keyword::Input) => FileMode::Input,
+            TokenKind::Keyword(Keyword::Output) => FileMode::Output,
+            TokenKind::Keyword(Keyword::Append) => FileMode::Append,
+            _ => return Err(self.error("Expected INPUT, OUTPUT or APPEND in OPEN")),
+        };
+        self.advance();
+        self.expect(TokenKind::Keyword(Keyword::As))?;
+        let file_num = self.parse_file_number()?;
+        Ok(Stmt::Open { path, mode, file_num })
+    }
+
+    fn parse_print_using(&mut self) -> Result<Stmt, String> {
+        let format = self.parse_expression()?;
+        self.expect(TokenKind::Semicolon)?;
+
+        let mut values = Vec::new();
+        let mut no_newline = false;
+        while !self.at_statement_end() {
+            if matches!(self.peek(), TokenKind::Comma | TokenKind::Semicolon) {
+                self.advance();
+                no_newline = true;
+            } else {
+                values.push(self.parse_expression()?);
+                no_newline = false;
+            }
+        }
+
+        Ok(Stmt::PrintUsing(format, values, no_newline))
+    }
+
+    fn parse_input(&mut self) -> Result<Stmt, String> {
+        let mut question_mark = true;
+        let prompt = if let TokenKind::String(s) = self.peek().clone() {
+            self.advance();
+            match self.peek() {
+                TokenKind::Semicolon => {
+                    self.advance();
+                }
+                TokenKind::Comma => {
+                    self.advance();
+                    question_mark = false;
+                }
+                _ => return Err(self.error("Expected ; or , after INPUT prompt")),
+            }
+            Some(s)
+        } else {
+            None
+        };
+
+        let vars = self.parse_input_vars();
+        Ok(Stmt::Input { prompt, question_mark, vars })
+    }
+
+    fn parse_input_vars(&mut self) -> Vec<String> {
+        let mut vars = Vec::new();
+        loop {
+            if let TokenKind::Identifier(name) = self.peek().clone() {
+                self.advance();
+                vars.push(name);
+            } else {
+                break;
+            }
+            if matches!(self.peek(), TokenKind::Comma) {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        vars
+    }
+
+    fn parse_line_input(&mut self) -> Result<Stmt, String> {
+        // A leading semicolon keeps the cursor on the input line; output here is line-based
+        if matches!(self.peek(), TokenKind::Semicolon) {
+            self.advance();
+        }
+
+        let file_num = if matches!(self.peek(), TokenKind::Hash) {
+            let n = self.parse_file_number()?;
+            self.expect(TokenKind::Comma)?;
+            Some(n)
+        } else {
+            None
+        };
+
+        let prompt = if file_num.is_some() {
+            None
+        } else if let TokenKind::String(s) = self.peek().clone() {
             self.advance();
-            // Accept either semicolon or comma after prompt
-            if matches!(self.peek(), TokenKind::Semicolon | TokenKind::Comma) {
-                self.advance();
+            if !matches!(self.peek(), TokenKind::Semicolon | TokenKind::Comma) {
+                return Err(self.error("Expected ; after LINE INPUT prompt"));
             }
+            self.advance();
             Some(s)
         } else {
             None
         };
 
-        let mut vars = Vec::new();
-        loop {
-            if let TokenKind::Identifier(name) = self.peek().clone() {
-                self.advance();
-                vars.push(name);
-            } else {
-                break;
-            }
-            if matches!(self.peek(), TokenKind::Comma) {
+        match self.peek().clone() {
+            TokenKind::Identifier(name) if name.ends_with('$') => {
                 self.advance();
-            } else {
-                break;
+                match file_num {
+                    Some(n) => Ok(Stmt::LineInputFile(n, name)),
+                    None => Ok(Stmt::LineInput(prompt, name)),
+                }
             }
+            _ => Err(self.error("Expected string variable in LINE INPUT")),
         }
-
-        Ok(Stmt::Input(prompt, vars))
     }
 
     fn parse_if(&mut self) -> Result<Stmt, String> {
+        let opened_at = self.pos - 1;
         self.push_context("IF statement");
         let condition = self.parse_expression()?;
         self.expect(TokenKind::Keyword(Keyword::Then))?;
@@ -655,63 +1436,65 @@ impl Parser {
         // Check for single-line IF
         if !matches!(self.peek(), TokenKind::Newline | TokenKind::Eof) {
             // Single line IF - parse statement(s) after THEN
-            let then_stmt = self.parse_statement_inner()?;
+            let then_branch = self.parse_inline_statements()?;
             let else_branch = if matches!(self.peek(), TokenKind::Keyword(Keyword::Else)) {
                 self.advance();
-                Some(vec![self.parse_statement_inner()?])
+                Some(self.parse_inline_statements()?)
             } else {
                 None
             };
             self.pop_context();
             return Ok(Stmt::If {
                 condition,
-                then_branch: vec![then_stmt],
+                then_branch,
                 else_branch,
             });
         }
 
         // Multi-line IF
         self.advance(); // Skip newline
-        let mut then_branch = Vec::new();
+        let mut then_branch = Block::default();
 
         loop {
-            self.skip_newlines();
+            self.skip_separators();
             match self.peek() {
                 TokenKind::Keyword(Keyword::Else) | TokenKind::Keyword(Keyword::ElseIf) | TokenKind::Keyword(Keyword::EndIf) => break,
                 TokenKind::Keyword(Keyword::End) if self.is_end_if() => break,
                 TokenKind::Eof => break,
                 _ => {
-                    then_branch.extend(self.parse_statement()?);
+                    then_branch.append(self.parse_statement()?);
                 }
             }
         }
 
         let else_branch = if matches!(self.peek(), TokenKind::Keyword(Keyword::ElseIf)) {
             // ELSEIF becomes a nested IF in the else branch
+            let line = self.line();
             self.advance(); // consume ELSEIF
-            let nested_if = self.parse_elseif()?;
-            Some(vec![nested_if])
+            let mut else_stmts = Block::default();
+            else_stmts.push(self.parse_elseif(opened_at)?, line);
+            Some(else_stmts)
         } else if matches!(self.peek(), TokenKind::Keyword(Keyword::Else)) {
             self.advance();
-            self.skip_newlines();
-            let mut else_stmts = Vec::new();
+            self.skip_separators();
+            let mut else_stmts = Block::default();
             loop {
-                self.skip_newlines();
+                self.skip_separators();
                 match self.peek() {
                     TokenKind::Keyword(Keyword::EndIf) => break,
                     TokenKind::Keyword(Keyword::End) if self.is_end_if() => break,
                     TokenKind::Eof => break,
                     _ => {
-                        else_stmts.extend(self.parse_statement()?);
+                        else_stmts.append(self.parse_statement()?);
                     }
                 }
             }
             // Consume END IF
-            self.consume_end_if();
+            self.consume_end_if(opened_at)?;
             Some(else_stmts)
         } else {
             // Consume END IF
-            self.consume_end_if();
+            self.consume_end_if(opened_at)?;
             None
         };
 
@@ -723,48 +1506,73 @@ impl Parser {
         })
     }
 
-    /// Parse ELSEIF clause (similar to IF but handles chained ELSEIF/ELSE)
-    fn parse_elseif(&mut self) -> Result<Stmt, String> {
+    /// Parse the colon-separated statements of a single-line IF branch,
+    /// up to the end of the line or ELSE. A bare line number (THEN 100)
+    /// is a GOTO.
+    fn parse_inline_statements(&mut self) -> Result<Block, String> {
+        let line = self.line();
+        let mut stmts = Block::default();
+        if let TokenKind::Integer(n) = self.peek().clone() {
+            self.advance();
+            stmts.push(Stmt::GoTo(n), line);
+            return Ok(stmts);
+        }
+        stmts.push(self.parse_statement_inner()?, line);
+        while matches!(self.peek(), TokenKind::Colon) {
+            self.advance();
+            if matches!(self.peek(), TokenKind::Newline | TokenKind::Eof | TokenKind::Keyword(Keyword::Else)) {
+                break;
+            }
+            stmts.push(self.parse_statement_inner()?, line);
+        }
+        Ok(stmts)
+    }
+
+    /// Parse ELSEIF clause (similar to IF but handles chained ELSEIF/ELSE);
+    /// `opened_at` is the token index of the IF it belongs to
+    fn parse_elseif(&mut self, opened_at: usize) -> Result<Stmt, String> {
         let condition = self.parse_expression()?;
         self.expect(TokenKind::Keyword(Keyword::Then))?;
-        self.skip_newlines();
+        self.skip_separators();
 
-        let mut then_branch = Vec::new();
+        let mut then_branch = Block::default();
         loop {
-            self.skip_newlines();
+            self.skip_separators();
             match self.peek() {
                 TokenKind::Keyword(Keyword::Else) | TokenKind::Keyword(Keyword::ElseIf) | TokenKind::Keyword(Keyword::EndIf) => break,
                 TokenKind::Keyword(Keyword::End) if self.is_end_if() => break,
                 TokenKind::Eof => break,
                 _ => {
-                    then_branch.extend(self.parse_statement()?);
+                    then_branch.append(self.parse_statement()?);
                 }
             }
         }
 
         let else_branch = if matches!(self.peek(), TokenKind::Keyword(Keyword::ElseIf)) {
+            let line = self.line();
             self.advance();
-            let nested_if = self.parse_elseif()?;
-            Some(vec![nested_if])
+            let mut else_stmts = Block::default();
+            else_stmts.push(self.parse_elseif(opened_at)?, line);
+            Some(else_stmts)
         } else if matches!(self.peek(), TokenKind::Keyword(Keyword::Else)) {
             self.advance();
-            self.skip_newlines();
-            let mut else_stmts = Vec::new();
+            self.skip_separators();
+            let mut else_stmts = Block::default();
             loop {
-                self.skip_newlines();
+                self.skip_separators();
                 match self.peek() {
                     TokenKind::Keyword(Keyword::EndIf) => break,
                     TokenKind::Keyword(Keyword::End) if self.is_end_if() => break,
                     TokenKind::Eof => break,
                     _ => {
-                        else_stmts.extend(self.parse_statement()?);
+                        else_stmts.append(self.parse_statement()?);
                     }
                 }
             }
-            self.consume_end_if();
+            self.consume_end_if(opened_at)?;
             Some(else_stmts)
         } else {
-            self.consume_end_if();
+            self.consume_end_if(opened_at)?;
             None
         };
 
@@ -775,8 +1583,8 @@ impl Parser {
         })
     }
 
-    /// Consume END IF tokens
-    fn consume_end_if(&mut self) {
+    /// Consume END IF tokens, or fail if the program ended first
+    fn consume_end_if(&mut self, opened_at: usize) -> Result<(), String> {
         if matches!(self.peek(), TokenKind::Keyword(Keyword::End)) {
             self.advance();
             if matches!(self.peek(), TokenKind::Keyword(Keyword::If)) {
@@ -784,10 +1592,14 @@ impl Parser {
             }
         } else if matches!(self.peek(), TokenKind::Keyword(Keyword::EndIf)) {
             self.advance();
+        } else {
+            return Err(self.unclosed_block(opened_at, "Block IF without END IF"));
         }
+        Ok(())
     }
 
     fn parse_for(&mut self) -> Result<Stmt, String> {
+        let opened_at = self.pos - 1;
         self.push_context("FOR loop");
         let var = if let TokenKind::Identifier(name) = self.peek().clone() {
             self.advance();
@@ -808,23 +1620,24 @@ impl Parser {
             None
         };
 
-        self.skip_newlines();
+        self.skip_separators();
 
-        let mut body = Vec::new();
+        let mut body = Block::default();
         loop {
-            self.skip_newlines();
+            self.skip_separators();
             if matches!(self.peek(), TokenKind::Keyword(Keyword::Next) | TokenKind::Eof) {
                 break;
             }
-            body.extend(self.parse_statement()?);
+            body.append(self.parse_statement()?);
         }
 
         // Consume NEXT [var]
-        if matches!(self.peek(), TokenKind::Keyword(Keyword::Next)) {
+        if !matches!(self.peek(), TokenKind::Keyword(Keyword::Next)) {
+            return Err(self.unclosed_block(opened_at, "FOR without NEXT"));
+        }
+        self.advance();
+        if let TokenKind::Identifier(_) = self.peek() {
             self.advance();
-            if let TokenKind::Identifier(_) = self.peek() {
-                self.advance();
-            }
         }
 
         self.pop_context();
@@ -832,28 +1645,31 @@ impl Parser {
     }
 
     fn parse_while(&mut self) -> Result<Stmt, String> {
+        let opened_at = self.pos - 1;
         self.push_context("WHILE loop");
         let condition = self.parse_expression()?;
-        self.skip_newlines();
+        self.skip_separators();
 
-        let mut body = Vec::new();
+        let mut body = Block::default();
         loop {
-            self.skip_newlines();
+            self.skip_separators();
             if matches!(self.peek(), TokenKind::Keyword(Keyword::Wend) | TokenKind::Eof) {
                 break;
             }
-            body.extend(self.parse_statement()?);
+            body.append(self.parse_statement()?);
         }
 
-        if matches!(self.peek(), TokenKind::Keyword(Keyword::Wend)) {
-            self.advance();
+        if !matches!(self.peek(), TokenKind::Keyword(Keyword::Wend)) {
+            return Err(self.unclosed_block(opened_at, "WHILE without WEND"));
         }
+        self.advance();
 
         self.pop_context();
         Ok(Stmt::While { condition, body })
     }
 
     fn parse_do_loop(&mut self) -> Result<Stmt, String> {
+        let opened_at = self.pos - 1;
         self.push_context("DO loop");
         // Check for DO WHILE/UNTIL
         let (pre_condition, is_while) = if matches!(self.peek(), TokenKind::Keyword(Keyword::While)) {
@@ -866,18 +1682,21 @@ impl Parser {
             (None, true)
         };
 
-        self.skip_newlines();
+        self.skip_separators();
 
-        let mut body = Vec::new();
+        let mut body = Block::default();
         loop {
-            self.skip_newlines();
+            self.skip_separators();
             if matches!(self.peek(), TokenKind::Keyword(Keyword::Loop) | TokenKind::Eof) {
                 break;
             }
-            body.extend(self.parse_statement()?);
+            body.append(self.parse_statement()?);
         }
 
-        self.expect(TokenKind::Keyword(Keyword::Loop))?;
+        if !matches!(self.peek(), TokenKind::Keyword(Keyword::Loop)) {
+            return Err(self.unclosed_block(opened_at, "DO without LOOP"));
+        }
+        self.advance();
 
         // Check for LOOP WHILE/UNTIL
         if pre_condition.is_none() {
@@ -942,6 +1761,43 @@ impl Parser {
     }
 
     fn parse_dim(&mut self) -> Result<Stmt, String> {
+        let shared = matches!(self.peek(), TokenKind::Keyword(Keyword::Shared));
+        if shared {
+            self.advance();
+        }
+        Ok(Stmt::Dim { shared, vars: self.parse_dim_vars()? })
+    }
+
+    /// Parse the variable list of SHARED or COMMON: names, each with an
+    /// optional () for arrays and AS type
+    fn parse_shared_names(&mut self) -> Result<Vec<String>, String> {
+        let mut names = Vec::new();
+        loop {
+            match self.peek().clone() {
+                TokenKind::Identifier(name) => {
+                    self.advance();
+                    names.push(name);
+                }
+                _ => return Err(self.error("Expected variable name")),
+            }
+            if matches!(self.peek(), TokenKind::LeftParen) {
+                self.advance();
+                self.expect(TokenKind::RightParen)?;
+            }
+            if matches!(self.peek(), TokenKind::Keyword(Keyword::As)) {
+                self.advance();
+                self.parse_type_name()?;
+            }
+            if !matches!(self.peek(), TokenKind::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        Ok(names)
+    }
+
+    /// Parse the variable list shared by DIM and REDIM
+    fn parse_dim_vars(&mut self) -> Result<Vec<DimVar>, String> {
         let mut vars = Vec::new();
 
         loop {
@@ -949,14 +1805,20 @@ impl Parser {
                 self.advance();
                 name
             } else {
-                return Err("Expected variable name in DIM".to_string());
+                return Err(self.error("Expected variable name in DIM"));
             };
 
             let dimensions = if matches!(self.peek(), TokenKind::LeftParen) {
                 self.advance();
                 let mut dims = Vec::new();
                 loop {
-                    dims.push(self.parse_expression()?);
+                    let bound = self.parse_expression()?;
+                    if matches!(self.peek(), TokenKind::Keyword(Keyword::To)) {
+                        self.advance();
+                        dims.push((Some(bound), self.parse_expression()?));
+                    } else {
+                        dims.push((None, bound));
+                    }
                     if matches!(self.peek(), TokenKind::Comma) {
                         self.advance();
                     } else {
@@ -971,29 +1833,7 @@ impl Parser {
 
             let var_type = if matches!(self.peek(), TokenKind::Keyword(Keyword::As)) {
                 self.advance();
-                match self.peek() {
-                    TokenKind::Keyword(Keyword::Integer) => {
-                        self.advance();
-                        Some(VarType::Integer)
-                    }
-                    TokenKind::Keyword(Keyword::Long) => {
-                        self.advance();
-                        Some(VarType::Long)
-                    }
-                    TokenKind::Keyword(Keyword::Single) => {
-                        self.advance();
-                        Some(VarType::Single)
-                    }
-                    TokenKind::Keyword(Keyword::Double) => {
-                        self.advance();
-                        Some(VarType::Double)
-                    }
-                    TokenKind::Keyword(Keyword::StringType) => {
-                        self.advance();
-                        Some(VarType::String)
-                    }
-                    _ => None,
-                }
+                Some(self.parse_type_name()?)
             } else {
                 None
             };
@@ -1007,7 +1847,98 @@ impl Parser {
             }
         }
 
-        Ok(Stmt::Dim(vars))
+        Ok(vars)
+    }
+
+    /// Parse the type name following AS
+    fn parse_type_name(&mut self) -> Result<VarType, String> {
+        match self.peek().clone() {
+            TokenKind::Keyword(Keyword::Integer) => {
+                self.advance();
+                Ok(VarType::Integer)
+            }
+            TokenKind::Keyword(Keyword::Long) => {
+                self.advance();
+                Ok(VarType::Long)
+            }
+            TokenKind::Keyword(Keyword::Single) => {
+                self.advance();
+                Ok(VarType::Single)
+            }
+            TokenKind::Keyword(Keyword::Double) => {
+                self.advance();
+                Ok(VarType::Double)
+            }
+            TokenKind::Keyword(Keyword::StringType) => {
+                self.advance();
+                // STRING * n declares a fixed-length string
+                if matches!(self.peek(), TokenKind::Star) {
+                    self.advance();
+                    if let TokenKind::Integer(n) = self.peek().clone() {
+                        self.advance();
+                        Ok(VarType::FixedString(n.max(0) as usize))
+                    } else {
+                        Err(self.error("Expected string length after STRING *"))
+                    }
+                } else {
+                    Ok(VarType::String)
+                }
+            }
+            TokenKind::Identifier(name) => {
+                self.advance();
+                Ok(VarType::UserType(name))
+            }
+            _ => Err(self.error(&format!("Expected type name, got {:?}", self.peek()))),
+        }
+    }
+
+    /// Parse TYPE name / field AS type ... / END TYPE
+    fn parse_type_def(&mut self) -> Result<Stmt, String> {
+        self.push_context("TYPE definition");
+        let name = if let TokenKind::Identifier(name) = self.peek().clone() {
+            self.advance();
+            name
+        } else {
+            return Err(self.error("Expected TYPE name"));
+        };
+
+        let mut fields = Vec::new();
+        loop {
+            self.skip_separators();
+            match self.peek().clone() {
+                TokenKind::Keyword(Keyword::End) => {
+                    self.advance();
+                    self.expect(TokenKind::Keyword(Keyword::Type))?;
+                    break;
+                }
+                TokenKind::Identifier(field) => {
+                    self.advance();
+                    self.expect(TokenKind::Keyword(Keyword::As))?;
+                    let field_type = self.parse_type_name()?;
+                    fields.push((field, field_type));
+                }
+                TokenKind::Eof => return Err(self.error("Unexpected end of file in TYPE")),
+                _ => return Err(self.error(&format!("Expected field name in TYPE, got {:?}", self.peek()))),
+            }
+        }
+
+        self.pop_context();
+        Ok(Stmt::TypeDef { name, fields })
+    }
+
+    /// Parse a trailing `.field.field` path after a variable or array element
+    fn parse_field_path(&mut self) -> Result<Vec<String>, String> {
+        let mut fields = Vec::new();
+        while matches!(self.peek(), TokenKind::Identifier(s) if s == ".") {
+            self.advance();
+            if let TokenKind::Identifier(field) = self.peek().clone() {
+                self.advance();
+                fields.push(field);
+            } else {
+                return Err(self.error("Expected field name after '.'"));
+            }
+        }
+        Ok(fields)
     }
 
     fn parse_color(&mut self) -> Result<Stmt, String> {
@@ -1021,11 +1952,45 @@ impl Parser {
         Ok(Stmt::Color(fg, bg))
     }
 
+    fn parse_width(&mut self) -> Result<Stmt, String> {
+        let cols = if matches!(self.peek(), TokenKind::Comma) || self.at_statement_end() {
+            None
+        } else {
+            Some(self.parse_expression()?)
+        };
+        let rows = if matches!(self.peek(), TokenKind::Comma) {
+            self.advance();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        if cols.is_none() && rows.is_none() {
+            return Err(self.error("Expected expression after WIDTH"));
+        }
+        Ok(Stmt::Width(cols, rows))
+    }
+
     fn parse_locate(&mut self) -> Result<Stmt, String> {
-        let row = self.parse_expression()?;
-        self.expect(TokenKind::Comma)?;
-        let col = self.parse_expression()?;
-        Ok(Stmt::Locate(row, col))
+        let mut args = Vec::new();
+        loop {
+            if matches!(self.peek(), TokenKind::Comma) || self.at_statement_end() {
+                args.push(None);
+            } else {
+                args.push(Some(self.parse_expression()?));
+            }
+            if !matches!(self.peek(), TokenKind::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        if args.len() > 5 {
+            return Err(self.error("Too many arguments to LOCATE"));
+        }
+        let mut args = args.into_iter();
+        let row = args.next().flatten();
+        let col = args.next().flatten();
+        let cursor = args.next().flatten();
+        Ok(Stmt::Locate { row, col, cursor })
     }
 
     fn parse_pset(&mut self) -> Result<Stmt, String> {
@@ -1182,6 +2147,54 @@ impl Parser {
         Ok(Stmt::Bezier { x1, y1, cx, cy, x2, y2, color, thickness })
     }
 
+    /// Parse the `(x1, y1)-(x2, y2)` rectangle of VIEW and WINDOW, after an
+    /// optional SCREEN; None if the statement ends first
+    fn parse_view_coords(&mut self) -> Result<(Option<BoxCoords>, bool), String> {
+        let screen = matches!(self.peek(), TokenKind::Keyword(Keyword::Screen));
+        if screen {
+            self.advance();
+        } else if self.at_statement_end() {
+            return Ok((None, false));
+        }
+        self.expect(TokenKind::LeftParen)?;
+        let x1 = self.parse_expression()?;
+        self.expect(TokenKind::Comma)?;
+        let y1 = self.parse_expression()?;
+        self.expect(TokenKind::RightParen)?;
+        self.expect(TokenKind::Minus)?;
+        self.expect(TokenKind::LeftParen)?;
+        let x2 = self.parse_expression()?;
+        self.expect(TokenKind::Comma)?;
+        let y2 = self.parse_expression()?;
+        self.expect(TokenKind::RightParen)?;
+        Ok((Some((x1, y1, x2, y2)), screen))
+    }
+
+    fn parse_view(&mut self) -> Result<Stmt, String> {
+        if matches!(self.peek(), TokenKind::Keyword(Keyword::Print)) {
+            return Err(self.error("VIEW PRINT is not supported"));
+        }
+        let (coords, screen) = self.parse_view_coords()?;
+        let mut fill = None;
+        let mut border = None;
+        if coords.is_some() && matches!(self.peek(), TokenKind::Comma) {
+            self.advance();
+            if !matches!(self.peek(), TokenKind::Comma) {
+                fill = Some(self.parse_expression()?);
+            }
+            if matches!(self.peek(), TokenKind::Comma) {
+                self.advance();
+                border = Some(self.parse_expression()?);
+            }
+        }
+        Ok(Stmt::View { coords, screen, fill, border })
+    }
+
+    fn parse_window(&mut self) -> Result<Stmt, String> {
+        let (coords, screen) = self.parse_view_coords()?;
+        Ok(Stmt::Window { coords, screen })
+    }
+
     fn parse_paint(&mut self) -> Result<Stmt, String> {
         self.expect(TokenKind::LeftParen)?;
         let x = self.parse_expression()?;
@@ -1190,8 +2203,71 @@ impl Parser {
         self.expect(TokenKind::RightParen)?;
         self.expect(TokenKind::Comma)?;
         let color = self.parse_expression()?;
+        let border = if matches!(self.peek(), TokenKind::Comma) {
+            self.advance();
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+
+        Ok(Stmt::Paint(x, y, color, border))
+    }
+
+    /// Parse GET (x1, y1)-(x2, y2), array[(index)]
+    fn parse_get_image(&mut self) -> Result<Stmt, String> {
+        self.expect(TokenKind::LeftParen)?;
+        let x1 = self.parse_expression()?;
+        self.expect(TokenKind::Comma)?;
+        let y1 = self.parse_expression()?;
+        self.expect(TokenKind::RightParen)?;
+        self.expect(TokenKind::Minus)?;
+        self.expect(TokenKind::LeftParen)?;
+        let x2 = self.parse_expression()?;
+        self.expect(TokenKind::Comma)?;
+        let y2 = self.parse_expression()?;
+        self.expect(TokenKind::RightParen)?;
+        self.expect(TokenKind::Comma)?;
+        let (array, indices) = self.parse_image_array()?;
+
+        Ok(Stmt::GetImage { x1, y1, x2, y2, array, indices })
+    }
+
+    /// Parse PUT (x, y), array[(index)] [, PSET|PRESET|AND|OR|XOR]
+    fn parse_put_image(&mut self) -> Result<Stmt, String> {
+        self.expect(TokenKind::LeftParen)?;
+        let x = self.parse_expression()?;
+        self.expect(TokenKind::Comma)?;
+        let y = self.parse_expression()?;
+        self.expect(TokenKind::RightParen)?;
+        self.expect(TokenKind::Comma)?;
+        let (array, indices) = self.parse_image_array()?;
+
+        let action = if matches!(self.peek(), TokenKind::Comma) {
+            self.advance();
+            let action = match self.peek() {
+                TokenKind::Keyword(Keyword::Pset) => PutAction::Pset,
+                TokenKind::Keyword(Keyword::Preset) => PutAction::Preset,
+                TokenKind::Keyword(Keyword::And) => PutAction::And,
+                TokenKind::Keyword(Keyword::Or) => PutAction::Or,
+                TokenKind::Keyword(Keyword::Xor) => PutAction::Xor,
+                _ => return Err("Expected PSET, PRESET, AND, OR or XOR".to_string()),
+            };
+            self.advance();
+            action
+        } else {
+            PutAction::default()
+        };
+
+        Ok(Stmt::PutImage { x, y, array, indices, action })
+    }
 
-        Ok(Stmt::Paint(x, y, color))
+    /// Parse the array argument of GET/PUT: a name with an optional starting element
+    fn parse_image_array(&mut self) -> Result<(String, Vec<Expr>), String> {
+        match self.parse_expression()? {
+            Expr::Variable(name) => Ok((name, Vec::new())),
+            Expr::ArrayAccess(name, indices) => Ok((name, indices)),
+            _ => Err("Expected array".to_string()),
+        }
     }
 
     fn parse_data(&mut self) -> Result<Stmt, String> {
@@ -1227,6 +2303,7 @@ impl Parser {
 
     /// Parse SUB definition
     fn parse_sub(&mut self) -> Result<Stmt, String> {
+        let opened_at = self.pos - 1;
         // SUB name [(params)]
         let name = if let TokenKind::Identifier(name) = self.peek().clone() {
             self.advance();
@@ -1242,6 +2319,10 @@ impl Parser {
         } else {
             Vec::new()
         };
+        let is_static = matches!(self.peek(), TokenKind::Keyword(Keyword::Static));
+        if is_static {
+            self.advance();
+        }
 
         // Skip to newline
         while matches!(self.peek(), TokenKind::Newline) {
@@ -1249,28 +2330,33 @@ impl Parser {
         }
 
         // Parse body until END SUB
-        let mut body = Vec::new();
+        let mut body = Block::default();
         loop {
+            self.skip_separators();
             if matches!(self.peek(), TokenKind::Keyword(Keyword::End)) {
+                let line = self.line();
                 self.advance();
                 if matches!(self.peek(), TokenKind::Keyword(Keyword::Sub)) {
                     self.advance();
+                    // Kept as a statement of its own so the debugger can stop on it
+                    body.push(Stmt::Empty, line);
                     break;
                 } else {
-                    body.push(Stmt::End);
+                    body.push(Stmt::End, line);
                 }
             } else if matches!(self.peek(), TokenKind::Eof) {
-                return Err("Unexpected end of file in SUB".to_string());
+                return Err(self.unclosed_block(opened_at, "SUB without END SUB"));
             } else {
-                body.extend(self.parse_statement()?);
+                body.append(self.parse_statement()?);
             }
         }
 
-        Ok(Stmt::Sub { name, params, body })
+        Ok(Stmt::Sub { name, params, body, is_static })
     }
 
     /// Parse FUNCTION definition
     fn parse_function(&mut self) -> Result<Stmt, String> {
+        let opened_at = self.pos - 1;
         // FUNCTION name [(params)]
         let name = if let TokenKind::Identifier(name) = self.peek().clone() {
             self.advance();
@@ -1286,6 +2372,10 @@ impl Parser {
         } else {
             Vec::new()
         };
+        let is_static = matches!(self.peek(), TokenKind::Keyword(Keyword::Static));
+        if is_static {
+            self.advance();
+        }
 
         // Skip to newline
         while matches!(self.peek(), TokenKind::Newline) {
@@ -1293,38 +2383,72 @@ impl Parser {
         }
 
         // Parse body until END FUNCTION
-        let mut body = Vec::new();
+        let mut body = Block::default();
         loop {
+            self.skip_separators();
             if matches!(self.peek(), TokenKind::Keyword(Keyword::End)) {
+                let line = self.line();
                 self.advance();
                 if matches!(self.peek(), TokenKind::Keyword(Keyword::Function)) {
                     self.advance();
+                    // Kept as a statement of its own so the debugger can stop on it
+                    body.push(Stmt::Empty, line);
                     break;
                 } else {
-                    body.push(Stmt::End);
+                    body.push(Stmt::End, line);
                 }
             } else if matches!(self.peek(), TokenKind::Eof) {
-                return Err("Unexpected end of file in FUNCTION".to_string());
+                return Err(self.unclosed_block(opened_at, "FUNCTION without END FUNCTION"));
             } else {
-                body.extend(self.parse_statement()?);
+                body.append(self.parse_statement()?);
             }
         }
 
-        Ok(Stmt::Function { name, params, body })
+        Ok(Stmt::Function { name, params, body, is_static })
+    }
+
+    /// Parse DECLARE SUB/FUNCTION name [(params)]
+    fn parse_declare(&mut self) -> Result<Stmt, String> {
+        let is_function = match self.peek() {
+            TokenKind::Keyword(Keyword::Sub) => false,
+            TokenKind::Keyword(Keyword::Function) => true,
+            _ => return Err(self.error("Expected SUB or FUNCTION after DECLARE")),
+        };
+        self.advance();
+        let name = if let TokenKind::Identifier(name) = self.peek().clone() {
+            self.advance();
+            name
+        } else {
+            return Err("Expected procedure name".to_string());
+        };
+        let params = if matches!(self.peek(), TokenKind::LeftParen) {
+            self.advance();
+            self.parse_param_list()?
+        } else {
+            Vec::new()
+        };
+        Ok(Stmt::Declare { name, is_function, params })
     }
 
-    /// Parse parameter list
-    fn parse_param_list(&mut self) -> Result<Vec<String>, String> {
+    /// Parse parameter list: name [AS type], ...
+    fn parse_param_list(&mut self) -> Result<Vec<Param>, String> {
         let mut params = Vec::new();
 
         if !matches!(self.peek(), TokenKind::RightParen) {
             loop {
-                if let TokenKind::Identifier(name) = self.peek().clone() {
+                let name = if let TokenKind::Identifier(name) = self.peek().clone() {
                     self.advance();
-                    params.push(name);
+                    name
                 } else {
                     return Err("Expected parameter name".to_string());
-                }
+                };
+                let var_type = if matches!(self.peek(), TokenKind::Keyword(Keyword::As)) {
+                    self.advance();
+                    Some(self.parse_type_name()?)
+                } else {
+                    None
+                };
+                params.push(Param { name, var_type });
 
                 if matches!(self.peek(), TokenKind::Comma) {
                     self.advance();
@@ -1537,6 +2661,18 @@ impl Parser {
                 self.advance();
                 Ok(Expr::String(s))
             }
+            TokenKind::Keyword(Keyword::Fn) => {
+                // FN name(args) written with a space
+                self.advance();
+                let name = self.parse_fn_name()?;
+                let args = self.parse_fn_args()?;
+                Ok(Expr::FunctionCall(name, args))
+            }
+            TokenKind::Identifier(name) if is_def_fn_name(&name) => {
+                self.advance();
+                let args = self.parse_fn_args()?;
+                Ok(Expr::FunctionCall(name, args))
+            }
             TokenKind::Identifier(name) => {
                 self.advance();
                 // Check for function call or array access
@@ -1561,13 +2697,23 @@ impl Parser {
                     if is_builtin_function(&name) {
                         Ok(Expr::FunctionCall(name, args))
                     } else {
-                        Ok(Expr::ArrayAccess(name, args))
+                        let fields = self.parse_field_path()?;
+                        if fields.is_empty() {
+                            Ok(Expr::ArrayAccess(name, args))
+                        } else {
+                            Ok(Expr::FieldAccess(name, args, fields))
+                        }
                     }
                 } else if is_parameterless_function(&name) {
                     // Handle functions that can be called without parentheses (RND, TIMER, etc.)
                     Ok(Expr::FunctionCall(name, vec![]))
                 } else {
-                    Ok(Expr::Variable(name))
+                    let fields = self.parse_field_path()?;
+                    if fields.is_empty() {
+                        Ok(Expr::Variable(name))
+                    } else {
+                        Ok(Expr::FieldAccess(name, Vec::new(), fields))
+                    }
                 }
             }
             TokenKind::LeftParen => {
@@ -1581,6 +2727,11 @@ impl Parser {
     }
 }
 
+/// Names starting with FN are reserved for DEF FN functions
+fn is_def_fn_name(name: &str) -> bool {
+    name.len() > 2 && name[..2].eq_ignore_ascii_case("FN")
+}
+
 fn is_builtin_function(name: &str) -> bool {
     let name_upper = name.to_uppercase();
     matches!(name_upper.as_str(),
@@ -1588,8 +2739,9 @@ fn is_builtin_function(name: &str) -> bool {
         "LEN" | "LEFT$" | "RIGHT$" | "MID$" | "STR$" | "VAL" | "CHR$" | "ASC" | "INSTR" |
         "UCASE$" | "LCASE$" | "LTRIM$" | "RTRIM$" | "SPACE$" | "STRING$" |
         "CINT" | "CLNG" | "CSNG" | "CDBL" |
-        "TIMER" | "DATE$" | "TIME$" | "INKEY$" |
-        "PEEK" | "FRE" | "POS" | "CSRLIN" | "POINT"
+        "TIMER" | "DATE$" | "TIME$" | "INKEY$" | "ERR" | "ERL" |
+        "PEEK" | "FRE" | "POS" | "CSRLIN" | "POINT" |
+        "EOF" | "LOF" | "LBOUND" | "UBOUND"
     )
 }
 
@@ -1597,6 +2749,78 @@ fn is_builtin_function(name: &str) -> bool {
 fn is_parameterless_function(name: &str) -> bool {
     let name_upper = name.to_uppercase();
     matches!(name_upper.as_str(),
-        "RND" | "TIMER" | "DATE$" | "TIME$" | "INKEY$" | "POS" | "CSRLIN"
+        "RND" | "TIMER" | "DATE$" | "TIME$" | "INKEY$" | "POS" | "CSRLIN" | "ERR" | "ERL"
     )
 }
+
+/// Parse `source` and return the first syntax error, if any, as a 0-based
+/// line and a one-line message
+pub fn syntax_error(source: &str) -> Option<(usize, String)> {
+    let err = Parser::new(Lexer::new(source).tokenize()).parse().err()?;
+    let first = err.lines().next().unwrap_or_default();
+    let located = first
+        .strip_prefix("Line ")
+        .and_then(|rest| rest.split_once(", col "))
+        .and_then(|(line, rest)| Some((line.parse::<usize>().ok()?, rest.split_once(": ")?.1)));
+    Some(match located {
+        Some((line, message)) => (line.saturating_sub(1), message.to_string()),
+        None => (source.lines().count().saturating_sub(1), first.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Block {
+        Parser::new(Lexer::new(source).tokenize()).parse().expect("Should parse")
+    }
+
+    #[test]
+    fn test_colon_separates_statements() {
+        let stmts = parse("A = 1: B = 2: PRINT A + B\nPRINT \"a:b\"");
+        assert_eq!(stmts.len(), 4);
+        assert!(matches!(&stmts[0], Stmt::Let(name, _) if name == "A"));
+        assert!(matches!(&stmts[1], Stmt::Let(name, _) if name == "B"));
+        assert!(matches!(stmts[2], Stmt::Print(_)));
+        assert!(matches!(stmts[3], Stmt::Print(_)));
+    }
+
+    #[test]
+    fn test_inline_if_takes_all_colon_statements() {
+        let stmts = parse("IF x THEN A = 1: PRINT A ELSE B = 2: C = 3\nD = 4");
+        assert_eq!(stmts.len(), 2);
+        match &stmts[0] {
+            Stmt::If { then_branch, else_branch: Some(else_branch), .. } => {
+                assert_eq!(then_branch.len(), 2);
+                assert!(matches!(then_branch[1], Stmt::Print(_)));
+                assert_eq!(else_branch.len(), 2);
+            }
+            other => panic!("expected IF, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_label_before_colon_statements() {
+        let stmts = parse("start: A = 1: GOTO start");
+        assert!(matches!(&stmts[0], Stmt::TextLabel(name) if name == "start"));
+        assert!(matches!(stmts[1], Stmt::Let(..)));
+        assert!(matches!(&stmts[2], Stmt::GoToLabel(name) if name == "start"));
+    }
+
+    #[test]
+    fn test_shared_declarations() {
+        let stmts = parse("DIM SHARED a(10) AS INTEGER, b\nCOMMON SHARED /blk/ c(), d AS STRING\nCOMMON e\nSHARED f, g()");
+        assert!(matches!(&stmts[0], Stmt::Dim { shared: true, vars } if vars.len() == 2));
+        assert!(matches!(&stmts[1], Stmt::Shared(names) if names == &["c", "d"]));
+        assert!(matches!(stmts[2], Stmt::Empty));
+        assert!(matches!(&stmts[3], Stmt::Shared(names) if names == &["f", "g"]));
+    }
+
+    #[test]
+    fn test_syntax_error_points_at_unclosed_block() {
+        assert_eq!(syntax_error("CLS\nFOR i = 1 TO 3\n  PRINT i\nPRINT \"done\"\n"), Some((1, "FOR without NEXT".to_string())));
+        assert_eq!(syntax_error("PRINT 1\nWEND\n"), Some((1, "WEND without WHILE".to_string())));
+        assert_eq!(syntax_error("FOR i = 1 TO 3: PRINT i: NEXT\n"), None);
+    }
+}
@@ -0,0 +1,300 @@
+//! PRINT USING format strings
+//!
+//! Numeric fields: `#` digit, `.` decimal point, `,` thousands separator,
+//! leading `+` or trailing `+`/`-` sign, `$$` floating dollar sign, `**`
+//! asterisk fill (`**$` combines both) and `^^^^`/`^^^^^` exponent.
+//! String fields: `!` first character, `\  \` fixed width, `&` whole string.
+//! `_` prints the next character literally. Numbers too wide for their
+//! field are printed in full with a leading `%`.
+
+use crate::basic::interpreter::Value;
+
+/// A parsed numeric field specification
+#[derive(Debug, Default)]
+struct NumberField {
+    plus_sign: bool,
+    trailing_sign: Option<char>,
+    asterisks: bool,
+    dollar: bool,
+    commas: bool,
+    /// Characters left of the decimal point, including sign/fill/dollar positions
+    left_width: usize,
+    int_digits: usize,
+    decimal_point: bool,
+    dec_digits: usize,
+    exponent_digits: Option<usize>,
+}
+
+enum Field {
+    FirstChar,
+    Fixed(usize),
+    Whole,
+    Number(NumberField),
+}
+
+/// Format values according to a PRINT USING format string.
+/// The format is reused from the start when there are more values than fields.
+pub fn format_using(format: &str, values: &[Value]) -> Result<String, String> {
+    let chars: Vec<char> = format.chars().collect();
+    let mut out = String::new();
+    let mut values = values.iter();
+    let mut next = values.next();
+
+    if next.is_none() {
+        return Err("Missing operand".to_string());
+    }
+
+    loop {
+        let mut pos = 0;
+        let mut found_field = false;
+        while pos < chars.len() {
+            if let Some((field, len)) = parse_field(&chars, pos) {
+                let Some(value) = next else {
+                    return Ok(out);
+                };
+                out.push_str(&format_field(&field, value)?);
+                found_field = true;
+                next = values.next();
+                pos += len;
+            } else if chars[pos] == '_' && pos + 1 < chars.len() {
+                out.push(chars[pos + 1]);
+                pos += 2;
+            } else {
+                out.push(chars[pos]);
+                pos += 1;
+            }
+        }
+        if !found_field {
+            return Err("Illegal function call".to_string());
+        }
+        if next.is_none() {
+            return Ok(out);
+        }
+    }
+}
+
+/// Recognise a field starting at `pos`, returning it and its length in characters
+fn parse_field(chars: &[char], pos: usize) -> Option<(Field, usize)> {
+    let at = |i: usize| chars.get(i).copied();
+    match chars[pos] {
+        '!' => Some((Field::FirstChar, 1)),
+        '&' => Some((Field::Whole, 1)),
+        '\\' => {
+            let mut end = pos + 1;
+            while at(end) == Some(' ') {
+                end += 1;
+            }
+            (at(end) == Some('\\')).then(|| (Field::Fixed(end - pos + 1), end - pos + 1))
+        }
+        _ => parse_number_field(chars, pos).map(|(f, len)| (Field::Number(f), len)),
+    }
+}
+
+fn parse_number_field(chars: &[char], start: usize) -> Option<(NumberField, usize)> {
+    let at = |i: usize| chars.get(i).copied();
+    let mut field = NumberField::default();
+    let mut pos = start;
+
+    if at(pos) == Some('+') {
+        field.plus_sign = true;
+        pos += 1;
+    }
+    if at(pos) == Some('*') && at(pos + 1) == Some('*') {
+        field.asterisks = true;
+        field.int_digits += 2;
+        pos += 2;
+        if at(pos) == Some('$') {
+            field.dollar = true;
+            pos += 1;
+        }
+    } else if at(pos) == Some('$') && at(pos + 1) == Some('$') {
+        field.dollar = true;
+        field.int_digits += 1;
+        pos += 2;
+    }
+
+    while let Some(c @ ('#' | ',')) = at(pos) {
+        field.commas |= c == ',';
+        field.int_digits += 1;
+        pos += 1;
+    }
+    field.left_width = pos - start;
+
+    if at(pos) == Some('.') && (field.left_width > usize::from(field.plus_sign) || at(pos + 1) == Some('#')) {
+        field.decimal_point = true;
+        pos += 1;
+        while at(pos) == Some('#') {
+            field.dec_digits += 1;
+            pos += 1;
+        }
+    }
+
+    // A field must contain at least one digit position or a fill/dollar marker
+    if field.int_digits == 0 && field.dec_digits == 0 && !field.dollar {
+        return None;
+    }
+
+    let carets = chars[pos..].iter().take(5).take_while(|&&c| c == '^').count();
+    if carets >= 4 {
+        field.exponent_digits = Some(carets - 2);
+        pos += carets;
+    }
+
+    if !field.plus_sign {
+        if let Some(c @ ('+' | '-')) = at(pos) {
+            field.trailing_sign = Some(c);
+            pos += 1;
+        }
+    }
+
+    Some((field, pos - start))
+}
+
+fn format_field(field: &Field, value: &Value) -> Result<String, String> {
+    match (field, value) {
+        (Field::FirstChar, Value::String(s)) => Ok(s.chars().next().unwrap_or(' ').to_string()),
+        (Field::Fixed(width), Value::String(s)) => {
+            let mut text: String = s.chars().take(*width).collect();
+            let len = text.chars().count();
+            text.push_str(&" ".repeat(width - len));
+            Ok(text)
+        }
+        (Field::Whole, Value::String(s)) => Ok(s.clone()),
+        (Field::Number(spec), Value::Integer(_) | Value::Float(_)) => Ok(format_number(spec, value.to_float())),
+        _ => Err("Type mismatch".to_string()),
+    }
+}
+
+fn format_number(spec: &NumberField, value: f64) -> String {
+    let negative = value < 0.0;
+    let mut magnitude = value.abs();
+
+    let exponent = spec.exponent_digits.map(|digits| {
+        // Without an explicit sign, one leading position is reserved for it
+        let reserved = usize::from(!spec.plus_sign && spec.trailing_sign.is_none());
+        let int_digits = spec.int_digits.saturating_sub(reserved) as i32;
+        let mut exp = if magnitude == 0.0 { 0 } else { magnitude.log10().floor() as i32 - int_digits + 1 };
+        let mut mantissa = magnitude / 10f64.powi(exp);
+        let rounded: f64 = format!("{:.*}", spec.dec_digits, mantissa).parse().unwrap_or(mantissa);
+        if magnitude != 0.0 && rounded >= 10f64.powi(int_digits.max(0)) {
+            exp += 1;
+            mantissa = magnitude / 10f64.powi(exp);
+        }
+        magnitude = mantissa;
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("E{}{:0width$}", sign, exp.abs(), width = digits)
+    });
+
+    let digits = format!("{:.*}", spec.dec_digits, magnitude);
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((&digits, ""));
+    let mut int_text = if int_part == "0" && spec.int_digits == 0 {
+        String::new()
+    } else {
+        int_part.to_string()
+    };
+    if spec.commas {
+        int_text = group_thousands(&int_text);
+    }
+
+    let mut left = String::new();
+    if spec.plus_sign {
+        left.push(if negative { '-' } else { '+' });
+    } else if negative && spec.trailing_sign.is_none() {
+        left.push('-');
+    }
+    if spec.dollar {
+        left.push('$');
+    }
+    left.push_str(&int_text);
+
+    let mut right = String::new();
+    if spec.decimal_point {
+        right.push('.');
+        right.push_str(frac_part);
+    }
+    if let Some(exp) = exponent {
+        right.push_str(&exp);
+    }
+    match spec.trailing_sign {
+        Some('-') => right.push(if negative { '-' } else { ' ' }),
+        Some(_) => right.push(if negative { '-' } else { '+' }),
+        None => {}
+    }
+
+    let len = left.chars().count();
+    if len > spec.left_width {
+        return format!("%{}{}", left, right);
+    }
+    let fill = if spec.asterisks { "*" } else { " " };
+    format!("{}{}{}", fill.repeat(spec.left_width - len), left, right)
+}
+
+/// Insert commas between groups of three digits
+fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fmt(format: &str, values: &[Value]) -> String {
+        format_using(format, values).expect("format should succeed")
+    }
+
+    #[test]
+    fn test_digits_and_rounding() {
+        assert_eq!(fmt("###.##", &[Value::Float(12.3456)]), " 12.35");
+        assert_eq!(fmt("##.##", &[Value::Float(-1.5)]), "-1.50");
+        assert_eq!(fmt(".##", &[Value::Float(0.5)]), ".50");
+    }
+
+    #[test]
+    fn test_currency() {
+        assert_eq!(fmt("$$###.##", &[Value::Float(12.5)]), "  $12.50");
+        assert_eq!(fmt("**$##.##", &[Value::Float(1.5)]), "***$1.50");
+        assert_eq!(fmt("**###", &[Value::Integer(42)]), "***42");
+    }
+
+    #[test]
+    fn test_thousands_separators() {
+        assert_eq!(fmt("#,###.##", &[Value::Float(1234.5)]), "1,234.50");
+        assert_eq!(fmt("##,###,###", &[Value::Integer(1234567)]), " 1,234,567");
+    }
+
+    #[test]
+    fn test_signs_and_exponent() {
+        assert_eq!(fmt("+###", &[Value::Integer(5)]), "  +5");
+        assert_eq!(fmt("###-", &[Value::Integer(-5)]), "  5-");
+        assert_eq!(fmt("###-", &[Value::Integer(5)]), "  5 ");
+        assert_eq!(fmt("##.##^^^^", &[Value::Float(1234.5)]), " 1.23E+03");
+    }
+
+    #[test]
+    fn test_overflow_prefix() {
+        assert_eq!(fmt("##.##", &[Value::Float(123.456)]), "%123.46");
+    }
+
+    #[test]
+    fn test_string_fields() {
+        let name = [Value::String("QBasic".to_string())];
+        assert_eq!(fmt("!", &name), "Q");
+        assert_eq!(fmt("\\  \\", &name), "QBas");
+        assert_eq!(fmt("[&]", &name), "[QBasic]");
+    }
+
+    #[test]
+    fn test_format_reuse_and_literals() {
+        let values = [Value::Integer(1), Value::Integer(2)];
+        assert_eq!(fmt("_##;", &values), "#1;#2;");
+        assert!(format_using("abc", &values).is_err());
+        assert!(format_using("#", &[Value::String("x".to_string())]).is_err());
+    }
+}
@@ -28,12 +28,21 @@ pub const PALETTE_16: [(u8, u8, u8); 16] = [
 pub struct SixelEncoder {
     /// Output buffer
     output: String,
+    /// RGB colour of each of the 16 attributes
+    palette: [(u8, u8, u8); 16],
 }
 
 impl SixelEncoder {
     pub fn new() -> Self {
+        Self::with_palette(PALETTE_16)
+    }
+
+    /// An encoder drawing the 16 attributes in the given colours (as
+    /// remapped by PALETTE)
+    pub fn with_palette(palette: [(u8, u8, u8); 16]) -> Self {
         Self {
             output: String::new(),
+            palette,
         }
     }
 
@@ -72,7 +81,7 @@ impl SixelEncoder {
         self.output.push_str("\x1bP0;0;q");
 
         // Define color palette (convert 0-255 to 0-100 for sixel)
-        for (i, &(r, g, b)) in PALETTE_16.iter().enumerate() {
+        for (i, &(r, g, b)) in self.palette.iter().enumerate() {
             let r100 = (r as u32 * 100 / 255) as u8;
             let g100 = (g as u32 * 100 / 255) as u8;
             let b100 = (b as u32 * 100 / 255) as u8;
@@ -151,7 +160,7 @@ impl SixelEncoder {
         self.output.push_str("\x1bP0;0;q");
 
         // Define color palette (convert 0-255 to 0-100 for sixel)
-        for (i, &(r, g, b)) in PALETTE_16.iter().enumerate() {
+        for (i, &(r, g, b)) in self.palette.iter().enumerate() {
             let r100 = (r as u32 * 100 / 255) as u8;
             let g100 = (g as u32 * 100 / 255) as u8;
             let b100 = (b as u32 * 100 / 255) as u8;
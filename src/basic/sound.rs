@@ -0,0 +1,212 @@
+//! BEEP, SOUND and PLAY
+//!
+//! There is no audio backend: statements queue `SoundEvent`s which the host
+//! collects with `Interpreter::take_sound_events` and may play or ignore.
+//!
+//! PLAY strings use QBasic's music macro language: notes `A`-`G` with an
+//! optional `#`/`+` (sharp) or `-` (flat), length and dots; `N` note number
+//! (0 is a rest); `O` octave, `<`/`>` octave down/up; `L` default length;
+//! `P` pause; `T` tempo; `MN`/`ML`/`MS` articulation and `MF`/`MB`
+//! foreground/background (accepted, but nothing waits on sound).
+
+/// A sound for the host to play
+#[derive(Clone, Debug, PartialEq)]
+pub enum SoundEvent {
+    /// BEEP - the terminal bell
+    Beep,
+    /// A tone of `frequency` Hz
+    Tone { frequency: f64, seconds: f64 },
+    /// Silence between notes
+    Pause { seconds: f64 },
+}
+
+/// SOUND durations are in clock ticks, 18.2 per second
+pub const TICKS_PER_SECOND: f64 = 18.2;
+
+/// How much of a note's length is sounded
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Articulation {
+    /// MN - 7/8 of the length
+    Normal,
+    /// ML - the full length
+    Legato,
+    /// MS - 3/4 of the length
+    Staccato,
+}
+
+impl Articulation {
+    fn fraction(self) -> f64 {
+        match self {
+            Articulation::Normal => 7.0 / 8.0,
+            Articulation::Legato => 1.0,
+            Articulation::Staccato => 3.0 / 4.0,
+        }
+    }
+}
+
+/// PLAY settings, which carry over from one PLAY statement to the next
+#[derive(Clone, Debug)]
+pub struct PlayState {
+    octave: i64,
+    length: i64,
+    tempo: i64,
+    articulation: Articulation,
+}
+
+impl Default for PlayState {
+    fn default() -> Self {
+        Self { octave: 4, length: 4, tempo: 120, articulation: Articulation::Normal }
+    }
+}
+
+impl PlayState {
+    /// Translate a PLAY string into sound events, or "Illegal function call"
+    /// if it isn't valid music macro language
+    pub fn play(&mut self, mml: &str) -> Result<Vec<SoundEvent>, String> {
+        let illegal = || "Illegal function call".to_string();
+        let chars: Vec<char> = mml.chars().filter(|c| !c.is_whitespace()).map(|c| c.to_ascii_uppercase()).collect();
+        let mut events = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let command = chars[i];
+            i += 1;
+            match command {
+                'A'..='G' => {
+                    let mut semitone = match command {
+                        'C' => 0,
+                        'D' => 2,
+                        'E' => 4,
+                        'F' => 5,
+                        'G' => 7,
+                        'A' => 9,
+                        _ => 11,
+                    };
+                    match chars.get(i) {
+                        Some('#' | '+') => {
+                            semitone += 1;
+                            i += 1;
+                        }
+                        Some('-') => {
+                            semitone -= 1;
+                            i += 1;
+                        }
+                        _ => {}
+                    }
+                    let length = match read_number(&chars, &mut i) {
+                        Some(n) if (1..=64).contains(&n) => n,
+                        Some(_) => return Err(illegal()),
+                        None => self.length,
+                    };
+                    let dots = read_dots(&chars, &mut i);
+                    self.note(self.octave * 12 + semitone + 1, length, dots, &mut events);
+                }
+                'N' => {
+                    let note = read_number(&chars, &mut i).filter(|n| (0..=84).contains(n)).ok_or_else(illegal)?;
+                    let dots = read_dots(&chars, &mut i);
+                    self.note(note, self.length, dots, &mut events);
+                }
+                'P' => {
+                    let length = read_number(&chars, &mut i).filter(|n| (1..=64).contains(n)).ok_or_else(illegal)?;
+                    let dots = read_dots(&chars, &mut i);
+                    events.push(SoundEvent::Pause { seconds: self.seconds(length, dots) });
+                }
+                'O' => {
+                    self.octave = read_number(&chars, &mut i).filter(|n| (0..=6).contains(n)).ok_or_else(illegal)?;
+                }
+                'L' => {
+                    self.length = read_number(&chars, &mut i).filter(|n| (1..=64).contains(n)).ok_or_else(illegal)?;
+                }
+                'T' => {
+                    self.tempo = read_number(&chars, &mut i).filter(|n| (32..=255).contains(n)).ok_or_else(illegal)?;
+                }
+                '<' => self.octave = (self.octave - 1).max(0),
+                '>' => self.octave = (self.octave + 1).min(6),
+                'M' => {
+                    match chars.get(i) {
+                        Some('N') => self.articulation = Articulation::Normal,
+                        Some('L') => self.articulation = Articulation::Legato,
+                        Some('S') => self.articulation = Articulation::Staccato,
+                        Some('F' | 'B') => {}
+                        _ => return Err(illegal()),
+                    }
+                    i += 1;
+                }
+                _ => return Err(illegal()),
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Queue note number `note` (1-84, 0 for a rest) played for `length`
+    fn note(&self, note: i64, length: i64, dots: usize, events: &mut Vec<SoundEvent>) {
+        let seconds = self.seconds(length, dots);
+        if note == 0 {
+            events.push(SoundEvent::Pause { seconds });
+            return;
+        }
+
+        // Note 34 (A in octave 2) is concert A
+        let frequency = 440.0 * 2f64.powf((note - 34) as f64 / 12.0);
+        let sounded = seconds * self.articulation.fraction();
+        events.push(SoundEvent::Tone { frequency, seconds: sounded });
+        if sounded < seconds {
+            events.push(SoundEvent::Pause { seconds: seconds - sounded });
+        }
+    }
+
+    /// Duration of a 1/`length` note at the current tempo (quarter notes per minute)
+    fn seconds(&self, length: i64, dots: usize) -> f64 {
+        let base = 240.0 / (self.tempo as f64 * length as f64);
+        base * 1.5f64.powi(dots as i32)
+    }
+}
+
+fn read_number(chars: &[char], i: &mut usize) -> Option<i64> {
+    let start = *i;
+    while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+        *i += 1;
+    }
+    chars[start..*i].iter().collect::<String>().parse().ok()
+}
+
+fn read_dots(chars: &[char], i: &mut usize) -> usize {
+    let start = *i;
+    while chars.get(*i) == Some(&'.') {
+        *i += 1;
+    }
+    *i - start
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_play_notes_and_settings() {
+        let mut state = PlayState::default();
+        let events = state.play("T240 L8 MLO2A P4 ms c#. >n0").unwrap();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[0], SoundEvent::Tone { frequency: 440.0, seconds: 0.125 });
+        assert_eq!(events[1], SoundEvent::Pause { seconds: 0.25 });
+        match events[2] {
+            SoundEvent::Tone { frequency, seconds } => {
+                assert!((frequency - 277.18).abs() < 0.01);
+                assert!((seconds - 0.140625).abs() < 1e-9);
+            }
+            ref other => panic!("expected a tone, got {:?}", other),
+        }
+        assert_eq!(events[4], SoundEvent::Pause { seconds: 0.125 });
+
+        // Octave and length carry over to the next PLAY
+        assert_eq!(state.play("A").unwrap()[0], SoundEvent::Tone { frequency: 880.0, seconds: 0.09375 });
+    }
+
+    #[test]
+    fn test_play_rejects_invalid_strings() {
+        for mml in ["H", "O7", "L0", "T20", "N85", "MX", "P"] {
+            assert_eq!(PlayState::default().play(mml), Err("Illegal function call".to_string()), "{}", mml);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! IDE settings persisted between sessions in `~/.qbasicrc`.
+//!
+//! The file holds one `key=value` setting per line. Unknown keys, malformed
+//! lines and out-of-range values are skipped, so a bad file never stops the
+//! IDE from starting - those settings just keep their defaults.
+
+use std::io;
+use std::path::PathBuf;
+
+use crate::state::AppState;
+
+/// Location of the settings file (`$HOME/.qbasicrc`)
+pub fn config_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".qbasicrc"))
+}
+
+/// Load saved settings into `state`, ignoring a missing or unreadable file
+pub fn load(state: &mut AppState) {
+    if let Some(text) = config_path().and_then(|path| std::fs::read_to_string(path).ok()) {
+        apply(state, &text);
+    }
+}
+
+/// Save the current settings
+pub fn save(state: &AppState) -> io::Result<()> {
+    match config_path() {
+        Some(path) => std::fs::write(path, to_config_string(state)),
+        None => Ok(()),
+    }
+}
+
+/// Serialize the persisted settings as `key=value` lines
+pub fn to_config_string(state: &AppState) -> String {
+    format!(
+        "tab_stops={}\nshow_scrollbars={}\ncolor_scheme={}\nhelp_path={}\nsyntax_checking={}\nauto_indent={}\nauto_capitalize={}\noutput_scrollback={}\n",
+        state.tab_stops,
+        state.show_scrollbars,
+        state.color_scheme,
+        state.help_path,
+        state.syntax_checking,
+        state.auto_indent,
+        state.auto_capitalize,
+        state.output_scrollback,
+    )
+}
+
+/// Apply `key=value` settings from `text` to `state`
+pub fn apply(state: &mut AppState, text: &str) {
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "tab_stops" => {
+                if let Some(n) = value.parse().ok().filter(|n| (1..=99).contains(n)) {
+                    state.tab_stops = n;
+                }
+            }
+            "color_scheme" => {
+                if let Some(n) = value.parse().ok().filter(|n| *n <= 2) {
+                    state.color_scheme = n;
+                }
+            }
+            "output_scrollback" => {
+                if let Some(n) = value.parse().ok().filter(|n| (100..=1_000_000).contains(n)) {
+                    state.output_scrollback = n;
+                }
+            }
+            "help_path" => state.help_path = value.to_string(),
+            "show_scrollbars" => set_bool(&mut state.show_scrollbars, value),
+            "syntax_checking" => set_bool(&mut state.syntax_checking, value),
+            "auto_indent" => set_bool(&mut state.auto_indent, value),
+            "auto_capitalize" => set_bool(&mut state.auto_capitalize, value),
+            _ => {}
+        }
+    }
+}
+
+fn set_bool(field: &mut bool, value: &str) {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => *field = true,
+        "false" | "no" | "off" | "0" => *field = false,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_round_trip() {
+        let mut state = AppState::new();
+        state.tab_stops = 4;
+        state.show_scrollbars = false;
+        state.color_scheme = 2;
+        state.help_path = "/usr/share/qbasic/help".to_string();
+        state.syntax_checking = false;
+        state.auto_indent = false;
+        state.output_scrollback = 500;
+
+        let mut loaded = AppState::new();
+        apply(&mut loaded, &to_config_string(&state));
+        assert_eq!(loaded.tab_stops, 4);
+        assert!(!loaded.show_scrollbars);
+        assert_eq!(loaded.color_scheme, 2);
+        assert_eq!(loaded.help_path, "/usr/share/qbasic/help");
+        assert!(!loaded.syntax_checking);
+        assert!(!loaded.auto_indent);
+        assert!(loaded.auto_capitalize);
+        assert_eq!(loaded.output_scrollback, 500);
+    }
+
+    #[test]
+    fn test_malformed_settings_keep_defaults() {
+        let mut state = AppState::new();
+        apply(&mut state, "tab_stops=lots\ncolor_scheme=9\noutput_scrollback=5\nshow_scrollbars\n# comment\nunknown=1\nsyntax_checking = maybe\n auto_indent = off \n");
+        let defaults = AppState::new();
+        assert_eq!(state.tab_stops, defaults.tab_stops);
+        assert_eq!(state.color_scheme, defaults.color_scheme);
+        assert_eq!(state.output_scrollback, defaults.output_scrollback);
+        assert_eq!(state.show_scrollbars, defaults.show_scrollbars);
+        assert_eq!(state.syntax_checking, defaults.syntax_checking);
+        assert!(!state.auto_indent);
+    }
+}
@@ -0,0 +1,93 @@
+//! Headless run mode - execute a BASIC program without the IDE.
+//!
+//! PRINT output goes to stdout, INPUT reads lines from stdin and INKEY$ always
+//! returns an empty string. BEEP, SOUND and PLAY are silent. Errors are
+//! reported on stderr.
+
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::basic::interpreter::ExecutionResult;
+use crate::basic::{Interpreter, Lexer, Parser};
+
+/// Run the program in `path`, returning the process exit code
+/// (0 on success, 1 on a load, syntax or runtime error)
+pub fn run_file(path: &Path) -> i32 {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Error loading {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+
+    // Input typed at a terminal is echoed by the terminal; echo piped input so
+    // answers appear after their prompts
+    let echo_input = unsafe { libc::isatty(libc::STDIN_FILENO) } == 0;
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    match run_source(&source, &mut stdin.lock(), &mut stdout.lock(), echo_input) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Run BASIC source with the given input and output streams, optionally
+/// copying each line of input to the output
+pub fn run_source(source: &str, input: &mut impl BufRead, output: &mut impl Write, echo_input: bool) -> Result<(), String> {
+    let mut lexer = Lexer::new(source);
+    let tokens = lexer.tokenize();
+    let mut parser = Parser::new(tokens);
+    let program = parser.parse().map_err(|e| format!("Syntax error: {}", e))?;
+
+    let mut interp = Interpreter::new();
+    let mut result = interp.execute_with_debug(&program);
+    loop {
+        let pending = interp.pending_input();
+        let mut lines = interp.take_output();
+        // Leave an INPUT prompt on the same line as the user's answer
+        let prompt = match &pending {
+            Some(p) if interp.output_line_open() && lines.last().is_some_and(|l| l.ends_with(&p.prompt)) => lines.pop(),
+            _ => None,
+        };
+        let written: io::Result<()> = lines
+            .iter()
+            .try_for_each(|line| writeln!(output, "{}", line))
+            .and_then(|_| prompt.map_or(Ok(()), |p| write!(output, "{}", p)))
+            .and_then(|_| output.flush());
+        written.map_err(|e| format!("Error writing output: {}", e))?;
+        // No audio device or terminal bell here: drop queued BEEP/SOUND/PLAY
+        interp.take_sound_events();
+
+        match result {
+            Ok(ExecutionResult::NeedsInput) if pending.is_some() => {
+                let mut line = String::new();
+                match input.read_line(&mut line) {
+                    Ok(0) => return Err("Runtime error: Input past end of file".to_string()),
+                    Ok(_) => {}
+                    Err(e) => return Err(format!("Error reading input: {}", e)),
+                }
+                let line = line.trim_end_matches(['\r', '\n']);
+                if echo_input {
+                    writeln!(output, "{}", line).map_err(|e| format!("Error writing output: {}", e))?;
+                }
+                line.chars().for_each(|c| interp.add_input_char(c));
+                interp.complete_input();
+            }
+            Ok(ExecutionResult::NeedsInput) => match interp.sleep_deadline() {
+                // Timed SLEEP: no key can cut it short, so wait it out
+                Some(deadline) => std::thread::sleep(deadline.saturating_duration_since(Instant::now())),
+                // SLEEP waiting for a key: there is no keyboard, so carry on
+                None => interp.set_last_key(Some(' ')),
+            },
+            Ok(ExecutionResult::Completed | ExecutionResult::Stopped | ExecutionResult::Exited) => return Ok(()),
+            Ok(_) => {}
+            Err(e) => return Err(format!("Runtime error: {}", e)),
+        }
+        result = interp.continue_execution(&program);
+    }
+}
@@ -69,6 +69,8 @@ pub struct HelpSystem {
     documents: HashMap<String, HelpDocument>,
     /// Navigation history
     pub history: Vec<String>,
+    /// Topics left by going back, most recent last
+    pub forward: Vec<String>,
     /// Current topic
     pub current_topic: String,
     /// Vertical scroll position
@@ -79,18 +81,28 @@ pub struct HelpSystem {
     pub selected_link: usize,
     /// Rendered lines cache (topic, lines, links, styles, max_line_width)
     rendered_cache: Option<(String, Vec<String>, Vec<HelpLink>, Vec<StyleSpan>, usize)>,
+    /// Results page of the last search, shown as the `search-results` topic
+    search_results: Option<HelpDocument>,
 }
 
+/// Topic name of the generated search results page
+pub const SEARCH_RESULTS_TOPIC: &str = "search-results";
+
+/// Longest snippet shown next to a search result
+const SNIPPET_WIDTH: usize = 60;
+
 impl HelpSystem {
     pub fn new() -> Self {
         Self {
             documents: HashMap::new(),
             history: Vec::new(),
+            forward: Vec::new(),
             current_topic: String::new(),
             scroll: 0,
             scroll_col: 0,
             selected_link: 0,
             rendered_cache: None,
+            search_results: None,
         }
     }
 
@@ -104,21 +116,11 @@ impl HelpSystem {
             Some(std::path::PathBuf::from("help")),
         ];
 
+        self.documents.clear();
         for help_dir in help_dirs.iter().flatten() {
             if help_dir.is_dir() {
-                if let Ok(entries) = std::fs::read_dir(help_dir) {
-                    for entry in entries.filter_map(|e| e.ok()) {
-                        let path = entry.path();
-                        if path.extension().map(|e| e == "md").unwrap_or(false) {
-                            if let Ok(content) = std::fs::read_to_string(&path) {
-                                let topic = path.file_stem()
-                                    .and_then(|s| s.to_str())
-                                    .unwrap_or("unknown")
-                                    .to_string();
-                                self.documents.insert(topic, parse_markdown(&content));
-                            }
-                        }
-                    }
+                if let Ok(documents) = read_help_dir(help_dir) {
+                    self.documents = documents;
                 }
                 break; // Use first directory that exists
             }
@@ -126,20 +128,84 @@ impl HelpSystem {
 
         // Add embedded fallback content
         self.add_embedded_content();
+        self.rendered_cache = None;
+    }
+
+    /// Reload help topics from a user-specified directory.
+    /// Returns the number of topics loaded; the current topics are kept if
+    /// the directory is missing or contains no readable help files.
+    pub fn load_help_files_from(&mut self, dir: &std::path::Path) -> Result<usize, String> {
+        if !dir.exists() {
+            return Err(format!("Path not found: {}", dir.display()));
+        }
+        if !dir.is_dir() {
+            return Err(format!("Not a directory: {}", dir.display()));
+        }
+        let documents = read_help_dir(dir)?;
+        if documents.is_empty() {
+            return Err(format!("No help files found in {}", dir.display()));
+        }
+
+        let count = documents.len();
+        self.documents = documents;
+        self.add_embedded_content();
+        self.rendered_cache = None;
+        Ok(count)
     }
 
-    /// Add embedded help content as fallback
+    /// Add embedded help content as fallback for topics not loaded from files
     fn add_embedded_content(&mut self) {
-        // Only add if not already loaded from files
-        if !self.documents.contains_key("survival-guide") {
-            self.documents.insert("survival-guide".to_string(), parse_markdown(SURVIVAL_GUIDE_MD));
+        for (topic, content) in EMBEDDED_TOPICS {
+            if !self.documents.contains_key(*topic) {
+                self.documents.insert(topic.to_string(), parse_markdown(content));
+            }
         }
-        if !self.documents.contains_key("index") {
-            self.documents.insert("index".to_string(), parse_markdown(INDEX_MD));
+    }
+
+    /// Number of loaded help topics
+    pub fn topic_count(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Whether a topic is loaded, using the same naming as navigate_to
+    pub fn has_topic(&self, topic: &str) -> bool {
+        self.documents.contains_key(&topic.to_lowercase().replace(' ', "-"))
+    }
+
+    /// Help topic for the keyword at `col` in `line`. Two-word keywords such
+    /// as END IF or PRINT USING are tried before the single word, and type
+    /// suffixes are dropped so LEFT$ finds LEFT.
+    pub fn context_topic(&self, line: &str, col: usize) -> Option<String> {
+        let chars: Vec<char> = line.chars().collect();
+        let is_word_char = |c: char| c.is_alphanumeric() || "$%&!#".contains(c);
+        let mut words = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            if is_word_char(chars[i]) {
+                let start = i;
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                words.push((start, i, word.trim_end_matches(|c| "$%&!#".contains(c)).to_uppercase()));
+            } else {
+                i += 1;
+            }
+        }
+
+        // The word under the cursor, or the one just before it
+        let index = words.iter().position(|(start, end, _)| (*start..*end).contains(&col))
+            .or_else(|| words.iter().position(|(_, end, _)| *end == col))?;
+        let word = &words[index].2;
+        let mut candidates = Vec::new();
+        if let Some((_, _, prev)) = index.checked_sub(1).map(|i| &words[i]) {
+            candidates.push(format!("{} {}", prev, word));
         }
-        if !self.documents.contains_key("shortcuts") {
-            self.documents.insert("shortcuts".to_string(), parse_markdown(SHORTCUTS_MD));
+        if let Some((_, _, next)) = words.get(index + 1) {
+            candidates.push(format!("{} {}", word, next));
         }
+        candidates.push(word.clone());
+        candidates.into_iter().find(|topic| self.has_topic(topic))
     }
 
     /// Navigate to a topic
@@ -148,29 +214,96 @@ impl HelpSystem {
         if !self.current_topic.is_empty() {
             self.history.push(self.current_topic.clone());
         }
-        self.current_topic = topic_lower;
-        self.scroll = 0;
-        self.scroll_col = 0;
-        self.selected_link = 0;
-        self.rendered_cache = None;
+        self.forward.clear();
+        self.show_topic(topic_lower);
     }
 
     /// Go back to previous topic
     pub fn go_back(&mut self) -> bool {
         if let Some(prev) = self.history.pop() {
-            self.current_topic = prev;
-            self.scroll = 0;
-            self.scroll_col = 0;
-            self.selected_link = 0;
-            self.rendered_cache = None;
+            self.forward.push(self.current_topic.clone());
+            self.show_topic(prev);
             true
         } else {
             false
         }
     }
 
+    /// Go forward to the topic last left with go_back
+    pub fn go_forward(&mut self) -> bool {
+        if let Some(next) = self.forward.pop() {
+            self.history.push(self.current_topic.clone());
+            self.show_topic(next);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Show a topic from the top, without touching the history
+    fn show_topic(&mut self, topic: String) {
+        self.current_topic = topic;
+        self.scroll = 0;
+        self.scroll_col = 0;
+        self.selected_link = 0;
+        self.rendered_cache = None;
+    }
+
+    /// Search the text of every topic for `query`, ignoring case.
+    /// Returns (topic, snippet) pairs ranked by title matches, then by the
+    /// number of matches in the body; the snippet is the first matching line.
+    pub fn search(&self, query: &str) -> Vec<(String, String)> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let mut ranked = Vec::new();
+        for (topic, doc) in &self.documents {
+            let title_hits = doc.title.to_lowercase().matches(&query).count();
+            let mut body_hits = 0;
+            let mut snippet = None;
+            for line in document_lines(doc) {
+                let hits = line.to_lowercase().matches(&query).count();
+                if hits > 0 && snippet.is_none() {
+                    snippet = Some(make_snippet(&line));
+                }
+                body_hits += hits;
+            }
+            if title_hits + body_hits > 0 {
+                let snippet = snippet.unwrap_or_else(|| doc.title.clone());
+                ranked.push((title_hits, body_hits, topic.clone(), snippet));
+            }
+        }
+
+        ranked.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+        ranked.into_iter().map(|(_, _, topic, snippet)| (topic, snippet)).collect()
+    }
+
+    /// Search for `query` and show the results as a page of links.
+    /// Returns the number of topics found.
+    pub fn show_search_results(&mut self, query: &str) -> usize {
+        let results = self.search(query);
+        let mut markdown = format!("# Search: {}\n\n", query.trim());
+        if results.is_empty() {
+            markdown.push_str("No topics found.\n");
+        }
+        for (topic, snippet) in &results {
+            let title = self.documents.get(topic).map(|d| d.title.as_str()).unwrap_or(topic);
+            markdown.push_str(&format!("- [{}]({}) - {}\n", title, topic, snippet));
+        }
+        self.search_results = Some(parse_markdown(&markdown));
+        self.navigate_to(SEARCH_RESULTS_TOPIC);
+        results.len()
+    }
+
     /// Get the current document
     pub fn current_document(&self) -> Option<&HelpDocument> {
+        if self.current_topic == SEARCH_RESULTS_TOPIC {
+            if let Some(doc) = &self.search_results {
+                return Some(doc);
+            }
+        }
         self.documents.get(&self.current_topic)
             .or_else(|| self.documents.get("index"))
     }
@@ -307,6 +440,71 @@ impl Default for HelpSystem {
     }
 }
 
+/// Searchable lines of a document: headings, paragraphs, each code line
+/// and each table row
+fn document_lines(doc: &HelpDocument) -> Vec<String> {
+    let mut lines = Vec::new();
+    for element in &doc.elements {
+        match element {
+            HelpElement::Heading(_, text) | HelpElement::Paragraph(text) => lines.push(plain_text(text)),
+            HelpElement::Code(code) => lines.extend(code.lines().map(|l| l.to_string())),
+            HelpElement::Table(headers, rows) => {
+                lines.push(headers.join(" "));
+                lines.extend(rows.iter().map(|row| plain_text(&row.join(" "))));
+            }
+            HelpElement::Blank => {}
+        }
+    }
+    lines
+}
+
+/// Strip markdown markup from a line, keeping the text of links
+fn plain_text(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find("](").map(|i| open + i) else { break };
+        let Some(end) = rest[close..].find(')').map(|i| close + i) else { break };
+        result.push_str(&rest[..open]);
+        result.push_str(&rest[open + 1..close]);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result.replace(['`', '*'], "")
+}
+
+/// A trimmed line, cut to SNIPPET_WIDTH characters, for a search result
+fn make_snippet(line: &str) -> String {
+    let line = line.trim().trim_start_matches('•').trim();
+    if line.chars().count() > SNIPPET_WIDTH {
+        let cut: String = line.chars().take(SNIPPET_WIDTH - 3).collect();
+        format!("{}...", cut.trim_end())
+    } else {
+        line.to_string()
+    }
+}
+
+/// Read every `.md` file in a directory, keyed by file stem
+fn read_help_dir(dir: &std::path::Path) -> Result<HashMap<String, HelpDocument>, String> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?;
+
+    let mut documents = HashMap::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.extension().map(|e| e == "md").unwrap_or(false) {
+            if let Ok(content) = std::fs::read_to_string(&path) {
+                let topic = path.file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("unknown")
+                    .to_string();
+                documents.insert(topic, parse_markdown(&content));
+            }
+        }
+    }
+    Ok(documents)
+}
+
 /// Parse markdown content into a HelpDocument
 fn parse_markdown(content: &str) -> HelpDocument {
     let mut elements = Vec::new();
@@ -609,72 +807,152 @@ fn render_paragraph_with_links(text: &str, start_line: usize, width: usize) -> (
     (result_lines, links, styles)
 }
 
-// Embedded help content as fallback
-const SURVIVAL_GUIDE_MD: &str = r#"# QBasic Survival Guide
-
-A quick reference for the most essential commands.
-
-## Getting Around
-
-| Action | Key |
-|--------|-----|
-| Access menus | Alt + letter |
-| Help on item | F1 |
-| Switch windows | F6 |
-
-## Running Programs
-
-| Action | Key |
-|--------|-----|
-| Run | F5 |
-| Step | F8 |
-| Breakpoint | F9 |
-| Stop | Ctrl+C |
-
-## See Also
-- [Index](index)
-- [Keyboard Shortcuts](shortcuts)
-"#;
-
-const INDEX_MD: &str = r#"# Help Index
-
-## Getting Started
-- [Survival Guide](survival-guide)
-- [Keyboard Shortcuts](shortcuts)
-
-## Statements
-- [PRINT](print) - Display output
-- [INPUT](input) - Read input
-- [IF...THEN](if) - Conditions
-- [FOR...NEXT](for) - Loops
-
-Press Escape to close help.
-"#;
-
-const SHORTCUTS_MD: &str = r#"# Keyboard Shortcuts
-
-## Navigation
-| Key | Action |
-|-----|--------|
-| Arrow keys | Move cursor |
-| Home/End | Start/end of line |
-| Ctrl+Home/End | Start/end of file |
-
-## Editing
-| Key | Action |
-|-----|--------|
-| Ctrl+C | Copy |
-| Ctrl+V | Paste |
-| Ctrl+X | Cut |
-| Ctrl+Z | Undo |
-
-## Running
-| Key | Action |
-|-----|--------|
-| F5 | Run |
-| F8 | Step |
-| F9 | Breakpoint |
-
-## See Also
-- [Survival Guide](survival-guide)
-"#;
+// Embedded help content, compiled in so F1 works without external help files.
+// Files in the help directory take precedence over these.
+const EMBEDDED_TOPICS: &[(&str, &str)] = &[
+    ("abs", include_str!("../help/abs.md")),
+    ("asc", include_str!("../help/asc.md")),
+    ("chr", include_str!("../help/chr.md")),
+    ("cls", include_str!("../help/cls.md")),
+    ("color", include_str!("../help/color.md")),
+    ("dim", include_str!("../help/dim.md")),
+    ("end", include_str!("../help/end.md")),
+    ("for", include_str!("../help/for.md")),
+    ("functions", include_str!("../help/functions.md")),
+    ("gosub", include_str!("../help/gosub.md")),
+    ("goto", include_str!("../help/goto.md")),
+    ("if", include_str!("../help/if.md")),
+    ("index", include_str!("../help/index.md")),
+    ("inkey", include_str!("../help/inkey.md")),
+    ("input", include_str!("../help/input.md")),
+    ("int", include_str!("../help/int.md")),
+    ("left", include_str!("../help/left.md")),
+    ("len", include_str!("../help/len.md")),
+    ("let", include_str!("../help/let.md")),
+    ("locate", include_str!("../help/locate.md")),
+    ("mid", include_str!("../help/mid.md")),
+    ("operators", include_str!("../help/operators.md")),
+    ("print", include_str!("../help/print.md")),
+    ("rem", include_str!("../help/rem.md")),
+    ("right", include_str!("../help/right.md")),
+    ("rnd", include_str!("../help/rnd.md")),
+    ("shortcuts", include_str!("../help/shortcuts.md")),
+    ("statements", include_str!("../help/statements.md")),
+    ("str", include_str!("../help/str.md")),
+    ("survival-guide", include_str!("../help/survival-guide.md")),
+    ("timer", include_str!("../help/timer.md")),
+    ("types", include_str!("../help/types.md")),
+    ("using-help", include_str!("../help/using-help.md")),
+    ("val", include_str!("../help/val.md")),
+    ("while", include_str!("../help/while.md")),
+    ("width", include_str!("../help/width.md")),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_ranks_title_matches_first() {
+        let mut help = HelpSystem::new();
+        help.documents.insert("print".to_string(), parse_markdown(
+            "# PRINT Statement\n\nWrites data to the screen.\n\n```basic\nPRINT \"Hello\"\n```"));
+        help.documents.insert("locate".to_string(), parse_markdown(
+            "# LOCATE Statement\n\nMoves the cursor before the next [PRINT](print).\n\nSee also CLS."));
+
+        let results = help.search("print");
+        let topics: Vec<&str> = results.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(topics, ["print", "locate"]);
+        assert_eq!(results[1].1, "Moves the cursor before the next PRINT.");
+        assert!(help.search("  ").is_empty());
+        assert!(help.search("sound").is_empty());
+
+        assert_eq!(help.show_search_results("cls"), 1);
+        assert_eq!(help.current_topic, SEARCH_RESULTS_TOPIC);
+        help.render(80);
+        assert_eq!(help.selected_link().map(|l| l.target.as_str()), Some("locate"));
+    }
+
+    #[test]
+    fn test_back_and_forward() {
+        let mut help = HelpSystem::new();
+        help.navigate_to("index");
+        help.navigate_to("statements");
+        help.navigate_to("print");
+
+        assert!(help.go_back());
+        assert!(help.go_back());
+        assert_eq!(help.current_topic, "index");
+        assert!(!help.go_back());
+
+        assert!(help.go_forward());
+        assert_eq!(help.current_topic, "statements");
+        assert!(help.go_forward());
+        assert_eq!(help.current_topic, "print");
+        assert!(!help.go_forward());
+
+        assert!(help.go_back());
+        assert_eq!(help.current_topic, "statements");
+        assert!(help.go_back());
+        assert!(help.go_forward());
+        assert_eq!(help.current_topic, "statements");
+    }
+
+    #[test]
+    fn test_new_topic_clears_forward_history() {
+        let mut help = HelpSystem::new();
+        help.navigate_to("index");
+        help.navigate_to("print");
+        assert!(help.go_back());
+
+        help.navigate_to("for");
+        assert!(!help.go_forward());
+        assert_eq!(help.current_topic, "for");
+        assert!(help.go_back());
+        assert_eq!(help.current_topic, "index");
+    }
+
+    #[test]
+    fn test_context_topic_prefers_two_word_keywords() {
+        let mut help = HelpSystem::new();
+        for topic in ["end", "if", "end-if", "left"] {
+            help.documents.insert(topic.to_string(), parse_markdown(&format!("# {}", topic)));
+        }
+
+        let line = "  END IF";
+        assert_eq!(help.context_topic(line, 2).as_deref(), Some("END IF"));
+        assert_eq!(help.context_topic(line, 6).as_deref(), Some("END IF"));
+        assert_eq!(help.context_topic("END", 1).as_deref(), Some("END"));
+        assert_eq!(help.context_topic("IF x THEN END", 0).as_deref(), Some("IF"));
+        assert_eq!(help.context_topic("a$ = LEFT$(b$, 2)", 8).as_deref(), Some("LEFT"));
+        assert_eq!(help.context_topic("a$ = LEFT$(b$, 2)", 10).as_deref(), Some("LEFT"));
+        assert_eq!(help.context_topic("x = 1", 0), None);
+        assert_eq!(help.context_topic("", 0), None);
+    }
+
+    #[test]
+    fn test_help_files_from_a_directory_override_built_in_topics() {
+        let dir = std::env::temp_dir().join(format!("qbasic-rs-help-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("print.md"), "# My PRINT\n\nCustom text.").unwrap();
+        std::fs::write(dir.join("extra.md"), "# Extra Topic\n\nMore.").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not help").unwrap();
+
+        let mut help = HelpSystem::new();
+        let loaded = help.load_help_files_from(&dir);
+        let empty = dir.join("empty");
+        std::fs::create_dir_all(&empty).unwrap();
+        let from_empty = help.load_help_files_from(&empty);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded, Ok(2));
+        assert_eq!(help.documents["print"].title, "My PRINT");
+        assert!(help.has_topic("extra"));
+        assert!(!help.has_topic("notes"));
+        // Topics missing from the directory still come from the built-in set
+        assert!(help.has_topic("locate"));
+        // An empty directory is refused and the loaded topics are kept
+        assert!(from_empty.is_err());
+        assert_eq!(help.documents["print"].title, "My PRINT");
+    }
+}
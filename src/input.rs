@@ -1,5 +1,6 @@
 //! Input handling and key event processing
 
+use crate::basic::interpreter::cp437_to_unicode;
 use crate::terminal::{Key, MouseEvent, MouseButton};
 
 /// Processed input events for the application
@@ -74,9 +75,12 @@ pub enum InputEvent {
     Escape,
     /// Function keys
     F(u8),
+    ShiftF(u8),
     /// Other
     Unknown,
     UnknownBytes(Vec<u8>),
+    /// Pasted text, inserted as typed
+    Paste(String),
     None,
 }
 
@@ -125,6 +129,7 @@ impl From<Key> for InputEvent {
             Key::AltUp => InputEvent::AltUp,
             Key::AltDown => InputEvent::AltDown,
             Key::F(n) => InputEvent::F(n),
+            Key::ShiftF(n) => InputEvent::ShiftF(n),
             Key::Alt(c) => InputEvent::Alt(c),
             Key::Ctrl(c) => InputEvent::Ctrl(c),
             Key::Mouse(MouseEvent { button: MouseButton::Left, row, col, pressed: true, motion: false, .. }) => {
@@ -158,11 +163,60 @@ impl From<Key> for InputEvent {
                 InputEvent::MouseMove { row, col }
             }
             Key::Mouse(_) => InputEvent::Unknown,
+            Key::Paste(text) => InputEvent::Paste(text),
             Key::Unknown(bytes) => InputEvent::UnknownBytes(bytes),
         }
     }
 }
 
+/// What INKEY$ returns for a key: the character itself, or for extended keys
+/// (arrows, function keys, Alt+letter) CHR$(0) followed by the key's scan
+/// code. Codes are encoded the way CHR$ does, so `INKEY$ = CHR$(0) + CHR$(72)`
+/// and `INKEY$ = CHR$(13)` compare as expected.
+pub fn inkey_code(key: &Key) -> Option<String> {
+    let chr = |code: u8| Some(cp437_to_unicode(code).to_string());
+    let extended = |code: u8| Some(format!("{}{}", cp437_to_unicode(0), cp437_to_unicode(code)));
+    match key {
+        Key::Char(c) => Some(c.to_string()),
+        Key::Enter => chr(13),
+        Key::Escape => chr(27),
+        Key::Tab => chr(9),
+        Key::Backspace => chr(8),
+        Key::ShiftSpace => Some(" ".to_string()),
+        Key::Ctrl(c) if c.is_ascii_lowercase() => chr(*c as u8 - b'a' + 1),
+        Key::Up | Key::ShiftUp => extended(72),
+        Key::Down | Key::ShiftDown => extended(80),
+        Key::Left | Key::ShiftLeft => extended(75),
+        Key::Right | Key::ShiftRight => extended(77),
+        Key::Home | Key::ShiftHome => extended(71),
+        Key::End | Key::ShiftEnd => extended(79),
+        Key::PageUp => extended(73),
+        Key::PageDown => extended(81),
+        Key::Insert => extended(82),
+        Key::Delete => extended(83),
+        Key::ShiftTab => extended(15),
+        Key::CtrlLeft => extended(115),
+        Key::CtrlRight => extended(116),
+        Key::CtrlEnd => extended(117),
+        Key::CtrlPageDown => extended(118),
+        Key::CtrlHome => extended(119),
+        Key::CtrlPageUp => extended(132),
+        Key::F(n @ 1..=10) => extended(58 + n),
+        Key::F(n @ 11..=12) => extended(122 + n),
+        Key::ShiftF(n @ 1..=10) => extended(83 + n),
+        Key::Alt(c) => {
+            // Scan codes follow the keyboard rows
+            let c = c.to_ascii_uppercase();
+            let code = ["QWERTYUIOP", "ASDFGHJKL", "ZXCVBNM"]
+                .iter()
+                .zip([16, 30, 44])
+                .find_map(|(row, start)| row.find(c).map(|i| start + i as u8))?;
+            extended(code)
+        }
+        _ => None,
+    }
+}
+
 /// Check if a key combination requires menu focus
 pub fn is_menu_trigger(event: &InputEvent) -> bool {
     matches!(
@@ -193,3 +247,34 @@ pub fn menu_index_from_alt(event: &InputEvent) -> Option<usize> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::basic::builtins::call_builtin;
+    use crate::basic::interpreter::Value;
+
+    /// CHR$(code) for each code, joined, as a BASIC program would build it
+    fn chr(codes: &[i64]) -> String {
+        codes
+            .iter()
+            .map(|&code| match call_builtin("CHR", &[Value::Integer(code)]) {
+                Some(Ok(Value::String(s))) => s,
+                other => panic!("CHR$({}) gave {:?}", code, other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_inkey_codes() {
+        assert_eq!(inkey_code(&Key::Up), Some(chr(&[0, 72])));
+        assert_eq!(inkey_code(&Key::F(1)), Some(chr(&[0, 59])));
+        assert_eq!(inkey_code(&Key::F(12)), Some(chr(&[0, 134])));
+        assert_eq!(inkey_code(&Key::Alt('x')), Some(chr(&[0, 45])));
+        assert_eq!(inkey_code(&Key::Enter), Some(chr(&[13])));
+        assert_eq!(inkey_code(&Key::Ctrl('c')), Some(chr(&[3])));
+        assert_eq!(inkey_code(&Key::Char('a')).as_deref(), Some("a"));
+        assert_eq!(inkey_code(&Key::Paste("text".to_string())), None);
+    }
+}
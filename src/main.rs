@@ -11,6 +11,8 @@ mod ui;
 mod basic;
 mod help;
 mod app;
+mod config;
+mod headless;
 
 use clap::Parser;
 use std::io;
@@ -23,15 +25,25 @@ use std::path::PathBuf;
 struct Args {
     /// BASIC file to load on startup
     file: Option<PathBuf>,
+
+    /// Run the file without the IDE, using stdin/stdout, and exit
+    #[arg(long, requires = "file")]
+    run: bool,
 }
 
 fn main() -> io::Result<()> {
     let args = Args::parse();
 
+    if args.run {
+        if let Some(path) = &args.file {
+            std::process::exit(headless::run_file(path));
+        }
+    }
+
     let mut app = app::App::new()?;
 
     if let Some(path) = args.file {
-        app.load_file_from_path(path);
+        app.open_startup_file(path);
     }
 
     app.run()
@@ -30,11 +30,22 @@ pub enum EditorMode {
     Overwrite,
 }
 
+/// What the Print dialog writes out
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PrintScope {
+    SelectedText,
+    /// The SUB or FUNCTION around the cursor, or the module-level code
+    CurrentProcedure,
+    Program,
+}
+
 /// Debug breakpoint
 #[derive(Clone, Debug)]
 pub struct Breakpoint {
     pub line: usize,
     pub enabled: bool,
+    /// Only stop when this BASIC expression is true
+    pub condition: Option<String>,
 }
 
 /// Main application state
@@ -51,6 +62,9 @@ pub struct AppState {
     /// File modified flag
     pub modified: bool,
 
+    /// Save with DOS (CRLF) line endings; follows the file when one is loaded
+    pub crlf_line_endings: bool,
+
     // === Layout cache ===
     /// Main screen layout (menu_bar, editor, immediate, status_bar)
     pub main_layout: Option<crate::ui::layout::ComputedLayout>,
@@ -106,6 +120,15 @@ pub struct AppState {
     /// Tab stop width
     pub tab_stops: usize,
 
+    /// Indent new lines to match the previous line and BASIC block structure
+    pub auto_indent: bool,
+
+    /// Uppercase keywords when the cursor leaves a line
+    pub auto_capitalize: bool,
+
+    /// Lines of program output kept for scrolling back
+    pub output_scrollback: usize,
+
     /// Show scrollbars
     pub show_scrollbars: bool,
 
@@ -133,6 +156,15 @@ pub struct AppState {
     /// Search whole word
     pub search_whole_word: bool,
 
+    /// Search pattern is a regular expression
+    pub search_regex: bool,
+
+    /// Editor cursor (line, col) when the Find dialog opened, restored on cancel
+    pub search_origin: Option<(usize, usize)>,
+
+    /// Print dialog choice
+    pub print_scope: PrintScope,
+
     /// Scrollbar dragging state
     pub vscroll_dragging: bool,
     pub hscroll_dragging: bool,
@@ -149,6 +181,7 @@ impl Default for AppState {
             run_state: RunState::Editing,
             file_path: None,
             modified: false,
+            crlf_line_endings: false,
             // no dialog tracked in AppState
             main_layout: None,
             last_screen_size: (0, 0),
@@ -168,6 +201,9 @@ impl Default for AppState {
             syntax_checking: true,
             syntax_errors: Vec::new(),
             tab_stops: 8,
+            auto_indent: true,
+            auto_capitalize: true,
+            output_scrollback: crate::ui::output::DEFAULT_SCROLLBACK,
             show_scrollbars: true,
             color_scheme: 0,
             breakpoints: Vec::new(),
@@ -177,6 +213,9 @@ impl Default for AppState {
             last_search: String::new(),
             search_case_sensitive: false,
             search_whole_word: false,
+            search_regex: false,
+            search_origin: None,
+            print_scope: PrintScope::Program,
             vscroll_dragging: false,
             hscroll_dragging: false,
             mouse_row: 0,
@@ -213,6 +252,11 @@ impl AppState {
         }
     }
 
+    /// Terminal window title, e.g. "QBasic - HELLO.BAS*"
+    pub fn window_title(&self) -> String {
+        format!("QBasic - {}", self.title())
+    }
+
     /// Mark the document as modified
     pub fn set_modified(&mut self, modified: bool) {
         self.modified = modified;
@@ -223,7 +267,16 @@ impl AppState {
         if let Some(idx) = self.breakpoints.iter().position(|b| b.line == line) {
             self.breakpoints.remove(idx);
         } else {
-            self.breakpoints.push(Breakpoint { line, enabled: true });
+            self.breakpoints.push(Breakpoint { line, enabled: true, condition: None });
+        }
+    }
+
+    /// Set or clear the condition of the breakpoint on a line, adding the
+    /// breakpoint if there isn't one
+    pub fn set_breakpoint_condition(&mut self, line: usize, condition: Option<String>) {
+        match self.breakpoints.iter_mut().find(|b| b.line == line) {
+            Some(bp) => bp.condition = condition,
+            None => self.breakpoints.push(Breakpoint { line, enabled: true, condition }),
         }
     }
 
@@ -269,3 +322,18 @@ impl AppState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_title() {
+        let mut state = AppState::new();
+        assert_eq!(state.window_title(), "QBasic - Untitled");
+        state.file_path = Some(PathBuf::from("/home/user/games/NIBBLES.BAS"));
+        assert_eq!(state.window_title(), "QBasic - NIBBLES.BAS");
+        state.set_modified(true);
+        assert_eq!(state.window_title(), "QBasic - NIBBLES.BAS*");
+    }
+}
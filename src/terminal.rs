@@ -51,6 +51,32 @@ impl Color {
         }
     }
 
+    /// The standard ANSI color number (0-7) and whether it is the bright variant
+    fn to_ansi(self) -> (u8, bool) {
+        // ANSI orders the colors red, green, blue bits the other way round from DOS
+        let dos = self as u8;
+        let ansi = ((dos & 1) << 2) | (dos & 2) | ((dos & 4) >> 2);
+        (ansi, dos >= 8)
+    }
+
+    /// Convert DOS color to an ANSI SGR foreground code in `mode`
+    pub fn fg_sgr(self, mode: ColorMode) -> String {
+        match (mode, self.to_ansi()) {
+            (ColorMode::TrueColor, _) => self.to_fg_sgr(),
+            (ColorMode::Ansi16, (n, false)) => (30 + n).to_string(),
+            (ColorMode::Ansi16, (n, true)) => (90 + n).to_string(),
+        }
+    }
+
+    /// Convert DOS color to an ANSI SGR background code in `mode`
+    pub fn bg_sgr(self, mode: ColorMode) -> String {
+        match (mode, self.to_ansi()) {
+            (ColorMode::TrueColor, _) => self.to_bg_sgr(),
+            (ColorMode::Ansi16, (n, false)) => (40 + n).to_string(),
+            (ColorMode::Ansi16, (n, true)) => (100 + n).to_string(),
+        }
+    }
+
     /// Convert DOS color to ANSI SGR foreground code (using true color)
     pub fn to_fg_sgr(self) -> String {
         let (r, g, b) = self.to_rgb();
@@ -97,6 +123,36 @@ impl Color {
     }
 }
 
+/// How colors are sent to the terminal
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorMode {
+    /// The 16 standard ANSI colors, drawn from the terminal's own palette
+    Ansi16,
+    /// 24-bit colors matching the DOS palette exactly
+    TrueColor,
+}
+
+impl ColorMode {
+    /// Pick the mode from `QBASIC_COLORS` ("16" or "truecolor"), falling back
+    /// to true color only when `COLORTERM` says the terminal supports it
+    pub fn detect() -> Self {
+        Self::from_env(
+            std::env::var("QBASIC_COLORS").ok().as_deref(),
+            std::env::var("COLORTERM").ok().as_deref(),
+        )
+    }
+
+    fn from_env(qbasic_colors: Option<&str>, colorterm: Option<&str>) -> Self {
+        let is_truecolor = |v: &str| v.eq_ignore_ascii_case("truecolor") || v.eq_ignore_ascii_case("24bit");
+        match qbasic_colors {
+            Some("16") => ColorMode::Ansi16,
+            Some(v) if is_truecolor(v) => ColorMode::TrueColor,
+            _ if colorterm.is_some_and(is_truecolor) => ColorMode::TrueColor,
+            _ => ColorMode::Ansi16,
+        }
+    }
+}
+
 /// Mouse button
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum MouseButton {
@@ -170,9 +226,12 @@ pub enum Key {
     AltUp,
     AltDown,
     F(u8), // F1-F12
+    ShiftF(u8), // Shift+F1-F4
     Alt(char),
     Ctrl(char),
     Mouse(MouseEvent),
+    /// Text pasted while bracketed paste mode is on
+    Paste(String),
     Unknown(Vec<u8>),
 }
 
@@ -188,6 +247,9 @@ pub struct Terminal {
     pixel_width: u16,
     /// Terminal height in pixels (0 if not available)
     pixel_height: u16,
+    color_mode: ColorMode,
+    /// Raw mode and the alternate screen were set up, so dropping restores them
+    active: bool,
 }
 
 impl Terminal {
@@ -199,6 +261,8 @@ impl Terminal {
             height: 25,
             pixel_width: 0,
             pixel_height: 0,
+            color_mode: ColorMode::detect(),
+            active: true,
         };
 
         // Get terminal size
@@ -206,13 +270,17 @@ impl Terminal {
 
         // Enable raw mode
         term.enable_raw_mode()?;
+        install_panic_hook();
 
         // Setup terminal
+        term.write_raw("\x1b[?1049h")?; // Switch to the alternate screen, saving the cursor
         term.write_raw("\x1b[?25l")?; // Hide cursor
         term.write_raw("\x1b[?1003h")?; // Enable any-event mouse tracking (motion without buttons)
         term.write_raw("\x1b[?1006h")?; // Enable SGR extended mouse mode
         term.write_raw("\x1b[>4;2m")?; // Enable modifyOtherKeys mode 2 (xterm)
         term.write_raw("\x1b[>1u")?; // Enable Kitty keyboard protocol
+        term.write_raw("\x1b[?2004h")?; // Enable bracketed paste
+        term.write_raw("\x1b[22;0t")?; // Save the window title
         term.write_raw("\x1b[2J")?; // Clear screen
         term.write_raw("\x1b[H")?; // Home cursor
 
@@ -244,6 +312,21 @@ impl Terminal {
     }
 
     /// Enable raw mode (disable canonical mode, echo, etc.)
+    /// An 80x25 terminal that leaves the real one alone, for driving the
+    /// app in tests
+    #[cfg(test)]
+    pub fn headless() -> Self {
+        Self {
+            stdout: io::stdout(),
+            width: 80,
+            height: 25,
+            pixel_width: 0,
+            pixel_height: 0,
+            color_mode: ColorMode::Ansi16,
+            active: false,
+        }
+    }
+
     fn enable_raw_mode(&self) -> io::Result<()> {
         unsafe {
             let fd = io::stdin().as_raw_fd();
@@ -276,15 +359,7 @@ impl Terminal {
 
     /// Disable raw mode (restore original settings)
     fn disable_raw_mode(&self) -> io::Result<()> {
-        unsafe {
-            if let Some(orig) = ORIG_TERMIOS {
-                let fd = io::stdin().as_raw_fd();
-                if libc::tcsetattr(fd, libc::TCSAFLUSH, &orig) != 0 {
-                    return Err(io::Error::last_os_error());
-                }
-            }
-        }
-        Ok(())
+        restore_termios()
     }
 
     /// Write raw bytes to terminal
@@ -306,10 +381,16 @@ impl Terminal {
 
     /// Set foreground and background colors
     pub fn set_colors(&mut self, fg: Color, bg: Color) -> io::Result<()> {
-        write!(self.stdout, "\x1b[{};{}m", fg.to_fg_sgr(), bg.to_bg_sgr())?;
+        write!(self.stdout, "\x1b[{};{}m", fg.fg_sgr(self.color_mode), bg.bg_sgr(self.color_mode))?;
         Ok(())
     }
 
+    /// Set the window title (control characters are dropped)
+    pub fn set_title(&mut self, title: &str) -> io::Result<()> {
+        let title: String = title.chars().filter(|c| !c.is_control()).collect();
+        write!(self.stdout, "\x1b]0;{}\x07", title)
+    }
+
     /// Reset colors to default
     pub fn reset_colors(&mut self) -> io::Result<()> {
         self.write_raw("\x1b[0m")
@@ -394,6 +475,24 @@ impl Terminal {
                 return Ok((None, vec![]));
             }
 
+            // A paste can span many reads: wait (briefly) for all of it
+            if buffer.starts_with(PASTE_START) {
+                let mut read_buf = [0u8; 4096];
+                let mut idle = 0;
+                while find_subslice(&buffer, PASTE_END).is_none() && idle < 100 {
+                    match io::stdin().read(&mut read_buf)? {
+                        0 => {
+                            idle += 1;
+                            std::thread::sleep(std::time::Duration::from_millis(5));
+                        }
+                        n => {
+                            idle = 0;
+                            buffer.extend_from_slice(&read_buf[..n]);
+                        }
+                    }
+                }
+            }
+
             // Find the end of the first complete event
             let event_len = Self::find_event_boundary(&buffer);
             let event_bytes: Vec<u8> = buffer.drain(..event_len).collect();
@@ -408,6 +507,11 @@ impl Terminal {
             return 0;
         }
 
+        // Bracketed paste: everything up to the end marker
+        if buf.starts_with(PASTE_START) {
+            return find_subslice(buf, PASTE_END).map_or(buf.len(), |at| at + PASTE_END.len());
+        }
+
         // SGR mouse event: \x1b[<...M or \x1b[<...m
         if buf.len() >= 3 && buf[0] == 0x1b && buf[1] == b'[' && buf[2] == b'<' {
             // Find the terminating M or m
@@ -456,6 +560,13 @@ impl Terminal {
 
     /// Parse raw bytes into a Key
     fn parse_key(buf: &[u8]) -> Key {
+        if let Some(text) = buf.strip_prefix(PASTE_START) {
+            let text = text.strip_suffix(PASTE_END).unwrap_or(text);
+            // Terminals send pasted line breaks as carriage returns
+            let text = String::from_utf8_lossy(text).replace("\r\n", "\n").replace('\r', "\n");
+            return Key::Paste(text);
+        }
+
         // Check for SGR mouse events: \x1b[<Cb;Cx;CyM or \x1b[<Cb;Cx;Cym
         if buf.len() >= 6 && buf[0] == 0x1b && buf[1] == b'[' && buf[2] == b'<' {
             if let Some(mouse) = Self::parse_sgr_mouse(buf) {
@@ -473,6 +584,7 @@ impl Terminal {
 
             // Ctrl+letter (0x01-0x1a = Ctrl+A through Ctrl+Z)
             [c] if *c >= 1 && *c <= 26 => Key::Ctrl((b'a' + c - 1) as char),
+            [0x1d] => Key::Ctrl(']'),
 
             // Regular printable character
             [c] if *c >= 32 && *c < 127 => Key::Char(*c as char),
@@ -515,6 +627,9 @@ impl Terminal {
             // CSI 32 ; 2 u  or  CSI 27 ; 2 ; 32 ~
             [0x1b, b'[', b'3', b'2', b';', b'2', b'u'] => Key::ShiftSpace,
             [0x1b, b'[', b'2', b'7', b';', b'2', b';', b'3', b'2', b'~'] => Key::ShiftSpace,
+            // Ctrl+/ in the same protocols: CSI 47 ; 5 u  or  CSI 27 ; 5 ; 47 ~
+            [0x1b, b'[', b'4', b'7', b';', b'5', b'u'] => Key::Ctrl('/'),
+            [0x1b, b'[', b'2', b'7', b';', b'5', b';', b'4', b'7', b'~'] => Key::Ctrl('/'),
 
             // Ctrl+Arrow keys (modifier 5 = ctrl)
             [0x1b, b'[', b'1', b';', b'5', b'A'] => Key::CtrlUp,
@@ -560,6 +675,10 @@ impl Terminal {
             [0x1b, b'[', b'2', b'3', b'~'] => Key::F(11),
             [0x1b, b'[', b'2', b'4', b'~'] => Key::F(12),
 
+            // Shift+F1-F4: CSI 1 ; 2 P..S  or  CSI 11..14 ; 2 ~
+            [0x1b, b'[', b'1', b';', b'2', c @ b'P'..=b'S'] => Key::ShiftF(c - b'P' + 1),
+            [0x1b, b'[', b'1', c @ b'1'..=b'4', b';', b'2', b'~'] => Key::ShiftF(c - b'0'),
+
             // Alt+letter (ESC followed by letter)
             [0x1b, c] if *c >= b'a' && *c <= b'z' => Key::Alt(*c as char),
             [0x1b, c] if *c >= b'A' && *c <= b'Z' => Key::Alt((*c as char).to_ascii_lowercase()),
@@ -751,23 +870,72 @@ impl Terminal {
 
 impl Drop for Terminal {
     fn drop(&mut self) {
-        // Disable extended keyboard protocols
-        let _ = self.write_raw("\x1b[<u"); // Disable Kitty keyboard protocol
-        let _ = self.write_raw("\x1b[>4;0m"); // Disable modifyOtherKeys
-        // Disable mouse tracking
-        let _ = self.write_raw("\x1b[?1006l");
-        let _ = self.write_raw("\x1b[?1003l");
-        // Restore terminal state
-        let _ = self.write_raw("\x1b[0 q"); // Reset cursor to terminal default
-        let _ = self.show_cursor();
-        let _ = self.reset_colors();
-        let _ = self.clear();
-        let _ = self.goto(1, 1);
+        if !self.active {
+            return;
+        }
+        let _ = write_restore_sequences(&mut self.stdout);
         let _ = self.flush();
         let _ = self.disable_raw_mode();
     }
 }
 
+/// Bracketed paste markers around pasted text
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Position of `needle` in `haystack`
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Undo everything `Terminal::new` turned on, leaving the alternate screen
+/// so the user's original screen and cursor come back
+fn write_restore_sequences(out: &mut impl Write) -> io::Result<()> {
+    // Disable extended keyboard protocols
+    out.write_all(b"\x1b[<u")?; // Disable Kitty keyboard protocol
+    out.write_all(b"\x1b[>4;0m")?; // Disable modifyOtherKeys
+    out.write_all(b"\x1b[?2004l")?; // Disable bracketed paste
+    out.write_all(b"\x1b[23;0t")?; // Restore the window title
+    // Disable mouse tracking
+    out.write_all(b"\x1b[?1006l")?;
+    out.write_all(b"\x1b[?1003l")?;
+    // Restore terminal state
+    out.write_all(b"\x1b[0 q")?; // Reset cursor to terminal default
+    out.write_all(b"\x1b[?25h")?; // Show cursor
+    out.write_all(b"\x1b[0m")?; // Reset colors
+    out.write_all(b"\x1b[?1049l")?; // Back to the main screen and its cursor
+    Ok(())
+}
+
+/// Put back the terminal settings saved before raw mode
+fn restore_termios() -> io::Result<()> {
+    unsafe {
+        if let Some(orig) = ORIG_TERMIOS {
+            let fd = io::stdin().as_raw_fd();
+            if libc::tcsetattr(fd, libc::TCSAFLUSH, &orig) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Restore the terminal before a panic message is printed, so it isn't lost
+/// on the alternate screen or garbled by raw mode
+fn install_panic_hook() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let mut stdout = io::stdout();
+            let _ = write_restore_sequences(&mut stdout);
+            let _ = stdout.flush();
+            let _ = restore_termios();
+            previous(info);
+        }));
+    });
+}
+
 #[derive(Clone, Copy, Debug)]
 #[allow(dead_code)]
 pub enum CursorStyle {
@@ -778,3 +946,46 @@ pub enum CursorStyle {
     BlinkingBar,
     SteadyBar,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_sgr_codes() {
+        assert_eq!(Color::Blue.fg_sgr(ColorMode::Ansi16), "34");
+        assert_eq!(Color::Brown.bg_sgr(ColorMode::Ansi16), "43");
+        assert_eq!(Color::LightCyan.fg_sgr(ColorMode::Ansi16), "96");
+        assert_eq!(Color::Yellow.bg_sgr(ColorMode::Ansi16), "103");
+        assert_eq!(Color::Blue.bg_sgr(ColorMode::TrueColor), "48;2;0;0;170");
+        assert_eq!(Color::Brown.fg_sgr(ColorMode::TrueColor), "38;2;170;85;0");
+    }
+
+    #[test]
+    fn test_restore_sequences_leave_alternate_screen() {
+        let mut out = Vec::new();
+        write_restore_sequences(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        for seq in ["\x1b[?1003l", "\x1b[?1006l", "\x1b[?25h", "\x1b[0m"] {
+            assert!(out.contains(seq), "missing {:?}", seq);
+        }
+        // Leaving the alternate screen comes last so nothing is drawn on the main one
+        assert!(out.ends_with("\x1b[?1049l"));
+    }
+
+    #[test]
+    fn test_bracketed_paste_is_one_event() {
+        let buf = b"\x1b[200~FOR i = 1 TO 3\r  PRINT i\r\nNEXT\x1b[201~x";
+        let len = Terminal::find_event_boundary(buf);
+        assert_eq!(&buf[len..], b"x");
+        assert_eq!(Terminal::parse_key(&buf[..len]), Key::Paste("FOR i = 1 TO 3\n  PRINT i\nNEXT".to_string()));
+    }
+
+    #[test]
+    fn test_color_mode_from_env() {
+        assert_eq!(ColorMode::from_env(None, None), ColorMode::Ansi16);
+        assert_eq!(ColorMode::from_env(None, Some("truecolor")), ColorMode::TrueColor);
+        assert_eq!(ColorMode::from_env(Some("16"), Some("24bit")), ColorMode::Ansi16);
+        assert_eq!(ColorMode::from_env(Some("TrueColor"), None), ColorMode::TrueColor);
+    }
+}
@@ -10,13 +10,22 @@ use crate::ui::widgets::{Button, Label, Spacer};
 
 use super::{DialogContext, DialogController, DialogResult, DialogWidget};
 
+/// Button the user chose to close a confirm dialog
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConfirmChoice {
+    Yes,
+    No,
+    #[default]
+    Cancel,
+}
+
 pub struct ConfirmDialog {
     dialog: Option<DialogWidget>,
     title: String,
     text: String,
     open: bool,
-    /// Result of the dialog: true if user clicked Yes, false if No/Cancel
-    pub confirmed: bool,
+    /// Result of the dialog (Escape counts as Cancel)
+    pub choice: ConfirmChoice,
 }
 
 impl ConfirmDialog {
@@ -26,7 +35,7 @@ impl ConfirmDialog {
             title: String::new(),
             text: String::new(),
             open: false,
-            confirmed: false,
+            choice: ConfirmChoice::Cancel,
         }
     }
 
@@ -82,6 +91,7 @@ impl DialogController for ConfirmDialog {
 
     fn open(&mut self, ctx: &mut DialogContext) {
         self.open = true;
+        self.choice = ConfirmChoice::Cancel;
         self.ensure_dialog();
         if let Some(ref mut dialog) = self.dialog {
             dialog.focus_first();
@@ -125,11 +135,15 @@ impl DialogController for ConfirmDialog {
             if let EventResult::Action(action) = result {
                 match action.as_str() {
                     "yes" => {
-                        self.confirmed = true;
+                        self.choice = ConfirmChoice::Yes;
                         return DialogResult::Closed;
                     }
-                    "no" | "cancel" | "dialog_cancel" => {
-                        self.confirmed = false;
+                    "no" => {
+                        self.choice = ConfirmChoice::No;
+                        return DialogResult::Closed;
+                    }
+                    "cancel" | "dialog_cancel" => {
+                        self.choice = ConfirmChoice::Cancel;
                         return DialogResult::Closed;
                     }
                     _ => {}
@@ -139,3 +153,32 @@ impl DialogController for ConfirmDialog {
         DialogResult::Open
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::editor::Editor;
+
+    #[test]
+    fn test_cancel_leaves_buffer_untouched() {
+        let mut editor = Editor::new();
+        editor.load("PRINT \"unsaved\"");
+        let mut state = AppState::new();
+        state.set_modified(true);
+        let mut dialog = ConfirmDialog::new();
+        dialog.set_message("Unsaved Changes".to_string(), "Save changes to Untitled?".to_string());
+        dialog.set_screen_size(80, 25);
+        let mut ctx = DialogContext { editor: &mut editor, state: &mut state };
+
+        dialog.open(&mut ctx);
+        assert_eq!(dialog.handle_event(&InputEvent::Escape, &mut ctx), DialogResult::Closed);
+        assert_eq!(dialog.choice, ConfirmChoice::Cancel);
+        assert_eq!(ctx.editor.content(), "PRINT \"unsaved\"");
+        assert!(ctx.state.modified);
+
+        // Yes is focused first; a new prompt forgets the previous answer
+        dialog.open(&mut ctx);
+        assert_eq!(dialog.handle_event(&InputEvent::Enter, &mut ctx), DialogResult::Closed);
+        assert_eq!(dialog.choice, ConfirmChoice::Yes);
+    }
+}
@@ -5,7 +5,7 @@ use crate::screen::Screen;
 use crate::state::AppState;
 
 use super::{
-    AboutDialog, CommandArgsDialog, ConfirmDialog, DialogContext, DialogController,
+    AboutDialog, BreakpointConditionDialog, CommandArgsDialog, ConfirmDialog, DialogContext, DialogController,
     DialogResult, DisplayOptionsDialog, FileOpenDialog, FileSaveDialog, FindDialog,
     FindLabelDialog, GoToDialog, HelpDialog, HelpPathDialog, MessageDialog,
     NewFunctionDialog, NewProgramDialog, NewSubDialog, PrintDialog, ReplaceDialog,
@@ -36,6 +36,7 @@ pub struct Dialogs {
     pub find_label: FindLabelDialog,
     pub command_args: CommandArgsDialog,
     pub help_path: HelpPathDialog,
+    pub breakpoint_condition: BreakpointConditionDialog,
 }
 
 impl Dialogs {
@@ -60,6 +61,7 @@ impl Dialogs {
             find_label: FindLabelDialog::new(),
             command_args: CommandArgsDialog::new(),
             help_path: HelpPathDialog::new(),
+            breakpoint_condition: BreakpointConditionDialog::new(),
         }
     }
 
@@ -84,6 +86,7 @@ impl Dialogs {
             &mut self.find_label,
             &mut self.command_args,
             &mut self.help_path,
+            &mut self.breakpoint_condition,
         ]
         .into_iter()
     }
@@ -51,6 +51,11 @@ impl DialogWidget {
         self
     }
 
+    /// Change the title shown in the title bar
+    pub fn set_title(&mut self, title: impl Into<String>) {
+        self.window.title = title.into();
+    }
+
     /// Center the dialog on screen
     pub fn center(&mut self) {
         self.window.center(self.screen_size.0, self.screen_size.1);
@@ -83,7 +88,7 @@ impl DialogWidget {
 
     /// Focus the first focusable widget
     pub fn focus_first(&mut self) {
-        self.content.focus_next();
+        self.content.focus_first();
     }
 
     /// Draw the dialog with theme-based colors
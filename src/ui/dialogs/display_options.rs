@@ -20,7 +20,7 @@ impl DisplayOptionsDialog {
     pub fn new() -> Self {
         let content = Self::build_content();
         let mut dialog = DialogWidget::with_theme("Display", content, Theme::qbasic_dialog())
-            .with_size(50, 14)
+            .with_size(50, 15)
             .with_min_size(40, 10);
         dialog.set_show_maximize(false);
         Self { dialog, open: false }
@@ -38,6 +38,7 @@ impl DisplayOptionsDialog {
             )
             .child(WidgetNode::leaf("spacer1", Spacer::fixed(1)))
             .child(WidgetNode::leaf("scrollbars_checkbox", Checkbox::new("Scroll Bars", "toggle_scrollbars")))
+            .child(WidgetNode::leaf("autoindent_checkbox", Checkbox::new("Auto Indent", "toggle_autoindent")))
             .child(WidgetNode::leaf("spacer2", Spacer::fixed(1)))
             .child(WidgetNode::leaf("scheme_label", Label::new("Color Scheme:")))
             .child(
@@ -85,6 +86,12 @@ impl DisplayOptionsDialog {
         {
             cb.set_checked(state.show_scrollbars);
         }
+        if let Some(cb) = self.dialog.content_mut()
+            .get_widget_mut(&["root", "autoindent_checkbox"])
+            .and_then(|w| w.as_any_mut().downcast_mut::<Checkbox>())
+        {
+            cb.set_checked(state.auto_indent);
+        }
         self.sync_scheme_radios(state.color_scheme);
     }
 
@@ -134,7 +141,7 @@ impl DisplayOptionsDialog {
         }
     }
 
-    fn read_settings(&self) -> (usize, bool) {
+    fn read_settings(&self) -> (usize, bool, bool) {
         let tab_stops = self.get_tab_field()
             .and_then(|f| f.text().parse::<usize>().ok())
             .unwrap_or(4);
@@ -143,7 +150,12 @@ impl DisplayOptionsDialog {
             .and_then(|w| w.as_any().downcast_ref::<Checkbox>())
             .map(|cb| cb.checked())
             .unwrap_or(true);
-        (tab_stops, show_scrollbars)
+        let auto_indent = self.dialog.content()
+            .get_widget(&["root", "autoindent_checkbox"])
+            .and_then(|w| w.as_any().downcast_ref::<Checkbox>())
+            .map(|cb| cb.checked())
+            .unwrap_or(true);
+        (tab_stops, show_scrollbars, auto_indent)
     }
 }
 
@@ -189,9 +201,10 @@ impl DialogController for DisplayOptionsDialog {
                 }
                 "ok" => {
                     // Save settings to state
-                    let (tab_stops, show_scrollbars) = self.read_settings();
+                    let (tab_stops, show_scrollbars, auto_indent) = self.read_settings();
                     ctx.state.tab_stops = tab_stops;
                     ctx.state.show_scrollbars = show_scrollbars;
+                    ctx.state.auto_indent = auto_indent;
                     // Determine which scheme is selected
                     if let Some(rb) = self.dialog.content()
                         .get_widget(&["root", "scheme_dark_row", "scheme_dark"])
@@ -16,6 +16,9 @@ pub struct FileSaveDialog {
     dialog: DialogWidget,
     current_path: PathBuf,
     open: bool,
+    /// Text to save instead of the program (Save Output... or Print), with
+    /// what to call it in the status bar
+    output: Option<(String, &'static str)>,
 }
 
 impl FileSaveDialog {
@@ -30,6 +33,7 @@ impl FileSaveDialog {
             dialog,
             current_path: std::env::current_dir().unwrap_or_default(),
             open: false,
+            output: None,
         }
     }
 
@@ -199,8 +203,37 @@ impl FileSaveDialog {
 }
 
 impl FileSaveDialog {
+    /// Open the dialog to save program output rather than the program
+    pub fn open_for_output(&mut self, ctx: &mut DialogContext, output: String) {
+        self.open(ctx);
+        self.dialog.set_title("Save Output As");
+        self.set_filename("output.txt");
+        self.output = Some((output, "Output"));
+    }
+
+    /// Open the dialog to write text from the Print dialog, named after the program
+    pub fn open_for_print(&mut self, ctx: &mut DialogContext, text: String) {
+        let name = ctx.state.file_path
+            .as_ref()
+            .and_then(|p| p.file_stem())
+            .map(|s| format!("{}.txt", s.to_string_lossy()))
+            .unwrap_or_else(|| "untitled.txt".to_string());
+        self.open(ctx);
+        self.dialog.set_title("Print to File");
+        self.set_filename(&name);
+        self.output = Some((text, "Printout"));
+    }
+
     fn save_file(&self, ctx: &mut DialogContext, path: PathBuf) {
-        let content = ctx.editor.content();
+        if let Some((output, what)) = &self.output {
+            match std::fs::write(&path, output) {
+                Ok(()) => ctx.state.set_status(format!("{} saved to {}", what, path.display())),
+                Err(e) => ctx.state.set_status(format!("Error saving {}: {}", what.to_lowercase(), e)),
+            }
+            return;
+        }
+
+        let content = ctx.editor.content_for_save(ctx.state.crlf_line_endings);
         match std::fs::write(&path, &content) {
             Ok(()) => {
                 ctx.state.file_path = Some(path);
@@ -218,6 +251,8 @@ impl DialogController for FileSaveDialog {
 
     fn open(&mut self, ctx: &mut DialogContext) {
         self.open = true;
+        self.output = None;
+        self.dialog.set_title("Save Program As");
         if let Some(path) = &ctx.state.file_path {
             if let Some(parent) = path.parent() {
                 self.current_path = parent.to_path_buf();
@@ -287,7 +322,7 @@ impl DialogController for FileSaveDialog {
                     let mut filename = self.get_filename();
                     if !filename.is_empty() {
                         // Add .bas extension if missing
-                        if !filename.to_lowercase().ends_with(".bas") {
+                        if self.output.is_none() && !filename.to_lowercase().ends_with(".bas") {
                             filename.push_str(".bas");
                         }
                         let path = self.current_path.join(&filename);
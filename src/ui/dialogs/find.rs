@@ -3,6 +3,7 @@
 use crate::input::InputEvent;
 use crate::screen::Screen;
 use crate::state::AppState;
+use crate::terminal::Color;
 use crate::ui::theme::Theme;
 use crate::ui::widget::EventResult;
 use crate::ui::widget_tree::WidgetNode;
@@ -10,19 +11,26 @@ use crate::ui::widgets::{Button, Checkbox, Label, Spacer, TextField};
 
 use super::{DialogContext, DialogController, DialogResult, DialogWidget};
 
+/// Search text and Match Case / Whole Word / Regular Expression options
+type SearchQuery = (String, bool, bool, bool);
+
 pub struct FindDialog {
     dialog: DialogWidget,
     open: bool,
+    /// Query the editor selection currently reflects
+    last_query: SearchQuery,
+    /// Whether the search-as-you-type moved the editor since the dialog opened
+    searched: bool,
 }
 
 impl FindDialog {
     pub fn new() -> Self {
         let content = Self::build_content();
         let mut dialog = DialogWidget::with_theme("Find", content, Theme::qbasic_dialog())
-            .with_size(55, 10)
+            .with_size(68, 10)
             .with_min_size(40, 8);
         dialog.set_show_maximize(false);
-        Self { dialog, open: false }
+        Self { dialog, open: false, last_query: SearchQuery::default(), searched: false }
     }
 
     fn build_content() -> WidgetNode {
@@ -40,6 +48,7 @@ impl FindDialog {
                 WidgetNode::hstack("options_row")
                     .leaf("case_checkbox", Checkbox::new("Match Case", "toggle_case").min_width(20))
                     .leaf("whole_checkbox", Checkbox::new("Whole Word", "toggle_whole").min_width(18))
+                    .leaf("regex_checkbox", Checkbox::new("Regular Expression", "toggle_regex").min_width(24))
                     .spacing(0)
                     .build(),
             )
@@ -48,6 +57,7 @@ impl FindDialog {
                 WidgetNode::hstack("buttons_row")
                     .child(WidgetNode::leaf("btn_spacer_left", Spacer::new()))
                     .leaf("ok_button", Button::new("Find", "find").min_width(8))
+                    .leaf("count_button", Button::new("Count", "count").min_width(9))
                     .leaf("cancel_button", Button::new("Cancel", "cancel").min_width(10))
                     .child(WidgetNode::leaf("btn_spacer_right", Spacer::new()))
                     .spacing(2)
@@ -91,9 +101,15 @@ impl FindDialog {
         {
             cb.set_checked(state.search_whole_word);
         }
+        if let Some(cb) = self.dialog.content_mut()
+            .get_widget_mut(&["root", "options_row", "regex_checkbox"])
+            .and_then(|w| w.as_any_mut().downcast_mut::<Checkbox>())
+        {
+            cb.set_checked(state.search_regex);
+        }
     }
 
-    fn read_search_state(&self) -> (String, bool, bool) {
+    fn read_search_state(&self) -> SearchQuery {
         let query = self.dialog.content()
             .get_widget(&["root", "find_row", "find_field"])
             .and_then(|w| w.as_any().downcast_ref::<TextField>())
@@ -109,13 +125,51 @@ impl FindDialog {
             .and_then(|w| w.as_any().downcast_ref::<Checkbox>())
             .map(|cb| cb.checked())
             .unwrap_or(false);
-        (query, case_sensitive, whole_word)
+        let regex = self.dialog.content()
+            .get_widget(&["root", "options_row", "regex_checkbox"])
+            .and_then(|w| w.as_any().downcast_ref::<Checkbox>())
+            .map(|cb| cb.checked())
+            .unwrap_or(false);
+        (query, case_sensitive, whole_word, regex)
     }
 }
 
 impl FindDialog {
+    /// Jump to the first match at or after the pre-search cursor position as
+    /// the query changes, flagging the field when nothing matches
+    fn search_incrementally(&mut self, ctx: &mut DialogContext) {
+        let Some(origin) = ctx.state.search_origin else {
+            return;
+        };
+        let (search, case_sensitive, whole_word, regex) = &self.last_query;
+        let found = ctx.editor
+            .find_text_at(search, *case_sensitive, *whole_word, *regex, false, origin)
+            .ok()
+            .flatten();
+        match found {
+            Some(m) => ctx.editor.go_to_and_select(m.line, m.col, m.len),
+            None => Self::restore_origin(origin, ctx),
+        }
+        self.searched = true;
+
+        let no_match = !search.is_empty() && found.is_none();
+        if let Some(tf) = self.dialog.content_mut()
+            .get_widget_mut(&["root", "find_row", "find_field"])
+            .and_then(|w| w.as_any_mut().downcast_mut::<TextField>())
+        {
+            tf.set_text_color(no_match.then_some(Color::Red));
+        }
+    }
+
+    fn restore_origin((line, col): (usize, usize), ctx: &mut DialogContext) {
+        ctx.editor.cursor_line = line;
+        ctx.editor.cursor_col = col;
+        ctx.editor.clear_selection();
+    }
+
     fn find_next(&self, ctx: &mut DialogContext) {
-        let (search, case_sensitive, whole_word) = self.read_search_state();
+        let (search, case_sensitive, whole_word, regex) = self.read_search_state();
+        let origin = ctx.state.search_origin.take();
         if search.is_empty() {
             ctx.state.set_status("No search text");
             return;
@@ -124,13 +178,32 @@ impl FindDialog {
         ctx.state.last_search = search.clone();
         ctx.state.search_case_sensitive = case_sensitive;
         ctx.state.search_whole_word = whole_word;
+        ctx.state.search_regex = regex;
 
-        if let Some((line, col)) = ctx.editor.find_text(&search, case_sensitive, whole_word) {
-            ctx.editor.go_to_and_select(line, col, search.len());
-            ctx.state.set_status(format!("Found at line {}", line + 1));
-        } else {
-            ctx.state.set_status("Match not found");
+        // Accept the match search-as-you-type already found, if any
+        let result = match origin {
+            Some(origin) if self.searched => {
+                ctx.editor.find_text_at(&search, case_sensitive, whole_word, regex, false, origin)
+            }
+            _ => ctx.editor.find_text(&search, case_sensitive, whole_word, regex, false),
+        };
+        let status = ctx.editor.select_search_result(result, false);
+        ctx.state.set_status(status);
+    }
+
+    /// Report how many matches the current query has in the whole program
+    fn count(&self, ctx: &mut DialogContext) {
+        let (search, case_sensitive, whole_word, regex) = self.read_search_state();
+        if search.is_empty() {
+            ctx.state.set_status("No search text");
+            return;
         }
+        let status = match ctx.editor.count_matches(&search, case_sensitive, whole_word, regex) {
+            Ok(1) => "1 match".to_string(),
+            Ok(count) => format!("{} matches", count),
+            Err(e) => e,
+        };
+        ctx.state.set_status(status);
     }
 }
 
@@ -139,6 +212,15 @@ impl DialogController for FindDialog {
     fn open(&mut self, ctx: &mut DialogContext) {
         self.open = true;
         self.sync_from_state(ctx.state);
+        ctx.state.search_origin = Some((ctx.editor.cursor_line, ctx.editor.cursor_col));
+        self.last_query = self.read_search_state();
+        self.searched = false;
+        if let Some(tf) = self.dialog.content_mut()
+            .get_widget_mut(&["root", "find_row", "find_field"])
+            .and_then(|w| w.as_any_mut().downcast_mut::<TextField>())
+        {
+            tf.set_text_color(None);
+        }
         self.dialog.focus_first();
         self.dialog.center();
         ctx.state.focus_dialog();
@@ -171,11 +253,17 @@ impl DialogController for FindDialog {
         }
 
         let result = self.dialog.handle_event(event);
-        let (_, case_sensitive, whole_word) = self.read_search_state();
+        let query = self.read_search_state();
 
         // Keep search options in sync
-        ctx.state.search_case_sensitive = case_sensitive;
-        ctx.state.search_whole_word = whole_word;
+        ctx.state.search_case_sensitive = query.1;
+        ctx.state.search_whole_word = query.2;
+        ctx.state.search_regex = query.3;
+
+        if query != self.last_query {
+            self.last_query = query;
+            self.search_incrementally(ctx);
+        }
 
         if let EventResult::Action(a) = result {
             match a.as_str() {
@@ -184,7 +272,16 @@ impl DialogController for FindDialog {
                     self.sync_focus_decor();
                     return DialogResult::Closed;
                 }
-                "cancel" | "dialog_cancel" => return DialogResult::Closed,
+                "count" => self.count(ctx),
+                "cancel" | "dialog_cancel" => {
+                    // Put the cursor back where it was before searching
+                    if let Some(origin) = ctx.state.search_origin.take() {
+                        if self.searched {
+                            Self::restore_origin(origin, ctx);
+                        }
+                    }
+                    return DialogResult::Closed;
+                }
                 _ => {}
             }
         }
@@ -194,3 +291,37 @@ impl DialogController for FindDialog {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::editor::Editor;
+
+    #[test]
+    fn test_search_as_you_type() {
+        let mut editor = Editor::new();
+        editor.load("PRINT a\nPRINT ab\nPRINT abc");
+        let mut state = AppState::new();
+        let mut dialog = FindDialog::new();
+        dialog.set_screen_size(80, 25);
+        let mut ctx = DialogContext { editor: &mut editor, state: &mut state };
+        dialog.open(&mut ctx);
+
+        let mut type_char = |c: char, ctx: &mut DialogContext| {
+            dialog.handle_event(&InputEvent::Char(c), ctx);
+            (ctx.editor.cursor_line, ctx.editor.cursor_col, ctx.editor.get_selected_text())
+        };
+        assert_eq!(type_char('a', &mut ctx), (0, 6, Some("a".to_string())));
+        assert_eq!(type_char('b', &mut ctx), (1, 6, Some("ab".to_string())));
+        assert_eq!(type_char('c', &mut ctx), (2, 6, Some("abc".to_string())));
+        // No match: the cursor goes back to where the search started
+        assert_eq!(type_char('d', &mut ctx), (0, 0, None));
+        dialog.handle_event(&InputEvent::Backspace, &mut ctx);
+        assert_eq!((ctx.editor.cursor_line, ctx.editor.cursor_col), (2, 6));
+
+        // Escape restores the pre-search cursor position
+        dialog.handle_event(&InputEvent::Escape, &mut ctx);
+        assert_eq!((ctx.editor.cursor_line, ctx.editor.cursor_col), (0, 0));
+        assert!(ctx.state.search_origin.is_none());
+    }
+}
@@ -83,7 +83,7 @@ impl GoToDialog {
             let target = line_num.saturating_sub(1).min(ctx.editor.buffer.line_count().saturating_sub(1));
             ctx.editor.cursor_line = target;
             ctx.editor.cursor_col = 0;
-            ctx.editor.ensure_cursor_visible(20, 80);
+            ctx.editor.ensure_cursor_visible(20, 80, ctx.state.tab_stops);
             ctx.state.set_status(format!("Jumped to line {}", line_num));
         } else {
             ctx.state.set_status("Invalid line number");
@@ -5,7 +5,7 @@ use crate::input::InputEvent;
 use crate::screen::Screen;
 use crate::state::AppState;
 use crate::terminal::Color;
-use crate::ui::editor::{tokenize_line, TokenKind};
+use crate::ui::editor::{tokenize_line, SyntaxColors};
 use crate::ui::floating_window::FloatingWindow;
 use crate::ui::layout::{compute_layout, ComputedLayout, LayoutItem, Size};
 use crate::ui::scrollbar::{self, ScrollbarColors, ScrollbarState};
@@ -55,6 +55,8 @@ pub struct HelpDialog {
     hscroll_dragging: bool,
     open: bool,
     topic: String,
+    /// Search text being typed after Ctrl+F, shown in the nav bar
+    search_query: Option<String>,
 }
 
 impl HelpDialog {
@@ -74,14 +76,32 @@ impl HelpDialog {
             hscroll_dragging: false,
             open: false,
             topic: String::new(),
+            search_query: None,
         }
     }
 
+    /// Reload help topics from the given directory, or from the default
+    /// locations when the path is empty. Returns the number of topics loaded.
+    pub fn load_help_path(&mut self, path: &str) -> Result<usize, String> {
+        let path = path.trim();
+        if path.is_empty() {
+            self.help.load_help_files();
+            return Ok(self.help.topic_count());
+        }
+        self.help.load_help_files_from(std::path::Path::new(path))
+    }
+
     /// Set the topic to display (call before open)
     pub fn set_topic(&mut self, topic: String) {
         self.topic = topic;
     }
 
+    /// Set the topic for the keyword at `col` in `line`, or General help
+    /// when no topic matches (call before open)
+    pub fn set_topic_at(&mut self, line: &str, col: usize) {
+        self.topic = self.help.context_topic(line, col).unwrap_or_else(|| "General".to_string());
+    }
+
     fn sync_topic(&mut self) {
         let normalized = self.topic.to_lowercase().replace(' ', "-");
         if self.help.current_topic != normalized {
@@ -102,6 +122,7 @@ impl DialogController for HelpDialog {
         self.layout = None;
         self.vscroll_dragging = false;
         self.hscroll_dragging = false;
+        self.search_query = None;
         self.sync_topic();
         self.ensure_window_active();
         ctx.state.focus_dialog();
@@ -185,18 +206,10 @@ impl DialogController for HelpDialog {
 
                 if is_code_block && line_links.is_empty() {
                     let tokens = tokenize_line(line);
+                    let colors = SyntaxColors::for_scheme(0);
                     let mut x_pos = 0usize;
                     for token in tokens {
-                        let token_fg = match token.kind {
-                            TokenKind::Keyword => Color::White,
-                            TokenKind::String => Color::LightMagenta,
-                            TokenKind::Number => Color::LightCyan,
-                            TokenKind::Comment => Color::LightGray,
-                            TokenKind::Operator => Color::LightGreen,
-                            TokenKind::Identifier => Color::Yellow,
-                            TokenKind::Punctuation => Color::White,
-                            TokenKind::Whitespace => Color::Yellow,
-                        };
+                        let token_fg = colors.token(token.kind);
                         for ch in token.text.chars() {
                             if x_pos >= scroll_col && x_pos - scroll_col < content_width {
                                 screen.set(row, col + (x_pos - scroll_col) as u16, ch, token_fg, Color::Black);
@@ -254,23 +267,47 @@ impl DialogController for HelpDialog {
         }
 
         if let Some(nav_rect) = layout.get("nav_bar") {
-            let nav_hint = if self.help.link_count() > 0 {
-                "Tab:Link  Enter:Follow  Backspace:Back  Esc:Close"
+            if let Some(query) = &self.search_query {
+                let prompt = format!("Search: {}_", query);
+                screen.write_str(nav_rect.y, nav_rect.x, &prompt, Color::White, Color::Black);
             } else {
-                "Arrows:Scroll  Backspace:Back  Esc:Close"
-            };
-            screen.write_str(nav_rect.y, nav_rect.x, nav_hint, Color::Cyan, Color::Black);
+                let nav_hint = if self.help.link_count() > 0 {
+                    "Tab:Link  Enter:Follow  Backspace:Back  Ctrl+F:Search  Esc:Close"
+                } else {
+                    "Arrows:Scroll  Backspace:Back  Ctrl+F:Search  Esc:Close"
+                };
+                screen.write_str(nav_rect.y, nav_rect.x, nav_hint, Color::Cyan, Color::Black);
+            }
         }
 
         self.layout = Some(layout);
     }
 
-    fn handle_event(&mut self, event: &InputEvent, _ctx: &mut DialogContext) -> DialogResult {
+    fn handle_event(&mut self, event: &InputEvent, ctx: &mut DialogContext) -> DialogResult {
         if !self.open {
             return DialogResult::Open;
         }
 
         self.sync_topic();
+
+        if let Some(query) = &mut self.search_query {
+            match event {
+                InputEvent::Char(c) => query.push(*c),
+                InputEvent::Paste(text) => query.extend(text.chars().filter(|c| !c.is_control())),
+                InputEvent::Backspace => { query.pop(); }
+                InputEvent::Escape => self.search_query = None,
+                InputEvent::Enter => {
+                    let query = self.search_query.take().unwrap_or_default();
+                    if !query.trim().is_empty() {
+                        let found = self.help.show_search_results(&query);
+                        self.topic = self.help.current_topic.clone();
+                        ctx.state.set_status(format!("{} help topics found", found));
+                    }
+                }
+                _ => {}
+            }
+            return DialogResult::Open;
+        }
         let (sw, sh) = self.screen_size;
 
         if matches!(event, InputEvent::MouseRelease { .. }) {
@@ -290,6 +327,7 @@ impl DialogController for HelpDialog {
 
         match event {
             InputEvent::Escape => return DialogResult::Closed,
+            InputEvent::Ctrl('f') => self.search_query = Some(String::new()),
             InputEvent::Enter => {
                 if let Some(link) = self.help.selected_link().cloned() {
                     self.help.navigate_to(&link.target);
@@ -300,6 +338,7 @@ impl DialogController for HelpDialog {
                     return DialogResult::Closed;
                 }
             }
+            InputEvent::CtrlBackspace => { self.help.go_forward(); }
             InputEvent::Tab => {
                 let count = self.help.link_count();
                 if count > 0 {
@@ -324,6 +363,8 @@ impl DialogController for HelpDialog {
             InputEvent::ScrollDown { .. } => { self.help.scroll += 3; }
             _ => {}
         }
+        // Keep the requested topic in step with links followed and Back
+        self.topic = self.help.current_topic.clone();
 
         DialogResult::Open
     }
@@ -20,6 +20,7 @@ mod new_program;
 mod print;
 mod replace;
 mod simple_input;
+mod subs;
 mod welcome;
 
 pub(super) use dialog_widget::DialogWidget;
@@ -29,7 +30,7 @@ pub use dialog_registry::Dialogs;
 
 // Re-export dialog types for direct access
 pub use about::AboutDialog;
-pub use confirm::ConfirmDialog;
+pub use confirm::{ConfirmChoice, ConfirmDialog};
 pub use display_options::DisplayOptionsDialog;
 pub use file_open::FileOpenDialog;
 pub use file_save::FileSaveDialog;
@@ -40,5 +41,8 @@ pub use message::MessageDialog;
 pub use new_program::NewProgramDialog;
 pub use print::PrintDialog;
 pub use replace::ReplaceDialog;
-pub use simple_input::{NewSubDialog, NewFunctionDialog, FindLabelDialog, CommandArgsDialog, HelpPathDialog};
+pub use simple_input::{
+    BreakpointConditionDialog, NewSubDialog, NewFunctionDialog, FindLabelDialog, CommandArgsDialog, HelpPathDialog,
+};
+pub use subs::{find_definition, SubsDialog};
 pub use welcome::WelcomeDialog;
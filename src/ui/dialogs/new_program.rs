@@ -57,7 +57,7 @@ impl NewProgramDialog {
     fn save_then_clear(&self, ctx: &mut DialogContext) {
         // Save current file first
         if let Some(path) = &ctx.state.file_path {
-            let content = ctx.editor.content();
+            let content = ctx.editor.content_for_save(ctx.state.crlf_line_endings);
             if let Err(e) = std::fs::write(path, &content) {
                 ctx.state.set_status(format!("Error saving: {}", e));
                 return;
@@ -1,8 +1,10 @@
-//! Print dialog.
+//! Print dialog. Terminals can't print, so the chosen text is written to a
+//! file instead; the app asks where once the dialog closes.
 
 use crate::input::InputEvent;
 use crate::screen::Screen;
-use crate::state::AppState;
+use crate::state::{AppState, PrintScope};
+use crate::ui::editor::Editor;
 use crate::ui::theme::Theme;
 use crate::ui::widget::EventResult;
 use crate::ui::widget_tree::WidgetNode;
@@ -10,29 +12,37 @@ use crate::ui::widgets::{Button, RadioButton, Spacer};
 
 use super::{DialogContext, DialogController, DialogResult, DialogWidget};
 
+/// Radio button ids, in the order of the options
+const OPTIONS: [(&str, PrintScope); 3] = [
+    ("option_selected", PrintScope::SelectedText),
+    ("option_procedure", PrintScope::CurrentProcedure),
+    ("option_program", PrintScope::Program),
+];
+
 pub struct PrintDialog {
     dialog: DialogWidget,
-    selected: usize,
     open: bool,
+    /// Text to write out, set when OK closes the dialog
+    pub pending_text: Option<String>,
 }
 
 impl PrintDialog {
     pub fn new() -> Self {
         let content = Self::build_content();
         let mut dialog = DialogWidget::with_theme("Print", content, Theme::qbasic_dialog())
-            .with_size(50, 10)
-            .with_min_size(30, 8);
+            .with_size(50, 11)
+            .with_min_size(30, 9);
         dialog.set_show_maximize(false);
-        Self { dialog, selected: 0, open: false }
+        Self { dialog, open: false, pending_text: None }
     }
 
     fn build_content() -> WidgetNode {
         WidgetNode::vstack("root")
             .padding(1)
             .leaf("option_selected", RadioButton::new("Selected Text Only", "option_selected"))
-            .child(WidgetNode::leaf("spacer1", Spacer::fixed(1)))
-            .leaf("option_range", RadioButton::new("Lines:", "option_range"))
-            .child(WidgetNode::leaf("spacer2", Spacer::new()))
+            .leaf("option_procedure", RadioButton::new("Current SUB/FUNCTION", "option_procedure"))
+            .leaf("option_program", RadioButton::new("Entire Program", "option_program"))
+            .child(WidgetNode::leaf("spacer", Spacer::new()))
             .child(
                 WidgetNode::hstack("buttons_row")
                     .child(WidgetNode::leaf("btn_spacer_left", Spacer::new()))
@@ -45,28 +55,72 @@ impl PrintDialog {
             .build()
     }
 
-    fn sync_radio_state(&mut self) {
-        if let Some(rb) = self.dialog.content_mut()
-            .get_widget_mut(&["root", "option_selected"])
-            .and_then(|w| w.as_any_mut().downcast_mut::<RadioButton>())
-        {
-            rb.set_selected(self.selected == 0);
+    fn sync_radio_state(&mut self, scope: PrintScope) {
+        for (id, option) in OPTIONS {
+            if let Some(rb) = self.dialog.content_mut()
+                .get_widget_mut(&["root", id])
+                .and_then(|w| w.as_any_mut().downcast_mut::<RadioButton>())
+            {
+                rb.set_selected(option == scope);
+            }
         }
-        if let Some(rb) = self.dialog.content_mut()
-            .get_widget_mut(&["root", "option_range"])
-            .and_then(|w| w.as_any_mut().downcast_mut::<RadioButton>())
-        {
-            rb.set_selected(self.selected == 1);
+    }
+}
+
+/// The text the Print dialog writes for `scope`, or None if there is no selection
+pub fn print_text(editor: &Editor, scope: PrintScope) -> Option<String> {
+    let lines = &editor.buffer.lines;
+    match scope {
+        PrintScope::SelectedText => editor.get_selected_text().filter(|text| !text.is_empty()),
+        PrintScope::Program => Some(editor.content()),
+        PrintScope::CurrentProcedure => {
+            let procedures = procedure_ranges(lines);
+            let text = match procedures.iter().find(|(first, last)| (*first..=*last).contains(&editor.cursor_line)) {
+                Some(&(first, last)) => lines[first..=last].join("\n"),
+                // Outside any procedure: the module-level code
+                None => lines
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| !procedures.iter().any(|(first, last)| (*first..=*last).contains(i)))
+                    .map(|(_, line)| line.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            };
+            Some(text)
         }
     }
 }
 
+/// Line ranges (first, last inclusive) of each SUB and FUNCTION, from its
+/// header to its END SUB / END FUNCTION (or the end of the program)
+fn procedure_ranges(lines: &[String]) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.to_uppercase();
+        let words: Vec<&str> = line.split_whitespace().take(2).collect();
+        match words.as_slice() {
+            ["SUB" | "FUNCTION", ..] if start.is_none() => start = Some(i),
+            ["END", "SUB" | "FUNCTION"] => {
+                if let Some(first) = start.take() {
+                    ranges.push((first, i));
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = start {
+        ranges.push((first, lines.len().saturating_sub(1)));
+    }
+    ranges
+}
+
 impl DialogController for PrintDialog {
 
     fn open(&mut self, ctx: &mut DialogContext) {
         self.open = true;
-        self.selected = 0;
-        self.sync_radio_state();
+        self.pending_text = None;
+        self.sync_radio_state(ctx.state.print_scope);
         self.dialog.focus_first();
         self.dialog.center();
         ctx.state.focus_dialog();
@@ -98,17 +152,17 @@ impl DialogController for PrintDialog {
         }
         let result = self.dialog.handle_event(event);
         if let EventResult::Action(action) = result {
+            if let Some(&(_, scope)) = OPTIONS.iter().find(|(id, _)| *id == action) {
+                ctx.state.print_scope = scope;
+                self.sync_radio_state(scope);
+                return DialogResult::Open;
+            }
             match action.as_str() {
-                "option_selected" => {
-                    self.selected = 0;
-                    self.sync_radio_state();
-                }
-                "option_range" => {
-                    self.selected = 1;
-                    self.sync_radio_state();
-                }
                 "ok" => {
-                    ctx.state.set_status("Printing is not supported".to_string());
+                    self.pending_text = print_text(ctx.editor, ctx.state.print_scope);
+                    if self.pending_text.is_none() {
+                        ctx.state.set_status("No text selected");
+                    }
                     return DialogResult::Closed;
                 }
                 "cancel" | "dialog_cancel" => return DialogResult::Closed,
@@ -119,3 +173,28 @@ impl DialogController for PrintDialog {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_text_for_each_scope() {
+        let mut editor = Editor::new();
+        editor.load("CALL Hello\nEND\n\nSUB Hello\n  PRINT \"hi\"\nEND SUB");
+        assert_eq!(print_text(&editor, PrintScope::SelectedText), None);
+
+        editor.selection_start = Some((4, 2));
+        editor.selection_end = Some((4, 7));
+        assert_eq!(print_text(&editor, PrintScope::SelectedText).as_deref(), Some("PRINT"));
+        assert_eq!(print_text(&editor, PrintScope::Program), Some(editor.content()));
+
+        editor.cursor_line = 4;
+        assert_eq!(
+            print_text(&editor, PrintScope::CurrentProcedure).as_deref(),
+            Some("SUB Hello\n  PRINT \"hi\"\nEND SUB")
+        );
+        editor.cursor_line = 1;
+        assert_eq!(print_text(&editor, PrintScope::CurrentProcedure).as_deref(), Some("CALL Hello\nEND\n"));
+    }
+}
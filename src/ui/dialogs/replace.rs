@@ -8,6 +8,8 @@ use crate::ui::widget::EventResult;
 use crate::ui::widget_tree::WidgetNode;
 use crate::ui::widgets::{Button, Checkbox, Label, Spacer, TextField};
 
+use crate::ui::editor::build_search_regex;
+
 use super::{DialogContext, DialogController, DialogResult, DialogWidget};
 
 pub struct ReplaceDialog {
@@ -19,7 +21,7 @@ impl ReplaceDialog {
     pub fn new() -> Self {
         let content = Self::build_content();
         let mut dialog = DialogWidget::with_theme("Change", content, Theme::qbasic_dialog())
-            .with_size(55, 12)
+            .with_size(68, 12)
             .with_min_size(40, 10);
         dialog.set_show_maximize(false);
         Self { dialog, open: false }
@@ -48,6 +50,7 @@ impl ReplaceDialog {
                 WidgetNode::hstack("options_row")
                     .leaf("case_checkbox", Checkbox::new("Match Case", "toggle_case").min_width(20))
                     .leaf("whole_checkbox", Checkbox::new("Whole Word", "toggle_whole").min_width(18))
+                    .leaf("regex_checkbox", Checkbox::new("Regular Expression", "toggle_regex").min_width(24))
                     .spacing(0)
                     .build(),
             )
@@ -107,9 +110,15 @@ impl ReplaceDialog {
         {
             cb.set_checked(state.search_whole_word);
         }
+        if let Some(cb) = self.dialog.content_mut()
+            .get_widget_mut(&["root", "options_row", "regex_checkbox"])
+            .and_then(|w| w.as_any_mut().downcast_mut::<Checkbox>())
+        {
+            cb.set_checked(state.search_regex);
+        }
     }
 
-    fn read_state(&self) -> (String, String, bool, bool) {
+    fn read_state(&self) -> (String, String, bool, bool, bool) {
         let find_text = self.dialog.content()
             .get_widget(&["root", "find_row", "find_field"])
             .and_then(|w| w.as_any().downcast_ref::<TextField>())
@@ -130,13 +139,18 @@ impl ReplaceDialog {
             .and_then(|w| w.as_any().downcast_ref::<Checkbox>())
             .map(|cb| cb.checked())
             .unwrap_or(false);
-        (find_text, replace_text, case_sensitive, whole_word)
+        let regex = self.dialog.content()
+            .get_widget(&["root", "options_row", "regex_checkbox"])
+            .and_then(|w| w.as_any().downcast_ref::<Checkbox>())
+            .map(|cb| cb.checked())
+            .unwrap_or(false);
+        (find_text, replace_text, case_sensitive, whole_word, regex)
     }
 }
 
 impl ReplaceDialog {
     fn find_and_verify(&self, ctx: &mut DialogContext) {
-        let (search, _, case_sensitive, whole_word) = self.read_state();
+        let (search, _, case_sensitive, whole_word, regex) = self.read_state();
         if search.is_empty() {
             ctx.state.set_status("No search text");
             return;
@@ -145,34 +159,44 @@ impl ReplaceDialog {
         ctx.state.last_search = search.clone();
         ctx.state.search_case_sensitive = case_sensitive;
         ctx.state.search_whole_word = whole_word;
+        ctx.state.search_regex = regex;
 
-        if let Some((line, col)) = ctx.editor.find_text(&search, case_sensitive, whole_word) {
-            ctx.editor.go_to_and_select(line, col, search.len());
-            ctx.state.set_status(format!("Found at line {}", line + 1));
-        } else {
-            ctx.state.set_status("Match not found");
-        }
+        let status = ctx.editor.find_and_select(&search, case_sensitive, whole_word, regex, false);
+        ctx.state.set_status(status);
     }
 
     fn replace_selection(&self, ctx: &mut DialogContext) {
-        let (_, replacement, _, _) = self.read_state();
+        let (search, mut replacement, case_sensitive, whole_word, regex) = self.read_state();
+        if regex {
+            // Expand capture groups against the selected match
+            let selected = ctx.editor.get_selected_text().unwrap_or_default();
+            match build_search_regex(&search, case_sensitive, whole_word) {
+                Ok(re) => replacement = re.replace(&selected, replacement.as_str()).into_owned(),
+                Err(e) => {
+                    ctx.state.set_status(e);
+                    return;
+                }
+            }
+        }
         if ctx.editor.replace_selection(&replacement) {
             ctx.state.set_modified(true);
         }
     }
 
     fn replace_all(&self, ctx: &mut DialogContext) {
-        let (search, replacement, case_sensitive, whole_word) = self.read_state();
+        let (search, replacement, case_sensitive, whole_word, regex) = self.read_state();
         ctx.state.last_search = search.clone();
         ctx.state.search_case_sensitive = case_sensitive;
         ctx.state.search_whole_word = whole_word;
+        ctx.state.search_regex = regex;
 
-        let count = ctx.editor.replace_all(&search, &replacement, case_sensitive, whole_word);
-        if count > 0 {
-            ctx.state.set_modified(true);
-            ctx.state.set_status(format!("Replaced {} occurrences", count));
-        } else {
-            ctx.state.set_status("No matches found");
+        match ctx.editor.replace_all(&search, &replacement, case_sensitive, whole_word, regex) {
+            Ok(0) => ctx.state.set_status("No matches found"),
+            Ok(count) => {
+                ctx.state.set_modified(true);
+                ctx.state.set_status(format!("Replaced {} occurrences", count));
+            }
+            Err(e) => ctx.state.set_status(e),
         }
     }
 }
@@ -214,11 +238,12 @@ impl DialogController for ReplaceDialog {
         }
 
         let result = self.dialog.handle_event(event);
-        let (_, _, case_sensitive, whole_word) = self.read_state();
+        let (_, _, case_sensitive, whole_word, regex) = self.read_state();
 
         // Keep search options in sync
         ctx.state.search_case_sensitive = case_sensitive;
         ctx.state.search_whole_word = whole_word;
+        ctx.state.search_regex = regex;
 
         if let EventResult::Action(a) = result {
             match a.as_str() {
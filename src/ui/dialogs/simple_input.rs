@@ -1,7 +1,9 @@
-//! Simple input dialogs (NewSub, NewFunction, FindLabel, CommandArgs, HelpPath).
+//! Simple input dialogs (NewSub, NewFunction, FindLabel, CommandArgs, HelpPath,
+//! BreakpointCondition).
 //!
 //! Each is a separate struct implementing DialogController, sharing common UI building code.
 
+use crate::basic::{Lexer, Parser};
 use crate::input::InputEvent;
 use crate::screen::Screen;
 use crate::state::AppState;
@@ -410,6 +412,8 @@ impl DialogController for CommandArgsDialog {
 pub struct HelpPathDialog {
     dialog: DialogWidget,
     open: bool,
+    /// Path entered when OK was pressed; the app reloads help from it on close
+    pub committed_path: Option<String>,
 }
 
 impl HelpPathDialog {
@@ -419,7 +423,7 @@ impl HelpPathDialog {
             .with_size(55, 7)
             .with_min_size(30, 7);
         dialog.set_show_maximize(false);
-        Self { dialog, open: false }
+        Self { dialog, open: false, committed_path: None }
     }
 }
 
@@ -451,6 +455,92 @@ impl DialogController for HelpPathDialog {
         self.dialog.draw_with_theme(screen);
     }
 
+    fn handle_event(&mut self, event: &InputEvent, _ctx: &mut DialogContext) -> DialogResult {
+        if !self.open { return DialogResult::Open; }
+        let result = self.dialog.handle_event(event);
+        self.sync_focus_decor();
+        if let EventResult::Action(a) = result {
+            match a.as_str() {
+                "ok" | "input_submit" => {
+                    self.committed_path = Some(self.get_input_text());
+                    return DialogResult::Closed;
+                }
+                "cancel" | "dialog_cancel" => return DialogResult::Closed,
+                _ => {}
+            }
+        }
+        DialogResult::Open
+    }
+}
+
+// ============= BreakpointConditionDialog =============
+
+pub struct BreakpointConditionDialog {
+    dialog: DialogWidget,
+    open: bool,
+    /// Editor line whose breakpoint is being edited
+    line: usize,
+}
+
+impl BreakpointConditionDialog {
+    pub fn new() -> Self {
+        let content = build_content("Break when:");
+        let mut dialog = DialogWidget::with_theme("Breakpoint Condition", content, Theme::qbasic_dialog())
+            .with_size(55, 7)
+            .with_min_size(30, 7);
+        dialog.set_show_maximize(false);
+        Self { dialog, open: false, line: 0 }
+    }
+
+    fn apply_condition(&self, ctx: &mut DialogContext) {
+        let text = self.get_input_text().trim().to_string();
+        if text.is_empty() {
+            ctx.state.set_breakpoint_condition(self.line, None);
+            ctx.state.set_status(format!("Breakpoint at line {}", self.line + 1));
+            return;
+        }
+        if let Err(e) = Parser::new(Lexer::new(&text).tokenize()).parse_expression() {
+            ctx.state.set_status(format!("Invalid condition: {}", e));
+            return;
+        }
+        ctx.state.set_status(format!("Breakpoint at line {} when {}", self.line + 1, text));
+        ctx.state.set_breakpoint_condition(self.line, Some(text));
+    }
+}
+
+impl SimpleInputCommon for BreakpointConditionDialog {
+    fn dialog(&self) -> &DialogWidget { &self.dialog }
+    fn dialog_mut(&mut self) -> &mut DialogWidget { &mut self.dialog }
+}
+
+impl DialogController for BreakpointConditionDialog {
+    fn open(&mut self, ctx: &mut DialogContext) {
+        self.open = true;
+        self.line = ctx.editor.cursor_line;
+        let condition = ctx.state.breakpoints.iter()
+            .find(|bp| bp.line == self.line)
+            .and_then(|bp| bp.condition.clone())
+            .unwrap_or_default();
+        self.set_input_text(&condition);
+        self.dialog.focus_first();
+        self.dialog.center();
+        ctx.state.focus_dialog();
+    }
+
+    fn is_open(&self) -> bool { self.open }
+    fn close(&mut self) { self.open = false; }
+
+    fn set_screen_size(&mut self, width: u16, height: u16) {
+        self.dialog.set_screen_size(width, height);
+    }
+
+    fn draw(&mut self, screen: &mut Screen, _state: &AppState) {
+        if !self.open { return; }
+        self.sync_focus_decor();
+        self.dialog.center();
+        self.dialog.draw_with_theme(screen);
+    }
+
     fn handle_event(&mut self, event: &InputEvent, ctx: &mut DialogContext) -> DialogResult {
         if !self.open { return DialogResult::Open; }
         let result = self.dialog.handle_event(event);
@@ -458,7 +548,7 @@ impl DialogController for HelpPathDialog {
         if let EventResult::Action(a) = result {
             match a.as_str() {
                 "ok" | "input_submit" => {
-                    ctx.state.help_path = self.get_input_text();
+                    self.apply_condition(ctx);
                     return DialogResult::Closed;
                 }
                 "cancel" | "dialog_cancel" => return DialogResult::Closed,
@@ -0,0 +1,170 @@
+//! SUBs dialog (F2) - lists the main module and its procedures and jumps to one.
+//! While a program is paused it also lists the call stack, and choosing a
+//! frame jumps to the line that made the call.
+
+use crate::input::InputEvent;
+use crate::screen::Screen;
+use crate::ui::{ModalAction, ModalDialog, ModalResult};
+use crate::ui::theme::Theme;
+use crate::ui::widget::EventResult;
+use crate::ui::widget_tree::WidgetNode;
+use crate::ui::widgets::{Button, Label, ListView, Spacer};
+
+use super::DialogWidget;
+
+pub struct SubsDialog {
+    dialog: DialogWidget,
+    /// 1-based editor line for each list entry (none for headings)
+    lines: Vec<Option<usize>>,
+}
+
+impl SubsDialog {
+    /// Build the dialog from the current editor content and the paused
+    /// program's call stack (procedure names with 0-based call-site lines,
+    /// outermost first)
+    pub fn new(module_name: &str, source: &str, call_stack: &[(String, usize)], screen_width: u16, screen_height: u16) -> Self {
+        let procedures = find_procedures(source);
+        let mut items = vec![module_name.to_string()];
+        let mut lines = vec![Some(1)];
+        for (name, line) in procedures {
+            items.push(format!("  {:<30} line {}", name, line));
+            lines.push(Some(line));
+        }
+        if !call_stack.is_empty() {
+            items.push("Call stack:".to_string());
+            lines.push(None);
+            for (name, line) in call_stack.iter().rev() {
+                items.push(format!("  {:<30} called from line {}", name, line + 1));
+                lines.push(Some(line + 1));
+            }
+        }
+
+        let content = Self::build_content();
+        let mut dialog = DialogWidget::with_theme("SUBs", content, Theme::qbasic_dialog())
+            .with_size(50, 16)
+            .with_min_size(36, 10);
+        dialog.set_show_maximize(false);
+        dialog.set_screen_size(screen_width, screen_height);
+
+        if let Some(list) = dialog.content_mut()
+            .get_widget_mut(&["root", "subs_list"])
+            .and_then(|w| w.as_any_mut().downcast_mut::<ListView>())
+        {
+            list.set_items(items);
+        }
+        dialog.focus_first();
+        dialog.center();
+
+        Self { dialog, lines }
+    }
+
+    fn build_content() -> WidgetNode {
+        WidgetNode::vstack("root")
+            .padding(1)
+            .leaf("subs_label", Label::new("Choose program item to edit:"))
+            .leaf("subs_list", ListView::new("subs").with_border(true))
+            .child(WidgetNode::leaf("spacer1", Spacer::fixed(1)))
+            .child(
+                WidgetNode::hstack("buttons_row")
+                    .child(WidgetNode::leaf("btn_spacer_left", Spacer::new()))
+                    .leaf("ok_button", Button::new("Edit", "ok").min_width(8))
+                    .leaf("cancel_button", Button::new("Cancel", "cancel").min_width(10))
+                    .child(WidgetNode::leaf("btn_spacer_right", Spacer::new()))
+                    .spacing(2)
+                    .build(),
+            )
+            .build()
+    }
+
+    fn selected_line(&self) -> Option<usize> {
+        self.dialog.content()
+            .get_widget(&["root", "subs_list"])
+            .and_then(|w| w.as_any().downcast_ref::<ListView>())
+            .and_then(|list| self.lines.get(list.selected_index()).copied().flatten())
+    }
+}
+
+impl ModalDialog for SubsDialog {
+    fn draw(&self, screen: &mut Screen) {
+        self.dialog.draw_with_theme(screen);
+    }
+
+    fn handle_event(&mut self, event: &InputEvent) -> ModalResult {
+        if let EventResult::Action(action) = self.dialog.handle_event(event) {
+            match action.as_str() {
+                "ok" | "subs_activate" => {
+                    return match self.selected_line() {
+                        Some(line) => ModalResult::Action(ModalAction::GoToLine(line)),
+                        None => ModalResult::Continue,
+                    };
+                }
+                "cancel" | "dialog_cancel" => return ModalResult::Close,
+                _ => {}
+            }
+        }
+        ModalResult::Continue
+    }
+
+    fn title(&self) -> &str {
+        "SUBs"
+    }
+}
+
+/// Find the 1-based line of the SUB or FUNCTION called `name`, ignoring case
+/// and any type suffix (so `Name` finds `FUNCTION Name$`)
+pub fn find_definition(source: &str, name: &str) -> Option<usize> {
+    let base = |s: &str| s.trim_end_matches(['$', '%', '&', '!', '#']).to_uppercase();
+    let name = base(name);
+    find_procedures(source)
+        .into_iter()
+        .find(|(proc_name, _)| base(proc_name) == name)
+        .map(|(_, line)| line)
+}
+
+/// Find `SUB name` / `FUNCTION name` headers, returning each name with its 1-based line
+fn find_procedures(source: &str) -> Vec<(String, usize)> {
+    source
+        .lines()
+        .enumerate()
+        .filter_map(|(idx, line)| {
+            let mut words = line.split_whitespace();
+            let keyword = words.next()?.to_uppercase();
+            if keyword != "SUB" && keyword != "FUNCTION" {
+                return None;
+            }
+            let name = words.next()?.split('(').next()?;
+            (!name.is_empty()).then(|| (name.to_string(), idx + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_procedures() {
+        let source = "DECLARE SUB Draw ()\nCALL Draw\nEND\n\nSUB Draw\nEND SUB\n\n  function Area(r)\nEND FUNCTION";
+        assert_eq!(
+            find_procedures(source),
+            vec![("Draw".to_string(), 5), ("Area".to_string(), 8)]
+        );
+    }
+
+    #[test]
+    fn test_find_definition_from_call_site() {
+        let source = "DECLARE FUNCTION Twice$ (s$)
+CALL draw
+PRINT Twice(\"a\")
+END
+
+SUB Draw
+END SUB
+
+FUNCTION Twice$ (s$)
+END FUNCTION";
+        assert_eq!(find_definition(source, "draw"), Some(6));
+        assert_eq!(find_definition(source, "Twice"), Some(9));
+        assert_eq!(find_definition(source, "Missing"), None);
+    }
+}
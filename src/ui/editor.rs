@@ -2,13 +2,16 @@
 
 use crate::screen::Screen;
 use crate::terminal::Color;
+use std::collections::HashSet;
+
 use crate::state::{AppState, EditorMode};
+use regex::{Regex, RegexBuilder};
 use super::layout::Rect;
 use super::scrollbar::{self, ScrollbarState, ScrollbarColors};
 use super::window_chrome;
 
 /// BASIC keywords for syntax highlighting
-const KEYWORDS: &[&str] = &[
+pub(crate) const KEYWORDS: &[&str] = &[
     "ABS", "AND", "AS", "ASC", "ATN",
     "BEEP", "BLOAD", "BSAVE",
     "CALL", "CASE", "CDBL", "CHAIN", "CHDIR", "CHR$", "CINT", "CIRCLE", "CLEAR", "CLNG",
@@ -44,8 +47,9 @@ const KEYWORDS: &[&str] = &[
 ];
 
 /// Auto-format a line of BASIC code
-/// - Uppercase keywords
 /// - Add spacing around operators
+///
+/// Keyword case is left alone; see `capitalize_keywords`.
 pub fn format_basic_line(line: &str) -> String {
     if line.trim().is_empty() {
         return line.to_string();
@@ -110,16 +114,7 @@ pub fn format_basic_line(line: &str) -> String {
             while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$' || chars[i] == '%' || chars[i] == '&' || chars[i] == '!' || chars[i] == '#') {
                 i += 1;
             }
-            let word: String = chars[start..i].iter().collect();
-            let upper_word = word.to_uppercase();
-
-            // Check if it's a keyword
-            if KEYWORDS.contains(&upper_word.as_str()) {
-                result.push_str(&upper_word);
-            } else {
-                // Keep original case for identifiers
-                result.push_str(&word);
-            }
+            result.extend(&chars[start..i]);
             continue;
         }
 
@@ -157,6 +152,349 @@ pub fn format_basic_line(line: &str) -> String {
     result
 }
 
+/// Uppercase the BASIC keywords on a line, leaving identifiers, strings and
+/// comments untouched
+pub fn capitalize_keywords(line: &str) -> String {
+    tokenize_line(line)
+        .iter()
+        .map(|token| match token.kind {
+            TokenKind::Keyword => token.text.to_uppercase(),
+            _ => token.text.to_string(),
+        })
+        .collect()
+}
+
+/// Whether a file's text uses DOS (CRLF) line endings
+pub fn uses_crlf(text: &str) -> bool {
+    text.contains("\r\n")
+}
+
+/// Screen cells taken by a tab starting at screen column `x`
+fn tab_width(x: usize, tab_stops: usize) -> usize {
+    let tab_stops = tab_stops.max(1);
+    tab_stops - x % tab_stops
+}
+
+/// Screen column of character `col` on a line, with tabs expanded to the
+/// next multiple of `tab_stops`
+pub fn display_col(line: &str, col: usize, tab_stops: usize) -> usize {
+    line.chars().take(col).fold(0, |x, ch| if ch == '\t' { x + tab_width(x, tab_stops) } else { x + 1 })
+}
+
+/// Character on a line shown at screen column `x` (the tab, if `x` falls
+/// inside one), or the end of the line if `x` is past it
+pub fn char_col_at(line: &str, x: usize, tab_stops: usize) -> usize {
+    let mut end = 0;
+    for (col, ch) in line.chars().enumerate() {
+        end += if ch == '\t' { tab_width(end, tab_stops) } else { 1 };
+        if x < end {
+            return col;
+        }
+    }
+    line.chars().count()
+}
+
+/// A search hit: where it is, how long it is, and whether the search wrapped
+/// around the end (or start) of the buffer to reach it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchMatch {
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+    pub wrapped: bool,
+}
+
+/// Columns of the BASIC word at `col` as the lexer reads one: letters,
+/// digits and underscores with an optional type suffix. The suffix belongs
+/// to the word it ends. None when `col` isn't on a word.
+fn word_bounds_at(chars: &[char], col: usize) -> Option<(usize, usize)> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    let is_suffix = |c: char| "$%&!#".contains(c);
+
+    let col = match chars.get(col) {
+        Some(&c) if is_word_char(c) => col,
+        Some(&c) if is_suffix(c) && col > 0 && is_word_char(chars[col - 1]) => col - 1,
+        _ => return None,
+    };
+    let mut start = col;
+    while start > 0 && is_word_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = col;
+    while end < chars.len() && is_word_char(chars[end]) {
+        end += 1;
+    }
+    if chars.get(end).is_some_and(|&c| is_suffix(c)) {
+        end += 1;
+    }
+    Some((start, end))
+}
+
+/// Column ranges of the BASIC tokens on a line, which Ctrl+Left and
+/// Ctrl+Right move between: words with their type suffix, numbers, string
+/// literals, comments and operators (`<=`, `>=` and `<>` count as one)
+fn token_spans(chars: &[char]) -> Vec<(usize, usize)> {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        i += 1;
+        match c {
+            '"' => {
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                i = (i + 1).min(chars.len());
+            }
+            '\'' => i = chars.len(),
+            '<' | '>' if matches!((c, chars.get(i)), ('<', Some('=' | '>')) | ('>', Some('='))) => i += 1,
+            _ if c.is_ascii_digit() => {
+                while i < chars.len() && (is_word_char(chars[i]) || chars[i] == '.') {
+                    i += 1;
+                }
+            }
+            _ if is_word_char(c) => {
+                while i < chars.len() && is_word_char(chars[i]) {
+                    i += 1;
+                }
+                if chars.get(i).is_some_and(|&c| "$%&!#".contains(c)) {
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        spans.push((start, i));
+    }
+    spans
+}
+
+/// Columns and lengths of every literal occurrence of `search` on a line
+fn literal_matches(line: &str, search: &str, case_sensitive: bool, whole_word: bool) -> Vec<(usize, usize)> {
+    let (haystack, needle) = if case_sensitive {
+        (line.to_string(), search.to_string())
+    } else {
+        (line.to_uppercase(), search.to_uppercase())
+    };
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let mut matches = Vec::new();
+    let mut from = 0;
+    while let Some(pos) = haystack.get(from..).and_then(|rest| rest.find(&needle)) {
+        let col = from + pos;
+        let end = col + needle.len();
+        if !whole_word || (!is_word(haystack[..col].chars().next_back()) && !is_word(haystack[end..].chars().next())) {
+            matches.push((col, needle.len()));
+        }
+        from = col + haystack[col..].chars().next().map_or(1, char::len_utf8);
+    }
+    matches
+}
+
+/// Compile a Find/Change pattern, honoring the Match Case and Whole Word options.
+/// Errors are reduced to a one-line message suitable for the status bar.
+pub fn build_search_regex(pattern: &str, case_sensitive: bool, whole_word: bool) -> Result<Regex, String> {
+    let pattern = if whole_word { format!(r"\b(?:{})\b", pattern) } else { pattern.to_string() };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!case_sensitive)
+        .build()
+        .map_err(|e| {
+            let message = e.to_string();
+            let detail = message.lines().last().unwrap_or_default().trim_start_matches("error: ");
+            format!("Invalid regular expression: {}", detail)
+        })
+}
+
+/// Find the column of the parenthesis matching the one at `col`, ignoring
+/// parentheses inside strings and comments. Returns None if `col` is not on a
+/// parenthesis or it is unbalanced.
+pub fn find_matching_paren(line: &str, col: usize) -> Option<usize> {
+    let mut parens = Vec::new();
+    let mut x = 0;
+    for token in tokenize_line(line) {
+        if matches!(token.kind, TokenKind::Punctuation) && (token.text == "(" || token.text == ")") {
+            parens.push((x, token.text == "("));
+        }
+        x += token.text.chars().count();
+    }
+
+    let index = parens.iter().position(|(at, _)| *at == col)?;
+    let opens = parens[index].1;
+    let candidates: Vec<(usize, bool)> = if opens {
+        parens[index + 1..].to_vec()
+    } else {
+        parens[..index].iter().rev().copied().collect()
+    };
+
+    let mut depth = 0;
+    for (at, is_open) in candidates {
+        if is_open == opens {
+            depth += 1;
+        } else if depth == 0 {
+            return Some(at);
+        } else {
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Code words of a line, uppercased, stopping at a comment
+fn code_words(line: &str) -> Vec<String> {
+    code_statements(line).concat()
+}
+
+/// The code of a line split at its `:` separators, each statement as
+/// uppercased words like `code_words`
+fn code_statements(line: &str) -> Vec<Vec<String>> {
+    let mut statements = Vec::new();
+    let mut code = String::new();
+    let mut in_string = false;
+    for ch in line.chars() {
+        match ch {
+            '"' => in_string = !in_string,
+            '\'' if !in_string => break,
+            ':' if !in_string => {
+                statements.push(std::mem::take(&mut code));
+                continue;
+            }
+            _ => {}
+        }
+        code.push(ch);
+    }
+    statements.push(code);
+    statements
+        .iter()
+        .map(|code| {
+            code.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$' || c == '"'))
+                .filter(|w| !w.is_empty())
+                .map(|w| w.to_uppercase())
+                .collect::<Vec<_>>()
+        })
+        .filter(|words| !words.is_empty())
+        .collect()
+}
+
+/// Whether a line opens a block whose body should be indented one level
+fn opens_block(line: &str) -> bool {
+    let statements = code_statements(line);
+    let Some(words) = statements.first() else {
+        return false;
+    };
+    // A loop closed again on the same line has no body below it
+    let closed_here = |closer: &str| statements[1..].iter().any(|s| s[0] == closer);
+    match words[0].as_str() {
+        "FOR" => !closed_here("NEXT"),
+        "WHILE" => !closed_here("WEND"),
+        "DO" => !closed_here("LOOP"),
+        "SUB" | "FUNCTION" | "TYPE" | "ELSE" | "CASE" => true,
+        "SELECT" => words.get(1).is_some_and(|w| w == "CASE"),
+        // Only the block form of IF: THEN must end the line
+        "IF" | "ELSEIF" => statements.concat().last().is_some_and(|w| w == "THEN"),
+        _ => false,
+    }
+}
+
+/// Whether a line closes (or continues) a block and belongs one level out
+fn closes_block(line: &str) -> bool {
+    let words = code_words(line);
+    match words.first().map(String::as_str) {
+        Some("NEXT" | "WEND" | "LOOP" | "ELSE" | "ELSEIF" | "CASE") => true,
+        Some("END") => matches!(
+            words.get(1).map(String::as_str),
+            Some("IF" | "SUB" | "FUNCTION" | "TYPE" | "SELECT")
+        ),
+        _ => false,
+    }
+}
+
+/// Respace a line's code: one space around binary operators and after
+/// separators, none inside brackets or after a sign, keywords uppercased.
+/// The `-` joining a graphics statement's corners, as in
+/// `LINE (0, 0)-(9, 9)`, is kept tight. Strings and comments are kept as
+/// written.
+fn format_line(line: &str) -> String {
+    let mut out = String::new();
+    let mut prev: Option<Token> = None;
+    let mut gap = false;
+    let mut unary = false;
+    // First word of the current statement, and how deep in brackets we are
+    let mut statement = String::new();
+    let mut depth = 0usize;
+    for token in tokenize_line(line.trim()) {
+        if matches!(token.kind, TokenKind::Whitespace) {
+            gap = true;
+            continue;
+        }
+        if prev.as_ref().is_none_or(|p| p.text == ":") {
+            statement = token.text.to_ascii_uppercase();
+        }
+        let corners = token.text == "-"
+            && depth == 0
+            && prev.as_ref().is_some_and(|p| p.text == ")")
+            && matches!(statement.as_str(), "LINE" | "VIEW" | "WINDOW" | "GET");
+        let space = match (&prev, token.kind, token.text) {
+            (None, _, _) => false,
+            _ if corners => false,
+            (_, TokenKind::Punctuation, "," | ";" | ")" | ":") => false,
+            (Some(p), _, _) if p.text == "(" => false,
+            (Some(p), _, _) if matches!(p.text, "," | ";" | ":") => true,
+            _ if unary => false,
+            (_, TokenKind::Comment, _) => true,
+            (Some(p), _, _) if matches!(p.kind, TokenKind::Operator) => true,
+            (_, TokenKind::Operator, _) => true,
+            _ => gap,
+        };
+        // Nothing goes between a sign, or a corners `-`, and what follows
+        unary = corners
+            || matches!(token.text, "+" | "-")
+                && prev.as_ref().is_none_or(|p| {
+                    matches!(p.kind, TokenKind::Operator | TokenKind::Keyword) || matches!(p.text, "(" | "," | ";" | ":")
+                });
+        match token.text {
+            "(" => depth += 1,
+            ")" => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        if space {
+            out.push(' ');
+        }
+        out.push_str(token.text);
+        prev = Some(token);
+        gap = false;
+    }
+    capitalize_keywords(&out)
+}
+
+/// Lay out a whole program: every line respaced by `format_line` and
+/// indented `tab_stops` spaces per enclosing block
+pub fn format_source(lines: &[String], tab_stops: usize) -> Vec<String> {
+    let mut depth = 0usize;
+    lines
+        .iter()
+        .map(|line| {
+            let code = format_line(line);
+            if closes_block(&code) {
+                depth = depth.saturating_sub(1);
+            }
+            let indent = if code.is_empty() { 0 } else { depth * tab_stops.max(1) };
+            if opens_block(&code) {
+                depth += 1;
+            }
+            format!("{}{}", " ".repeat(indent), code)
+        })
+        .collect()
+}
+
+/// Number of leading spaces on a line
+fn indent_width(line: &str) -> usize {
+    line.chars().take_while(|c| *c == ' ').count()
+}
+
 /// Types of undoable actions
 #[derive(Clone, Debug)]
 pub enum UndoAction {
@@ -168,6 +506,8 @@ pub enum UndoAction {
     JoinLines { line: usize, col: usize },
     /// Insert a newline (splitting a line)
     SplitLine { line: usize, col: usize },
+    /// Replace a block of whole lines starting at `line` (block indent/dedent)
+    ReplaceLines { line: usize, before: Vec<String>, after: Vec<String> },
 }
 
 /// Text buffer for the editor
@@ -264,20 +604,6 @@ impl TextBuffer {
         }
     }
 
-    /// Insert a new line at the given position
-    pub fn insert_line(&mut self, line: usize, text: &str) {
-        if line <= self.lines.len() {
-            self.lines.insert(line, text.to_string());
-        }
-    }
-
-    /// Swap two adjacent lines
-    pub fn swap_lines(&mut self, line1: usize, line2: usize) {
-        if line1 < self.lines.len() && line2 < self.lines.len() {
-            self.lines.swap(line1, line2);
-        }
-    }
-
     pub fn to_string(&self) -> String {
         self.lines.join("\n")
     }
@@ -304,8 +630,13 @@ pub struct Editor {
     pub selection_end: Option<(usize, usize)>,    // (line, col)
     pub is_selecting: bool,  // True when mouse drag started in editor
     pub keyboard_select_mode: bool,  // True when Ctrl+Space activated selection mode
+    /// Selections are rectangles of columns rather than runs of text (Alt+B)
+    pub block_select: bool,
     pub undo_stack: Vec<UndoAction>,
     pub redo_stack: Vec<UndoAction>,
+    /// The last undo entry is a run of typing or deleting that the next
+    /// keystroke can join
+    undo_merge: bool,
     pub visible_lines: usize,  // Number of visible lines (for PageUp/PageDown)
     pub visible_cols: usize,   // Number of visible columns
     // Multi-click tracking
@@ -313,6 +644,12 @@ pub struct Editor {
     last_click_pos: (u16, u16),
     click_count: usize,
     selection_anchor: Option<((usize, usize), (usize, usize))>,
+    /// Bracket pair highlighted at the cursor: (line, open col, close col), set in draw()
+    pub paren_match: Option<(usize, usize, usize)>,
+    /// Bookmarked lines (0-based)
+    pub bookmarks: HashSet<usize>,
+    /// Buffer contents the bookmarks were last placed against
+    bookmark_snapshot: Vec<String>,
 }
 
 impl Editor {
@@ -327,17 +664,86 @@ impl Editor {
             selection_end: None,
             is_selecting: false,
             keyboard_select_mode: false,
+            block_select: false,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
+            undo_merge: false,
             visible_lines: 20,  // Default, updated in draw()
             visible_cols: 80,   // Default, updated in draw()
             last_click_time: std::time::Instant::now(),
             last_click_pos: (0, 0),
             click_count: 0,
             selection_anchor: None,
+            paren_match: None,
+            bookmarks: HashSet::new(),
+            bookmark_snapshot: Vec::new(),
         }
     }
 
+    /// Move bookmarks to follow lines inserted or deleted since they were last
+    /// placed, by comparing the buffer with a snapshot. Bookmarks on deleted
+    /// lines are dropped.
+    fn sync_bookmarks(&mut self) {
+        if self.bookmarks.is_empty() {
+            self.bookmark_snapshot.clear();
+            return;
+        }
+        let (old, new) = (&self.bookmark_snapshot, &self.buffer.lines);
+        if old == new {
+            return;
+        }
+        let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..].iter().rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+
+        self.bookmarks = self.bookmarks
+            .iter()
+            .filter_map(|&line| {
+                if line < prefix {
+                    Some(line)
+                } else if line >= old_end {
+                    Some(line - old_end + new_end)
+                } else {
+                    // Inside the changed region: keep it if that line still exists
+                    (line < new_end).then_some(line)
+                }
+            })
+            .collect();
+        self.bookmark_snapshot = self.buffer.lines.clone();
+    }
+
+    /// Toggle a bookmark on the current line
+    pub fn toggle_bookmark(&mut self) {
+        self.sync_bookmarks();
+        if !self.bookmarks.remove(&self.cursor_line) {
+            self.bookmarks.insert(self.cursor_line);
+        }
+        self.bookmark_snapshot = self.buffer.lines.clone();
+    }
+
+    /// Move the cursor to the next (or previous) bookmark, wrapping around.
+    /// Returns false if there are no bookmarks.
+    pub fn go_to_bookmark(&mut self, forward: bool) -> bool {
+        self.sync_bookmarks();
+        let mut lines: Vec<usize> = self.bookmarks.iter().copied().collect();
+        lines.sort_unstable();
+        let target = if forward {
+            lines.iter().find(|&&l| l > self.cursor_line).or(lines.first())
+        } else {
+            lines.iter().rev().find(|&&l| l < self.cursor_line).or(lines.last())
+        };
+        let Some(&line) = target else {
+            return false;
+        };
+        self.cursor_line = line;
+        self.cursor_col = 0;
+        self.clear_selection();
+        true
+    }
+
     /// Start a selection at the current cursor position
     pub fn start_selection(&mut self) {
         self.selection_start = Some((self.cursor_line, self.cursor_col));
@@ -357,38 +763,29 @@ impl Editor {
         self.is_selecting = false;
     }
 
-    /// Select the word at the current cursor position
-    pub fn select_word(&mut self) {
-        if let Some(line) = self.buffer.line(self.cursor_line) {
-            if line.is_empty() {
-                return;
-            }
-
-            let chars: Vec<char> = line.chars().collect();
-            let col = self.cursor_col.min(chars.len().saturating_sub(1));
-
-            // Find word boundaries
-            let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
-
-            // If we're on a non-word char, select just that character
-            if !is_word_char(chars[col]) {
-                self.selection_start = Some((self.cursor_line, col));
-                self.selection_end = Some((self.cursor_line, col + 1));
-                return;
-            }
-
-            // Find start of word
-            let mut start = col;
-            while start > 0 && is_word_char(chars[start - 1]) {
-                start -= 1;
-            }
+    /// Column Home goes to: the first non-blank character, or column 0 when
+    /// the cursor is already there
+    fn home_col(&self) -> usize {
+        let line = self.buffer.line(self.cursor_line).unwrap_or("");
+        let first = line.chars().take_while(|c| c.is_whitespace()).count();
+        if self.cursor_col == first { 0 } else { first }
+    }
 
-            // Find end of word
-            let mut end = col;
-            while end < chars.len() && is_word_char(chars[end]) {
-                end += 1;
-            }
+    /// Column End goes to: just after the last non-blank character, or the
+    /// end of the line when the cursor is already there
+    fn end_col(&self) -> usize {
+        let line = self.buffer.line(self.cursor_line).unwrap_or("");
+        let len = line.chars().count();
+        let last = line.trim_end().chars().count();
+        if self.cursor_col == last { len } else { last }
+    }
 
+    /// Select the word at the current cursor position
+    pub fn select_word(&mut self) {
+        if self.buffer.line(self.cursor_line).is_none_or(str::is_empty) {
+            return;
+        }
+        if let Some((start, end)) = self.get_word_bounds(self.cursor_line, self.cursor_col) {
             self.selection_start = Some((self.cursor_line, start));
             self.selection_end = Some((self.cursor_line, end));
         }
@@ -465,19 +862,8 @@ impl Editor {
                 return;
             }
 
-            let mut col = self.cursor_col.min(chars.len());
-
-            // Skip whitespace going left
-            while col > 0 && !chars[col - 1].is_alphanumeric() && chars[col - 1] != '_' {
-                col -= 1;
-            }
-
-            // Skip word characters going left
-            while col > 0 && (chars[col - 1].is_alphanumeric() || chars[col - 1] == '_') {
-                col -= 1;
-            }
-
-            self.cursor_col = col;
+            let col = self.cursor_col.min(chars.len());
+            self.cursor_col = token_spans(&chars).into_iter().rev().map(|(start, _)| start).find(|&start| start < col).unwrap_or(0);
         }
     }
 
@@ -495,19 +881,8 @@ impl Editor {
                 return;
             }
 
-            let mut col = self.cursor_col;
-
-            // Skip word characters going right
-            while col < chars.len() && (chars[col].is_alphanumeric() || chars[col] == '_') {
-                col += 1;
-            }
-
-            // Skip whitespace/punctuation going right
-            while col < chars.len() && !chars[col].is_alphanumeric() && chars[col] != '_' {
-                col += 1;
-            }
-
-            self.cursor_col = col;
+            let col = self.cursor_col;
+            self.cursor_col = token_spans(&chars).into_iter().map(|(start, _)| start).find(|&start| start > col).unwrap_or(chars.len());
         }
     }
 
@@ -524,12 +899,10 @@ impl Editor {
 
         // Delete from new position to old position
         if self.cursor_line == start_line {
-            // Same line - delete characters
-            for _ in self.cursor_col..start_col {
-                self.buffer.delete_char(self.cursor_line, self.cursor_col);
-            }
+            self.delete_chars(self.cursor_line, self.cursor_col, start_col);
         } else {
             // Crossed lines - join with previous line
+            self.record_undo(UndoAction::JoinLines { line: self.cursor_line, col: self.cursor_col });
             self.buffer.join_lines(self.cursor_line);
         }
     }
@@ -551,36 +924,68 @@ impl Editor {
 
             if self.cursor_col >= chars.len() {
                 // At end of line - join with next line
+                self.record_undo(UndoAction::JoinLines { line: start_line, col: self.cursor_col });
                 self.buffer.join_lines(self.cursor_line);
                 return;
             }
 
-            let mut col = self.cursor_col;
-
-            // Skip word characters going right
-            while col < chars.len() && (chars[col].is_alphanumeric() || chars[col] == '_') {
-                col += 1;
-            }
+            // Up to the end of the next word, or of the line after the last
+            let end = token_spans(&chars).into_iter().map(|(_, end)| end).find(|&end| end > start_col).unwrap_or(chars.len());
+            self.delete_chars(start_line, start_col, end);
+        }
+    }
 
-            // Skip whitespace/punctuation going right
-            while col < chars.len() && !chars[col].is_alphanumeric() && chars[col] != '_' {
-                col += 1;
-            }
+    /// Delete columns `start..end` of a line as one undo step
+    fn delete_chars(&mut self, line: usize, start: usize, end: usize) {
+        let text: String = self.buffer.line(line).unwrap_or("").chars().skip(start).take(end - start).collect();
+        if text.is_empty() {
+            return;
+        }
+        for _ in start..end {
+            self.buffer.delete_char(line, start);
+        }
+        self.record_undo(UndoAction::Delete { line, col: start, text });
+    }
 
-            // Delete from cursor to col
-            for _ in start_col..col {
-                self.buffer.delete_char(start_line, start_col);
-            }
+    /// Re-indent and respace the whole program as one undo step, refusing
+    /// with the first syntax error's (0-based) line and message if it does
+    /// not parse. Returns whether anything changed.
+    pub fn format_program(&mut self, tab_stops: usize) -> Result<bool, (usize, String)> {
+        if let Some(error) = crate::basic::parser::syntax_error(&self.content()) {
+            return Err(error);
+        }
+        let before = self.buffer.lines.clone();
+        let after = format_source(&before, tab_stops);
+        if after == before {
+            return Ok(false);
         }
+        self.buffer.lines = after.clone();
+        self.record_undo(UndoAction::ReplaceLines { line: 0, before, after });
+        self.clear_selection();
+        self.clamp_cursor();
+        Ok(true)
     }
 
-    /// Duplicate the current line
-    pub fn duplicate_line(&mut self) {
-        if let Some(line) = self.buffer.line(self.cursor_line) {
-            let line_copy = line.to_string();
-            self.buffer.insert_line(self.cursor_line + 1, &line_copy);
-            self.cursor_line += 1;
+    /// Duplicate the current line, or every line touched by the selection, below
+    /// itself as one undo step. The cursor and selection move to the copy.
+    pub fn duplicate_line(&mut self) -> bool {
+        let (start, end) = self.selected_line_range().unwrap_or((self.cursor_line, self.cursor_line));
+        let end = end.min(self.buffer.line_count().saturating_sub(1));
+        if start > end {
+            return false;
         }
+
+        let before: Vec<String> = self.buffer.lines[start..=end].to_vec();
+        let after = [before.clone(), before.clone()].concat();
+        self.buffer.lines.splice(start..=end, after.iter().cloned());
+        self.record_undo(UndoAction::ReplaceLines { line: start, before, after });
+
+        let height = end - start + 1;
+        let shift = |(line, col): (usize, usize)| (line + height, col);
+        self.selection_start = self.selection_start.map(shift);
+        self.selection_end = self.selection_end.map(shift);
+        self.cursor_line += height;
+        true
     }
 
     /// Delete the current line
@@ -598,57 +1003,89 @@ impl Editor {
         }
     }
 
-    /// Move the current line up
-    pub fn move_line_up(&mut self) {
-        if self.cursor_line > 0 {
-            self.buffer.swap_lines(self.cursor_line - 1, self.cursor_line);
-            self.cursor_line -= 1;
-        }
+    /// Move the current line, or every line touched by the selection, up one line
+    pub fn move_line_up(&mut self) -> bool {
+        self.move_lines(true)
     }
 
-    /// Move the current line down
-    pub fn move_line_down(&mut self) {
-        if self.cursor_line + 1 < self.buffer.line_count() {
-            self.buffer.swap_lines(self.cursor_line, self.cursor_line + 1);
-            self.cursor_line += 1;
-        }
+    /// Move the current line, or every line touched by the selection, down one line
+    pub fn move_line_down(&mut self) -> bool {
+        self.move_lines(false)
     }
 
-    /// Toggle comment on the current line (BASIC uses ' for comments)
-    pub fn toggle_comment(&mut self) {
-        let line = match self.buffer.line(self.cursor_line) {
-            Some(l) => l.to_string(),
-            None => return,
-        };
+    /// Rotate the selected block past its neighbouring line as one undo step,
+    /// keeping the cursor and selection on the moved text.
+    /// Returns false at the top or bottom of the buffer.
+    fn move_lines(&mut self, up: bool) -> bool {
+        let (start, end) = self.selected_line_range().unwrap_or((self.cursor_line, self.cursor_line));
+        if (up && start == 0) || (!up && end + 1 >= self.buffer.line_count()) {
+            return false;
+        }
 
-        let trimmed = line.trim_start();
-        if trimmed.starts_with('\'') {
-            // Remove comment - find and remove the first '
-            let comment_pos = line.find('\'').unwrap();
-            let mut new_line = line.clone();
-            new_line.remove(comment_pos);
-            // Check if there was a space after the apostrophe
-            let had_space = line.chars().nth(comment_pos + 1) == Some(' ');
-            // Also remove space after ' if present
-            if new_line.chars().nth(comment_pos) == Some(' ') {
-                new_line.remove(comment_pos);
-            }
-            self.buffer.replace_line(self.cursor_line, &new_line);
-            // Adjust cursor if it was after the comment char
-            if self.cursor_col > comment_pos {
-                self.cursor_col = self.cursor_col.saturating_sub(if had_space { 2 } else { 1 });
-            }
+        let (first, last) = if up { (start - 1, end) } else { (start, end + 1) };
+        let before: Vec<String> = self.buffer.lines[first..=last].to_vec();
+        let mut after = before.clone();
+        if up {
+            after.rotate_left(1);
         } else {
-            // Add comment at start of content (after leading whitespace)
-            let leading_space: String = line.chars().take_while(|c| c.is_whitespace()).collect();
-            let content = &line[leading_space.len()..];
-            let new_line = format!("{}' {}", leading_space, content);
-            self.buffer.replace_line(self.cursor_line, &new_line);
-            // Adjust cursor position
-            if self.cursor_col >= leading_space.len() {
-                self.cursor_col += 2; // Account for "' "
-            }
+            after.rotate_right(1);
         }
+        self.buffer.lines.splice(first..=last, after.iter().cloned());
+        self.record_undo(UndoAction::ReplaceLines { line: first, before, after });
+
+        let shift = |(line, col): (usize, usize)| if up { (line - 1, col) } else { (line + 1, col) };
+        self.selection_start = self.selection_start.map(shift);
+        self.selection_end = self.selection_end.map(shift);
+        self.cursor_line = shift((self.cursor_line, 0)).0;
+        true
+    }
+
+    /// Toggle a `'` comment on every selected line (or the current line) as one
+    /// undo step. Lines are uncommented when every non-blank line is commented.
+    /// Returns false if nothing changed.
+    pub fn toggle_comment(&mut self) -> bool {
+        let (start, end) = self.selected_line_range().unwrap_or((self.cursor_line, self.cursor_line));
+        let end = end.min(self.buffer.line_count().saturating_sub(1));
+        if start > end {
+            return false;
+        }
+        let before: Vec<String> = self.buffer.lines[start..=end].to_vec();
+        let uncomment = before
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .all(|line| line.trim_start().starts_with('\''));
+
+        // Column of the marker on each line and how many characters were added (+) or removed (-)
+        let mut edits: Vec<(usize, isize)> = Vec::with_capacity(before.len());
+        let after: Vec<String> = before
+            .iter()
+            .map(|line| {
+                let indent = line.len() - line.trim_start().len();
+                if line.trim().is_empty() {
+                    edits.push((indent, 0));
+                    line.clone()
+                } else if uncomment {
+                    // Remove the marker and the space after it, if any
+                    let width = if line[indent + 1..].starts_with(' ') { 2 } else { 1 };
+                    edits.push((indent, -(width as isize)));
+                    format!("{}{}", &line[..indent], &line[indent + width..])
+                } else {
+                    edits.push((indent, 2));
+                    format!("{}' {}", &line[..indent], &line[indent..])
+                }
+            })
+            .collect();
+
+        self.replace_line_block(start, before, after, |line, col| {
+            let (at, delta) = edits[line - start];
+            if col < at {
+                col
+            } else if delta >= 0 {
+                col + delta as usize
+            } else {
+                col.saturating_sub(delta.unsigned_abs()).max(at)
+            }
+        })
     }
 
     /// Get word bounds at a given position
@@ -662,26 +1099,8 @@ impl Editor {
         let chars: Vec<char> = line.chars().collect();
         let col = col.min(chars.len().saturating_sub(1));
 
-        let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
-
-        // If we're on a non-word char, return just that character
-        if !is_word_char(chars[col]) {
-            return Some((col, col + 1));
-        }
-
-        // Find start of word
-        let mut start = col;
-        while start > 0 && is_word_char(chars[start - 1]) {
-            start -= 1;
-        }
-
-        // Find end of word
-        let mut end = col;
-        while end < chars.len() && is_word_char(chars[end]) {
-            end += 1;
-        }
-
-        Some((start, end))
+        // On a non-word char, return just that character
+        Some(word_bounds_at(&chars, col).unwrap_or((col, col + 1)))
     }
 
     /// Get line bounds for selection purposes
@@ -797,6 +1216,9 @@ impl Editor {
 
     /// Check if a position is within the selection
     pub fn is_selected(&self, line: usize, col: usize) -> bool {
+        if let Some((top, bottom, left, right)) = self.block_bounds() {
+            return (top..=bottom).contains(&line) && (left..right).contains(&col);
+        }
         let (start, end) = match (self.selection_start, self.selection_end) {
             (Some(s), Some(e)) => {
                 // Normalize so start is before end
@@ -848,8 +1270,196 @@ impl Editor {
         }
     }
 
+    /// The rectangle covered by a block selection as (top line, bottom line,
+    /// left column, right column), with the right column exclusive
+    pub fn block_bounds(&self) -> Option<(usize, usize, usize, usize)> {
+        if !self.block_select {
+            return None;
+        }
+        let ((start_line, start_col), (end_line, end_col)) = (self.selection_start?, self.selection_end?);
+        Some((
+            start_line.min(end_line),
+            start_line.max(end_line),
+            start_col.min(end_col),
+            start_col.max(end_col),
+        ))
+    }
+
+    /// Replace columns `left..right` of lines `top..=bottom` with `text` as a
+    /// single undo step. Lines too short to reach a column are edited at their
+    /// end. The selection becomes an empty block just after the new text, so
+    /// typing carries on in every line. Returns false if nothing changed.
+    fn replace_block(&mut self, (top, bottom, left, right): (usize, usize, usize, usize), text: &str) -> bool {
+        let bottom = bottom.min(self.buffer.line_count().saturating_sub(1));
+        if top > bottom {
+            return false;
+        }
+        let before: Vec<String> = self.buffer.lines[top..=bottom].to_vec();
+        let after: Vec<String> = before
+            .iter()
+            .map(|line| {
+                let chars: Vec<char> = line.chars().collect();
+                let (start, end) = (left.min(chars.len()), right.min(chars.len()));
+                let mut edited: String = chars[..start].iter().collect();
+                edited.push_str(text);
+                edited.extend(&chars[end..]);
+                edited
+            })
+            .collect();
+        let col = left + text.chars().count();
+        if !self.replace_line_block(top, before, after, |_, _| col) {
+            return false;
+        }
+        self.selection_start = self.selection_start.map(|(line, _)| (line, col));
+        self.selection_end = self.selection_end.map(|(line, _)| (line, col));
+        self.cursor_line = self.cursor_line.clamp(top, bottom);
+        self.cursor_col = col;
+        true
+    }
+
+    /// Type `c` into every line of a block selection, replacing its contents
+    pub fn type_in_block(&mut self, c: char) -> bool {
+        match self.block_bounds() {
+            Some(bounds) => self.replace_block(bounds, &c.to_string()),
+            None => false,
+        }
+    }
+
+    /// Delete from every line of a block selection: its contents, or for an
+    /// empty block the character before (or with `forward`, after) it
+    pub fn delete_in_block(&mut self, forward: bool) -> bool {
+        let Some((top, bottom, left, right)) = self.block_bounds() else {
+            return false;
+        };
+        let (left, right) = match (left == right, forward) {
+            (false, _) => (left, right),
+            (true, false) if left > 0 => (left - 1, right),
+            (true, false) => return false,
+            (true, true) => (left, right + 1),
+        };
+        self.replace_block((top, bottom, left, right), "")
+    }
+
+    /// Lines covered by the selection for block operations. A selection that
+    /// ends at column 0 of a later line does not include that line.
+    fn selected_line_range(&self) -> Option<(usize, usize)> {
+        let ((start_line, _), (end_line, end_col)) = self.get_selection_bounds()?;
+        if end_line > start_line && end_col == 0 {
+            Some((start_line, end_line - 1))
+        } else {
+            Some((start_line, end_line))
+        }
+    }
+
+    /// Indent or dedent lines `start..=end` by up to `width` spaces as a single
+    /// undo step, keeping the cursor and selection on the same text.
+    /// Returns false if no line changed.
+    pub fn shift_lines(&mut self, start: usize, end: usize, width: usize, indent: bool) -> bool {
+        let end = end.min(self.buffer.line_count().saturating_sub(1));
+        if start > end || width == 0 {
+            return false;
+        }
+        let before: Vec<String> = self.buffer.lines[start..=end].to_vec();
+        let mut deltas = Vec::with_capacity(before.len());
+        let after: Vec<String> = before
+            .iter()
+            .map(|line| {
+                if indent {
+                    deltas.push(width);
+                    format!("{}{}", " ".repeat(width), line)
+                } else {
+                    let remove = line.chars().take(width).take_while(|c| *c == ' ').count();
+                    deltas.push(remove);
+                    line[remove..].to_string()
+                }
+            })
+            .collect();
+
+        self.replace_line_block(start, before, after, |line, col| {
+            let delta = deltas[line - start];
+            if indent {
+                if col == 0 { 0 } else { col + delta }
+            } else {
+                col.saturating_sub(delta)
+            }
+        })
+    }
+
+    /// Split the current line like Enter, formatting it and indenting the new
+    /// line to match. Block openers indent the new line by `width` spaces and a
+    /// block closer typed at the body's indentation is moved out one level.
+    /// The whole edit is a single undo step.
+    pub fn newline_with_indent(&mut self, width: usize) {
+        let line_idx = self.cursor_line;
+        let Some(original) = self.buffer.line(line_idx).map(str::to_string) else {
+            return;
+        };
+        let mut line = format_basic_line(&original);
+        let mut col = self.cursor_col.min(line.len());
+
+        if closes_block(&line) {
+            let expected = self.buffer.lines[..line_idx]
+                .iter()
+                .rev()
+                .find(|prev| !prev.trim().is_empty())
+                .map(|prev| indent_width(prev) + if opens_block(prev) { width } else { 0 })
+                .unwrap_or(0);
+            let indent = indent_width(&line);
+            if indent > 0 && indent >= expected {
+                let remove = indent.min(width);
+                line.replace_range(..remove, "");
+                col = col.saturating_sub(remove);
+            }
+        }
+
+        let (head, tail) = line.split_at(col);
+        let mut indent = indent_width(head);
+        if opens_block(head) {
+            indent += width;
+        }
+        let after = vec![head.to_string(), format!("{}{}", " ".repeat(indent), tail.trim_start())];
+        self.replace_line_block(line_idx, vec![original], after, |_, col| col);
+        self.cursor_line = line_idx + 1;
+        self.cursor_col = indent;
+    }
+
+    /// Replace the lines starting at `start` with `after` as a single undo step.
+    /// `adjust(line, col)` maps cursor and selection columns on the replaced lines.
+    /// Returns false (and changes nothing) if the lines are unchanged.
+    fn replace_line_block(
+        &mut self,
+        start: usize,
+        before: Vec<String>,
+        after: Vec<String>,
+        adjust: impl Fn(usize, usize) -> usize,
+    ) -> bool {
+        if after == before {
+            return false;
+        }
+        let end = start + before.len();
+        let map = |(line, col): (usize, usize)| {
+            if (start..end).contains(&line) { (line, adjust(line, col)) } else { (line, col) }
+        };
+        self.selection_start = self.selection_start.map(map);
+        self.selection_end = self.selection_end.map(map);
+        let (_, cursor_col) = map((self.cursor_line, self.cursor_col));
+        self.cursor_col = cursor_col;
+
+        self.buffer.lines.splice(start..end, after.iter().cloned());
+        self.record_undo(UndoAction::ReplaceLines { line: start, before, after });
+        true
+    }
+
     /// Get the selected text as a String
     pub fn get_selected_text(&self) -> Option<String> {
+        if let Some((top, bottom, left, right)) = self.block_bounds() {
+            // One row of the rectangle per line, cut short where lines are
+            let rows: Vec<String> = (top..=bottom)
+                .filter_map(|line| self.buffer.line(line))
+                .map(|line| line.chars().skip(left).take(right - left).collect())
+                .collect();
+            return Some(rows.join("\n"));
+        }
         let ((start_line, start_col), (end_line, end_col)) = self.get_selection_bounds()?;
 
         if start_line == end_line {
@@ -890,6 +1500,13 @@ impl Editor {
     /// Delete the selected text and position cursor at selection start
     /// Returns true if selection was deleted
     pub fn delete_selection(&mut self) -> bool {
+        if self.block_select && self.has_selection() {
+            // Only the block's contents go: an empty block has none
+            return match self.block_bounds() {
+                Some((top, bottom, left, right)) if left < right => self.replace_block((top, bottom, left, right), ""),
+                _ => false,
+            };
+        }
         let bounds = match self.get_selection_bounds() {
             Some(b) => b,
             None => return false,
@@ -999,10 +1616,52 @@ impl Editor {
         self.undo_stack.push(action);
         // Clear redo stack when new action is performed
         self.redo_stack.clear();
+        self.undo_merge = false;
+    }
+
+    /// Record a one-character insert or delete, joining it to the previous
+    /// one if `merge` is set and it carries on from where that left off, so
+    /// undo takes back a word at a time. Whitespace ends the word.
+    fn record_keystroke(&mut self, action: UndoAction, merge: bool) {
+        let ends_word = match &action {
+            UndoAction::Insert { text, .. } | UndoAction::Delete { text, .. } => text.chars().all(char::is_whitespace),
+            _ => true,
+        };
+        let merged = merge && match (self.undo_stack.last_mut(), &action) {
+            (Some(UndoAction::Insert { line, col, text }), UndoAction::Insert { line: l, col: c, text: t })
+                if line == l && *col + text.chars().count() == *c =>
+            {
+                text.push_str(t);
+                true
+            }
+            // Delete key: the text after keeps moving up to the same column
+            (Some(UndoAction::Delete { line, col, text }), UndoAction::Delete { line: l, col: c, text: t })
+                if line == l && col == c =>
+            {
+                text.push_str(t);
+                true
+            }
+            // Backspace: each deleted character is just before the last
+            (Some(UndoAction::Delete { line, col, text }), UndoAction::Delete { line: l, col: c, text: t })
+                if line == l && *c + t.chars().count() == *col =>
+            {
+                *col = *c;
+                text.insert_str(0, t);
+                true
+            }
+            _ => false,
+        };
+        if merged {
+            self.redo_stack.clear();
+        } else {
+            self.record_undo(action);
+        }
+        self.undo_merge = !ends_word;
     }
 
     /// Undo the last action
     pub fn undo(&mut self) -> bool {
+        self.undo_merge = false;
         if let Some(action) = self.undo_stack.pop() {
             match &action {
                 UndoAction::Insert { line, col, text } => {
@@ -1056,6 +1715,12 @@ impl Editor {
                     self.cursor_line = line;
                     self.cursor_col = col;
                 }
+                UndoAction::ReplaceLines { line, before, after } => {
+                    let end = (*line + after.len()).min(self.buffer.line_count());
+                    self.buffer.lines.splice(*line..end, before.iter().cloned());
+                    self.cursor_line = *line;
+                    self.cursor_col = 0;
+                }
                 UndoAction::JoinLines { line, col } => {
                     // To undo a join, split the line
                     let line = *line;
@@ -1078,6 +1743,7 @@ impl Editor {
 
     /// Redo the last undone action
     pub fn redo(&mut self) -> bool {
+        self.undo_merge = false;
         if let Some(action) = self.redo_stack.pop() {
             match &action {
                 UndoAction::Insert { line, col, text } => {
@@ -1130,6 +1796,12 @@ impl Editor {
                     self.cursor_line = line + 1;
                     self.cursor_col = 0;
                 }
+                UndoAction::ReplaceLines { line, before, after } => {
+                    let end = (*line + before.len()).min(self.buffer.line_count());
+                    self.buffer.lines.splice(*line..end, after.iter().cloned());
+                    self.cursor_line = *line;
+                    self.cursor_col = 0;
+                }
                 UndoAction::JoinLines { line, col } => {
                     // To redo a join, join the lines again
                     let line = *line;
@@ -1157,10 +1829,12 @@ impl Editor {
         let width = bounds.width;
         let height = bounds.height;
 
+        let colors = SyntaxColors::for_scheme(state.color_scheme);
+
         // Draw editor background
         for r in 0..height {
             for c in 0..width {
-                screen.set(row + r, col + c, ' ', Color::Yellow, Color::Blue);
+                screen.set(row + r, col + c, ' ', colors.text, colors.background);
             }
         }
 
@@ -1177,7 +1851,11 @@ impl Editor {
         self.visible_lines = content_height.saturating_sub(1) as usize;
         self.visible_cols = content_width.saturating_sub(1) as usize;
 
-        // Draw lines
+        self.paren_match = self.paren_at_cursor();
+
+        self.sync_bookmarks();
+
+        // Draw lines - only the visible ones are tokenized
         for r in 0..content_height as usize {
             let line_num = self.scroll_row + r;
             let screen_row = content_row + r as u16;
@@ -1186,12 +1864,20 @@ impl Editor {
                 // Draw the line with syntax highlighting
                 self.draw_line(screen, screen_row, content_col, content_width, line, state, line_num);
             }
+            // Debugger or bookmark marker in the left border
+            if let Some((glyph, fg, bg)) = LineMarkers::for_line(state, line_num).gutter(colors.background) {
+                screen.set(screen_row, col, glyph, fg, bg);
+            } else if self.bookmarks.contains(&line_num) {
+                screen.set(screen_row, col, '\u{25BA}', Color::White, colors.background);
+            }
         }
 
         // Update cursor position (only if cursor is visible in current scroll view)
-        if self.cursor_line >= self.scroll_row && self.cursor_col >= self.scroll_col {
+        let cursor_x = self.buffer.line(self.cursor_line)
+            .map_or(self.cursor_col, |line| display_col(line, self.cursor_col, state.tab_stops));
+        if self.cursor_line >= self.scroll_row && cursor_x >= self.scroll_col {
             let cursor_screen_row = content_row + (self.cursor_line - self.scroll_row) as u16;
-            let cursor_screen_col = content_col + (self.cursor_col - self.scroll_col) as u16;
+            let cursor_screen_col = content_col + (cursor_x - self.scroll_col) as u16;
 
             if cursor_screen_row >= content_row
                 && cursor_screen_row < content_row + content_height
@@ -1217,19 +1903,21 @@ impl Editor {
         let width = bounds.width;
         let height = bounds.height;
 
+        let background = SyntaxColors::for_scheme(state.color_scheme).background;
+
         // Draw border
-        screen.draw_box(row, col, width, height, Color::LightGray, Color::Blue);
+        screen.draw_box(row, col, width, height, Color::LightGray, background);
 
         // Draw title (inverted colors: Blue on LightGray)
         let title = format!(" {} ", state.title());
         let title_x = col + (width.saturating_sub(title.len() as u16 + window_chrome::MAXIMIZE_BUTTON_OFFSET)) / 2;
         screen.write_str(row, title_x, &title, Color::Blue, Color::LightGray);
 
-        // Draw maximize button (border colors: LightGray on Blue)
+        // Draw maximize button (border colors)
         window_chrome::draw_maximize_button(
             screen, row, col, width,
             state.editor_maximized,
-            Color::LightGray, Color::Blue,
+            Color::LightGray, background,
         );
 
         // Draw scroll bars
@@ -1272,77 +1960,138 @@ impl Editor {
     }
 
     fn draw_line(&self, screen: &mut Screen, row: u16, col: u16, width: u16, line: &str, state: &AppState, line_num: usize) {
-        // Check for breakpoint
-        let has_bp = state.has_breakpoint(line_num);
-        let is_current = state.current_line == Some(line_num);
+        let colors = SyntaxColors::for_scheme(state.color_scheme);
+
+        let markers = LineMarkers::for_line(state, line_num);
 
         // Check for syntax error on this line
         let syntax_error = state.syntax_errors.iter().find(|(l, _)| *l == line_num);
         let has_error = syntax_error.is_some();
 
         // Background color
-        let normal_bg = if is_current {
+        let normal_bg = if markers.current {
             Color::Cyan
-        } else if has_bp {
+        } else if markers.breakpoint {
             Color::Red
         } else if has_error {
             Color::Magenta // Highlight error lines
         } else {
-            Color::Blue
+            colors.background
         };
 
-        // Clear line (check selection for each character position)
+        // Clear line (check selection for each character position; past the
+        // end of the line, screen columns and character columns line up)
+        let line_chars = line.chars().count();
+        let line_width = display_col(line, line_chars, state.tab_stops);
         for c in 0..width {
-            let char_col = self.scroll_col + c as usize;
+            let x = self.scroll_col + c as usize;
+            let char_col = if x < line_width { char_col_at(line, x, state.tab_stops) } else { line_chars + x - line_width };
             let (fg, bg) = if self.is_selected(line_num, char_col) {
-                (Color::Yellow.invert(), Color::LightGray)  // Selection with inverted fg
+                (colors.text.invert(), Color::LightGray)  // Selection with inverted fg
             } else {
-                (Color::Yellow, normal_bg)
+                (colors.text, normal_bg)
             };
             screen.set(row, col + c, ' ', fg, bg);
         }
 
-        // Tokenize and draw with syntax highlighting
+        // Tokenize and draw with syntax highlighting. `x` is the screen
+        // column and `char_col` the character; tabs expand to the next stop.
         let tokens = tokenize_line(line);
         let mut x = 0usize;
+        let mut char_col = 0usize;
 
         for token in tokens {
             if x >= self.scroll_col + width as usize {
                 break;
             }
 
-            let token_fg = match token.kind {
-                TokenKind::Keyword => Color::White,
-                TokenKind::String => Color::LightMagenta,
-                TokenKind::Number => Color::LightCyan,
-                TokenKind::Comment => Color::LightGray,
-                TokenKind::Operator => Color::LightGreen,
-                TokenKind::Identifier => Color::Yellow,
-                TokenKind::Punctuation => Color::White,
-                TokenKind::Whitespace => Color::Yellow,
-            };
+            let token_fg = colors.token(token.kind);
 
             for ch in token.text.chars() {
-                if x >= self.scroll_col && x - self.scroll_col < width as usize {
-                    let screen_x = col + (x - self.scroll_col) as u16;
-                    let (fg, bg) = if self.is_selected(line_num, x) {
-                        (token_fg.invert(), Color::LightGray)  // Selection with inverted fg
-                    } else {
-                        (token_fg, normal_bg)
-                    };
-                    screen.set(row, screen_x, ch, fg, bg);
+                let (fg, bg) = if self.is_selected(line_num, char_col) {
+                    (token_fg.invert(), Color::LightGray)  // Selection with inverted fg
+                } else if matches!(self.paren_match, Some((l, a, b)) if l == line_num && (char_col == a || char_col == b)) {
+                    (Color::White, Color::Green)  // Matching bracket pair
+                } else {
+                    (token_fg, normal_bg)
+                };
+                let (glyph, cells) = if ch == '\t' { (' ', tab_width(x, state.tab_stops)) } else { (ch, 1) };
+                for _ in 0..cells {
+                    if x >= self.scroll_col && x - self.scroll_col < width as usize {
+                        screen.set(row, col + (x - self.scroll_col) as u16, glyph, fg, bg);
+                    }
+                    x += 1;
                 }
-                x += 1;
+                char_col += 1;
             }
         }
     }
 
+    /// Bracket pair at the cursor, checking the character under the cursor and
+    /// then the one before it: (line, open col, close col)
+    fn paren_at_cursor(&self) -> Option<(usize, usize, usize)> {
+        let line = self.buffer.line(self.cursor_line)?;
+        [Some(self.cursor_col), self.cursor_col.checked_sub(1)]
+            .into_iter()
+            .flatten()
+            .find_map(|col| find_matching_paren(line, col).map(|other| (col.min(other), col.max(other))))
+            .map(|(open, close)| (self.cursor_line, open, close))
+    }
+
+    /// Jump the cursor to the bracket matching the one at (or before) the cursor.
+    /// Returns false if the cursor is not next to a balanced bracket.
+    pub fn go_to_matching_paren(&mut self) -> bool {
+        let Some(line) = self.buffer.line(self.cursor_line) else {
+            return false;
+        };
+        let target = [Some(self.cursor_col), self.cursor_col.checked_sub(1)]
+            .into_iter()
+            .flatten()
+            .find_map(|col| find_matching_paren(line, col));
+        match target {
+            Some(col) => {
+                self.cursor_col = col;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Uppercase the keywords on `line` (as QBasic does when the cursor leaves it)
+    pub fn capitalize_line(&mut self, line: usize) {
+        if let Some(text) = self.buffer.line_mut(line) {
+            *text = capitalize_keywords(text);
+        }
+    }
+
     /// Handle input for the editor
     pub fn handle_input(&mut self, event: &crate::input::InputEvent, state: &mut AppState) -> bool {
+        // Capitalize the line the cursor moved off (Enter handles its own line)
+        let line = self.cursor_line;
+        let line_count = self.buffer.line_count();
+        let handled = self.handle_key(event, state);
+        if state.auto_capitalize && self.cursor_line != line && self.buffer.line_count() == line_count {
+            self.capitalize_line(line);
+        }
+        handled
+    }
+
+    fn handle_key(&mut self, event: &crate::input::InputEvent, state: &mut AppState) -> bool {
         use crate::input::InputEvent;
 
+        // Only typing or deleting straight after more of the same joins its
+        // undo step; anything else in between (like moving the cursor) ends it
+        let merge_undo = std::mem::take(&mut self.undo_merge);
+
         match event {
             InputEvent::Char(c) => {
+                // Typing into a block selection goes into every line
+                if self.block_select && self.has_selection() {
+                    if self.type_in_block(*c) {
+                        state.set_modified(true);
+                    }
+                    return true;
+                }
                 // If there's a selection, delete it first (typing replaces selection)
                 if self.has_selection() {
                     self.delete_selection();
@@ -1361,17 +2110,28 @@ impl Editor {
                     }
                     self.buffer.delete_char(self.cursor_line, self.cursor_col);
                 }
-                // Record insert for undo
-                self.record_undo(UndoAction::Insert {
+                // Record insert for undo; overtyping keeps one step per key
+                let merge = merge_undo && state.editor_mode == EditorMode::Insert;
+                self.record_keystroke(UndoAction::Insert {
                     line: self.cursor_line,
                     col: self.cursor_col,
                     text: c.to_string(),
-                });
+                }, merge);
                 self.buffer.insert_char(self.cursor_line, self.cursor_col, *c);
                 self.cursor_col += 1;
                 state.set_modified(true);
                 true
             }
+            InputEvent::Paste(text) => {
+                // Pasted text goes in verbatim as one undo step, without the
+                // indenting and capitalizing typing would do
+                if self.has_selection() {
+                    self.delete_selection();
+                }
+                self.insert_text(text);
+                state.set_modified(true);
+                true
+            }
             InputEvent::Enter => {
                 // If there's a selection, delete it first
                 if self.has_selection() {
@@ -1379,6 +2139,16 @@ impl Editor {
                     state.set_modified(true);
                 }
 
+                if state.auto_capitalize {
+                    self.capitalize_line(self.cursor_line);
+                }
+
+                if state.auto_indent {
+                    self.newline_with_indent(state.tab_stops);
+                    state.set_modified(true);
+                    return true;
+                }
+
                 // Auto-format the current line before inserting newline
                 if let Some(line) = self.buffer.line(self.cursor_line) {
                     let formatted = format_basic_line(line);
@@ -1407,6 +2177,12 @@ impl Editor {
                 true
             }
             InputEvent::Backspace => {
+                if self.block_select && self.has_selection() {
+                    if self.delete_in_block(false) {
+                        state.set_modified(true);
+                    }
+                    return true;
+                }
                 // If there's a selection, delete it instead of single char
                 if self.has_selection() {
                     self.delete_selection();
@@ -1418,11 +2194,11 @@ impl Editor {
                     if let Some(line) = self.buffer.line(self.cursor_line) {
                         if self.cursor_col <= line.len() {
                             let deleted_char = line.chars().nth(self.cursor_col - 1).unwrap_or(' ');
-                            self.record_undo(UndoAction::Delete {
+                            self.record_keystroke(UndoAction::Delete {
                                 line: self.cursor_line,
                                 col: self.cursor_col - 1,
                                 text: deleted_char.to_string(),
-                            });
+                            }, merge_undo);
                         }
                     }
                 } else if self.cursor_line > 0 {
@@ -1440,6 +2216,12 @@ impl Editor {
                 true
             }
             InputEvent::Delete => {
+                if self.block_select && self.has_selection() {
+                    if self.delete_in_block(true) {
+                        state.set_modified(true);
+                    }
+                    return true;
+                }
                 // If there's a selection, delete it instead of single char
                 if self.has_selection() {
                     self.delete_selection();
@@ -1451,11 +2233,11 @@ impl Editor {
                     // Record delete for undo
                     if let Some(line) = self.buffer.line(self.cursor_line) {
                         let deleted_char = line.chars().nth(self.cursor_col).unwrap_or(' ');
-                        self.record_undo(UndoAction::Delete {
+                        self.record_keystroke(UndoAction::Delete {
                             line: self.cursor_line,
                             col: self.cursor_col,
                             text: deleted_char.to_string(),
-                        });
+                        }, merge_undo);
                     }
                     self.buffer.delete_char(self.cursor_line, self.cursor_col);
                 } else if self.cursor_line + 1 < self.buffer.line_count() {
@@ -1475,23 +2257,11 @@ impl Editor {
                 if self.has_selection() {
                     if let Some(((start_line, _), (end_line, _))) = self.get_selection_bounds() {
                         if start_line != end_line {
-                            // Indent all selected lines
-                            for line_num in start_line..=end_line {
-                                if let Some(line) = self.buffer.line(line_num) {
-                                    // Prepend 4 spaces to the line
-                                    let new_line = format!("    {}", line);
-                                    self.buffer.replace_line(line_num, &new_line);
+                            if let Some((start, end)) = self.selected_line_range() {
+                                if self.shift_lines(start, end, state.tab_stops, true) {
+                                    state.set_modified(true);
                                 }
                             }
-                            // Adjust selection to account for added spaces
-                            if let Some((s, e)) = self.selection_start {
-                                self.selection_start = Some((s, e + 4));
-                            }
-                            if let Some((s, e)) = self.selection_end {
-                                self.selection_end = Some((s, e + 4));
-                            }
-                            self.cursor_col += 4;
-                            state.set_modified(true);
                             return true;
                         }
                     }
@@ -1514,48 +2284,11 @@ impl Editor {
                 true
             }
             InputEvent::ShiftTab => {
-                // If there's a selection, dedent all selected lines
-                if self.has_selection() {
-                    if let Some(((start_line, _), (end_line, _))) = self.get_selection_bounds() {
-                        for line_num in start_line..=end_line {
-                            if let Some(line) = self.buffer.line(line_num) {
-                                // Remove up to 4 leading spaces
-                                let spaces_to_remove = line.chars()
-                                    .take(4)
-                                    .take_while(|c| *c == ' ')
-                                    .count();
-                                if spaces_to_remove > 0 {
-                                    let new_line = line[spaces_to_remove..].to_string();
-                                    self.buffer.replace_line(line_num, &new_line);
-                                }
-                            }
-                        }
-                        // Adjust cursor
-                        if self.cursor_col >= 4 {
-                            self.cursor_col -= 4;
-                        } else {
-                            self.cursor_col = 0;
-                        }
-                        state.set_modified(true);
-                        return true;
-                    }
-                }
-                // No selection - dedent current line
-                if let Some(line) = self.buffer.line(self.cursor_line) {
-                    let spaces_to_remove = line.chars()
-                        .take(4)
-                        .take_while(|c| *c == ' ')
-                        .count();
-                    if spaces_to_remove > 0 {
-                        let new_line = line[spaces_to_remove..].to_string();
-                        self.buffer.replace_line(self.cursor_line, &new_line);
-                        if self.cursor_col >= spaces_to_remove {
-                            self.cursor_col -= spaces_to_remove;
-                        } else {
-                            self.cursor_col = 0;
-                        }
-                        state.set_modified(true);
-                    }
+                // Dedent all selected lines, or the current line without a selection
+                let (start, end) = self.selected_line_range()
+                    .unwrap_or((self.cursor_line, self.cursor_line));
+                if self.shift_lines(start, end, state.tab_stops, false) {
+                    state.set_modified(true);
                 }
                 true
             }
@@ -1563,15 +2296,13 @@ impl Editor {
                 if self.keyboard_select_mode {
                     // Extend selection in keyboard select mode
                     if self.cursor_line > 0 {
-                        self.cursor_line -= 1;
-                        self.clamp_cursor();
+                        self.move_to_line(self.cursor_line - 1, state.tab_stops);
                     }
                     self.selection_end = Some((self.cursor_line, self.cursor_col));
                 } else {
                     self.clear_selection();
                     if self.cursor_line > 0 {
-                        self.cursor_line -= 1;
-                        self.clamp_cursor();
+                        self.move_to_line(self.cursor_line - 1, state.tab_stops);
                     }
                 }
                 true
@@ -1579,15 +2310,13 @@ impl Editor {
             InputEvent::CursorDown => {
                 if self.keyboard_select_mode {
                     if self.cursor_line + 1 < self.buffer.line_count() {
-                        self.cursor_line += 1;
-                        self.clamp_cursor();
+                        self.move_to_line(self.cursor_line + 1, state.tab_stops);
                     }
                     self.selection_end = Some((self.cursor_line, self.cursor_col));
                 } else {
                     self.clear_selection();
                     if self.cursor_line + 1 < self.buffer.line_count() {
-                        self.cursor_line += 1;
-                        self.clamp_cursor();
+                        self.move_to_line(self.cursor_line + 1, state.tab_stops);
                     }
                 }
                 true
@@ -1636,21 +2365,21 @@ impl Editor {
             }
             InputEvent::Home => {
                 if self.keyboard_select_mode {
-                    self.cursor_col = 0;
+                    self.cursor_col = self.home_col();
                     self.selection_end = Some((self.cursor_line, self.cursor_col));
                 } else {
                     self.clear_selection();
-                    self.cursor_col = 0;
+                    self.cursor_col = self.home_col();
                 }
                 true
             }
             InputEvent::End => {
                 if self.keyboard_select_mode {
-                    self.cursor_col = self.buffer.line(self.cursor_line).map(|l| l.len()).unwrap_or(0);
+                    self.cursor_col = self.end_col();
                     self.selection_end = Some((self.cursor_line, self.cursor_col));
                 } else {
                     self.clear_selection();
-                    self.cursor_col = self.buffer.line(self.cursor_line).map(|l| l.len()).unwrap_or(0);
+                    self.cursor_col = self.end_col();
                 }
                 true
             }
@@ -1740,7 +2469,7 @@ impl Editor {
                 if !self.has_selection() {
                     self.selection_start = Some((self.cursor_line, self.cursor_col));
                 }
-                self.cursor_col = 0;
+                self.cursor_col = self.home_col();
                 self.selection_end = Some((self.cursor_line, self.cursor_col));
                 true
             }
@@ -1748,7 +2477,7 @@ impl Editor {
                 if !self.has_selection() {
                     self.selection_start = Some((self.cursor_line, self.cursor_col));
                 }
-                self.cursor_col = self.buffer.line(self.cursor_line).map(|l| l.len()).unwrap_or(0);
+                self.cursor_col = self.end_col();
                 self.selection_end = Some((self.cursor_line, self.cursor_col));
                 true
             }
@@ -1938,8 +2667,9 @@ impl Editor {
                 }
             }
             InputEvent::Ctrl('d') => {
-                // Duplicate current line
-                self.duplicate_line();
+                if self.duplicate_line() {
+                    state.set_modified(true);
+                }
                 true
             }
             InputEvent::CtrlShiftK => {
@@ -1948,32 +2678,67 @@ impl Editor {
                 true
             }
             InputEvent::AltUp => {
-                // Move line up
-                self.move_line_up();
+                if self.move_line_up() {
+                    state.set_modified(true);
+                }
                 true
             }
             InputEvent::AltDown => {
-                // Move line down
-                self.move_line_down();
+                if self.move_line_down() {
+                    state.set_modified(true);
+                }
+                true
+            }
+            InputEvent::Ctrl('b') => {
+                self.toggle_bookmark();
+                true
+            }
+            InputEvent::Alt('b') => {
+                self.block_select = !self.block_select;
+                state.set_status(if self.block_select { "Block selection on" } else { "Block selection off" });
+                true
+            }
+            InputEvent::Ctrl('k') => {
+                self.go_to_bookmark(true);
+                true
+            }
+            InputEvent::Ctrl('p') => {
+                self.go_to_bookmark(false);
+                true
+            }
+            InputEvent::Ctrl(']') => {
+                // Jump to matching bracket
+                self.go_to_matching_paren();
                 true
             }
             InputEvent::Ctrl('/') => {
                 // Toggle comment
-                self.toggle_comment();
+                if self.toggle_comment() {
+                    state.set_modified(true);
+                }
                 true
             }
             _ => false,
         }
     }
 
+    /// Move the cursor to another line, keeping it in the same screen column
+    /// when the lines are indented differently with tabs
+    fn move_to_line(&mut self, line: usize, tab_stops: usize) {
+        let x = self.buffer.line(self.cursor_line).map_or(0, |text| display_col(text, self.cursor_col, tab_stops));
+        self.cursor_line = line;
+        self.cursor_col = self.buffer.line(line).map_or(0, |text| char_col_at(text, x, tab_stops));
+    }
+
     pub fn clamp_cursor(&mut self) {
         let line_len = self.buffer.line(self.cursor_line).map(|l| l.len()).unwrap_or(0);
         self.cursor_col = self.cursor_col.min(line_len);
     }
 
-    /// Adjust scroll position to keep cursor visible
+    /// Adjust scroll position to keep cursor visible; `scroll_col` counts
+    /// screen columns, so tabs widen the cursor's position
     #[allow(dead_code)]
-    pub fn ensure_cursor_visible(&mut self, visible_lines: usize, visible_cols: usize) {
+    pub fn ensure_cursor_visible(&mut self, visible_lines: usize, visible_cols: usize, tab_stops: usize) {
         // Vertical scrolling
         if self.cursor_line < self.scroll_row {
             self.scroll_row = self.cursor_line;
@@ -1982,10 +2747,12 @@ impl Editor {
         }
 
         // Horizontal scrolling
-        if self.cursor_col < self.scroll_col {
-            self.scroll_col = self.cursor_col;
-        } else if self.cursor_col >= self.scroll_col + visible_cols {
-            self.scroll_col = self.cursor_col - visible_cols + 1;
+        let cursor_x = self.buffer.line(self.cursor_line)
+            .map_or(self.cursor_col, |line| display_col(line, self.cursor_col, tab_stops));
+        if cursor_x < self.scroll_col {
+            self.scroll_col = cursor_x;
+        } else if cursor_x >= self.scroll_col + visible_cols {
+            self.scroll_col = cursor_x - visible_cols + 1;
         }
     }
 
@@ -1999,6 +2766,7 @@ impl Editor {
         self.undo_stack.clear();
         self.redo_stack.clear();
         self.clear_selection();
+        self.bookmarks.clear();
     }
 
     /// Get content as string
@@ -2006,6 +2774,11 @@ impl Editor {
         self.buffer.to_string()
     }
 
+    /// Content to write to a file, with DOS (CRLF) or Unix (LF) line endings
+    pub fn content_for_save(&self, crlf: bool) -> String {
+        self.buffer.lines.join(if crlf { "\r\n" } else { "\n" })
+    }
+
     /// Clear the editor
     pub fn clear(&mut self) {
         self.buffer.clear();
@@ -2016,12 +2789,96 @@ impl Editor {
         self.undo_stack.clear();
         self.redo_stack.clear();
         self.clear_selection();
+        self.bookmarks.clear();
     }
 
-    /// Find text in the buffer starting from the cursor position
-    /// Returns (line, col) of the match, or None if not found
-    pub fn find_text(&self, search: &str, case_sensitive: bool, whole_word: bool) -> Option<(usize, usize)> {
-        self.find_text_from(search, self.cursor_line, self.cursor_col + 1, case_sensitive, whole_word)
+    /// Find the next match after the cursor (or the previous one before it when
+    /// `backward` is set), wrapping around the buffer. `search` is a regular
+    /// expression when `regex` is set; an invalid pattern is an error.
+    pub fn find_text(&self, search: &str, case_sensitive: bool, whole_word: bool, regex: bool, backward: bool) -> Result<Option<SearchMatch>, String> {
+        let start = if backward { (self.cursor_line, self.cursor_col) } else { (self.cursor_line, self.cursor_col + 1) };
+        self.find_text_at(search, case_sensitive, whole_word, regex, backward, start)
+    }
+
+    /// Like `find_text`, but searching from `start`: forward searches take the
+    /// first match at or after it, backward searches the last match before it.
+    pub fn find_text_at(&self, search: &str, case_sensitive: bool, whole_word: bool, regex: bool, backward: bool, start: (usize, usize)) -> Result<Option<SearchMatch>, String> {
+        if search.is_empty() {
+            return Ok(None);
+        }
+        let re = if regex { Some(build_search_regex(search, case_sensitive, whole_word)?) } else { None };
+        let matches_in = |line: &str| -> Vec<(usize, usize)> {
+            match &re {
+                Some(re) => re.find_iter(line).map(|m| (m.start(), m.len())).collect(),
+                None => literal_matches(line, search, case_sensitive, whole_word),
+            }
+        };
+
+        // Visit the cursor line's remainder, the rest of the buffer, then wrap
+        // around back to the cursor line
+        let line_count = self.buffer.line_count();
+        let (here, at) = (start.0.min(line_count.saturating_sub(1)), start.1);
+        let order: Vec<(usize, bool)> = if backward {
+            (0..=here).rev().map(|l| (l, false)).chain((here..line_count).rev().map(|l| (l, true))).collect()
+        } else {
+            (here..line_count).map(|l| (l, false)).chain((0..=here).map(|l| (l, true))).collect()
+        };
+
+        for (line_num, wrapped) in order {
+            let Some(line) = self.buffer.line(line_num) else { continue };
+            let matches = matches_in(line);
+            let eligible = |col: usize| match (line_num == here, wrapped, backward) {
+                (false, _, _) => true,
+                (true, false, false) | (true, true, true) => col >= at,
+                (true, true, false) | (true, false, true) => col < at,
+            };
+            let found = if backward {
+                matches.into_iter().rev().find(|(col, _)| eligible(*col))
+            } else {
+                matches.into_iter().find(|(col, _)| eligible(*col))
+            };
+            if let Some((col, len)) = found {
+                return Ok(Some(SearchMatch { line: line_num, col, len, wrapped }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Count every match in the buffer with the same rules as `find_text`,
+    /// without moving the cursor
+    pub fn count_matches(&self, search: &str, case_sensitive: bool, whole_word: bool, regex: bool) -> Result<usize, String> {
+        if search.is_empty() {
+            return Ok(0);
+        }
+        if regex {
+            let re = build_search_regex(search, case_sensitive, whole_word)?;
+            return Ok(self.buffer.lines.iter().map(|line| re.find_iter(line).count()).sum());
+        }
+        Ok(self.buffer.lines.iter().map(|line| literal_matches(line, search, case_sensitive, whole_word).len()).sum())
+    }
+
+    /// Find the next (or previous) match from the cursor and select it.
+    /// Returns the message for the status bar.
+    pub fn find_and_select(&mut self, search: &str, case_sensitive: bool, whole_word: bool, regex: bool, backward: bool) -> String {
+        let result = self.find_text(search, case_sensitive, whole_word, regex, backward);
+        self.select_search_result(result, backward)
+    }
+
+    /// Select a search result, returning the message for the status bar
+    pub fn select_search_result(&mut self, result: Result<Option<SearchMatch>, String>, backward: bool) -> String {
+        match result {
+            Ok(Some(m)) => {
+                self.go_to_and_select(m.line, m.col, m.len);
+                match (m.wrapped, backward) {
+                    (true, false) => "Reached end of file, continued from top".to_string(),
+                    (true, true) => "Reached top of file, continued from bottom".to_string(),
+                    _ => format!("Found at line {}", m.line + 1),
+                }
+            }
+            Ok(None) => "No matches".to_string(),
+            Err(e) => e,
+        }
     }
 
     /// Find text starting from a specific position
@@ -2125,11 +2982,17 @@ impl Editor {
         true
     }
 
-    /// Replace all occurrences of search text with replacement text
-    /// Returns the number of replacements made
-    pub fn replace_all(&mut self, search: &str, replace: &str, case_sensitive: bool, whole_word: bool) -> usize {
+    /// Replace all occurrences of search text with replacement text. In regex
+    /// mode `$1`-style capture groups are expanded and the whole change is a
+    /// single undo step.
+    /// Returns the number of replacements made, or an error for an invalid pattern
+    pub fn replace_all(&mut self, search: &str, replace: &str, case_sensitive: bool, whole_word: bool, regex: bool) -> Result<usize, String> {
         if search.is_empty() {
-            return 0;
+            return Ok(0);
+        }
+        if regex {
+            let re = build_search_regex(search, case_sensitive, whole_word)?;
+            return Ok(self.replace_all_regex(&re, replace));
         }
 
         let mut count = 0;
@@ -2158,6 +3021,19 @@ impl Editor {
             }
         }
 
+        Ok(count)
+    }
+
+    fn replace_all_regex(&mut self, re: &Regex, replace: &str) -> usize {
+        let before = self.buffer.lines.clone();
+        let count: usize = before.iter().map(|line| re.find_iter(line).count()).sum();
+        let after: Vec<String> = before
+            .iter()
+            .map(|line| re.replace_all(line, replace).into_owned())
+            .collect();
+        self.clear_selection();
+        self.replace_line_block(0, before, after, |_, col| col);
+        self.clamp_cursor();
         count
     }
 }
@@ -2168,6 +3044,35 @@ impl Default for Editor {
     }
 }
 
+/// Debugger markers on an editor line: the statement about to run and an
+/// enabled breakpoint. A line can carry both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LineMarkers {
+    pub current: bool,
+    pub breakpoint: bool,
+}
+
+impl LineMarkers {
+    pub fn for_line(state: &AppState, line: usize) -> Self {
+        Self {
+            current: state.current_line == Some(line),
+            breakpoint: state.has_breakpoint(line),
+        }
+    }
+
+    /// Glyph and colors for the left-border gutter: a red dot for a
+    /// breakpoint, a yellow arrow for the current line, and the arrow on red
+    /// when the current line has a breakpoint
+    pub fn gutter(self, background: Color) -> Option<(char, Color, Color)> {
+        match (self.current, self.breakpoint) {
+            (true, true) => Some(('\u{25BA}', Color::Yellow, Color::Red)),
+            (true, false) => Some(('\u{25BA}', Color::Yellow, background)),
+            (false, true) => Some(('\u{25CF}', Color::LightRed, background)),
+            (false, false) => None,
+        }
+    }
+}
+
 /// Token types for syntax highlighting
 #[derive(Clone, Copy, Debug)]
 pub enum TokenKind {
@@ -2186,10 +3091,75 @@ pub struct Token<'a> {
     pub text: &'a str,
 }
 
+/// Editor colors for a Display color scheme (0=Classic Blue, 1=Dark, 2=Light)
+pub struct SyntaxColors {
+    pub background: Color,
+    /// Identifiers and whitespace
+    pub text: Color,
+    pub keyword: Color,
+    pub string: Color,
+    pub number: Color,
+    pub comment: Color,
+    pub operator: Color,
+    pub punctuation: Color,
+}
+
+impl SyntaxColors {
+    pub fn for_scheme(scheme: usize) -> Self {
+        match scheme {
+            1 => Self {
+                background: Color::Black,
+                text: Color::LightGray,
+                keyword: Color::LightBlue,
+                string: Color::LightRed,
+                number: Color::LightGreen,
+                comment: Color::DarkGray,
+                operator: Color::Yellow,
+                punctuation: Color::White,
+            },
+            2 => Self {
+                background: Color::LightGray,
+                text: Color::Black,
+                keyword: Color::Blue,
+                string: Color::Red,
+                number: Color::Magenta,
+                comment: Color::DarkGray,
+                operator: Color::Green,
+                punctuation: Color::Black,
+            },
+            _ => Self {
+                background: Color::Blue,
+                text: Color::Yellow,
+                keyword: Color::White,
+                string: Color::LightMagenta,
+                number: Color::LightCyan,
+                comment: Color::LightGray,
+                operator: Color::LightGreen,
+                punctuation: Color::White,
+            },
+        }
+    }
+
+    /// Foreground color for a token
+    pub fn token(&self, kind: TokenKind) -> Color {
+        match kind {
+            TokenKind::Keyword => self.keyword,
+            TokenKind::String => self.string,
+            TokenKind::Number => self.number,
+            TokenKind::Comment => self.comment,
+            TokenKind::Operator => self.operator,
+            TokenKind::Punctuation => self.punctuation,
+            TokenKind::Identifier | TokenKind::Whitespace => self.text,
+        }
+    }
+}
+
 /// Simple tokenizer for BASIC syntax highlighting
 pub fn tokenize_line(line: &str) -> Vec<Token<'_>> {
     let mut tokens = Vec::new();
     let chars: Vec<char> = line.chars().collect();
+    // Byte offset of each char (and of the end), for slicing non-ASCII lines
+    let at: Vec<usize> = line.char_indices().map(|(b, _)| b).chain(std::iter::once(line.len())).collect();
     let mut i = 0;
 
     while i < chars.len() {
@@ -2202,16 +3172,16 @@ pub fn tokenize_line(line: &str) -> Vec<Token<'_>> {
             }
             tokens.push(Token {
                 kind: TokenKind::Whitespace,
-                text: &line[start..i],
+                text: &line[at[start]..at[i]],
             });
             continue;
         }
 
         // Comment (REM or ')
-        if chars[i] == '\'' || (i + 3 <= chars.len() && line[i..].to_uppercase().starts_with("REM") && (i + 3 >= chars.len() || !chars[i + 3].is_alphanumeric())) {
+        if chars[i] == '\'' || (i + 3 <= chars.len() && line[at[i]..].to_uppercase().starts_with("REM") && (i + 3 >= chars.len() || !chars[i + 3].is_alphanumeric())) {
             tokens.push(Token {
                 kind: TokenKind::Comment,
-                text: &line[start..],
+                text: &line[at[start]..],
             });
             break;
         }
@@ -2227,19 +3197,20 @@ pub fn tokenize_line(line: &str) -> Vec<Token<'_>> {
             }
             tokens.push(Token {
                 kind: TokenKind::String,
-                text: &line[start..i],
+                text: &line[at[start]..at[i]],
             });
             continue;
         }
 
         // Number
         if chars[i].is_ascii_digit() || (chars[i] == '.' && i + 1 < chars.len() && chars[i + 1].is_ascii_digit()) {
-            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.' || chars[i] == 'E' || chars[i] == 'e' || chars[i] == '+' || chars[i] == '-' || chars[i] == '#' || chars[i] == '!') {
+            // A sign only belongs to the number as an exponent's, as in 1E-5
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.' || chars[i] == 'E' || chars[i] == 'e' || ((chars[i] == '+' || chars[i] == '-') && matches!(chars[i - 1], 'E' | 'e')) || chars[i] == '#' || chars[i] == '!') {
                 i += 1;
             }
             tokens.push(Token {
                 kind: TokenKind::Number,
-                text: &line[start..i],
+                text: &line[at[start]..at[i]],
             });
             continue;
         }
@@ -2249,7 +3220,7 @@ pub fn tokenize_line(line: &str) -> Vec<Token<'_>> {
             while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$' || chars[i] == '%' || chars[i] == '!' || chars[i] == '#' || chars[i] == '&') {
                 i += 1;
             }
-            let word = &line[start..i];
+            let word = &line[at[start]..at[i]];
             let kind = if KEYWORDS.contains(&word.to_uppercase().as_str()) {
                 TokenKind::Keyword
             } else {
@@ -2263,12 +3234,12 @@ pub fn tokenize_line(line: &str) -> Vec<Token<'_>> {
         if "+-*/\\^=<>".contains(chars[i]) {
             // Check for compound operators
             if i + 1 < chars.len() {
-                let two = &line[i..i + 2];
+                let two = &line[at[i]..at[i + 2]];
                 if two == "<>" || two == "<=" || two == ">=" {
                     i += 2;
                     tokens.push(Token {
                         kind: TokenKind::Operator,
-                        text: &line[start..i],
+                        text: &line[at[start]..at[i]],
                     });
                     continue;
                 }
@@ -2276,7 +3247,7 @@ pub fn tokenize_line(line: &str) -> Vec<Token<'_>> {
             i += 1;
             tokens.push(Token {
                 kind: TokenKind::Operator,
-                text: &line[start..i],
+                text: &line[at[start]..at[i]],
             });
             continue;
         }
@@ -2285,7 +3256,7 @@ pub fn tokenize_line(line: &str) -> Vec<Token<'_>> {
         i += 1;
         tokens.push(Token {
             kind: TokenKind::Punctuation,
-            text: &line[start..i],
+            text: &line[at[start]..at[i]],
         });
     }
 
@@ -2318,6 +3289,8 @@ impl MainWidget for Editor {
 
             // Focus the editor
             state.focus = Focus::Editor;
+            // Typing after a click starts a new undo step
+            self.undo_merge = false;
 
             // Use editor_widgets for hit testing
             let line_count = self.buffer.line_count().max(1);
@@ -2391,12 +3364,14 @@ impl MainWidget for Editor {
                 EditorClickAction::ContentClick { editor_y, editor_x } => {
                     // Set cursor position
                     let target_line = self.scroll_row + editor_y;
-                    let target_col = self.scroll_col + editor_x;
+                    let target_x = self.scroll_col + editor_x;
 
                     if target_line < self.buffer.line_count() {
+                        if state.auto_capitalize && target_line != self.cursor_line {
+                            self.capitalize_line(self.cursor_line);
+                        }
                         self.cursor_line = target_line;
-                        let line_len = self.buffer.line(target_line).map(|l| l.len()).unwrap_or(0);
-                        self.cursor_col = target_col.min(line_len);
+                        self.cursor_col = self.buffer.line(target_line).map_or(0, |l| char_col_at(l, target_x, state.tab_stops));
                     }
 
                     // Multi-click detection
@@ -2438,6 +3413,13 @@ impl MainWidget for Editor {
                     }
                     WidgetAction::Consumed
                 }
+                EditorClickAction::GutterClick { editor_y } => {
+                    let line = self.scroll_row + editor_y;
+                    if line < self.buffer.line_count() {
+                        state.toggle_breakpoint(line);
+                    }
+                    WidgetAction::Consumed
+                }
                 EditorClickAction::MaximizeToggle | EditorClickAction::TitleBarDoubleClick => {
                     WidgetAction::Toggle("editor_maximized")
                 }
@@ -2493,32 +3475,41 @@ impl MainWidget for Editor {
                 return WidgetAction::Consumed;
             }
 
-            // Handle selection drag
-            if self.is_selecting && event_in_bounds(event, bounds) {
+            // Handle selection drag. Above or below the text the view scrolls
+            // a line per drag event so the selection keeps growing.
+            if self.is_selecting {
                 let content_top = bounds.y + 2;
                 let content_left = bounds.x + 2;
+                let visible_lines = self.visible_lines.max(1);
+
+                let editor_y = if *row < content_top {
+                    self.scroll_row = self.scroll_row.saturating_sub(1);
+                    0
+                } else if (*row - content_top) as usize >= visible_lines {
+                    if self.scroll_row + visible_lines < self.buffer.line_count() {
+                        self.scroll_row += 1;
+                    }
+                    visible_lines - 1
+                } else {
+                    (*row - content_top) as usize
+                };
+                let editor_x = col.saturating_sub(content_left) as usize;
 
-                if *row >= content_top && *col >= content_left {
-                    let editor_y = row.saturating_sub(content_top) as usize;
-                    let editor_x = col.saturating_sub(content_left) as usize;
-
-                    let target_line = self.scroll_row + editor_y;
-                    let target_col = self.scroll_col + editor_x;
+                let target_line = self.scroll_row + editor_y;
+                let target_x = self.scroll_col + editor_x;
 
-                    if target_line < self.buffer.line_count() {
-                        self.cursor_line = target_line;
-                        let line_len = self.buffer.line(target_line).map(|l| l.len()).unwrap_or(0);
-                        self.cursor_col = target_col.min(line_len);
-                    }
+                if target_line < self.buffer.line_count() {
+                    self.cursor_line = target_line;
+                    self.cursor_col = self.buffer.line(target_line).map_or(0, |l| char_col_at(l, target_x, state.tab_stops));
+                }
 
-                    match (self.click_count, self.selection_anchor) {
-                        (2, Some(anchor)) => self.extend_selection_by_word(anchor),
-                        (3, Some(anchor)) => self.extend_selection_by_line(anchor),
-                        (4, Some(anchor)) => self.extend_selection_by_paragraph(anchor),
-                        _ => self.update_selection(),
-                    }
-                    return WidgetAction::Consumed;
+                match (self.click_count, self.selection_anchor) {
+                    (2, Some(anchor)) => self.extend_selection_by_word(anchor),
+                    (3, Some(anchor)) => self.extend_selection_by_line(anchor),
+                    (4, Some(anchor)) => self.extend_selection_by_paragraph(anchor),
+                    _ => self.update_selection(),
                 }
+                return WidgetAction::Consumed;
             }
         }
 
@@ -2580,3 +3571,868 @@ impl MainWidget for Editor {
         Some(Focus::Editor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputEvent;
+
+    fn editor_with(text: &str, selection: ((usize, usize), (usize, usize))) -> (Editor, AppState) {
+        let mut editor = Editor::new();
+        editor.load(text);
+        editor.selection_start = Some(selection.0);
+        editor.selection_end = Some(selection.1);
+        editor.cursor_line = selection.1 .0;
+        editor.cursor_col = selection.1 .1;
+        let mut state = AppState::new();
+        state.tab_stops = 4;
+        (editor, state)
+    }
+
+    #[test]
+    fn test_tab_indents_selected_lines() {
+        let (mut editor, mut state) = editor_with("a\nb\nc", ((0, 0), (1, 1)));
+        editor.handle_input(&InputEvent::Tab, &mut state);
+        assert_eq!(editor.content(), "    a\n    b\nc");
+        assert_eq!(editor.get_selection_bounds(), Some(((0, 0), (1, 5))));
+
+        // Selection is kept, so the block can be indented again
+        editor.handle_input(&InputEvent::Tab, &mut state);
+        assert_eq!(editor.content(), "        a\n        b\nc");
+
+        // Each block operation is a single undo step
+        editor.undo();
+        assert_eq!(editor.content(), "    a\n    b\nc");
+        editor.undo();
+        assert_eq!(editor.content(), "a\nb\nc");
+    }
+
+    #[test]
+    fn test_shift_tab_dedent_stops_at_column_zero() {
+        let (mut editor, mut state) = editor_with("  a\nb\n      c", ((0, 2), (2, 7)));
+        editor.handle_input(&InputEvent::ShiftTab, &mut state);
+        assert_eq!(editor.content(), "a\nb\n  c");
+        assert_eq!(editor.get_selection_bounds(), Some(((0, 0), (2, 3))));
+        editor.handle_input(&InputEvent::ShiftTab, &mut state);
+        assert_eq!(editor.content(), "a\nb\nc");
+        assert_eq!(editor.undo_stack.len(), 2);
+    }
+
+    #[test]
+    fn test_toggle_comment_on_block() {
+        let (mut editor, mut state) = editor_with("PRINT 1\n  ' note\n  x = 2", ((0, 3), (2, 1)));
+        editor.handle_input(&InputEvent::Ctrl('/'), &mut state);
+        assert_eq!(editor.content(), "' PRINT 1\n  ' ' note\n  ' x = 2");
+        assert!(state.modified);
+        editor.undo();
+        assert_eq!(editor.content(), "PRINT 1\n  ' note\n  x = 2");
+    }
+
+    #[test]
+    fn test_toggle_comment_off_block() {
+        let (mut editor, mut state) = editor_with("' PRINT 1\n\n  'x = 2\n  ' y", ((0, 0), (3, 5)));
+        editor.handle_input(&InputEvent::Ctrl('/'), &mut state);
+        assert_eq!(editor.content(), "PRINT 1\n\n  x = 2\n  y");
+        assert_eq!(editor.undo_stack.len(), 1);
+    }
+
+    #[test]
+    fn test_toggle_comment_skips_line_selected_to_column_zero() {
+        let (mut editor, mut state) = editor_with("a = 1\nb = 2\nc = 3", ((0, 0), (2, 0)));
+        editor.handle_input(&InputEvent::Ctrl('/'), &mut state);
+        assert_eq!(editor.content(), "' a = 1\n' b = 2\nc = 3");
+    }
+
+    #[test]
+    fn test_move_single_line() {
+        let mut editor = Editor::new();
+        editor.load("a\nb\nc");
+        let mut state = AppState::new();
+        editor.cursor_line = 1;
+        editor.cursor_col = 1;
+        editor.handle_input(&InputEvent::AltUp, &mut state);
+        assert_eq!(editor.content(), "b\na\nc");
+        assert_eq!((editor.cursor_line, editor.cursor_col), (0, 1));
+
+        // Already at the top: nothing changes
+        editor.handle_input(&InputEvent::AltUp, &mut state);
+        assert_eq!(editor.content(), "b\na\nc");
+        assert_eq!(editor.undo_stack.len(), 1);
+
+        editor.undo();
+        assert_eq!(editor.content(), "a\nb\nc");
+    }
+
+    #[test]
+    fn test_move_selected_lines_to_bottom() {
+        let (mut editor, mut state) = editor_with("a\nb\nc\nd", ((1, 0), (2, 1)));
+        editor.handle_input(&InputEvent::AltDown, &mut state);
+        assert_eq!(editor.content(), "a\nd\nb\nc");
+        assert_eq!(editor.get_selection_bounds(), Some(((2, 0), (3, 1))));
+        assert_eq!(editor.cursor_line, 3);
+
+        // The block now touches the last line, so it can't move further down
+        assert!(!editor.move_line_down());
+        editor.handle_input(&InputEvent::AltUp, &mut state);
+        assert_eq!(editor.content(), "a\nb\nc\nd");
+    }
+
+    #[test]
+    fn test_move_lines_skips_line_selected_to_column_zero() {
+        let (mut editor, mut state) = editor_with("a\nb\nc\nd", ((0, 0), (2, 0)));
+        editor.handle_input(&InputEvent::AltDown, &mut state);
+        assert_eq!(editor.content(), "c\na\nb\nd");
+        assert_eq!(editor.get_selection_bounds(), Some(((1, 0), (3, 0))));
+        editor.handle_input(&InputEvent::AltUp, &mut state);
+        assert_eq!(editor.content(), "a\nb\nc\nd");
+        assert_eq!(editor.get_selection_bounds(), Some(((0, 0), (2, 0))));
+    }
+
+    #[test]
+    fn test_duplicate_current_line() {
+        let mut editor = Editor::new();
+        editor.load("a\nb");
+        let mut state = AppState::new();
+        editor.cursor_col = 1;
+        editor.handle_input(&InputEvent::Ctrl('d'), &mut state);
+        assert_eq!(editor.content(), "a\na\nb");
+        assert_eq!((editor.cursor_line, editor.cursor_col), (1, 1));
+        assert!(state.modified);
+        editor.undo();
+        assert_eq!(editor.content(), "a\nb");
+    }
+
+    #[test]
+    fn test_duplicate_selected_block() {
+        let (mut editor, mut state) = editor_with("a\nb\nc", ((0, 1), (1, 1)));
+        editor.handle_input(&InputEvent::Ctrl('d'), &mut state);
+        assert_eq!(editor.content(), "a\nb\na\nb\nc");
+        assert_eq!(editor.get_selection_bounds(), Some(((2, 1), (3, 1))));
+        assert_eq!(editor.cursor_line, 3);
+        assert_eq!(editor.undo_stack.len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_skips_line_selected_to_column_zero() {
+        let (mut editor, mut state) = editor_with("a\nb\nc", ((0, 0), (2, 0)));
+        editor.handle_input(&InputEvent::Ctrl('d'), &mut state);
+        assert_eq!(editor.content(), "a\nb\na\nb\nc");
+        assert_eq!(editor.get_selection_bounds(), Some(((2, 0), (4, 0))));
+    }
+
+    #[test]
+    fn test_indent_mixed_lines_and_redo() {
+        let (mut editor, mut state) = editor_with("x\n    y\n\tz\nw", ((0, 0), (3, 0)));
+        editor.handle_input(&InputEvent::Tab, &mut state);
+        // A selection ending at column 0 leaves that line alone
+        assert_eq!(editor.content(), "    x\n        y\n    \tz\nw");
+        editor.undo();
+        editor.redo();
+        assert_eq!(editor.content(), "    x\n        y\n    \tz\nw");
+    }
+
+    #[test]
+    fn test_enter_indents_after_for_header() {
+        let (mut editor, mut state) = editor_with("  for i = 1 to 3", ((0, 16), (0, 16)));
+        editor.clear_selection();
+        editor.handle_input(&InputEvent::Enter, &mut state);
+        assert_eq!(editor.content(), "  FOR i = 1 TO 3\n      ");
+        assert_eq!((editor.cursor_line, editor.cursor_col), (1, 6));
+
+        // The body keeps its indentation and NEXT moves back out to the FOR
+        for c in "PRINT i".chars() {
+            editor.handle_input(&InputEvent::Char(c), &mut state);
+        }
+        editor.handle_input(&InputEvent::Enter, &mut state);
+        assert_eq!((editor.cursor_line, editor.cursor_col), (2, 6));
+        for c in "next".chars() {
+            editor.handle_input(&InputEvent::Char(c), &mut state);
+        }
+        editor.handle_input(&InputEvent::Enter, &mut state);
+        assert_eq!(editor.content(), "  FOR i = 1 TO 3\n      PRINT i\n  NEXT\n  ");
+
+        // Each Enter is a single undo step
+        editor.undo();
+        assert_eq!(editor.content(), "  FOR i = 1 TO 3\n      PRINT i\n      NEXT");
+    }
+
+    #[test]
+    fn test_enter_indent_rules() {
+        assert!(opens_block("IF x > 1 THEN ' big"));
+        assert!(!opens_block("IF x > 1 THEN PRINT x"));
+        assert!(opens_block("DO WHILE x < 3"));
+        assert!(closes_block("END SUB"));
+        assert!(!closes_block("END"));
+        assert!(!opens_block("PRINT \"FOR ' x\""));
+    }
+
+    #[test]
+    fn test_enter_without_auto_indent() {
+        let (mut editor, mut state) = editor_with("  FOR i = 1 TO 3", ((0, 16), (0, 16)));
+        editor.clear_selection();
+        state.auto_indent = false;
+        editor.handle_input(&InputEvent::Enter, &mut state);
+        assert_eq!(editor.content(), "  FOR i = 1 TO 3\n");
+        assert_eq!((editor.cursor_line, editor.cursor_col), (1, 0));
+    }
+
+    #[test]
+    fn test_keywords_capitalized_on_enter() {
+        assert_eq!(capitalize_keywords("print \"hi\""), "PRINT \"hi\"");
+        assert_eq!(
+            capitalize_keywords("if x then print \"for\" ' next"),
+            "IF x THEN PRINT \"for\" ' next"
+        );
+        assert_eq!(capitalize_keywords("rem print this"), "rem print this");
+
+        let (mut editor, mut state) = editor_with("print \"hi\"", ((0, 10), (0, 10)));
+        editor.clear_selection();
+        editor.handle_input(&InputEvent::Enter, &mut state);
+        assert_eq!(editor.content(), "PRINT \"hi\"\n");
+    }
+
+    #[test]
+    fn test_keywords_capitalized_when_cursor_leaves_line() {
+        let (mut editor, mut state) = editor_with("print a\nend", ((0, 3), (0, 3)));
+        editor.clear_selection();
+        editor.handle_input(&InputEvent::CursorRight, &mut state);
+        assert_eq!(editor.content(), "print a\nend");
+        editor.handle_input(&InputEvent::CursorDown, &mut state);
+        assert_eq!(editor.content(), "PRINT a\nend");
+
+        state.auto_capitalize = false;
+        editor.handle_input(&InputEvent::CursorUp, &mut state);
+        assert_eq!(editor.content(), "PRINT a\nend");
+    }
+
+    #[test]
+    fn test_find_matching_paren_nested() {
+        let line = "x = (a * (b + c(1)) - \")\") ' (";
+        assert_eq!(find_matching_paren(line, 4), Some(25));
+        assert_eq!(find_matching_paren(line, 25), Some(4));
+        assert_eq!(find_matching_paren(line, 9), Some(18));
+        assert_eq!(find_matching_paren(line, 15), Some(17));
+        // Not a bracket, inside a string, in a comment, or unbalanced
+        assert_eq!(find_matching_paren(line, 0), None);
+        assert_eq!(find_matching_paren(line, 23), None);
+        assert_eq!(find_matching_paren(line, 29), None);
+        assert_eq!(find_matching_paren("PRINT (1 + (2)", 6), None);
+    }
+
+    #[test]
+    fn test_go_to_matching_paren() {
+        let (mut editor, mut state) = editor_with("y = f(a, (b))", ((0, 13), (0, 13)));
+        editor.clear_selection();
+        editor.handle_input(&InputEvent::Ctrl(']'), &mut state);
+        assert_eq!(editor.cursor_col, 5);
+        editor.handle_input(&InputEvent::Ctrl(']'), &mut state);
+        assert_eq!(editor.cursor_col, 12);
+        assert_eq!(editor.paren_at_cursor(), Some((0, 5, 12)));
+    }
+
+    #[test]
+    fn test_regex_replace_all_with_capture_groups() {
+        let (mut editor, _) = editor_with("x = foo(1)\nPRINT FOO(22), bar(3)", ((0, 0), (0, 0)));
+        editor.clear_selection();
+        let count = editor.replace_all(r"foo\((\d+)\)", "bar($1 + 1)", false, false, true);
+        assert_eq!(count, Ok(2));
+        assert_eq!(editor.content(), "x = bar(1 + 1)\nPRINT bar(22 + 1), bar(3)");
+
+        // A regex Replace All is a single undo step
+        editor.undo();
+        assert_eq!(editor.content(), "x = foo(1)\nPRINT FOO(22), bar(3)");
+
+        assert_eq!(
+            editor.find_text(r"b\w+", true, true, true, false).map(|m| m.map(|m| (m.line, m.col, m.len))),
+            Ok(Some((1, 15, 3)))
+        );
+    }
+
+    #[test]
+    fn test_regex_invalid_pattern_is_an_error() {
+        let (mut editor, _) = editor_with("a(b", ((0, 0), (0, 0)));
+        editor.clear_selection();
+        assert_eq!(
+            editor.find_text("(b", false, false, true, false),
+            Err("Invalid regular expression: unclosed group".to_string())
+        );
+        assert!(editor.replace_all("(b", "x", false, false, true).is_err());
+        assert_eq!(editor.content(), "a(b");
+        // The same text is fine as a literal search
+        assert_eq!(
+            editor.find_text("(b", false, false, false, false),
+            Ok(Some(SearchMatch { line: 0, col: 1, len: 2, wrapped: false }))
+        );
+    }
+
+    #[test]
+    fn test_repeat_find_wraps_around() {
+        let (mut editor, _) = editor_with("x = 1\nPRINT x\ny = x", ((1, 0), (1, 0)));
+        editor.clear_selection();
+        assert_eq!(editor.find_and_select("x", false, true, false, false), "Found at line 2");
+        assert_eq!((editor.cursor_line, editor.cursor_col), (1, 6));
+        assert_eq!(editor.find_and_select("x", false, true, false, false), "Found at line 3");
+        assert_eq!(
+            editor.find_and_select("x", false, true, false, false),
+            "Reached end of file, continued from top"
+        );
+        assert_eq!((editor.cursor_line, editor.cursor_col), (0, 0));
+        assert_eq!(editor.get_selected_text().as_deref(), Some("x"));
+
+        // Backward search from the first match wraps to the last one
+        assert_eq!(
+            editor.find_and_select("x", false, true, false, true),
+            "Reached top of file, continued from bottom"
+        );
+        assert_eq!((editor.cursor_line, editor.cursor_col), (2, 4));
+        assert_eq!(editor.find_and_select("x", false, true, false, true), "Found at line 2");
+    }
+
+    #[test]
+    fn test_find_no_matches() {
+        let (mut editor, _) = editor_with("PRINT 1\nEND", ((0, 3), (0, 3)));
+        editor.clear_selection();
+        assert_eq!(editor.find_and_select("GOTO", false, false, false, false), "No matches");
+        assert_eq!(editor.find_and_select("GOTO", false, false, false, true), "No matches");
+        assert!(!editor.has_selection());
+        assert_eq!((editor.cursor_line, editor.cursor_col), (0, 3));
+    }
+
+    #[test]
+    fn test_bookmark_toggle_and_navigation() {
+        let (mut editor, mut state) = editor_with("a\nb\nc\nd\ne", ((1, 0), (1, 0)));
+        editor.clear_selection();
+        assert!(!editor.go_to_bookmark(true));
+
+        editor.handle_input(&InputEvent::Ctrl('b'), &mut state);
+        editor.cursor_line = 3;
+        editor.handle_input(&InputEvent::Ctrl('b'), &mut state);
+        assert_eq!(editor.bookmarks, HashSet::from([1, 3]));
+
+        // Next/previous wrap around the buffer
+        editor.handle_input(&InputEvent::Ctrl('k'), &mut state);
+        assert_eq!(editor.cursor_line, 1);
+        editor.handle_input(&InputEvent::Ctrl('k'), &mut state);
+        assert_eq!(editor.cursor_line, 3);
+        editor.handle_input(&InputEvent::Ctrl('p'), &mut state);
+        assert_eq!(editor.cursor_line, 1);
+        editor.handle_input(&InputEvent::Ctrl('p'), &mut state);
+        assert_eq!(editor.cursor_line, 3);
+
+        // Toggling again removes the bookmark
+        editor.handle_input(&InputEvent::Ctrl('b'), &mut state);
+        assert_eq!(editor.bookmarks, HashSet::from([1]));
+    }
+
+    #[test]
+    fn test_bookmarks_follow_inserted_and_deleted_lines() {
+        let (mut editor, mut state) = editor_with("a\nb\nc\nd", ((3, 0), (3, 0)));
+        editor.clear_selection();
+        editor.toggle_bookmark();
+        editor.cursor_line = 1;
+        editor.toggle_bookmark();
+
+        // A new line above both bookmarks pushes them down
+        editor.cursor_line = 0;
+        editor.cursor_col = 1;
+        editor.handle_input(&InputEvent::Enter, &mut state);
+        editor.go_to_bookmark(true);
+        assert_eq!(editor.bookmarks, HashSet::from([2, 4]));
+        assert_eq!(editor.buffer.line(editor.cursor_line), Some("b"));
+
+        // Deleting a bookmarked line drops its bookmark; later ones move up
+        editor.handle_input(&InputEvent::CtrlShiftK, &mut state);
+        editor.go_to_bookmark(true);
+        assert_eq!(editor.bookmarks, HashSet::from([3]));
+        assert_eq!(editor.buffer.line(3), Some("d"));
+
+        // Undoing the Enter removes the inserted line again
+        editor.undo();
+        editor.go_to_bookmark(true);
+        assert_eq!(editor.bookmarks, HashSet::from([2]));
+        assert_eq!(editor.buffer.line(2), Some("d"));
+    }
+
+    #[test]
+    fn test_tokenize_line_keeps_keywords_in_strings() {
+        let tokens: Vec<(String, String)> = tokenize_line("PRINT \"IF it's café\"; n ' REM")
+            .iter()
+            .filter(|t| !matches!(t.kind, TokenKind::Whitespace))
+            .map(|t| (format!("{:?}", t.kind), t.text.to_string()))
+            .collect();
+        let expected = [
+            ("Keyword", "PRINT"),
+            ("String", "\"IF it's café\""),
+            ("Punctuation", ";"),
+            ("Identifier", "n"),
+            ("Comment", "' REM"),
+        ];
+        assert_eq!(tokens, expected.map(|(k, t)| (k.to_string(), t.to_string())));
+    }
+
+    #[test]
+    fn test_tab_column_math() {
+        let line = "\tPRINT x";
+        assert_eq!(display_col(line, 0, 8), 0);
+        assert_eq!(display_col(line, 1, 8), 8);
+        assert_eq!(display_col(line, 3, 4), 6);
+        assert_eq!(display_col("ab\tc", 3, 4), 4);
+
+        // Clicks inside the tab land on it; past the end go to the end
+        assert_eq!(char_col_at(line, 0, 8), 0);
+        assert_eq!(char_col_at(line, 7, 8), 0);
+        assert_eq!(char_col_at(line, 8, 8), 1);
+        assert_eq!(char_col_at(line, 40, 8), 8);
+    }
+
+    #[test]
+    fn test_cursor_keeps_screen_column_across_tabs() {
+        let mut editor = Editor::new();
+        editor.load("\tA = 1\n        B = 2");
+        let mut state = AppState::new();
+        state.tab_stops = 8;
+        editor.cursor_col = 1;
+
+        editor.handle_input(&InputEvent::CursorDown, &mut state);
+        assert_eq!((editor.cursor_line, editor.cursor_col), (1, 8));
+        editor.handle_input(&InputEvent::CursorUp, &mut state);
+        assert_eq!((editor.cursor_line, editor.cursor_col), (0, 1));
+
+        // The tab itself is kept
+        assert_eq!(editor.content(), "\tA = 1\n        B = 2");
+    }
+
+    #[test]
+    fn test_ensure_cursor_visible_counts_tab_width() {
+        let mut editor = Editor::new();
+        editor.load("\t\t\tPRINT x");
+        // Column 5 is screen column 26 past three 8-wide tabs
+        editor.cursor_col = 5;
+        editor.ensure_cursor_visible(10, 20, 8);
+        assert_eq!(editor.scroll_col, 7);
+
+        // Scrolled past it, the second tab's screen column is shown
+        editor.scroll_col = 30;
+        editor.cursor_col = 1;
+        editor.ensure_cursor_visible(10, 20, 8);
+        assert_eq!(editor.scroll_col, 8);
+    }
+
+    #[test]
+    fn test_crlf_file_saves_with_crlf() {
+        let text = "CLS\r\nPRINT \"Hi\"\r\nEND";
+        let mut editor = Editor::new();
+        editor.load(text);
+        assert!(uses_crlf(text));
+        assert_eq!(editor.buffer.line(1), Some("PRINT \"Hi\""));
+        assert_eq!(editor.content_for_save(uses_crlf(text)), text);
+        assert_eq!(editor.content_for_save(false), "CLS\nPRINT \"Hi\"\nEND");
+        assert!(!uses_crlf("CLS\nEND"));
+    }
+
+    #[test]
+    fn test_undo_takes_back_typing_a_word_at_a_time() {
+        let (mut editor, mut state) = editor_with("", ((0, 0), (0, 0)));
+        editor.selection_start = None;
+        editor.selection_end = None;
+        for c in "x = ab".chars() {
+            editor.handle_input(&InputEvent::Char(c), &mut state);
+        }
+        assert_eq!(editor.content(), "x = ab");
+        editor.undo();
+        assert_eq!(editor.content(), "x = ");
+        // A word and the space typed after it go together
+        editor.undo();
+        assert_eq!(editor.content(), "x ");
+        editor.redo();
+        assert_eq!(editor.content(), "x = ");
+
+        // Backspacing over a word is one step too
+        editor.handle_input(&InputEvent::Char('c'), &mut state);
+        editor.handle_input(&InputEvent::Char('d'), &mut state);
+        editor.handle_input(&InputEvent::Backspace, &mut state);
+        editor.handle_input(&InputEvent::Backspace, &mut state);
+        assert_eq!(editor.content(), "x = ");
+        editor.undo();
+        assert_eq!(editor.content(), "x = cd");
+    }
+
+    #[test]
+    fn test_typing_across_a_newline_splits_the_undo_step() {
+        let (mut editor, mut state) = editor_with("", ((0, 0), (0, 0)));
+        editor.selection_start = None;
+        editor.selection_end = None;
+        state.auto_indent = false;
+        for event in [InputEvent::Char('a'), InputEvent::Char('b'), InputEvent::Enter, InputEvent::Char('c'), InputEvent::Char('d')] {
+            editor.handle_input(&event, &mut state);
+        }
+        assert_eq!(editor.content(), "ab\ncd");
+        editor.undo();
+        assert_eq!(editor.content(), "ab\n");
+        editor.undo();
+        assert_eq!(editor.content(), "ab");
+        editor.undo();
+        assert_eq!(editor.content(), "");
+    }
+
+    #[test]
+    fn test_moving_the_cursor_ends_an_undo_step() {
+        let (mut editor, mut state) = editor_with("", ((0, 0), (0, 0)));
+        editor.selection_start = None;
+        editor.selection_end = None;
+        editor.handle_input(&InputEvent::Char('a'), &mut state);
+        editor.handle_input(&InputEvent::Char('b'), &mut state);
+        editor.handle_input(&InputEvent::CursorLeft, &mut state);
+        editor.handle_input(&InputEvent::CursorRight, &mut state);
+        editor.handle_input(&InputEvent::Char('c'), &mut state);
+        assert_eq!(editor.content(), "abc");
+        editor.undo();
+        assert_eq!(editor.content(), "ab");
+        editor.undo();
+        assert_eq!(editor.content(), "");
+    }
+
+    #[test]
+    fn test_block_selection_copies_a_rectangle() {
+        let (mut editor, _) = editor_with("DATA 1, 22, 3\nDATA 4\nDATA 5, 66, 7", ((0, 5), (2, 11)));
+        editor.block_select = true;
+        // Short lines give as much of the rectangle as they have
+        assert_eq!(editor.get_selected_text().as_deref(), Some("1, 22,\n4\n5, 66,"));
+        assert!(editor.is_selected(1, 8));
+        assert!(!editor.is_selected(1, 11));
+
+        // Selecting right to left or bottom to top gives the same rectangle
+        editor.selection_start = Some((2, 11));
+        editor.selection_end = Some((0, 5));
+        assert_eq!(editor.get_selected_text().as_deref(), Some("1, 22,\n4\n5, 66,"));
+    }
+
+    #[test]
+    fn test_typing_into_block_selection_edits_every_line() {
+        let (mut editor, mut state) = editor_with("DATA 1, 2\nDATA 3\nDATA 4, 5", ((0, 5), (2, 5)));
+        editor.block_select = true;
+        editor.handle_input(&InputEvent::Char('-'), &mut state);
+        editor.handle_input(&InputEvent::Char('9'), &mut state);
+        assert_eq!(editor.content(), "DATA -91, 2\nDATA -93\nDATA -94, 5");
+
+        editor.handle_input(&InputEvent::Backspace, &mut state);
+        assert_eq!(editor.content(), "DATA -1, 2\nDATA -3\nDATA -4, 5");
+
+        // Deleting a rectangle leaves lines too short to reach it alone
+        editor.selection_start = Some((0, 8));
+        editor.selection_end = Some((2, 11));
+        editor.handle_input(&InputEvent::Delete, &mut state);
+        assert_eq!(editor.content(), "DATA -1,\nDATA -3\nDATA -4,");
+
+        editor.undo();
+        assert_eq!(editor.content(), "DATA -1, 2\nDATA -3\nDATA -4, 5");
+    }
+
+    #[test]
+    fn test_paste_into_block_selection_keeps_typed_text() {
+        let (mut editor, mut state) = editor_with("DATA 1\nDATA 2", ((0, 5), (1, 5)));
+        editor.block_select = true;
+        editor.handle_input(&InputEvent::Char('x'), &mut state);
+        assert_eq!(editor.content(), "DATA x1\nDATA x2");
+
+        // The block is empty now, so pasting deletes nothing
+        editor.handle_input(&InputEvent::Paste("y".to_string()), &mut state);
+        assert_eq!(editor.content(), "DATA x1\nDATA xy2");
+
+        // A block with contents loses only them
+        editor.selection_start = Some((0, 5));
+        editor.selection_end = Some((1, 6));
+        assert!(editor.delete_selection());
+        assert_eq!(editor.content(), "DATA 1\nDATA y2");
+    }
+
+    #[test]
+    fn test_count_matches_overlapping_and_whole_word() {
+        let (editor, _) = editor_with("aaa = aa\nPRINT AA; aab", ((0, 0), (0, 0)));
+        // Overlapping occurrences each count, as Find steps through them
+        assert_eq!(editor.count_matches("aa", false, false, false), Ok(5));
+        assert_eq!(editor.count_matches("aa", true, false, false), Ok(4));
+        assert_eq!(editor.count_matches("aa", false, true, false), Ok(2));
+        assert_eq!(editor.count_matches("aa", true, true, false), Ok(1));
+        assert_eq!(editor.count_matches("a+", false, true, true), Ok(3));
+        assert_eq!(editor.count_matches("", false, false, false), Ok(0));
+        // The cursor stays put
+        assert_eq!((editor.cursor_line, editor.cursor_col), (0, 0));
+    }
+
+    #[test]
+    fn test_paste_inserts_text_verbatim() {
+        let (mut editor, mut state) = editor_with("' top", ((0, 5), (0, 5)));
+        editor.selection_start = None;
+        editor.selection_end = None;
+        let text = "\nfor i = 1 to 2\nprint i\nnext";
+        editor.handle_input(&InputEvent::Paste(text.to_string()), &mut state);
+        // No auto-indent or keyword capitalization
+        assert_eq!(editor.content(), "' top\nfor i = 1 to 2\nprint i\nnext");
+        assert_eq!((editor.cursor_line, editor.cursor_col), (3, 4));
+
+        editor.undo();
+        assert_eq!(editor.content(), "' top");
+    }
+
+    #[test]
+    fn test_drag_past_the_edge_scrolls_and_extends_selection() {
+        let text: Vec<String> = (0..30).map(|i| format!("PRINT {}", i)).collect();
+        let mut editor = Editor::new();
+        editor.load(&text.join("\n"));
+        let mut state = AppState::new();
+        // Text rows 3..=12 on screen: ten visible lines below the title
+        let bounds = Rect::new(0, 1, 80, 13);
+        editor.visible_lines = 10;
+        editor.cursor_line = 2;
+        editor.start_selection();
+
+        let drag = |editor: &mut Editor, state: &mut AppState, row: u16| {
+            editor.handle_event(&InputEvent::MouseDrag { row, col: 5 }, state, bounds);
+            (editor.scroll_row, editor.cursor_line)
+        };
+        // Inside the text the view stays put
+        assert_eq!(drag(&mut editor, &mut state, 12), (0, 9));
+        // Below it each drag scrolls a line and selects to the bottom row
+        assert_eq!(drag(&mut editor, &mut state, 20), (1, 10));
+        assert_eq!(drag(&mut editor, &mut state, 20), (2, 11));
+        assert_eq!(editor.get_selection_bounds(), Some(((2, 0), (11, 3))));
+
+        // It stops once the last line is in view
+        for _ in 0..30 {
+            drag(&mut editor, &mut state, 20);
+        }
+        assert_eq!((editor.scroll_row, editor.cursor_line), (20, 29));
+
+        // Above the text it scrolls back up, past where the drag started
+        editor.scroll_row = 1;
+        assert_eq!(drag(&mut editor, &mut state, 1), (0, 0));
+        assert_eq!(editor.get_selection_bounds(), Some(((0, 3), (2, 0))));
+        assert_eq!(drag(&mut editor, &mut state, 1), (0, 0));
+    }
+
+    #[test]
+    fn test_double_click_selects_type_suffix() {
+        let mut editor = Editor::new();
+        editor.load("count% = LEN(name$) + total#");
+        let mut select_at = |col: usize| {
+            editor.cursor_col = col;
+            editor.select_word();
+            editor.get_selection_bounds()
+        };
+        assert_eq!(select_at(2), Some(((0, 0), (0, 6))));
+        // On the suffix itself
+        assert_eq!(select_at(5), Some(((0, 0), (0, 6))));
+        assert_eq!(select_at(13), Some(((0, 13), (0, 18))));
+        assert_eq!(select_at(17), Some(((0, 13), (0, 18))));
+        assert_eq!(select_at(27), Some(((0, 22), (0, 28))));
+        // Punctuation is selected on its own
+        assert_eq!(select_at(18), Some(((0, 18), (0, 19))));
+    }
+
+    #[test]
+    fn test_home_and_end_toggle_around_blanks() {
+        let mut editor = Editor::new();
+        editor.load("    PRINT x   ");
+        let mut state = AppState::new();
+        let mut press = |event: InputEvent| {
+            editor.handle_input(&event, &mut state);
+            editor.cursor_col
+        };
+        press(InputEvent::CursorRight);
+        assert_eq!(press(InputEvent::Home), 4);
+        assert_eq!(press(InputEvent::Home), 0);
+        assert_eq!(press(InputEvent::Home), 4);
+        assert_eq!(press(InputEvent::End), 11);
+        assert_eq!(press(InputEvent::End), 14);
+        assert_eq!(press(InputEvent::End), 11);
+        // Shift+Home selects back to the first non-blank
+        assert_eq!(press(InputEvent::ShiftHome), 4);
+        assert_eq!(editor.get_selection_bounds(), Some(((0, 4), (0, 11))));
+    }
+
+    #[test]
+    fn test_ctrl_arrows_stop_at_each_token() {
+        let mut editor = Editor::new();
+        editor.load("IF (a% + b) <= 10.5 THEN PRINT \"x y\"; n$ ' done\nNEXT");
+        let mut state = AppState::new();
+        let mut stops = Vec::new();
+        loop {
+            editor.handle_input(&InputEvent::CtrlRight, &mut state);
+            stops.push(editor.cursor_col);
+            if editor.cursor_line == 1 {
+                break;
+            }
+        }
+        assert_eq!(stops, vec![3, 4, 7, 9, 10, 12, 15, 20, 25, 31, 36, 38, 41, 47, 0]);
+
+        // And back again, from the end of the first line
+        editor.handle_input(&InputEvent::CtrlLeft, &mut state);
+        assert_eq!((editor.cursor_line, editor.cursor_col), (0, 47));
+        editor.handle_input(&InputEvent::CtrlLeft, &mut state);
+        assert_eq!(editor.cursor_col, 41);
+        editor.handle_input(&InputEvent::CtrlShiftLeft, &mut state);
+        editor.handle_input(&InputEvent::CtrlShiftLeft, &mut state);
+        assert_eq!(editor.get_selection_bounds(), Some(((0, 36), (0, 41))));
+    }
+
+    #[test]
+    fn test_ctrl_backspace_and_delete_remove_words() {
+        let mut editor = Editor::new();
+        editor.load("x = a% * (b + 10)\nEND");
+        let mut state = AppState::new();
+        editor.cursor_col = 17;
+        let mut press = |editor: &mut Editor, event: InputEvent| {
+            editor.handle_input(&event, &mut state);
+            editor.buffer.line(0).unwrap_or("").to_string()
+        };
+        assert_eq!(press(&mut editor, InputEvent::CtrlBackspace), "x = a% * (b + 10");
+        assert_eq!(press(&mut editor, InputEvent::CtrlBackspace), "x = a% * (b + ");
+        assert_eq!(press(&mut editor, InputEvent::CtrlBackspace), "x = a% * (b ");
+        // Each word is its own undo step
+        editor.undo();
+        assert_eq!(editor.buffer.line(0), Some("x = a% * (b + "));
+
+        editor.cursor_col = 4;
+        assert_eq!(press(&mut editor, InputEvent::CtrlDelete), "x =  * (b + ");
+        assert_eq!(press(&mut editor, InputEvent::CtrlDelete), "x =  (b + ");
+        editor.undo();
+        assert_eq!(editor.buffer.line(0), Some("x =  * (b + "));
+
+        // Past the end of the line it joins the next, and undo splits them again
+        editor.cursor_col = 12;
+        assert_eq!(press(&mut editor, InputEvent::CtrlDelete), "x =  * (b + END");
+        editor.undo();
+        assert_eq!(editor.content(), "x =  * (b + \nEND");
+
+        // Nothing to delete at either end of the buffer
+        let steps = editor.undo_stack.len();
+        editor.cursor_col = 0;
+        assert_eq!(press(&mut editor, InputEvent::CtrlBackspace), "x =  * (b + ");
+        editor.cursor_line = 1;
+        editor.cursor_col = 3;
+        press(&mut editor, InputEvent::CtrlDelete);
+        assert_eq!(editor.content(), "x =  * (b + \nEND");
+        assert_eq!(editor.undo_stack.len(), steps);
+    }
+
+    #[test]
+    fn test_format_program_reindents_and_respaces() {
+        let mut editor = Editor::new();
+        editor.load("sub greet (n$)\nprint \"hi \";n$\nend sub\nfor i=1 to 10 step -1\n      if i mod 2=0 then\nprint i*-2,( i+1 )   ' keep  this\n  else\nx=1E-5:y = -x\n end if\n  do while i>100\nwhile 0\nwend\n loop\nnext i");
+
+        assert_eq!(editor.format_program(2), Ok(true));
+        assert_eq!(
+            editor.buffer.lines,
+            [
+                "SUB greet (n$)",
+                "  PRINT \"hi \"; n$",
+                "END SUB",
+                "FOR i = 1 TO 10 STEP -1",
+                "  IF i MOD 2 = 0 THEN",
+                "    PRINT i * -2, (i + 1) ' keep  this",
+                "  ELSE",
+                "    x = 1E-5: y = -x",
+                "  END IF",
+                "  DO WHILE i > 100",
+                "    WHILE 0",
+                "    WEND",
+                "  LOOP",
+                "NEXT i",
+            ]
+        );
+        assert_eq!(editor.format_program(2), Ok(false));
+
+        // The whole reformat is a single undo step
+        assert!(editor.undo());
+        assert_eq!(editor.buffer.lines[4], "      if i mod 2=0 then");
+        assert_eq!(editor.buffer.lines[0], "sub greet (n$)");
+    }
+
+    #[test]
+    fn test_format_source_one_line_loops_select_case_and_corners() {
+        let source = "do: x = x + 1: loop until x > 3\nfor i=1 to 2: print i: next\nselect case x\ncase 1\nprint \"one\"\ncase else\nprint \"other\"\nend select\nline (0,0)-(10,10), 4\nline (0,0)-step(5,5)\ny = (x)-(i)";
+        let lines: Vec<String> = source.lines().map(str::to_string).collect();
+
+        // CASE lines sit level with SELECT, like ELSE with IF
+        assert_eq!(
+            format_source(&lines, 2),
+            [
+                "DO: x = x + 1: LOOP UNTIL x > 3",
+                "FOR i = 1 TO 2: PRINT i: NEXT",
+                "SELECT CASE x",
+                "CASE 1",
+                "  PRINT \"one\"",
+                "CASE ELSE",
+                "  PRINT \"other\"",
+                "END SELECT",
+                "LINE (0, 0)-(10, 10), 4",
+                "LINE (0, 0)-STEP(5, 5)",
+                "y = (x) - (i)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_program_refuses_a_syntax_error() {
+        let mut editor = Editor::new();
+        editor.load("print 1\nfor i=1 to\nnext");
+
+        let (line, _) = editor.format_program(4).unwrap_err();
+        assert_eq!(line, 1);
+        assert_eq!(editor.buffer.lines, ["print 1", "for i=1 to", "next"]);
+    }
+
+    #[test]
+    fn test_gutter_shows_breakpoints_and_the_current_line() {
+        let mut state = AppState::new();
+        state.toggle_breakpoint(1);
+        state.toggle_breakpoint(2);
+        state.current_line = Some(2);
+        let markers: Vec<LineMarkers> = (0..4).map(|line| LineMarkers::for_line(&state, line)).collect();
+        assert_eq!(markers[0], LineMarkers::default());
+        assert_eq!(markers[1], LineMarkers { current: false, breakpoint: true });
+        assert_eq!(markers[2], LineMarkers { current: true, breakpoint: true });
+
+        state.current_line = Some(3);
+        assert_eq!(LineMarkers::for_line(&state, 3), LineMarkers { current: true, breakpoint: false });
+
+        let bg = Color::Blue;
+        assert_eq!(LineMarkers::default().gutter(bg), None);
+        assert_eq!(LineMarkers { current: false, breakpoint: true }.gutter(bg), Some(('\u{25CF}', Color::LightRed, bg)));
+        assert_eq!(LineMarkers { current: true, breakpoint: false }.gutter(bg), Some(('\u{25BA}', Color::Yellow, bg)));
+        // Both at once: the arrow on the breakpoint's red
+        assert_eq!(LineMarkers { current: true, breakpoint: true }.gutter(bg), Some(('\u{25BA}', Color::Yellow, Color::Red)));
+
+        // Drawn in the left border, with the current line highlighted
+        let mut editor = Editor::new();
+        editor.load("PRINT 1\nPRINT 2\nPRINT 3\nPRINT 4");
+        state.current_line = Some(2);
+        let mut screen = Screen::new(40, 12);
+        editor.draw(&mut screen, &state, Rect::new(0, 0, 40, 10));
+        let cell = |row: usize, col: u16| screen.get(2 + row as u16, col).unwrap();
+        assert_eq!(cell(1, 1).ch, '\u{25CF}');
+        assert_eq!((cell(2, 1).ch, cell(2, 1).bg), ('\u{25BA}', Color::Red));
+        assert_eq!(cell(2, 2).bg, Color::Cyan);
+        assert_eq!(cell(1, 2).bg, Color::Red);
+    }
+
+    #[test]
+    fn test_gutter_click_toggles_breakpoint() {
+        let mut editor = Editor::new();
+        editor.load(&(0..30).map(|n| format!("PRINT {}", n)).collect::<Vec<_>>().join("\n"));
+        editor.scroll_row = 10;
+        let mut state = AppState::new();
+        let bounds = Rect::new(0, 1, 40, 10);
+        // Text rows start at screen row 3 inside the border; the gutter is column 1
+        let click = InputEvent::MouseClick { row: 5, col: 1 };
+
+        editor.handle_event(&click, &mut state, bounds);
+        assert!(state.has_breakpoint(12));
+        assert_eq!(state.breakpoints.len(), 1);
+        assert_eq!(editor.cursor_line, 0, "a gutter click does not move the cursor");
+
+        editor.handle_event(&click, &mut state, bounds);
+        assert!(state.breakpoints.is_empty());
+    }
+}
@@ -14,6 +14,8 @@ pub enum EditorClickAction {
     None,
     /// Click in content area at (line, col) relative to scroll position
     ContentClick { editor_y: usize, editor_x: usize },
+    /// Click in the left-border gutter beside a line, relative to scroll position
+    GutterClick { editor_y: usize },
     /// Vertical scroll action
     VScroll(ScrollAction),
     /// Horizontal scroll action
@@ -33,6 +35,7 @@ pub enum EditorClickAction {
 /// The editor has a border, vertical scrollbar on right, horizontal scrollbar on bottom.
 /// Layout: [border][content][vscroll][border]
 ///         [border][hscroll area     ][border]
+/// The left border beside the text doubles as the breakpoint gutter.
 pub fn handle_editor_click(
     row: u16,
     col: u16,
@@ -104,6 +107,11 @@ pub fn handle_editor_click(
         return EditorClickAction::ContentClick { editor_y, editor_x };
     }
 
+    // Check the gutter (left border beside the content rows)
+    if col == editor_col && row >= content_top && row < content_bottom {
+        return EditorClickAction::GutterClick { editor_y: (row - content_top) as usize };
+    }
+
     EditorClickAction::None
 }
 
@@ -185,4 +193,22 @@ mod tests {
             other => panic!("Expected VScroll or StartVDrag action, got {:?}", other),
         }
     }
+
+    #[test]
+    fn test_gutter_click() {
+        let rect = Rect::new(0, 0, 80, 25);
+        // Left border column, fourth text row
+        let action = handle_editor_click(
+            5, 1,
+            rect,
+            0, 0,
+            100, 80,
+            20, 70,
+        );
+        assert_eq!(action, EditorClickAction::GutterClick { editor_y: 3 });
+
+        // The border corner above the text is not part of the gutter
+        let action = handle_editor_click(1, 1, rect, 0, 0, 100, 80, 20, 70);
+        assert_eq!(action, EditorClickAction::None);
+    }
 }
@@ -1,18 +1,26 @@
 //! QBasic Immediate window - a scratchpad for executing BASIC statements
 //!
 //! Works like real QBasic: a small editor where pressing Enter executes
-//! the current line instead of inserting a newline.
+//! the current line instead of inserting a newline. Up and Down recall
+//! earlier commands into the current line, like a shell, and Tab completes
+//! keywords and variable names.
 #![allow(dead_code)]
 
+use std::collections::VecDeque;
+
 use crate::screen::Screen;
 use crate::terminal::Color;
 use crate::state::AppState;
+use super::editor::KEYWORDS;
 use super::layout::{Rect, LayoutItem};
 use super::window_chrome;
 
 /// Maximum number of lines in the immediate window
 const MAX_LINES: usize = 10;
 
+/// Maximum number of commands remembered for Up/Down
+const MAX_HISTORY: usize = 50;
+
 /// The immediate window - a scratchpad editor where Enter executes the current line
 pub struct ImmediateWindow {
     /// Lines of code (up to MAX_LINES)
@@ -25,6 +33,17 @@ pub struct ImmediateWindow {
     scroll_x: usize,
     /// Scroll offset for vertical scrolling (when more than visible lines)
     scroll_y: usize,
+    /// Executed commands, oldest first
+    history: VecDeque<String>,
+    /// History entry shown in the current line, or None for the line being typed
+    history_pos: Option<usize>,
+    /// The line being typed, kept while browsing history
+    draft: String,
+    /// Variable names offered by Tab completion alongside the keywords
+    variables: Vec<String>,
+    /// Completion being cycled by repeated Tab: (column the word starts at,
+    /// matching words, index of the one shown)
+    completion: Option<(usize, Vec<String>, usize)>,
 }
 
 impl ImmediateWindow {
@@ -35,7 +54,100 @@ impl ImmediateWindow {
             cursor_col: 0,
             scroll_x: 0,
             scroll_y: 0,
+            history: VecDeque::new(),
+            history_pos: None,
+            draft: String::new(),
+            variables: Vec::new(),
+            completion: None,
+        }
+    }
+
+    /// Set the variable names offered by Tab completion
+    pub fn set_variables(&mut self, names: Vec<String>) {
+        self.variables = names;
+    }
+
+    /// Complete the word before the cursor against the keywords and
+    /// variables. Repeated Tabs cycle through the matches; with no match the
+    /// line is left alone.
+    fn complete_word(&mut self) {
+        let (start, matches, index) = match self.completion.take() {
+            Some((start, matches, index)) => {
+                let next = (index + 1) % matches.len();
+                (start, matches, next)
+            }
+            None => {
+                let chars: Vec<char> = self.current_line().chars().collect();
+                let end = self.cursor_col.min(chars.len());
+                let mut start = end;
+                while start > 0 && (chars[start - 1].is_alphanumeric() || "$%&!#.".contains(chars[start - 1])) {
+                    start -= 1;
+                }
+                let prefix: String = chars[start..end].iter().collect::<String>().to_uppercase();
+                if prefix.is_empty() {
+                    return;
+                }
+                let mut matches: Vec<String> = KEYWORDS
+                    .iter()
+                    .map(|k| k.to_string())
+                    .chain(self.variables.iter().cloned())
+                    .filter(|word| word.to_uppercase().starts_with(&prefix))
+                    .collect();
+                matches.sort();
+                matches.dedup();
+                if matches.is_empty() {
+                    return;
+                }
+                (start, matches, 0)
+            }
+        };
+
+        let cursor_col = self.cursor_col;
+        let word = matches[index].clone();
+        let line = self.current_line_mut();
+        let byte_start: usize = line.chars().take(start).map(|c| c.len_utf8()).sum();
+        let byte_end: usize = line.chars().take(cursor_col).map(|c| c.len_utf8()).sum();
+        line.replace_range(byte_start..byte_end, &word);
+        self.cursor_col = start + word.chars().count();
+        self.completion = Some((start, matches, index));
+    }
+
+    /// Remember an executed command, unless it repeats the previous one
+    fn add_to_history(&mut self, command: &str) {
+        if self.history.back().is_some_and(|last| last == command) {
+            return;
+        }
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
         }
+        self.history.push_back(command.to_string());
+    }
+
+    /// Step through the history (back for Up, forward for Down) and put the
+    /// entry in the current line. Going past either end wraps round through
+    /// the line that was being typed.
+    fn recall_history(&mut self, back: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+        if self.history_pos.is_none() {
+            self.draft = self.current_line().to_string();
+        }
+        let newest = self.history.len() - 1;
+        self.history_pos = match self.history_pos {
+            None if back => Some(newest),
+            None => Some(0),
+            Some(0) if back => None,
+            Some(pos) if back => Some(pos - 1),
+            Some(pos) if pos == newest => None,
+            Some(pos) => Some(pos + 1),
+        };
+        let text = match self.history_pos {
+            Some(pos) => self.history[pos].clone(),
+            None => std::mem::take(&mut self.draft),
+        };
+        self.cursor_col = text.chars().count();
+        *self.current_line_mut() = text;
     }
 
     /// Get the current line
@@ -52,6 +164,26 @@ impl ImmediateWindow {
         &mut self.lines[self.cursor_line]
     }
 
+    /// Insert a character at the cursor
+    fn insert_char(&mut self, c: char) {
+        let cursor_col = self.cursor_col;
+        let line = self.current_line_mut();
+        let byte_pos: usize = line.chars().take(cursor_col).map(|c| c.len_utf8()).sum();
+        line.insert(byte_pos, c);
+        self.cursor_col += 1;
+    }
+
+    /// Break the current line at the cursor, moving to the start of the new line
+    fn split_line(&mut self) {
+        let cursor_col = self.cursor_col;
+        let line = self.current_line_mut();
+        let byte_pos: usize = line.chars().take(cursor_col).map(|c| c.len_utf8()).sum();
+        let rest = line.split_off(byte_pos);
+        self.cursor_line += 1;
+        self.lines.insert(self.cursor_line, rest);
+        self.cursor_col = 0;
+    }
+
     /// Ensure cursor is within valid bounds
     fn clamp_cursor(&mut self) {
         if self.lines.is_empty() {
@@ -79,6 +211,13 @@ impl ImmediateWindow {
         }
     }
 
+    /// Scroll the lines by `delta` (negative is up) without scrolling past
+    /// the last line
+    fn scroll_by(&mut self, delta: isize, visible_height: usize) {
+        let max_scroll = self.lines.len().saturating_sub(visible_height);
+        self.scroll_y = self.scroll_y.saturating_add_signed(delta).min(max_scroll);
+    }
+
     /// Draw the immediate window
     pub fn draw(&self, screen: &mut Screen, state: &AppState, bounds: Rect, has_focus: bool) {
         let row = bounds.y + 1; // 1-based row
@@ -130,15 +269,14 @@ impl ImmediateWindow {
 
         // Set cursor position if focused
         if has_focus {
-            let cursor_screen_row = content_row + (self.cursor_line - self.scroll_y) as u16;
-            let cursor_screen_col = content_col + (self.cursor_col - self.scroll_x) as u16;
-
             // Only show cursor if within visible area
             if self.cursor_line >= self.scroll_y
                 && self.cursor_line < self.scroll_y + content_height
                 && self.cursor_col >= self.scroll_x
                 && self.cursor_col < self.scroll_x + content_width
             {
+                let cursor_screen_row = content_row + (self.cursor_line - self.scroll_y) as u16;
+                let cursor_screen_col = content_col + (self.cursor_col - self.scroll_x) as u16;
                 screen.set_cursor(cursor_screen_row, cursor_screen_col);
                 screen.set_cursor_style(crate::terminal::CursorStyle::BlinkingUnderline);
                 screen.set_cursor_visible(true);
@@ -154,25 +292,49 @@ impl ImmediateWindow {
         let content_height = bounds.height.saturating_sub(4) as usize; // Account for borders
         let content_width = bounds.width.saturating_sub(4) as usize;
 
+        if *event != InputEvent::Tab {
+            self.completion = None;
+        }
+
         match event {
+            InputEvent::Tab => {
+                self.complete_word();
+                self.ensure_visible(content_height, content_width);
+                None
+            }
             InputEvent::Char(c) => {
-                // Insert character at cursor
-                let cursor_col = self.cursor_col;
-                let line = self.current_line_mut();
-                let byte_pos: usize = line.chars().take(cursor_col).map(|c| c.len_utf8()).sum();
-                line.insert(byte_pos, *c);
-                self.cursor_col += 1;
+                self.insert_char(*c);
+                self.ensure_visible(content_height, content_width);
+                None
+            }
+            InputEvent::Paste(text) => {
+                // Pasted lines are entered like typed ones, each waiting for
+                // Enter to run it
+                for (i, part) in text.lines().enumerate() {
+                    if i > 0 {
+                        self.split_line();
+                    }
+                    part.chars().filter(|c| !c.is_control()).for_each(|c| self.insert_char(c));
+                }
+                while self.lines.len() > MAX_LINES {
+                    self.lines.remove(0);
+                    self.cursor_line = self.cursor_line.saturating_sub(1);
+                }
                 self.ensure_visible(content_height, content_width);
                 None
             }
             InputEvent::Enter => {
                 // Execute the current line
                 let line = self.current_line().trim().to_string();
+                self.history_pos = None;
+                self.draft.clear();
                 if !line.is_empty() {
+                    self.add_to_history(&line);
                     // Move to next line or create one
                     if self.cursor_line + 1 >= self.lines.len() {
                         self.lines.push(String::new());
                     }
+                    self.cursor_line += 1;
 
                     // Enforce MAX_LINES limit - remove oldest line if needed
                     while self.lines.len() > MAX_LINES {
@@ -252,18 +414,12 @@ impl ImmediateWindow {
                 None
             }
             InputEvent::CursorUp => {
-                if self.cursor_line > 0 {
-                    self.cursor_line -= 1;
-                    self.clamp_cursor();
-                }
+                self.recall_history(true);
                 self.ensure_visible(content_height, content_width);
                 None
             }
             InputEvent::CursorDown => {
-                if self.cursor_line + 1 < self.lines.len() {
-                    self.cursor_line += 1;
-                    self.clamp_cursor();
-                }
+                self.recall_history(false);
                 self.ensure_visible(content_height, content_width);
                 None
             }
@@ -406,10 +562,19 @@ impl MainWidget for ImmediateWindow {
     }
 
     fn handle_scroll(&mut self, event: &crate::input::InputEvent, bounds: Rect) -> WidgetAction {
-        if event_in_bounds(event, bounds) {
-            return WidgetAction::Consumed;
+        use crate::input::InputEvent;
+
+        if !event_in_bounds(event, bounds) {
+            return WidgetAction::Ignored;
         }
-        WidgetAction::Ignored
+
+        let content_height = bounds.height.saturating_sub(2) as usize;
+        match event {
+            InputEvent::ScrollUp { .. } => self.scroll_by(-1, content_height),
+            InputEvent::ScrollDown { .. } => self.scroll_by(1, content_height),
+            _ => return WidgetAction::Ignored,
+        }
+        WidgetAction::Consumed
     }
 
     fn focusable(&self) -> bool {
@@ -420,3 +585,147 @@ impl MainWidget for ImmediateWindow {
         Some(Focus::Immediate)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputEvent;
+
+    fn type_command(window: &mut ImmediateWindow, text: &str) -> Option<String> {
+        let bounds = Rect::new(0, 0, 60, 8);
+        for c in text.chars() {
+            window.handle_input(&InputEvent::Char(c), bounds);
+        }
+        window.handle_input(&InputEvent::Enter, bounds)
+    }
+
+    fn press(window: &mut ImmediateWindow, event: InputEvent) -> String {
+        window.handle_input(&event, Rect::new(0, 0, 60, 8));
+        window.current_line().to_string()
+    }
+
+    #[test]
+    fn test_paste_enters_lines_without_running_them() {
+        let mut window = ImmediateWindow::new();
+        press(&mut window, InputEvent::Char('X'));
+        press(&mut window, InputEvent::CursorLeft);
+        let pasted = press(&mut window, InputEvent::Paste("PRINT 1\r\nPRINT 2\t".to_string()));
+        assert_eq!(window.lines, vec!["PRINT 1", "PRINT 2X"]);
+        assert_eq!(pasted, "PRINT 2X");
+        assert_eq!((window.cursor_line, window.cursor_col), (1, 7));
+
+        // Enter runs the line the paste ended on
+        window.handle_input(&InputEvent::End, Rect::new(0, 0, 60, 8));
+        assert_eq!(type_command(&mut window, ""), Some("PRINT 2X".to_string()));
+    }
+
+    #[test]
+    fn test_history_navigation_wraps() {
+        let mut window = ImmediateWindow::new();
+        type_command(&mut window, "PRINT 1");
+        type_command(&mut window, "PRINT 2");
+        window.handle_input(&InputEvent::Char('X'), Rect::new(0, 0, 60, 8));
+
+        assert_eq!(press(&mut window, InputEvent::CursorUp), "PRINT 2");
+        assert_eq!(press(&mut window, InputEvent::CursorUp), "PRINT 1");
+        // Past the oldest command comes back round to what was being typed
+        assert_eq!(press(&mut window, InputEvent::CursorUp), "X");
+        assert_eq!(press(&mut window, InputEvent::CursorUp), "PRINT 2");
+        assert_eq!(press(&mut window, InputEvent::CursorDown), "X");
+        assert_eq!(press(&mut window, InputEvent::CursorDown), "PRINT 1");
+    }
+
+    #[test]
+    fn test_history_skips_repeats_and_keeps_edits() {
+        let mut window = ImmediateWindow::new();
+        type_command(&mut window, "PRINT 1");
+        type_command(&mut window, "PRINT 1");
+        assert_eq!(window.history, vec!["PRINT 1"]);
+
+        // Recall, edit and run: the edited command is added
+        press(&mut window, InputEvent::CursorUp);
+        assert_eq!(type_command(&mut window, "0"), Some("PRINT 10".to_string()));
+        assert_eq!(window.history, vec!["PRINT 1", "PRINT 10"]);
+    }
+
+    #[test]
+    fn test_tab_completes_a_single_keyword() {
+        let mut window = ImmediateWindow::new();
+        press(&mut window, InputEvent::Char('s'));
+        press(&mut window, InputEvent::Char('w'));
+        assert_eq!(press(&mut window, InputEvent::Tab), "SWAP");
+        assert_eq!(window.cursor_col, 4);
+        // The only match stays put on another Tab
+        assert_eq!(press(&mut window, InputEvent::Tab), "SWAP");
+
+        // No match leaves the text unchanged
+        press(&mut window, InputEvent::Char(' '));
+        press(&mut window, InputEvent::Char('q'));
+        assert_eq!(press(&mut window, InputEvent::Tab), "SWAP q");
+    }
+
+    #[test]
+    fn test_tab_cycles_through_keywords_and_variables() {
+        let mut window = ImmediateWindow::new();
+        window.set_variables(vec!["LOOPS".to_string(), "total".to_string()]);
+        for c in "PRINT loo".chars() {
+            window.handle_input(&InputEvent::Char(c), Rect::new(0, 0, 60, 8));
+        }
+        assert_eq!(press(&mut window, InputEvent::Tab), "PRINT LOOP");
+        assert_eq!(press(&mut window, InputEvent::Tab), "PRINT LOOPS");
+        assert_eq!(press(&mut window, InputEvent::Tab), "PRINT LOOP");
+
+        // Typing ends the cycle and starts a fresh completion
+        press(&mut window, InputEvent::Char(' '));
+        press(&mut window, InputEvent::Char('t'));
+        press(&mut window, InputEvent::Char('o'));
+        assert_eq!(press(&mut window, InputEvent::Tab), "PRINT LOOP TO");
+        assert_eq!(press(&mut window, InputEvent::Tab), "PRINT LOOP total");
+    }
+
+    #[test]
+    fn test_wheel_scrolling_is_clamped() {
+        let mut window = ImmediateWindow::new();
+        for i in 0..6 {
+            type_command(&mut window, &format!("PRINT {}", i));
+        }
+        // Four content rows inside the border; seven lines with the new one
+        let bounds = Rect::new(0, 0, 60, 6);
+        let wheel = |window: &mut ImmediateWindow, up: bool| {
+            let event = if up { InputEvent::ScrollUp { row: 3, col: 10 } } else { InputEvent::ScrollDown { row: 3, col: 10 } };
+            assert!(matches!(window.handle_scroll(&event, bounds), WidgetAction::Consumed));
+            window.scroll_y
+        };
+        window.scroll_y = 0;
+        assert_eq!(wheel(&mut window, true), 0);
+        assert_eq!(wheel(&mut window, false), 1);
+        assert_eq!(wheel(&mut window, false), 2);
+        assert_eq!(wheel(&mut window, false), 3);
+        assert_eq!(wheel(&mut window, false), 3);
+        assert_eq!(wheel(&mut window, true), 2);
+
+        // Outside the window the wheel is left for others
+        let event = InputEvent::ScrollUp { row: 20, col: 10 };
+        assert!(matches!(window.handle_scroll(&event, bounds), WidgetAction::Ignored));
+    }
+
+    #[test]
+    fn test_draw_after_scrolling_past_the_cursor() {
+        let mut window = ImmediateWindow::new();
+        for i in 0..6 {
+            type_command(&mut window, &format!("PRINT {}", i));
+        }
+        window.cursor_line = 0;
+        let bounds = Rect::new(0, 0, 60, 6);
+        for _ in 0..3 {
+            window.handle_scroll(&InputEvent::ScrollDown { row: 3, col: 10 }, bounds);
+        }
+        assert!(window.scroll_y > window.cursor_line);
+
+        // The cursor is above the view, so it isn't placed
+        let mut screen = Screen::new(60, 6);
+        let unplaced = screen.cursor();
+        window.draw(&mut screen, &AppState::new(), bounds, true);
+        assert_eq!(screen.cursor(), unplaced);
+    }
+}
@@ -101,6 +101,7 @@ impl MenuBar {
                     .item("Open Program...", Some(""))
                     .item("Save", Some(""))
                     .item("Save As...", Some(""))
+                    .item("Save Output...", Some(""))
                     .separator()
                     .item("Print...", Some(""))
                     .separator()
@@ -115,10 +116,13 @@ impl MenuBar {
                     .item("Clear", Some("Del"))
                     .separator()
                     .item("New SUB...", Some(""))
-                    .item("New FUNCTION...", Some("")),
+                    .item("New FUNCTION...", Some(""))
+                    .separator()
+                    .item("Format Program", Some("")),
 
                 Menu::new("View", 'V')
                     .item("SUBs...", Some("F2"))
+                    .item("Definition", Some("Shift+F2"))
                     .item("Next Statement", Some(""))
                     .item("Output Screen", Some("F4"))
                     .separator()
@@ -143,6 +147,7 @@ impl MenuBar {
                     .item("Procedure Step", Some("F10"))
                     .separator()
                     .item("Toggle Breakpoint", Some("F9"))
+                    .item("Breakpoint Condition...", Some(""))
                     .item("Clear All Breakpoints", Some(""))
                     .separator()
                     .item("Set Next Statement", Some("")),
@@ -150,7 +155,8 @@ impl MenuBar {
                 Menu::new("Options", 'O')
                     .item("Display...", Some(""))
                     .item("Help Path...", Some(""))
-                    .item("Syntax Checking", Some("")),
+                    .item("Syntax Checking", Some(""))
+                    .item("DOS Line Endings", Some("")),
 
                 Menu::new("Help", 'H')
                     .item("Index", Some(""))
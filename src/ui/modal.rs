@@ -8,6 +8,7 @@
 use std::path::PathBuf;
 use crate::input::InputEvent;
 use crate::screen::Screen;
+use crate::ui::dialogs::ConfirmChoice;
 
 /// Result of handling an event in a modal dialog
 pub enum ModalResult {
@@ -29,7 +30,9 @@ pub enum ModalAction {
     /// Help topic requested
     Help(String),
     /// Generic confirmation (Yes/No/Cancel dialogs)
-    Confirm(bool),
+    Confirm(ConfirmChoice),
+    /// Move the editor cursor to a 1-based line
+    GoToLine(usize),
 }
 
 /// A modal dialog that captures all events when open
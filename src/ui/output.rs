@@ -6,6 +6,9 @@ use crate::state::AppState;
 use crate::basic::graphics::GraphicsMode;
 use super::layout::{Rect, LayoutItem, compute_layout};
 
+/// Default number of output lines kept for scrollback
+pub const DEFAULT_SCROLLBACK: usize = 10_000;
+
 /// The output window for program execution (black background, white text)
 pub struct OutputWindow {
     /// Output lines
@@ -20,7 +23,7 @@ impl OutputWindow {
     pub fn new() -> Self {
         Self {
             output: Vec::new(),
-            max_output: 1000,
+            max_output: DEFAULT_SCROLLBACK,
             scroll: 0,
         }
     }
@@ -63,11 +66,7 @@ impl OutputWindow {
         let total_lines = self.output.len();
 
         // Calculate which lines to show based on scroll position
-        let start_line = if total_lines > visible_lines {
-            total_lines - visible_lines - self.scroll
-        } else {
-            0
-        };
+        let start_line = total_lines.saturating_sub(visible_lines + self.scroll);
 
         for (i, line) in self.output.iter().skip(start_line).take(visible_lines).enumerate() {
             let display = if line.len() > content_width as usize {
@@ -102,11 +101,7 @@ impl OutputWindow {
         let total_lines = self.output.len();
 
         // Calculate which lines to show based on scroll position
-        let start_line = if total_lines > visible_lines {
-            total_lines - visible_lines - self.scroll
-        } else {
-            0
-        };
+        let start_line = total_lines.saturating_sub(visible_lines + self.scroll);
 
         for (i, line) in self.output.iter().skip(start_line).take(visible_lines).enumerate() {
             let display = if line.len() > width as usize {
@@ -119,7 +114,7 @@ impl OutputWindow {
 
         // Show status at bottom if program completed
         if state.run_state == crate::state::RunState::Finished {
-            let msg = " Press any key to continue ";
+            let msg = " PgUp/PgDn to scroll, Ctrl+S to save, Enter to continue ";
             // Draw on last line with highlight
             let msg_x = (width.saturating_sub(msg.len() as u16)) / 2 + 1;
             screen.write_str(height, msg_x, msg, Color::Black, Color::White);
@@ -163,14 +158,14 @@ impl OutputWindow {
             for row in 1..=term_height.min(graphics.text_rows) {
                 for col in 1..=term_width.min(graphics.text_cols) {
                     let cell = graphics.get_char(row, col);
-                    let fg = dos_to_color(cell.fg);
+                    let fg = dos_to_color(graphics.display_attr(cell.fg));
                     // Use black background for text overlay
                     screen.set(row, col, cell.char, fg, Color::Black);
                 }
             }
 
             // Show cursor if waiting for input
-            if state.run_state == crate::state::RunState::WaitingForInput {
+            if state.run_state == crate::state::RunState::WaitingForInput && graphics.cursor_visible {
                 screen.set_cursor(graphics.cursor_row, graphics.cursor_col);
                 screen.set_cursor_visible(true);
             } else {
@@ -179,14 +174,18 @@ impl OutputWindow {
             return;
         }
 
-        // Text mode: Render each cell of the graphics text screen
-        // The buffer should be sized to match the terminal, so this covers everything
+        // Text mode: Render each cell of the graphics text screen. It is
+        // sized to the terminal unless WIDTH set a smaller one, which is
+        // centred.
+        let left = term_width.saturating_sub(graphics.text_cols) / 2;
+        let top = term_height.saturating_sub(graphics.text_rows) / 2;
         for row in 1..=term_height {
             for col in 1..=term_width {
-                if row <= graphics.text_rows && col <= graphics.text_cols {
-                    let cell = graphics.get_char(row, col);
-                    let fg = dos_to_color(cell.fg);
-                    let bg = dos_to_color(cell.bg);
+                let (text_row, text_col) = (row.saturating_sub(top), col.saturating_sub(left));
+                if (1..=graphics.text_rows).contains(&text_row) && (1..=graphics.text_cols).contains(&text_col) {
+                    let cell = graphics.get_char(text_row, text_col);
+                    let fg = dos_to_color(graphics.display_attr(cell.fg));
+                    let bg = dos_to_color(graphics.display_attr(cell.bg));
                     screen.set(row, col, cell.char, fg, bg);
                 } else {
                     // Fill any extra space with black
@@ -197,14 +196,14 @@ impl OutputWindow {
 
         // Show status at bottom if program completed
         if state.run_state == crate::state::RunState::Finished {
-            let msg = " Press any key to continue ";
+            let msg = " Press Enter to continue, Ctrl+S to save output ";
             let msg_x = (term_width.saturating_sub(msg.len() as u16)) / 2 + 1;
             screen.write_str(term_height, msg_x, msg, Color::Black, Color::White);
         }
 
         // Show cursor if waiting for input (in text mode too)
-        if state.run_state == crate::state::RunState::WaitingForInput {
-            screen.set_cursor(graphics.cursor_row, graphics.cursor_col);
+        if state.run_state == crate::state::RunState::WaitingForInput && graphics.cursor_visible {
+            screen.set_cursor(graphics.cursor_row + top, graphics.cursor_col + left);
             screen.set_cursor_visible(true);
         } else {
             screen.set_cursor_visible(false);
@@ -214,31 +213,64 @@ impl OutputWindow {
     /// Add output line
     pub fn add_output(&mut self, line: &str) {
         self.output.push(line.to_string());
-        while self.output.len() > self.max_output {
-            self.output.remove(0);
-        }
+        self.trim_to_max();
         // Reset scroll to show latest
         self.scroll = 0;
     }
 
+    /// Change how many lines of scrollback are kept, dropping the oldest
+    /// lines if there are now too many
+    pub fn set_max_output(&mut self, max_output: usize) {
+        self.max_output = max_output;
+        self.trim_to_max();
+    }
+
+    fn trim_to_max(&mut self) {
+        let excess = self.output.len().saturating_sub(self.max_output);
+        self.output.drain(..excess);
+    }
+
+    /// The captured output as text, one line per output line
+    pub fn to_text(&self) -> String {
+        self.output.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
     /// Clear output
     pub fn clear(&mut self) {
         self.output.clear();
         self.scroll = 0;
     }
 
-    /// Scroll up
-    #[allow(dead_code)]
-    pub fn scroll_up(&mut self, lines: usize) {
-        let max_scroll = self.output.len().saturating_sub(10); // Leave at least 10 lines visible
+    /// Scroll up towards older output, stopping once the first line is at
+    /// the top of `visible_lines` rows
+    pub fn scroll_up(&mut self, lines: usize, visible_lines: usize) {
+        let max_scroll = self.output.len().saturating_sub(visible_lines);
         self.scroll = (self.scroll + lines).min(max_scroll);
     }
 
-    /// Scroll down
-    #[allow(dead_code)]
+    /// Scroll down towards the latest output
     pub fn scroll_down(&mut self, lines: usize) {
         self.scroll = self.scroll.saturating_sub(lines);
     }
+
+    /// Scroll the captured output with the keyboard: arrows by a line,
+    /// PageUp/PageDown by a screen, Home/End to either end. Returns whether
+    /// the key was a scrolling key.
+    pub fn handle_scroll_key(&mut self, event: &crate::input::InputEvent, visible_lines: usize) -> bool {
+        use crate::input::InputEvent;
+
+        let page = visible_lines.saturating_sub(1).max(1);
+        match event {
+            InputEvent::CursorUp => self.scroll_up(1, visible_lines),
+            InputEvent::CursorDown => self.scroll_down(1),
+            InputEvent::PageUp => self.scroll_up(page, visible_lines),
+            InputEvent::PageDown => self.scroll_down(page),
+            InputEvent::Home | InputEvent::CtrlHome => self.scroll_up(self.output.len(), visible_lines),
+            InputEvent::End | InputEvent::CtrlEnd => self.scroll = 0,
+            _ => return false,
+        }
+        true
+    }
 }
 
 impl Default for OutputWindow {
@@ -362,7 +394,7 @@ impl MainWidget for OutputWindow {
 
         match event {
             InputEvent::ScrollUp { .. } => {
-                self.scroll_up(3);
+                self.scroll_up(3, bounds.height.saturating_sub(2) as usize);
                 WidgetAction::Consumed
             }
             InputEvent::ScrollDown { .. } => {
@@ -381,3 +413,67 @@ impl MainWidget for OutputWindow {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputEvent;
+
+    fn window_with_lines(count: usize) -> OutputWindow {
+        let mut window = OutputWindow::new();
+        for i in 0..count {
+            window.add_output(&format!("line {}", i));
+        }
+        window
+    }
+
+    #[test]
+    fn test_scroll_clamps_at_both_ends() {
+        let mut window = window_with_lines(30);
+
+        // Can't scroll past the latest output
+        window.handle_scroll_key(&InputEvent::CursorDown, 10);
+        assert_eq!(window.scroll, 0);
+
+        // Or further back than the first line being at the top
+        assert!(window.handle_scroll_key(&InputEvent::PageUp, 10));
+        assert_eq!(window.scroll, 9);
+        window.handle_scroll_key(&InputEvent::PageUp, 10);
+        window.handle_scroll_key(&InputEvent::PageUp, 10);
+        assert_eq!(window.scroll, 20);
+        window.handle_scroll_key(&InputEvent::CursorUp, 10);
+        assert_eq!(window.scroll, 20);
+
+        window.handle_scroll_key(&InputEvent::PageDown, 10);
+        assert_eq!(window.scroll, 11);
+        window.handle_scroll_key(&InputEvent::End, 10);
+        assert_eq!(window.scroll, 0);
+        window.handle_scroll_key(&InputEvent::Home, 10);
+        assert_eq!(window.scroll, 20);
+        assert!(!window.handle_scroll_key(&InputEvent::Enter, 10));
+
+        // Output shorter than the window doesn't scroll at all
+        let mut short = window_with_lines(5);
+        short.handle_scroll_key(&InputEvent::PageUp, 10);
+        assert_eq!(short.scroll, 0);
+    }
+
+    #[test]
+    fn test_output_text_has_a_newline_per_line() {
+        assert_eq!(OutputWindow::new().to_text(), "");
+        let mut window = window_with_lines(2);
+        window.add_output("");
+        assert_eq!(window.to_text(), "line 0\nline 1\n\n");
+    }
+
+    #[test]
+    fn test_scrollback_cap_drops_oldest_lines() {
+        let mut window = window_with_lines(30);
+        window.set_max_output(25);
+        assert_eq!(window.output.len(), 25);
+        assert_eq!(window.output[0], "line 5");
+        window.add_output("new");
+        assert_eq!(window.output.len(), 25);
+        assert_eq!(window.output[0], "line 6");
+    }
+}
@@ -5,6 +5,12 @@ use crate::terminal::Color;
 use crate::state::{AppState, EditorMode, RunState};
 use super::layout::Rect;
 
+/// File name with a `*` when there are unsaved changes, then the line
+/// endings it will be saved with
+pub fn file_info(name: &str, modified: bool, crlf: bool) -> String {
+    format!("{}{} {}", name, if modified { "*" } else { "" }, if crlf { "CRLF" } else { "LF" })
+}
+
 /// The status bar at the bottom of the screen (stateless)
 pub struct StatusBar;
 
@@ -27,8 +33,13 @@ impl StatusBar {
         // F1=Help shortcut on left
         screen.write_str(row, col, " <F1=Help>", Color::White, Color::Cyan);
 
-        // Status message or run state in center
-        let center_text = if let Some(msg) = &state.status_message {
+        // Syntax error on the cursor line, status message or run state in center
+        let syntax_error = state.syntax_errors.iter()
+            .find(|(line, _)| *line == cursor_line)
+            .filter(|_| state.run_state == RunState::Editing);
+        let center_text = if let Some((_, msg)) = syntax_error {
+            msg.clone()
+        } else if let Some(msg) = &state.status_message {
             msg.clone()
         } else {
             match state.run_state {
@@ -62,6 +73,17 @@ impl StatusBar {
         screen.draw_vrule(row, sep_x, Color::White, Color::Cyan);
 
         screen.write_str(row, right_x, &right_text, Color::White, Color::Cyan);
+
+        // File name, modified flag and line endings left of the separator
+        let name = state.file_path.as_ref()
+            .and_then(|p| p.file_name())
+            .and_then(|s| s.to_str())
+            .unwrap_or("Untitled");
+        let info = file_info(name, state.modified, state.crlf_line_endings);
+        let info_x = sep_x.saturating_sub(info.len() as u16 + 2);
+        if info_x > col + 10 {
+            screen.write_str(row, info_x, &info, Color::White, Color::Cyan);
+        }
     }
 
     /// Draw the function key bar (optional, at very bottom)
@@ -101,3 +123,14 @@ impl Default for StatusBar {
 
 // Note: StatusBar does NOT implement MainWidget because it needs cursor info from Editor.
 // It is drawn specially by the Widgets container which has access to both Editor and StatusBar.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_info() {
+        assert_eq!(file_info("HELLO.BAS", false, true), "HELLO.BAS CRLF");
+        assert_eq!(file_info("HELLO.BAS", true, false), "HELLO.BAS* LF");
+    }
+}
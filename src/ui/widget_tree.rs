@@ -525,6 +525,16 @@ impl WidgetTree {
         self.root.get_widget_mut(path)
     }
 
+    /// Move focus to the first focusable widget
+    pub fn focus_first(&mut self) {
+        let paths = self.root.collect_focusable(&[]);
+        if paths.is_empty() {
+            return;
+        }
+        let current_idx = paths.iter().position(|p| *p == self.focus_path);
+        self.update_focus(current_idx, 0, &paths);
+    }
+
     /// Move focus to the next focusable widget
     pub fn focus_next(&mut self) {
         let paths = self.root.collect_focusable(&[]);
@@ -49,6 +49,8 @@ pub struct TextField {
     focused: bool,
     /// Action prefix for events
     action_prefix: String,
+    /// Text color overriding the theme (e.g. to flag a search with no matches)
+    text_color: Option<Color>,
 }
 
 impl TextField {
@@ -61,6 +63,7 @@ impl TextField {
             colors: TextFieldColors::default(),
             focused: false,
             action_prefix: action_prefix.into(),
+            text_color: None,
         }
     }
 
@@ -75,6 +78,11 @@ impl TextField {
         self
     }
 
+    /// Override the theme's text color, or restore it with None
+    pub fn set_text_color(&mut self, color: Option<Color>) {
+        self.text_color = color;
+    }
+
     /// Get the text content
     pub fn text(&self) -> &str {
         &self.text
@@ -326,6 +334,7 @@ impl Widget for TextField {
             .collect();
 
         let selection_range = self.selection_range();
+        let text_fg = self.text_color.unwrap_or(self.colors.text_fg);
 
         // Draw each character
         for i in 0..visible_width {
@@ -342,11 +351,11 @@ impl Widget for TextField {
                     (self.colors.selection_fg, self.colors.selection_bg)
                 } else {
                     // Normal
-                    (self.colors.text_fg, self.colors.text_bg)
+                    (text_fg, self.colors.text_bg)
                 }
             } else {
                 // Normal
-                (self.colors.text_fg, self.colors.text_bg)
+                (text_fg, self.colors.text_bg)
             };
 
             screen.set(bounds.y, bounds.x + i as u16, ch, fg, bg);
@@ -365,6 +374,12 @@ impl Widget for TextField {
                     self.ensure_cursor_visible_with_width(visible_width);
                     return EventResult::Action(format!("{}_change", self.action_prefix));
                 }
+                InputEvent::Paste(text) => {
+                    // A field holds one line, so line breaks are dropped
+                    text.chars().filter(|c| !c.is_control()).for_each(|ch| self.insert_char(ch));
+                    self.ensure_cursor_visible_with_width(visible_width);
+                    return EventResult::Action(format!("{}_change", self.action_prefix));
+                }
                 InputEvent::Backspace => {
                     self.backspace();
                     self.ensure_cursor_visible_with_width(visible_width);
@@ -498,7 +513,8 @@ impl TreeWidget for TextField {
         let selection_range = self.selection_range();
 
         // Theme colors
-        let text_fg = if self.focused { theme.text_field_focused_fg } else { theme.text_field_fg };
+        let theme_fg = if self.focused { theme.text_field_focused_fg } else { theme.text_field_fg };
+        let text_fg = self.text_color.unwrap_or(theme_fg);
         let text_bg = if self.focused { theme.text_field_focused_bg } else { theme.text_field_bg };
         let cursor_fg = theme.text_field_cursor_fg;
         let cursor_bg = theme.text_field_cursor_bg;
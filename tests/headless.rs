@@ -0,0 +1,53 @@
+//! Integration tests for `qbasic-rs --run` (headless mode)
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Write `source` to a temporary .BAS file and run it headless with `stdin`
+fn run_program(name: &str, source: &str, stdin: &str) -> Output {
+    let path: PathBuf = std::env::temp_dir().join(format!("qbasic-rs-{}-{}.bas", name, std::process::id()));
+    std::fs::write(&path, source).expect("write program");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_qbasic-rs"))
+        .arg("--run")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("start qbasic-rs");
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).expect("write stdin");
+    let output = child.wait_with_output().expect("run qbasic-rs");
+
+    let _ = std::fs::remove_file(&path);
+    output
+}
+
+#[test]
+fn test_run_prints_to_stdout() {
+    let program = "PRINT \"Hello\"\nFOR i = 1 TO 3\nPRINT i * i\nNEXT\nk$ = INKEY$\nPRINT LEN(k$)\n";
+    let output = run_program("print", program, "");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Hello\n 1 \n 4 \n 9 \n 0 \n");
+}
+
+#[test]
+fn test_run_reads_input_from_stdin() {
+    let program = "INPUT \"Name\"; n$\nPRINT \"Hi \"; n$\n";
+    let output = run_program("input", program, "Bob\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "Name? Bob\nHi Bob\n");
+}
+
+#[test]
+fn test_run_errors_exit_nonzero() {
+    let output = run_program("runtime", "PRINT 1\nPRINT 1 / 0\n", "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), " 1 \n");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Division by zero"));
+
+    let output = run_program("syntax", "PRINT (\n", "");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Syntax error"));
+}